@@ -1,19 +1,25 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use serde_json::{Value, json};
 use tokio::sync::mpsc;
 
-use crate::AppState;
+use crate::{AiConfig, AiProvider, AppState, TokenPrices};
 use crate::Channel;
-use crate::parser::parse_line;
-use crate::server::{channel_dates, resolve_log_path, read_log_file};
+use crate::parser::{LineKind, detect_format, file_format, parse_line};
+use crate::cache::read_log;
+use crate::server::{channel_dates, looks_like_date, resolve_log_path};
 
 pub enum SseEvent {
     ToolCall { name: String, input_summary: String },
     ToolResult { name: String, output_preview: String },
     Display(String),
+    /// A fragment of the model's free text, sent as it is generated.
+    Text(String),
     Done { url: String, output: String },
+    /// Tokens of one API turn, and their price when `ai.prices` is set.
+    Usage { input: u64, output: u64, cache_read: u64, cache_create: u64, cost: Option<f64> },
     Error(String),
 }
 
@@ -22,7 +28,8 @@ You are an IRC log search assistant. Search IRC logs using tools and compile rel
 
 Workflow:
 1. Use display to tell the user what you're searching for
-2. Use search to find relevant messages (use n first to gauge volume, then C for context)
+2. Use search to find relevant messages (use n first to gauge volume, then C for context); list_dates shows which \
+days have logs, or matches, before searching a range
 3. Use copy to include relevant log lines in the output
 4. Use output to add titles, separators, and factual summaries
 5. Use done to save and finish -- you MUST always call done to produce a result
@@ -40,14 +47,14 @@ fn build_system_prompt(state: &AppState) -> String {
     let base = ai_config.system_prompt.as_deref().unwrap_or(DEFAULT_SYSTEM_PROMPT);
     let mut prompt = String::from(base);
     prompt.push_str("\nAvailable channels:\n");
-    collect_channels(&state.channels, &mut prompt);
+    collect_channels(&state.channels, &state.config, &mut prompt);
     prompt
 }
 
-fn collect_channels(node: &crate::ChannelNode, out: &mut String) {
+fn collect_channels(node: &crate::ChannelNode, config: &crate::Config, out: &mut String) {
     if let Some(channel) = &node.channel {
-        if channel.name.starts_with('#') {
-            let path = channel.path_segments.join("/");
+        let path = channel.path_segments.join("/");
+        if ai_can_read(channel, &path, config) {
             let dates = channel_dates(channel);
             if let (Some(first), Some(last)) = (dates.first(), dates.last()) {
                 out.push_str(&format!(
@@ -61,7 +68,7 @@ fn collect_channels(node: &crate::ChannelNode, out: &mut String) {
         }
     }
     for child in node.children.values() {
-        collect_channels(child, out);
+        collect_channels(child, config, out);
     }
 }
 
@@ -81,6 +88,11 @@ fn build_tool_definitions() -> Vec<Value> {
                         "type": "string",
                         "description": "Channel path (e.g. \"OFTC/#bcachefs-dev\")"
                     },
+                    "channels": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Several channel paths to search in one call, instead of channel; results are labeled per channel and share the match/size limits"
+                    },
                     "date": {
                         "type": "string",
                         "description": "Specific date YYYY-MM-DD to search"
@@ -123,7 +135,37 @@ fn build_tool_definitions() -> Vec<Value> {
                         "description": "Max number of matching lines to return (default 50)"
                     }
                 },
-                "required": ["pattern", "channel"]
+                "required": ["pattern"]
+            }
+        }),
+        json!({
+            "name": "list_dates",
+            "description": "List the dates a channel has logs for, grouped by month; optionally only a range, or dates with a match.",
+            "input_schema": {
+                "type": "object",
+                "properties": {
+                    "channel": {
+                        "type": "string",
+                        "description": "Channel path (e.g. \"OFTC/#bcachefs-dev\")"
+                    },
+                    "from_date": {
+                        "type": "string",
+                        "description": "Start of date range YYYY-MM-DD (inclusive)"
+                    },
+                    "to_date": {
+                        "type": "string",
+                        "description": "End of date range YYYY-MM-DD (inclusive)"
+                    },
+                    "pattern": {
+                        "type": "string",
+                        "description": "Case-insensitive regex; only dates with a matching message or action are listed"
+                    },
+                    "events": {
+                        "type": "boolean",
+                        "description": "Let pattern match join/part/quit/nick-change lines too"
+                    }
+                },
+                "required": ["channel"]
             }
         }),
         json!({
@@ -205,12 +247,15 @@ fn build_tool_definitions() -> Vec<Value> {
     ]
 }
 
-fn validate_channel<'a>(
-    channel_path: &str,
-    channels: &'a crate::ChannelNode,
-) -> Result<&'a Channel, String> {
+/// Channels are open to the AI when public (`#`) or listed in
+/// `include_channels`, and not turned off with `ai_enabled`.
+fn ai_can_read(channel: &Channel, path: &str, config: &crate::Config) -> bool {
+    (channel.name.starts_with('#') || config.lists_channel(path)) && channel.ai_enabled()
+}
+
+fn validate_channel<'a>(channel_path: &str, state: &'a AppState) -> Result<&'a Channel, String> {
     let segments: Vec<&str> = channel_path.split('/').collect();
-    let mut node = channels;
+    let mut node = &state.channels;
     for seg in &segments {
         match node.children.get(*seg) {
             Some(child) => node = child,
@@ -218,82 +263,179 @@ fn validate_channel<'a>(
         }
     }
     match &node.channel {
-        Some(ch) if ch.name.starts_with('#') => Ok(ch),
+        Some(ch) if ai_can_read(ch, channel_path, &state.config) => Ok(ch),
         Some(_) => Err(format!("channel not accessible: {channel_path}")),
         None => Err(format!("not a channel: {channel_path}")),
     }
 }
 
-fn execute_search(input: &Value, state: &AppState) -> String {
+struct SearchParams<'a> {
+    re: regex::Regex,
+    count_only: bool,
+    include_events: bool,
+    max_results: usize,
+    context_before: usize,
+    context_after: usize,
+    specific_date: Option<&'a str>,
+    from_date: Option<&'a str>,
+    to_date: Option<&'a str>,
+    oldest_first: bool,
+    max_dates: usize,
+    max_output: usize,
+    /// Checked before each date, so one file is never cut short.
+    deadline: Instant,
+    log_cache: Option<&'a crate::cache::LogCache>,
+}
+
+/// Running totals shared by every channel of one `search` call, so the
+/// limits bound the combined output.
+#[derive(Default)]
+struct SearchProgress {
+    out: String,
+    total_matches: usize,
+    dates_scanned: usize,
+    stopped: bool,
+    timed_out: bool,
+}
+
+/// Longest `search` pattern accepted; model-written patterns never need more.
+const MAX_PATTERN_LEN: usize = 1000;
+/// Compiled size allowed for a `search` regex, so patterns like `\w{1000}`
+/// repeated are rejected instead of built.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+/// Ends the output of a `search` call that ran out of `search_time_ms`.
+const TIMED_OUT: &str = "[search timed out]";
+
+/// A model-written pattern as a case-insensitive regex, within the length
+/// and size limits.
+fn build_pattern(pattern: &str) -> Result<regex::Regex, String> {
+    if pattern.len() > MAX_PATTERN_LEN {
+        return Err(format!("error: pattern longer than {MAX_PATTERN_LEN} bytes"));
+    }
+    regex::RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|e| format!("invalid regex: {e}"))
+}
+
+type LogTool = fn(&Value, &AppState, &AiConfig) -> String;
+
+/// Runs a tool that reads log files on the blocking pool, keeping the
+/// runtime thread free for other requests.
+async fn execute_blocking(tool: LogTool, input: &Value, state: &Arc<AppState>) -> String {
+    let (input, state) = (input.clone(), Arc::clone(state));
+    tokio::task::spawn_blocking(move || tool(&input, &state, state.config.ai.as_ref().unwrap()))
+        .await
+        .unwrap_or_else(|e| format!("error: {e}"))
+}
+
+fn execute_search(input: &Value, state: &AppState, ai_config: &AiConfig) -> String {
     let pattern = match input["pattern"].as_str() {
         Some(p) if !p.is_empty() => p,
         _ => return "error: pattern is required".into(),
     };
-    let channel_path = match input["channel"].as_str() {
-        Some(c) => c,
-        None => return "error: channel is required".into(),
+    let channel_paths: Vec<&str> = match (input["channels"].as_array(), input["channel"].as_str()) {
+        (Some(list), _) => list.iter().filter_map(|c| c.as_str()).collect(),
+        (None, Some(c)) => vec![c],
+        (None, None) => return "error: channel or channels is required".into(),
     };
-    let channel = match validate_channel(channel_path, &state.channels) {
-        Ok(c) => c,
+    if channel_paths.is_empty() {
+        return "error: channels must not be empty".into();
+    }
+    let mut channels = Vec::new();
+    for path in &channel_paths {
+        match validate_channel(path, state) {
+            Ok(c) => channels.push((*path, c)),
+            Err(e) => return e,
+        }
+    }
+
+    let re = match build_pattern(pattern) {
+        Ok(r) => r,
         Err(e) => return e,
     };
 
-    let re = match regex::RegexBuilder::new(pattern)
-        .case_insensitive(true)
-        .build()
-    {
-        Ok(r) => r,
-        Err(e) => return format!("invalid regex: {e}"),
+    let params = SearchParams {
+        re,
+        count_only: input["n"].as_bool().unwrap_or(false),
+        include_events: input["events"].as_bool().unwrap_or(false),
+        max_results: input["c"].as_u64().unwrap_or(50) as usize,
+        context_after: input["C"].as_u64().unwrap_or(0).max(input["A"].as_u64().unwrap_or(0)) as usize,
+        context_before: input["C"].as_u64().unwrap_or(0).max(input["B"].as_u64().unwrap_or(0)) as usize,
+        specific_date: input["date"].as_str().filter(|d| looks_like_date(d)),
+        from_date: input["from_date"].as_str().filter(|d| looks_like_date(d)),
+        to_date: input["to_date"].as_str().filter(|d| looks_like_date(d)),
+        oldest_first: input["order"].as_str() == Some("oldest"),
+        max_dates: ai_config.max_dates_scanned,
+        max_output: ai_config.search_output_bytes,
+        deadline: Instant::now() + Duration::from_millis(ai_config.search_time_ms),
+        log_cache: state.log_cache.as_ref(),
     };
 
-    let count_only = input["n"].as_bool().unwrap_or(false);
-    let include_events = input["events"].as_bool().unwrap_or(false);
-    let max_results = input["c"].as_u64().unwrap_or(50) as usize;
-    let context_after = input["C"].as_u64().unwrap_or(0).max(input["A"].as_u64().unwrap_or(0)) as usize;
-    let context_before = input["C"].as_u64().unwrap_or(0).max(input["B"].as_u64().unwrap_or(0)) as usize;
+    let mut progress = SearchProgress::default();
+    for (channel_path, channel) in &channels {
+        search_one_channel(channel_path, channel, &params, &mut progress);
+        if progress.stopped {
+            break;
+        }
+    }
 
-    let specific_date = input["date"].as_str().filter(|d| d.len() == 10);
-    let from_date = input["from_date"].as_str().filter(|d| d.len() == 10);
-    let to_date = input["to_date"].as_str().filter(|d| d.len() == 10);
-    let oldest_first = input["order"].as_str() == Some("oldest");
+    let SearchProgress { out, total_matches, dates_scanned, timed_out, .. } = progress;
+    if total_matches == 0 {
+        let note = if timed_out { format!(" {TIMED_OUT}") } else { String::new() };
+        format!("no matches for \"{pattern}\" in {}{note}", channel_paths.join(", "))
+    } else if params.count_only {
+        format!("{out}total: {total_matches} matches across {dates_scanned} dates scanned")
+    } else {
+        out
+    }
+}
 
-    let dates: Vec<String> = if let Some(date) = specific_date {
+fn search_one_channel(channel_path: &str, channel: &Channel, params: &SearchParams, progress: &mut SearchProgress) {
+    let dates: Vec<String> = if let Some(date) = params.specific_date {
         vec![date.to_string()]
     } else {
         let mut d = channel_dates(channel);
-        if let Some(from) = from_date {
+        if let Some(from) = params.from_date {
             d.retain(|date| date.as_str() >= from);
         }
-        if let Some(to) = to_date {
+        if let Some(to) = params.to_date {
             d.retain(|date| date.as_str() <= to);
         }
-        if !oldest_first {
+        if !params.oldest_first {
             d.reverse();
         }
         d
     };
 
-    let mut out = String::new();
-    let mut total_matches = 0;
-    let mut dates_scanned = 0;
-
+    let out = &mut progress.out;
     for date in &dates {
-        if dates_scanned >= 365 {
-            out.push_str("\n[stopped: 365 dates scanned]\n");
-            break;
+        if Instant::now() >= params.deadline {
+            out.push_str(&format!("\n{TIMED_OUT}\n"));
+            progress.stopped = true;
+            progress.timed_out = true;
+            return;
+        }
+        if progress.dates_scanned >= params.max_dates {
+            out.push_str(&format!("\n[stopped: {} dates scanned]\n", params.max_dates));
+            progress.stopped = true;
+            return;
         }
-        dates_scanned += 1;
+        progress.dates_scanned += 1;
 
         let Some((path, format)) = resolve_log_path(channel, date) else { continue };
-        let Ok(content) = read_log_file(&path) else { continue };
+        let Ok(content) = read_log(params.log_cache, &path) else { continue };
+        let format = file_format(&content, format);
         let all_lines: Vec<&str> = content.lines().collect();
 
         let mut date_matches = 0;
         let mut emitted_lines: BTreeSet<usize> = BTreeSet::new();
 
         for (i, line) in all_lines.iter().enumerate() {
-            if re.is_match(line) {
-                if !include_events {
+            if params.re.is_match(line) {
+                if !params.include_events {
                     if let Some(parsed) = parse_line(line, format) {
                         if parsed.is_event() {
                             continue;
@@ -301,9 +443,9 @@ fn execute_search(input: &Value, state: &AppState) -> String {
                     }
                 }
                 date_matches += 1;
-                if !count_only {
-                    let start = i.saturating_sub(context_before);
-                    let end = (i + context_after + 1).min(all_lines.len());
+                if !params.count_only {
+                    let start = i.saturating_sub(params.context_before);
+                    let end = (i + params.context_after + 1).min(all_lines.len());
                     for j in start..end {
                         emitted_lines.insert(j);
                     }
@@ -315,16 +457,16 @@ fn execute_search(input: &Value, state: &AppState) -> String {
             continue;
         }
 
-        if count_only {
-            out.push_str(&format!("{date}: {date_matches} matches\n"));
-            total_matches += date_matches;
+        if params.count_only {
+            out.push_str(&format!("{channel_path} {date}: {date_matches} matches\n"));
+            progress.total_matches += date_matches;
             continue;
         }
 
         out.push_str(&format!("--- {channel_path} {date} ({date_matches} matches) ---\n"));
         let mut prev_line: Option<usize> = None;
         for j in &emitted_lines {
-            if !include_events {
+            if !params.include_events {
                 if let Some(parsed) = parse_line(all_lines[*j], format) {
                     if parsed.is_event() {
                         continue;
@@ -339,35 +481,103 @@ fn execute_search(input: &Value, state: &AppState) -> String {
             out.push_str(&format!("{:>5}: {}\n", j + 1, all_lines[*j]));
             prev_line = Some(*j);
         }
-        total_matches += date_matches;
+        progress.total_matches += date_matches;
 
-        if total_matches >= max_results {
-            out.push_str(&format!("\n[stopped: {max_results} match limit reached]\n"));
-            break;
+        if progress.total_matches >= params.max_results {
+            out.push_str(&format!("\n[stopped: {} match limit reached]\n", params.max_results));
+            progress.stopped = true;
+            return;
         }
 
-        if out.len() > 8000 {
+        if out.len() > params.max_output {
             out.push_str("\n[stopped: output size limit]\n");
-            break;
+            progress.stopped = true;
+            return;
         }
     }
+}
 
-    if total_matches == 0 {
-        format!("no matches for \"{pattern}\" in {channel_path}")
-    } else if count_only {
-        format!("{out}total: {total_matches} matches across {dates_scanned} dates scanned")
-    } else {
-        out
+/// Dates of a channel's logs, one line per month (`2025-01: 02 03 17`) to
+/// keep long histories short. With a pattern, each date's file is read, so
+/// the `search` date and time limits apply.
+fn execute_list_dates(input: &Value, state: &AppState, ai_config: &AiConfig) -> String {
+    let Some(channel_path) = input["channel"].as_str() else {
+        return "error: channel is required".into();
+    };
+    let channel = match validate_channel(channel_path, state) {
+        Ok(c) => c,
+        Err(e) => return e,
+    };
+    let re = match input["pattern"].as_str().filter(|p| !p.is_empty()).map(build_pattern) {
+        Some(Ok(re)) => Some(re),
+        Some(Err(e)) => return e,
+        None => None,
+    };
+    let include_events = input["events"].as_bool().unwrap_or(false);
+    let mut dates = channel_dates(channel);
+    if let Some(from) = input["from_date"].as_str().filter(|d| looks_like_date(d)) {
+        dates.retain(|date| date.as_str() >= from);
+    }
+    if let Some(to) = input["to_date"].as_str().filter(|d| looks_like_date(d)) {
+        dates.retain(|date| date.as_str() <= to);
+    }
+
+    let mut stopped = None;
+    if let Some(re) = &re {
+        let deadline = Instant::now() + Duration::from_millis(ai_config.search_time_ms);
+        let mut matching = Vec::new();
+        for (scanned, date) in dates.into_iter().enumerate() {
+            if Instant::now() >= deadline {
+                stopped = Some(TIMED_OUT.to_string());
+                break;
+            }
+            if scanned >= ai_config.max_dates_scanned {
+                stopped = Some(format!("[stopped: {} dates scanned]", ai_config.max_dates_scanned));
+                break;
+            }
+            let Some((path, format)) = resolve_log_path(channel, &date) else { continue };
+            let Ok(content) = read_log(state.log_cache.as_ref(), &path) else { continue };
+            let format = file_format(&content, format);
+            let found = content.lines().any(|line| {
+                re.is_match(line) && (include_events || parse_line(line, format).is_none_or(|l| !l.is_event()))
+            });
+            if found {
+                matching.push(date);
+            }
+        }
+        dates = matching;
+    }
+
+    if dates.is_empty() {
+        let note = stopped.map(|s| format!(" {s}")).unwrap_or_default();
+        return format!("no dates for {channel_path}{note}");
+    }
+    let mut out = format!("{channel_path}: {} dates", dates.len());
+    let mut month = "";
+    for date in &dates {
+        if out.len() > ai_config.search_output_bytes {
+            out.push_str("\n[stopped: output size limit]");
+            break;
+        }
+        if &date[..7] != month {
+            month = &date[..7];
+            out.push_str(&format!("\n{month}:"));
+        }
+        out.push_str(&format!(" {}", &date[8..]));
     }
+    if let Some(stopped) = stopped {
+        out.push_str(&format!("\n{stopped}"));
+    }
+    out
 }
 
-fn execute_copy(input: &Value, state: &AppState, output_buf: &mut String) -> String {
+fn execute_copy(input: &Value, state: &AppState, output_buf: &mut String, limit: usize) -> String {
     let channel_path = match input["channel"].as_str() {
         Some(c) => c,
         None => return "error: channel is required".into(),
     };
     let date = match input["date"].as_str() {
-        Some(d) if d.len() == 10 => d,
+        Some(d) if looks_like_date(d) => d,
         _ => return "error: date (YYYY-MM-DD) is required".into(),
     };
     let lines_spec = match input["lines"].as_str() {
@@ -375,7 +585,7 @@ fn execute_copy(input: &Value, state: &AppState, output_buf: &mut String) -> Str
         None => return "error: lines spec is required".into(),
     };
 
-    let channel = match validate_channel(channel_path, &state.channels) {
+    let channel = match validate_channel(channel_path, state) {
         Ok(c) => c,
         Err(e) => return e,
     };
@@ -392,7 +602,7 @@ fn execute_copy(input: &Value, state: &AppState, output_buf: &mut String) -> Str
     let Some((path, _)) = resolve_log_path(channel, date) else {
         return format!("no log for {date} in {channel_path}");
     };
-    let Ok(content) = read_log_file(&path) else {
+    let Ok(content) = read_log(state.log_cache.as_ref(), &path) else {
         return format!("error reading log for {date}");
     };
 
@@ -408,15 +618,14 @@ fn execute_copy(input: &Value, state: &AppState, output_buf: &mut String) -> Str
         copied += 1;
     }
 
-    if output_buf.len() > 100_000 {
-        output_buf.truncate(100_000);
-        return format!("copied {copied} lines (output buffer truncated to 100KB)");
+    if truncate_buffer(output_buf, limit) {
+        return format!("copied {copied} lines (output buffer truncated to {limit} bytes)");
     }
 
     format!("copied {copied} lines")
 }
 
-fn execute_output(input: &Value, output_buf: &mut String) -> String {
+fn execute_output(input: &Value, output_buf: &mut String, limit: usize) -> String {
     let text = input["text"].as_str().unwrap_or("");
     if input["clear"].as_bool().unwrap_or(false) {
         output_buf.clear();
@@ -424,18 +633,35 @@ fn execute_output(input: &Value, output_buf: &mut String) -> String {
     output_buf.push_str(text);
     output_buf.push('\n');
 
-    if output_buf.len() > 100_000 {
-        output_buf.truncate(100_000);
-        return "appended (output buffer truncated to 100KB)".into();
+    if truncate_buffer(output_buf, limit) {
+        return format!("appended (output buffer truncated to {limit} bytes)");
     }
 
     "ok".into()
 }
 
-fn execute_done(
+/// Cuts `buf` to at most `limit` bytes on a character boundary; true if it
+/// was longer.
+fn truncate_buffer(buf: &mut String, limit: usize) -> bool {
+    if buf.len() <= limit {
+        return false;
+    }
+    let mut end = limit;
+    while !buf.is_char_boundary(end) {
+        end -= 1;
+    }
+    buf.truncate(end);
+    true
+}
+
+/// `origin` (`https://host`, or empty for a site-relative URL) prefixes the
+/// result URL.
+async fn execute_done(
     input: &Value,
-    state: &AppState,
+    state: &Arc<AppState>,
+    channel: &Channel,
     output_buf: &str,
+    origin: &str,
     tx: &mpsc::UnboundedSender<SseEvent>,
 ) -> String {
     let title = match input["title"].as_str() {
@@ -443,6 +669,9 @@ fn execute_done(
         _ => return "error: title is required".into(),
     };
 
+    if *state.shutdown.borrow() {
+        return format!("error: {SHUTTING_DOWN}");
+    }
     let ai_config = state.config.ai.as_ref().unwrap();
     let ts = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -450,28 +679,415 @@ fn execute_done(
         .as_secs();
     let slug = slugify(title);
     let filename = format!("{ts}-{slug}.md");
-    let path = ai_config.output_dir.join(&filename);
+    let tmp = output_path(ai_config, &format!("{filename}.tmp"));
+    let (Some(path), Some(tmp)) = (output_path(ai_config, &filename), tmp) else {
+        return "error: invalid output file name".into();
+    };
 
-    if let Err(e) = std::fs::write(&path, output_buf) {
-        eprintln!("ai: failed to write {}: {e}", path.display());
+    let mut output = output_buf.to_string();
+    if ai_config.verify_quotes {
+        // Reads every quoted day's log; kept off the runtime thread
+        let (quoted, state) = (output_buf.to_string(), Arc::clone(state));
+        let unverified = tokio::task::spawn_blocking(move || unverified_quotes(&quoted, &state))
+            .await
+            .unwrap_or_default();
+        if !unverified.is_empty() {
+            output.push_str(&format!(
+                "{UNVERIFIED_WARNING}{} quoted line(s) do not match the logs verbatim:\n\n",
+                unverified.len()
+            ));
+            for q in &unverified {
+                output.push_str(&format!("- `{q}`\n"));
+            }
+        }
+    }
+
+    // Written aside and renamed, so an interrupted write never leaves a
+    // truncated report behind
+    if let Err(e) = std::fs::write(&tmp, &output).and_then(|()| std::fs::rename(&tmp, &path)) {
+        let _ = std::fs::remove_file(&tmp);
+        tracing::error!(path = %path.display(), "failed to write report: {e}");
         return format!("error writing file: {e}");
     }
 
     let base_path = &state.config.base_path;
-    let url = format!("{base_path}/ask/output/{ts}-{slug}.html");
+    let url = format!("{origin}{base_path}/ask/output/{ts}-{slug}.html");
+
+    if ai_config.broadcast_results {
+        let key = ask_feed_key(channel);
+        if let Some(feed) = state.sse_feeds.read().await.get(&key) {
+            feed.publish(std::path::PathBuf::new(), vec![(0, json!({"url": url, "title": title}).to_string())]);
+        }
+    }
 
     let _ = tx.send(SseEvent::Done {
         url: url.clone(),
-        output: output_buf.to_string(),
+        output,
     });
 
     format!("saved: {url}")
 }
 
-fn execute_display(input: &Value, tx: &mpsc::UnboundedSender<SseEvent>) -> String {
-    let text = input["text"].as_str().unwrap_or("");
-    let _ = tx.send(SseEvent::Display(text.to_string()));
-    "ok".into()
+/// Log lines in the output that sit under a `--- channel date ---` header
+/// (as written by `copy`) but no longer match that day's file verbatim,
+/// formatted as `channel date: line`.
+fn unverified_quotes(output: &str, state: &AppState) -> Vec<String> {
+    let mut files: HashMap<(String, String), Option<Arc<str>>> = HashMap::new();
+    let mut section: Option<(String, String)> = None;
+    let mut bad = Vec::new();
+
+    for line in output.lines() {
+        if let Some(header) = line.strip_prefix("--- ").and_then(|l| l.strip_suffix(" ---")) {
+            section = header
+                .rsplit_once(' ')
+                .filter(|(c, d)| looks_like_date(d) && validate_channel(c, state).is_ok())
+                .map(|(c, d)| (c.to_string(), d.to_string()));
+            continue;
+        }
+        let Some((channel_path, date)) = &section else { continue };
+        // Only lines the parser recognizes are quotes; prose comes back raw
+        let parsed = parse_line(line, detect_format(line));
+        if parsed.is_none_or(|p| matches!(p.kind, LineKind::Raw { .. })) {
+            continue;
+        }
+        let content = files.entry((channel_path.clone(), date.clone())).or_insert_with(|| {
+            let channel = validate_channel(channel_path, state).ok()?;
+            let (path, _) = resolve_log_path(channel, date)?;
+            read_log(state.log_cache.as_ref(), &path).ok()
+        });
+        let found = content.as_deref().is_some_and(|c| c.lines().any(|l| l == line));
+        if !found {
+            bad.push(format!("{channel_path} {date}: {line}"));
+        }
+    }
+    bad
+}
+
+/// `sse_feeds` key under which finished reports for a channel are announced.
+pub fn ask_feed_key(channel: &Channel) -> String {
+    format!("ask:{}", channel.path_segments.join("/"))
+}
+
+/// A `messages` response rebuilt from its streamed events, in the same
+/// shape as the non-streaming body (`content`, `stop_reason`, `usage`).
+/// Text deltas and finished `display` calls are forwarded as they arrive.
+#[derive(Default)]
+struct StreamedMessage {
+    message: Value,
+    /// Raw `input_json_delta` fragments of the tool call being streamed.
+    partial_json: String,
+    /// Bytes received after the last complete frame, which may end inside
+    /// a UTF-8 sequence.
+    pending: Vec<u8>,
+    finished: bool,
+}
+
+impl StreamedMessage {
+    /// Buffers a chunk of the response body and applies the frames it
+    /// completes. Frames are only decoded once whole, so a character split
+    /// across chunks survives.
+    fn apply_chunk(&mut self, chunk: &[u8], tx: &mpsc::UnboundedSender<SseEvent>) -> Result<(), String> {
+        self.pending.extend_from_slice(chunk);
+        while let Some(end) = self.pending.windows(2).position(|w| w == b"\n\n") {
+            let frame: Vec<u8> = self.pending.drain(..end + 2).collect();
+            self.apply_frame(&String::from_utf8_lossy(&frame), tx)?;
+        }
+        Ok(())
+    }
+
+    /// Applies one SSE frame (`event:`/`data:` lines). API `error` events
+    /// and unparsable data end the stream with an error message.
+    fn apply_frame(&mut self, frame: &str, tx: &mpsc::UnboundedSender<SseEvent>) -> Result<(), String> {
+        let Some(data) = frame.lines().find_map(|l| l.strip_prefix("data:")) else { return Ok(()) };
+        let event: Value = serde_json::from_str(data.trim()).map_err(|e| format!("invalid API response: {e}"))?;
+        match event["type"].as_str().unwrap_or("") {
+            "message_start" => {
+                self.message = event["message"].clone();
+                self.message["content"] = json!([]);
+            }
+            "content_block_start" => {
+                self.partial_json.clear();
+                if let Some(content) = self.message["content"].as_array_mut() {
+                    content.push(event["content_block"].clone());
+                }
+            }
+            "content_block_delta" => {
+                let Some(block) = self.message["content"].as_array_mut().and_then(|c| c.last_mut()) else {
+                    return Ok(());
+                };
+                let delta = &event["delta"];
+                match delta["type"].as_str() {
+                    Some("text_delta") => {
+                        let text = delta["text"].as_str().unwrap_or("");
+                        let joined = format!("{}{text}", block["text"].as_str().unwrap_or(""));
+                        block["text"] = json!(joined);
+                        let _ = tx.send(SseEvent::Text(text.to_string()));
+                    }
+                    Some("input_json_delta") => self.partial_json.push_str(delta["partial_json"].as_str().unwrap_or("")),
+                    _ => {}
+                }
+            }
+            "content_block_stop" => {
+                let Some(block) = self.message["content"].as_array_mut().and_then(|c| c.last_mut()) else {
+                    return Ok(());
+                };
+                if block["type"].as_str() == Some("tool_use") {
+                    if !self.partial_json.is_empty() {
+                        block["input"] = serde_json::from_str(&self.partial_json)
+                            .map_err(|e| format!("invalid tool input: {e}"))?;
+                    }
+                    if block["name"].as_str() == Some("display") {
+                        let text = block["input"]["text"].as_str().unwrap_or("");
+                        let _ = tx.send(SseEvent::Display(text.to_string()));
+                    }
+                }
+            }
+            "message_delta" => {
+                self.message["stop_reason"] = event["delta"]["stop_reason"].clone();
+                if let Some(output) = event["usage"]["output_tokens"].as_u64() {
+                    self.message["usage"]["output_tokens"] = json!(output);
+                }
+            }
+            "message_stop" => self.finished = true,
+            "error" => {
+                let msg = event["error"]["message"].as_str().unwrap_or("unknown error");
+                return Err(format!("API error: {msg}"));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Longest wait between retries, whatever `retry-after` asks for.
+const MAX_RETRY_DELAY: u64 = 60;
+
+/// Seconds to wait before retry `attempt` (from 0) without a `retry-after`:
+/// doubling from 1s, capped so a large `max_retries` cannot overflow.
+fn backoff(attempt: u32) -> u64 {
+    1u64.checked_shl(attempt).unwrap_or(u64::MAX).min(MAX_RETRY_DELAY)
+}
+
+/// Sends `request`, retrying up to `max_retries` times on rate limiting and
+/// transient server errors. Waits `retry-after` seconds when the server
+/// gives it, else 1s, 2s, 4s... and tells the user through a display event.
+/// Any other failure status is returned as an error with the response body.
+async fn send_with_retry(
+    request: reqwest::RequestBuilder,
+    max_retries: u32,
+    tx: &mpsc::UnboundedSender<SseEvent>,
+) -> Result<reqwest::Response, String> {
+    let mut attempt = 0;
+    loop {
+        let this_try = request.try_clone().ok_or("API request cannot be retried")?;
+        let resp = this_try.send().await.map_err(|e| format!("API request failed: {e}"))?;
+        let status = resp.status();
+        if status.is_success() {
+            return Ok(resp);
+        }
+        let retryable = matches!(status.as_u16(), 429 | 500 | 503);
+        if !retryable || attempt >= max_retries {
+            let resp_text = resp.text().await.unwrap_or_default();
+            return Err(format!("API error {status}: {resp_text}"));
+        }
+        let delay = resp
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|v| v.to_str().ok()?.trim().parse::<u64>().ok())
+            .unwrap_or_else(|| backoff(attempt))
+            .min(MAX_RETRY_DELAY);
+        let reason = if status == reqwest::StatusCode::TOO_MANY_REQUESTS { "rate limited".into() } else { format!("API error {status}") };
+        tracing::warn!(delay, "{reason}, retrying");
+        let _ = tx.send(SseEvent::Display(format!("{reason}, retrying in {delay}s")));
+        tokio::time::sleep(std::time::Duration::from_secs(delay)).await;
+        attempt += 1;
+    }
+}
+
+/// One Anthropic `messages` call, streamed. Returns the assistant message
+/// in the non-streaming response shape.
+async fn anthropic_turn(
+    client: &reqwest::Client,
+    ai_config: &AiConfig,
+    system_prompt: &str,
+    tools: &[Value],
+    messages: &[Value],
+    tx: &mpsc::UnboundedSender<SseEvent>,
+) -> Result<Value, String> {
+    let base_url = ai_config.base_url.as_deref().unwrap_or("https://api.anthropic.com");
+    let body = json!({
+        "model": ai_config.model,
+        "max_tokens": ai_config.max_tokens,
+        "stream": true,
+        "system": [{
+            "type": "text",
+            "text": system_prompt,
+        }],
+        "messages": messages,
+        "tools": tools,
+    });
+
+    let request = client
+        .post(format!("{}/v1/messages", base_url.trim_end_matches('/')))
+        .header("x-api-key", &ai_config.api_key)
+        .header("anthropic-version", "2023-06-01")
+        .json(&body);
+    let mut resp = send_with_retry(request, ai_config.max_retries, tx).await?;
+
+    let mut streamed = StreamedMessage::default();
+    while !streamed.finished {
+        let Some(chunk) = resp.chunk().await.map_err(|e| format!("API read failed: {e}"))? else {
+            return Err("API stream ended early".into());
+        };
+        streamed.apply_chunk(&chunk, tx)?;
+    }
+    Ok(streamed.message)
+}
+
+/// One chat completion against an OpenAI-compatible server (OpenAI, Ollama,
+/// vLLM, LiteLLM...). The conversation is kept in Anthropic form and
+/// translated both ways, so the tool loop does not care which API ran.
+async fn openai_turn(
+    client: &reqwest::Client,
+    ai_config: &AiConfig,
+    system_prompt: &str,
+    tools: &[Value],
+    messages: &[Value],
+    tx: &mpsc::UnboundedSender<SseEvent>,
+) -> Result<Value, String> {
+    let base_url = ai_config.base_url.as_deref().unwrap_or("https://api.openai.com/v1");
+    let body = json!({
+        "model": ai_config.model,
+        "max_tokens": ai_config.max_tokens,
+        "messages": openai_messages(system_prompt, messages),
+        "tools": openai_tool_definitions(tools),
+    });
+
+    let mut request = client.post(format!("{}/chat/completions", base_url.trim_end_matches('/'))).json(&body);
+    if !ai_config.api_key.is_empty() {
+        request = request.bearer_auth(&ai_config.api_key);
+    }
+    let resp = send_with_retry(request, ai_config.max_retries, tx).await?;
+    let resp_text = resp.text().await.map_err(|e| format!("API read failed: {e}"))?;
+    let resp_json: Value = serde_json::from_str(&resp_text).map_err(|e| format!("invalid API response: {e}"))?;
+    let message = from_openai_response(&resp_json)?;
+    for block in message["content"].as_array().into_iter().flatten() {
+        match (block["type"].as_str(), block["name"].as_str()) {
+            (Some("text"), _) => {
+                let _ = tx.send(SseEvent::Text(block["text"].as_str().unwrap_or("").to_string()));
+            }
+            (Some("tool_use"), Some("display")) => {
+                let _ = tx.send(SseEvent::Display(block["input"]["text"].as_str().unwrap_or("").to_string()));
+            }
+            _ => {}
+        }
+    }
+    Ok(message)
+}
+
+/// The shared tool set as OpenAI function definitions.
+fn openai_tool_definitions(tools: &[Value]) -> Vec<Value> {
+    tools
+        .iter()
+        .map(|tool| {
+            json!({
+                "type": "function",
+                "function": {
+                    "name": tool["name"],
+                    "description": tool["description"],
+                    "parameters": tool["input_schema"],
+                },
+            })
+        })
+        .collect()
+}
+
+/// Anthropic-style conversation as chat completion messages: tool calls
+/// move to `tool_calls`, and each tool result becomes its own `tool` message.
+fn openai_messages(system_prompt: &str, messages: &[Value]) -> Vec<Value> {
+    let mut out = vec![json!({"role": "system", "content": system_prompt})];
+    for msg in messages {
+        let role = msg["role"].as_str().unwrap_or("user");
+        let Some(blocks) = msg["content"].as_array() else {
+            out.push(json!({"role": role, "content": msg["content"]}));
+            continue;
+        };
+        let text: String = blocks.iter().filter_map(|b| b["text"].as_str()).collect();
+        if role == "assistant" {
+            let calls: Vec<Value> = blocks
+                .iter()
+                .filter(|b| b["type"].as_str() == Some("tool_use"))
+                .map(|b| {
+                    json!({
+                        "id": b["id"],
+                        "type": "function",
+                        "function": {"name": b["name"], "arguments": b["input"].to_string()},
+                    })
+                })
+                .collect();
+            let mut assistant = json!({"role": "assistant", "content": if text.is_empty() { Value::Null } else { json!(text) }});
+            if !calls.is_empty() {
+                assistant["tool_calls"] = json!(calls);
+            }
+            out.push(assistant);
+            continue;
+        }
+        for block in blocks.iter().filter(|b| b["type"].as_str() == Some("tool_result")) {
+            out.push(json!({"role": "tool", "tool_call_id": block["tool_use_id"], "content": block["content"]}));
+        }
+        if !text.is_empty() {
+            out.push(json!({"role": role, "content": text}));
+        }
+    }
+    out
+}
+
+/// A chat completion response in the Anthropic message shape the tool loop
+/// reads: `content` blocks, `stop_reason` and `usage`.
+fn from_openai_response(resp: &Value) -> Result<Value, String> {
+    let choice = &resp["choices"][0];
+    if choice.is_null() {
+        return Err("invalid API response: no choices".into());
+    }
+    let message = &choice["message"];
+    let mut content = Vec::new();
+    if let Some(text) = message["content"].as_str().filter(|t| !t.is_empty()) {
+        content.push(json!({"type": "text", "text": text}));
+    }
+    for call in message["tool_calls"].as_array().into_iter().flatten() {
+        let arguments = call["function"]["arguments"].as_str().unwrap_or("{}");
+        let input: Value = serde_json::from_str(arguments).map_err(|e| format!("invalid tool input: {e}"))?;
+        content.push(json!({"type": "tool_use", "id": call["id"], "name": call["function"]["name"], "input": input}));
+    }
+    let has_calls = content.iter().any(|b| b["type"].as_str() == Some("tool_use"));
+    let stop_reason = match choice["finish_reason"].as_str() {
+        _ if has_calls => "tool_use",
+        Some("stop") => "end_turn",
+        Some(other) => other,
+        None => "",
+    };
+    Ok(json!({
+        "content": content,
+        "stop_reason": stop_reason,
+        "usage": {
+            "input_tokens": resp["usage"]["prompt_tokens"],
+            "output_tokens": resp["usage"]["completion_tokens"],
+        },
+    }))
+}
+
+/// A `Usage` event from a turn's `usage` object, in the Anthropic shape
+/// both providers are converted to.
+fn usage_event(usage: &Value, prices: Option<&TokenPrices>) -> SseEvent {
+    let input = usage["input_tokens"].as_u64().unwrap_or(0);
+    let cache_create = usage["cache_creation_input_tokens"].as_u64().unwrap_or(0);
+    let cache_read = usage["cache_read_input_tokens"].as_u64().unwrap_or(0);
+    let output = usage["output_tokens"].as_u64().unwrap_or(0);
+    let cost = prices.map(|p| {
+        (input as f64 * p.input + output as f64 * p.output + cache_read as f64 * p.cache_read + cache_create as f64 * p.cache_write)
+            / 1_000_000.0
+    });
+    SseEvent::Usage { input, output, cache_read, cache_create, cost }
 }
 
 fn execute_abort(tx: &mpsc::UnboundedSender<SseEvent>) -> String {
@@ -481,10 +1097,51 @@ fn execute_abort(tx: &mpsc::UnboundedSender<SseEvent>) -> String {
     "aborted".into()
 }
 
+/// Marks the start of the warning `execute_done` adds under a report.
+const UNVERIFIED_WARNING: &str = "\n---\n\n**Warning:** ";
+
+/// Sent to the client when a session stops because the server is exiting.
+const SHUTTING_DOWN: &str = "server is shutting down";
+
+/// Whether `name` can be joined onto `output_dir` without leaving it:
+/// lowercase letters, digits, `-` and `.`, not starting with a dot.
+pub fn is_output_name(name: &str) -> bool {
+    !name.starts_with('.') && name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'.')
+}
+
+/// `name` inside `output_dir`, or `None` if it is not an output name or is a
+/// symlink pointing out of the directory.
+pub fn output_path(ai_config: &AiConfig, name: &str) -> Option<std::path::PathBuf> {
+    if !is_output_name(name) {
+        return None;
+    }
+    crate::contained_path(&ai_config.output_dir, name)
+}
+
+/// The saved report `slug` (`1700000000-title`, with or without its `.md`
+/// or `.html` extension), without the unverified-quotes warning so a
+/// continued session does not repeat it.
+pub fn prior_output(ai_config: &AiConfig, slug: &str) -> Result<String, String> {
+    let slug = slug.strip_suffix(".html").or_else(|| slug.strip_suffix(".md")).unwrap_or(slug);
+    if slug.is_empty() || !is_output_name(slug) {
+        return Err("invalid append_to".into());
+    }
+    let path = output_path(ai_config, &format!("{slug}.md")).ok_or("no such ask result")?;
+    let content = std::fs::read_to_string(path).map_err(|_| "no such ask result".to_string())?;
+    Ok(match content.split_once(UNVERIFIED_WARNING) {
+        Some((report, _)) => format!("{}\n", report.trim_end()),
+        None => content,
+    })
+}
+
+/// `prior` is an earlier report to continue: it seeds the output buffer and
+/// is shown to the model with the query.
 pub async fn run_ai_session(
     query: String,
-    _channel: Channel,
+    channel: Channel,
     state: Arc<AppState>,
+    origin: String,
+    prior: Option<String>,
     tx: mpsc::UnboundedSender<SseEvent>,
 ) {
     let ai_config = match &state.config.ai {
@@ -502,16 +1159,20 @@ pub async fn run_ai_session(
         }
     };
 
-    let api_url = "https://api.anthropic.com/v1/messages";
-
     let system_prompt = build_system_prompt(&state);
     let tools = build_tool_definitions();
-    let model = ai_config.model.clone();
-    let api_key = ai_config.api_key.clone();
 
+    let content = match &prior {
+        Some(report) => format!(
+            "{query}\n\nThis continues an earlier report, which is already in the output buffer. \
+             Extend or refine it with `output` (only pass `clear` to rewrite it whole), then call `done`.\n\n\
+             <previous_report>\n{report}</previous_report>"
+        ),
+        None => query.clone(),
+    };
     let mut messages: Vec<Value> = vec![json!({
         "role": "user",
-        "content": query,
+        "content": content,
     })];
 
     // Put cache_control on the last tool so tools+system prefix can be cached.
@@ -524,11 +1185,16 @@ pub async fn run_ai_session(
 
     let max_tool_calls = ai_config.max_tool_calls;
 
-    let mut output_buf = String::new();
+    let mut output_buf = prior.unwrap_or_default();
+    let mut shutdown = state.shutdown.subscribe();
 
     for _iteration in 0..max_tool_calls {
+        if *shutdown.borrow() {
+            let _ = tx.send(SseEvent::Error(SHUTTING_DOWN.into()));
+            break;
+        }
         let msg_json = serde_json::to_string(&messages).unwrap_or_default();
-        if msg_json.len() > 150_000 {
+        if msg_json.len() > ai_config.max_context_bytes {
             let _ = tx.send(SseEvent::Error("context limit reached".into()));
             break;
         }
@@ -559,66 +1225,32 @@ pub async fn run_ai_session(
             }
         }
 
-        let body = json!({
-            "model": model,
-            "max_tokens": 4096,
-            "system": [{
-                "type": "text",
-                "text": system_prompt,
-            }],
-            "messages": messages,
-            "tools": tools,
-        });
-
-        let resp = match client
-            .post(api_url)
-            .header("x-api-key", &api_key)
-            .header("anthropic-version", "2023-06-01")
-            .json(&body)
-            .send()
-            .await
-        {
-            Ok(r) => r,
-            Err(e) => {
-                eprintln!("ai: API request failed: {e}");
-                let _ = tx.send(SseEvent::Error(format!("API request failed: {e}")));
-                break;
+        let turn = async {
+            match ai_config.provider {
+                AiProvider::Anthropic => anthropic_turn(&client, ai_config, &system_prompt, &tools, &messages, &tx).await,
+                AiProvider::OpenAi => openai_turn(&client, ai_config, &system_prompt, &tools, &messages, &tx).await,
             }
         };
-
-        let status = resp.status();
-        let resp_text = match resp.text().await {
-            Ok(t) => t,
-            Err(e) => {
-                eprintln!("ai: API read failed: {e}");
-                let _ = tx.send(SseEvent::Error(format!("API read failed: {e}")));
-                break;
-            }
+        // A turn can stream for minutes; shutdown abandons it rather than wait
+        let turn = tokio::select! {
+            turn = turn => turn,
+            _ = shutdown.wait_for(|stopping| *stopping) => Err(SHUTTING_DOWN.into()),
         };
-
-        if !status.is_success() {
-            eprintln!("ai: API error {status}: {resp_text}");
-            let _ = tx.send(SseEvent::Error(format!("API error {status}: {resp_text}")));
-            break;
-        }
-
-        let resp_json: Value = match serde_json::from_str(&resp_text) {
+        let resp_json = match turn {
             Ok(v) => v,
             Err(e) => {
-                eprintln!("ai: invalid API response: {e}");
-                let _ = tx.send(SseEvent::Error(format!("invalid API response: {e}")));
+                tracing::error!("{e}");
+                let _ = tx.send(SseEvent::Error(e));
                 break;
             }
         };
 
         if let Some(usage) = resp_json.get("usage") {
-            let input = usage["input_tokens"].as_u64().unwrap_or(0);
-            let cache_create = usage["cache_creation_input_tokens"].as_u64().unwrap_or(0);
-            let cache_read = usage["cache_read_input_tokens"].as_u64().unwrap_or(0);
-            let output = usage["output_tokens"].as_u64().unwrap_or(0);
-            eprintln!(
-                "ai: tokens: input={input} cache_create={cache_create} cache_read={cache_read} output={output}"
-            );
+            let event = usage_event(usage, ai_config.prices.as_ref());
+            if let SseEvent::Usage { input, output, cache_read, cache_create, .. } = event {
+                tracing::info!(input, cache_create, cache_read, output, "tokens");
+            }
+            let _ = tx.send(event);
         }
 
         let stop_reason = resp_json["stop_reason"].as_str().unwrap_or("");
@@ -643,7 +1275,7 @@ pub async fn run_ai_session(
 
             if !output_buf.trim().is_empty() {
                 let done_input = json!({"title": query});
-                execute_done(&done_input, &state, &output_buf, &tx);
+                execute_done(&done_input, &state, &channel, &output_buf, &origin, &tx).await;
             } else {
                 let _ = tx.send(SseEvent::Error("no results found".into()));
             }
@@ -682,14 +1314,16 @@ pub async fn run_ai_session(
                 }
 
                 let result = match tool_name {
-                    "search" => execute_search(tool_input, &state),
-                    "copy" => execute_copy(tool_input, &state, &mut output_buf),
-                    "output" => execute_output(tool_input, &mut output_buf),
+                    "search" => execute_blocking(execute_search, tool_input, &state).await,
+                    "list_dates" => execute_blocking(execute_list_dates, tool_input, &state).await,
+                    "copy" => execute_copy(tool_input, &state, &mut output_buf, ai_config.output_buffer_bytes),
+                    "output" => execute_output(tool_input, &mut output_buf, ai_config.output_buffer_bytes),
                     "done" => {
                         should_stop = true;
-                        execute_done(tool_input, &state, &output_buf, &tx)
+                        execute_done(tool_input, &state, &channel, &output_buf, &origin, &tx).await
                     }
-                    "display" => execute_display(tool_input, &tx),
+                    // Already shown when its block finished streaming
+                    "display" => "ok".into(),
                     "abort" => {
                         should_stop = true;
                         execute_abort(&tx)
@@ -732,8 +1366,16 @@ fn summarize_input(tool_name: &str, input: &Value) -> String {
     match tool_name {
         "search" => {
             let pattern = input["pattern"].as_str().unwrap_or("?");
-            let channel = input["channel"].as_str().unwrap_or("?");
-            let mut s = format!("pattern={pattern:?}, channel={channel}");
+            let mut s = match input["channels"].as_array() {
+                Some(list) => {
+                    let names: Vec<&str> = list.iter().filter_map(|c| c.as_str()).collect();
+                    format!("pattern={pattern:?}, channels={}", names.join(","))
+                }
+                None => {
+                    let channel = input["channel"].as_str().unwrap_or("?");
+                    format!("pattern={pattern:?}, channel={channel}")
+                }
+            };
             if let Some(date) = input["date"].as_str() {
                 s.push_str(&format!(", date={date}"));
             }
@@ -754,6 +1396,19 @@ fn summarize_input(tool_name: &str, input: &Value) -> String {
             }
             s
         }
+        "list_dates" => {
+            let mut s = input["channel"].as_str().unwrap_or("?").to_string();
+            if let Some(pattern) = input["pattern"].as_str() {
+                s.push_str(&format!(", pattern={pattern:?}"));
+            }
+            if let Some(from) = input["from_date"].as_str() {
+                s.push_str(&format!(", from={from}"));
+            }
+            if let Some(to) = input["to_date"].as_str() {
+                s.push_str(&format!(", to={to}"));
+            }
+            s
+        }
         "copy" => {
             let channel = input["channel"].as_str().unwrap_or("?");
             let date = input["date"].as_str().unwrap_or("?");
@@ -851,6 +1506,7 @@ pub fn parse_line_spec(spec: &str) -> Result<BTreeSet<usize>, String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fixture::LogTree;
 
     #[test]
     fn test_slugify_basic() {
@@ -925,4 +1581,371 @@ mod tests {
     fn test_parse_line_spec_reversed_range() {
         assert!(parse_line_spec("30-20").is_err());
     }
+
+    #[test]
+    fn test_search_multiple_channels() {
+        let dir = LogTree::new("ai-search");
+        dir.write("OFTC/#a/2025-01-01.log", "[10:00:00] <x> hello from a\n");
+        dir.write("OFTC/#b/2025-01-01.log", "[11:00:00] <y> hello from b\n");
+        let state = dir.state(Default::default());
+        let ai: AiConfig = serde_yaml::from_str("output_dir: /nonexistent").unwrap();
+
+        let out = execute_search(&json!({"pattern": "hello", "channels": ["OFTC/#a", "OFTC/#b"]}), &state, &ai);
+        assert!(out.contains("--- OFTC/#a 2025-01-01 (1 matches) ---"));
+        assert!(out.contains("--- OFTC/#b 2025-01-01 (1 matches) ---"));
+
+        let out = execute_search(&json!({"pattern": "hello", "channel": "OFTC/#b"}), &state, &ai);
+        assert!(!out.contains("OFTC/#a"));
+
+        let out = execute_search(&json!({"pattern": "hello", "channels": ["OFTC/#a", "OFTC/#nope"]}), &state, &ai);
+        assert_eq!(out, "unknown channel: OFTC/#nope");
+    }
+
+    #[test]
+    fn test_search_max_dates_scanned() {
+        let dir = LogTree::new("ai-dates");
+        for day in 1..=5 {
+            dir.write(format!("OFTC/#a/2025-01-{day:02}.log"), format!("[10:00:00] <x> hello {day}\n"));
+        }
+        let state = dir.state(Default::default());
+        let search = json!({"pattern": "hello", "channel": "OFTC/#a"});
+
+        let ai: AiConfig = serde_yaml::from_str("output_dir: /nonexistent").unwrap();
+        let out = execute_search(&search, &state, &ai);
+        assert_eq!(out.matches("(1 matches)").count(), 5);
+        assert!(!out.contains("[stopped"));
+
+        let ai: AiConfig = serde_yaml::from_str("output_dir: /nonexistent\nmax_dates_scanned: 2").unwrap();
+        let out = execute_search(&search, &state, &ai);
+        assert_eq!(out.matches("(1 matches)").count(), 2);
+        assert!(out.contains("2025-01-05") && out.contains("2025-01-04") && !out.contains("2025-01-03"));
+        assert!(out.ends_with("[stopped: 2 dates scanned]\n"), "{out}");
+
+        assert_eq!(ai.validate(), Ok(()));
+        let zero: AiConfig = serde_yaml::from_str("output_dir: /nonexistent\nmax_dates_scanned: 0").unwrap();
+        assert_eq!(zero.validate(), Err("ai.max_dates_scanned must be greater than 0".into()));
+    }
+
+    #[test]
+    fn test_search_time_budget() {
+        let dir = LogTree::new("ai-budget");
+        let day: String = (0..2000).map(|i| format!("[10:{:02}:{:02}] <x> hello {i}\n", i / 60 % 60, i % 60)).collect();
+        for n in 0..400 {
+            let (year, month, day_of_month) = (2020 + n / 336, n / 28 % 12 + 1, n % 28 + 1);
+            dir.write(format!("OFTC/#a/{year}-{month:02}-{day_of_month:02}.log"), &day);
+        }
+        let state = dir.state(Default::default());
+        let ai: AiConfig =
+            serde_yaml::from_str("output_dir: /nonexistent\nmax_dates_scanned: 1000\nsearch_time_ms: 20").unwrap();
+
+        let started = Instant::now();
+        let out = execute_search(&json!({"pattern": "hello", "channel": "OFTC/#a", "n": true}), &state, &ai);
+        assert!(started.elapsed() < Duration::from_secs(2), "{:?}", started.elapsed());
+        assert!(out.contains(TIMED_OUT), "{out}");
+        assert!(out.lines().filter(|l| l.ends_with(" matches")).count() < 400, "{out}");
+
+        let out = execute_search(&json!({"pattern": "nothing", "channel": "OFTC/#a"}), &state, &ai);
+        assert!(out.starts_with("no matches") && out.ends_with(TIMED_OUT), "{out}");
+
+        let huge = execute_search(&json!({"pattern": "\\w{1000}{1000}", "channel": "OFTC/#a"}), &state, &ai);
+        assert!(huge.starts_with("invalid regex"), "{huge}");
+        let long = execute_search(&json!({"pattern": "a".repeat(MAX_PATTERN_LEN + 1), "channel": "OFTC/#a"}), &state, &ai);
+        assert!(long.starts_with("error: pattern longer"), "{long}");
+    }
+
+    #[test]
+    fn test_list_dates() {
+        let dir = LogTree::new("ai-list-dates");
+        for (date, line) in [
+            ("2025-01-02", "<x> hello"),
+            ("2025-01-17", "*** Joins: hello (h@host)"),
+            ("2025-02-03", "<y> bye"),
+            ("2025-02-20", "<z> HELLO again"),
+        ] {
+            dir.write(format!("OFTC/#a/{date}.log"), format!("[10:00:00] {line}\n"));
+        }
+        let state = dir.state(Default::default());
+        let ai: AiConfig = serde_yaml::from_str("output_dir: /nonexistent").unwrap();
+        let list = |input: Value| execute_list_dates(&input, &state, &ai);
+
+        let channel = state.channels.children["OFTC"].children["#a"].channel.as_ref().unwrap();
+        assert_eq!(channel_dates(channel).len(), 4);
+        assert_eq!(list(json!({"channel": "OFTC/#a"})), "OFTC/#a: 4 dates\n2025-01: 02 17\n2025-02: 03 20");
+        let range = json!({"channel": "OFTC/#a", "from_date": "2025-01-10", "to_date": "2025-02-10"});
+        assert_eq!(list(range), "OFTC/#a: 2 dates\n2025-01: 17\n2025-02: 03");
+        let matching = list(json!({"channel": "OFTC/#a", "pattern": "hello"}));
+        assert_eq!(matching, "OFTC/#a: 2 dates\n2025-01: 02\n2025-02: 20");
+        let with_events = list(json!({"channel": "OFTC/#a", "pattern": "hello", "events": true}));
+        assert!(with_events.starts_with("OFTC/#a: 3 dates"), "{with_events}");
+        assert_eq!(list(json!({"channel": "OFTC/#a", "pattern": "nowhere"})), "no dates for OFTC/#a");
+        assert!(list(json!({"channel": "OFTC/#a", "pattern": "("})).starts_with("invalid regex"));
+        assert_eq!(list(json!({"channel": "OFTC/#nope"})), "unknown channel: OFTC/#nope");
+        assert_eq!(list(json!({})), "error: channel is required");
+    }
+
+    #[test]
+    fn test_unverified_quotes() {
+        let dir = LogTree::new("ai-verify");
+        let state = dir.write("OFTC/#a/2025-01-01.log", "[10:00:00] <x> the real line\n").state(Default::default());
+
+        let output = "# Summary\n--- OFTC/#a 2025-01-01 ---\n[10:00:00] <x> the real line\n[10:00:01] <x> a paraphrase\nsome prose\n";
+        assert_eq!(unverified_quotes(output, &state), vec!["OFTC/#a 2025-01-01: [10:00:01] <x> a paraphrase"]);
+    }
+
+    #[tokio::test]
+    async fn test_done_url_uses_forwarded_origin() {
+        let dir = LogTree::new("ai-done");
+        dir.write("OFTC/#a/2025-01-01.log", "[10:00:00] <x> hi\n").mkdir("out");
+        let yaml = format!(
+            "logs_dirs: [{0}]\nbase_path: /irc\nforwarded_headers: true\ntrusted_proxies: [127.0.0.1]\n\
+             ai:\n  api_key: x\n  output_dir: {0}/out\n",
+            dir.display()
+        );
+        let state = Arc::new(AppState::for_tests(serde_yaml::from_str(&yaml).unwrap()));
+        let channel = state.channels.children["OFTC"].children["#a"].channel.clone().unwrap();
+
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("host", "127.0.0.1:8080".parse().unwrap());
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        headers.insert("x-forwarded-host", "logs.example.org".parse().unwrap());
+        let proxy = "127.0.0.1:5000".parse().ok();
+        let origin = crate::proxy::forwarded_origin(&headers, proxy, &state.config).unwrap();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let out = execute_done(&json!({"title": "t"}), &state, &channel, "# t\n", &origin, &tx).await;
+        let Some(SseEvent::Done { url, .. }) = rx.recv().await else { panic!("no done event") };
+        assert!(url.starts_with("https://logs.example.org/irc/ask/output/"), "{url}");
+        assert_eq!(out, format!("saved: {url}"));
+    }
+
+    #[tokio::test]
+    async fn test_session_appends_to_prior_report() {
+        let dir = LogTree::new("ai-append");
+        dir.write("OFTC/#a/2025-01-01.log", "[10:00:00] <x> hi\n").mkdir("out");
+        dir.write("out/100-week-one.md", "# Week one\nquiet\n\n---\n\n**Warning:** 1 quoted line(s)\n");
+
+        let reply = json!({"choices": [{"message": {"content": "# Week two\nbusy"}, "finish_reason": "stop"}]}).to_string();
+        let mock = axum::Router::new().fallback(move || async move { reply });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await.unwrap() });
+
+        let yaml = format!(
+            "logs_dirs: [{0}]\nai:\n  provider: openai\n  base_url: http://{addr}\n  output_dir: {0}/out\n",
+            dir.display()
+        );
+        let state = AppState::for_tests(serde_yaml::from_str(&yaml).unwrap());
+        let channel = state.channels.children["OFTC"].children["#a"].channel.clone().unwrap();
+        let ai_config = state.config.ai.as_ref().unwrap();
+
+        assert_eq!(prior_output(ai_config, "../100-week-one"), Err("invalid append_to".into()));
+        assert_eq!(prior_output(ai_config, "..md"), Err("invalid append_to".into()));
+        assert_eq!(prior_output(ai_config, "101-missing"), Err("no such ask result".into()));
+        #[cfg(unix)]
+        {
+            dir.write("secret.md", "secret\n");
+            std::os::unix::fs::symlink("../secret.md", dir.join("out/102-leak.md")).unwrap();
+            assert_eq!(prior_output(ai_config, "102-leak"), Err("no such ask result".into()));
+        }
+        assert!(output_path(ai_config, "../secret.md").is_none());
+        assert!(output_path(ai_config, "103-new.md").is_some());
+        let prior = prior_output(ai_config, "100-week-one.html").unwrap();
+        assert_eq!(prior, "# Week one\nquiet\n");
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        run_ai_session("next week".into(), channel, Arc::new(state), String::new(), Some(prior), tx).await;
+        let mut saved = None;
+        while let Some(event) = rx.recv().await {
+            if let SseEvent::Done { output, .. } = event {
+                saved = Some(output);
+            }
+        }
+        assert_eq!(saved.as_deref(), Some("# Week one\nquiet\n# Week two\nbusy\n"));
+    }
+
+    /// A session waiting on the provider stops once shutdown starts, without
+    /// saving anything, and live feeds are closed.
+    #[tokio::test]
+    async fn test_shutdown_stops_session() {
+        let dir = LogTree::new("ai-shutdown");
+        dir.write("OFTC/#a/2025-01-01.log", "[10:00:00] <x> hi\n").mkdir("out");
+
+        let mock = axum::Router::new().fallback(std::future::pending::<String>);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await.unwrap() });
+
+        let yaml = format!(
+            "logs_dirs: [{0}]\nai:\n  provider: openai\n  base_url: http://{addr}\n  output_dir: {0}/out\n",
+            dir.display()
+        );
+        let state = Arc::new(AppState::for_tests(serde_yaml::from_str(&yaml).unwrap()));
+        let channel = state.channels.children["OFTC"].children["#a"].channel.clone().unwrap();
+        let mut feed = {
+            let mut feeds = state.sse_feeds.write().await;
+            feeds.entry("OFTC/#a".into()).or_insert_with(|| crate::tail::ChannelFeed::new(256)).sender.subscribe()
+        };
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let session = tokio::spawn(run_ai_session("q".into(), channel, Arc::clone(&state), String::new(), None, tx));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!session.is_finished());
+
+        state.shut_down().await;
+        tokio::time::timeout(std::time::Duration::from_secs(5), session).await.unwrap().unwrap();
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+        assert!(events.iter().any(|e| matches!(e, SseEvent::Error(m) if m == SHUTTING_DOWN)));
+        assert!(!events.iter().any(|e| matches!(e, SseEvent::Done { .. })));
+        assert_eq!(std::fs::read_dir(dir.join("out")).unwrap().count(), 0);
+        assert!(matches!(feed.recv().await, Err(tokio::sync::broadcast::error::RecvError::Closed)));
+    }
+
+    #[test]
+    fn test_usage_event() {
+        let usage = json!({"input_tokens": 1_000_000, "output_tokens": 200_000, "cache_read_input_tokens": 500_000});
+        let prices = TokenPrices { input: 1.0, output: 5.0, cache_read: 0.1, cache_write: 1.25 };
+        let SseEvent::Usage { input, output, cache_read, cache_create, cost } = usage_event(&usage, Some(&prices)) else {
+            panic!("not a usage event");
+        };
+        assert_eq!((input, output, cache_read, cache_create), (1_000_000, 200_000, 500_000, 0));
+        assert!((cost.unwrap() - 2.05).abs() < 1e-9);
+        assert!(matches!(usage_event(&usage, None), SseEvent::Usage { cost: None, .. }));
+    }
+
+    #[test]
+    fn test_streamed_message() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut msg = StreamedMessage::default();
+        let frames = [
+            r#"{"type":"message_start","message":{"usage":{"input_tokens":5}}}"#,
+            r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hel"}}"#,
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"lo"}}"#,
+            r#"{"type":"content_block_stop","index":0}"#,
+            r#"{"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"t1","name":"display","input":{}}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"text\": \"look"}}"#,
+            r#"{"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"ing\"}"}}"#,
+            r#"{"type":"content_block_stop","index":1}"#,
+            r#"{"type":"message_delta","delta":{"stop_reason":"tool_use"},"usage":{"output_tokens":9}}"#,
+            r#"{"type":"message_stop"}"#,
+        ];
+        for data in frames {
+            msg.apply_frame(&format!("event: x\ndata: {data}\n\n"), &tx).unwrap();
+        }
+        assert!(msg.finished);
+        assert_eq!(msg.message["content"][0]["text"], "Hello");
+        assert_eq!(msg.message["content"][1]["input"], json!({"text": "looking"}));
+        assert_eq!(msg.message["stop_reason"], "tool_use");
+        assert_eq!(msg.message["usage"], json!({"input_tokens": 5, "output_tokens": 9}));
+        assert!(matches!(rx.try_recv(), Ok(SseEvent::Text(t)) if t == "Hel"));
+        assert!(matches!(rx.try_recv(), Ok(SseEvent::Text(t)) if t == "lo"));
+        assert!(matches!(rx.try_recv(), Ok(SseEvent::Display(t)) if t == "looking"));
+
+        let err = msg.apply_frame("event: error\ndata: {\"type\":\"error\",\"error\":{\"message\":\"Overloaded\"}}\n\n", &tx);
+        assert_eq!(err, Err("API error: Overloaded".into()));
+    }
+
+    #[test]
+    fn test_streamed_chunk_splits_character() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut msg = StreamedMessage::default();
+        msg.apply_chunk(b"data: {\"type\":\"message_start\",\"message\":{}}\n\n", &tx).unwrap();
+        let block = r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#;
+        msg.apply_chunk(format!("data: {block}\n\n").as_bytes(), &tx).unwrap();
+        let delta = r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"café"}}"#;
+        let frame = format!("data: {delta}\n\n");
+        // The chunk boundary falls between the two bytes of the é
+        let split = frame.find('é').unwrap() + 1;
+        msg.apply_chunk(&frame.as_bytes()[..split], &tx).unwrap();
+        assert!(rx.try_recv().is_err());
+        msg.apply_chunk(&frame.as_bytes()[split..], &tx).unwrap();
+        assert!(matches!(rx.try_recv(), Ok(SseEvent::Text(t)) if t == "café"));
+        assert_eq!(msg.message["content"][0]["text"], "café");
+    }
+
+    #[test]
+    fn test_openai_tool_definitions() {
+        let tools = build_tool_definitions();
+        let defs = openai_tool_definitions(&tools);
+        let names: Vec<&str> = defs.iter().map(|d| d["function"]["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["search", "list_dates", "copy", "output", "done", "display", "abort"]);
+        for def in &defs {
+            assert_eq!(def["type"], "function");
+            assert!(def["function"]["description"].as_str().is_some_and(|d| !d.is_empty()));
+            let params = &def["function"]["parameters"];
+            assert_eq!(params["type"], "object");
+            assert!(params["properties"].is_object());
+            for required in params["required"].as_array().into_iter().flatten() {
+                assert!(params["properties"].get(required.as_str().unwrap()).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_openai_round_trip() {
+        let messages = vec![
+            json!({"role": "user", "content": [{"type": "text", "text": "find x", "cache_control": {"type": "ephemeral"}}]}),
+            json!({"role": "assistant", "content": [{"type": "tool_use", "id": "c1", "name": "display", "input": {"text": "hi"}}]}),
+            json!({"role": "user", "content": [{"type": "tool_result", "tool_use_id": "c1", "content": "ok"}]}),
+        ];
+        let out = openai_messages("sys", &messages);
+        assert_eq!(out[0], json!({"role": "system", "content": "sys"}));
+        assert_eq!(out[1], json!({"role": "user", "content": "find x"}));
+        assert_eq!(out[2]["tool_calls"][0]["function"]["arguments"], r#"{"text":"hi"}"#);
+        assert_eq!(out[3], json!({"role": "tool", "tool_call_id": "c1", "content": "ok"}));
+
+        let resp = json!({
+            "choices": [{"finish_reason": "tool_calls", "message": {"content": null, "tool_calls": [
+                {"id": "c2", "type": "function", "function": {"name": "done", "arguments": "{\"title\":\"t\"}"}}
+            ]}}],
+            "usage": {"prompt_tokens": 3, "completion_tokens": 4},
+        });
+        let msg = from_openai_response(&resp).unwrap();
+        assert_eq!(msg["stop_reason"], "tool_use");
+        assert_eq!(msg["content"][0], json!({"type": "tool_use", "id": "c2", "name": "done", "input": {"title": "t"}}));
+        assert_eq!(msg["usage"]["output_tokens"], 4);
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!([0, 1, 2, 5].map(backoff), [1, 2, 4, 32]);
+        assert_eq!(backoff(6), MAX_RETRY_DELAY);
+        assert_eq!(backoff(64), MAX_RETRY_DELAY);
+        assert_eq!(backoff(u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_after_rate_limit() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use axum::http::StatusCode;
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&hits);
+        let mock = axum::Router::new().fallback(move || async move {
+            match counter.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => (StatusCode::TOO_MANY_REQUESTS, [("retry-after", "0")], "slow down"),
+                _ => (StatusCode::OK, [("retry-after", "0")], "fine"),
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, mock).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let resp = send_with_retry(client.post(format!("http://{addr}/")).body("q"), 3, &tx).await.unwrap();
+        assert_eq!(resp.text().await.unwrap(), "fine");
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        for _ in 0..2 {
+            assert!(matches!(rx.try_recv(), Ok(SseEvent::Display(t)) if t == "rate limited, retrying in 0s"));
+        }
+
+        hits.store(0, Ordering::SeqCst);
+        let err = send_with_retry(client.post(format!("http://{addr}/")), 1, &tx).await.unwrap_err();
+        assert_eq!(err, "API error 429 Too Many Requests: slow down");
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
 }
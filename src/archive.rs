@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// Name of the directory an archive stands in for (`#chan.tar.zst` → `#chan`).
+pub fn archive_stem(name: &str) -> Option<&str> {
+    name.strip_suffix(".tar.zst")
+        .or_else(|| name.strip_suffix(".tar"))
+        .filter(|s| !s.is_empty())
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .and_then(archive_stem)
+        .is_some()
+}
+
+fn is_zst(path: &Path) -> bool {
+    path.extension().and_then(|e| e.to_str()) == Some("zst")
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect()
+}
+
+/// Splits a virtual `archive.tar.zst/member/path` into the archive file and
+/// the member path. Returns `None` for paths that exist on disk.
+pub fn split_member_path(path: &Path) -> Option<(&Path, &Path)> {
+    if path.exists() {
+        return None;
+    }
+    let archive = path.ancestors().skip(1).find(|a| is_archive(a) && a.is_file())?;
+    Some((archive, path.strip_prefix(archive).ok()?))
+}
+
+/// Regular-file members grouped by their parent directory inside the archive.
+pub fn list_members(archive: &Path) -> io::Result<BTreeMap<PathBuf, Vec<String>>> {
+    let file = File::open(archive)?;
+    let mut dirs = BTreeMap::new();
+    if is_zst(archive) {
+        let mut tar = tar::Archive::new(zstd::Decoder::new(file)?);
+        collect_members(tar.entries()?, &mut dirs)?;
+    } else {
+        let mut tar = tar::Archive::new(BufReader::new(file));
+        collect_members(tar.entries_with_seek()?, &mut dirs)?;
+    }
+    Ok(dirs)
+}
+
+fn collect_members<R: Read>(
+    entries: tar::Entries<'_, R>,
+    dirs: &mut BTreeMap<PathBuf, Vec<String>>,
+) -> io::Result<()> {
+    for entry in entries {
+        let entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = normalize(&entry.path()?);
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else { continue };
+        dirs.entry(parent.to_path_buf())
+            .or_default()
+            .push(name.to_string_lossy().into_owned());
+    }
+    Ok(())
+}
+
+/// Extracts a single member. Plain `.tar` files seek over other members;
+/// `.tar.zst` has to be decompressed up to the member.
+pub fn read_member(archive: &Path, member: &Path) -> io::Result<Vec<u8>> {
+    let file = File::open(archive)?;
+    if is_zst(archive) {
+        let mut tar = tar::Archive::new(zstd::Decoder::new(file)?);
+        find_member(tar.entries()?, member)
+    } else {
+        let mut tar = tar::Archive::new(BufReader::new(file));
+        find_member(tar.entries_with_seek()?, member)
+    }
+}
+
+/// Calls `f` with the path and contents of every regular-file member, in
+/// one pass over the archive.
+pub fn for_each_member(archive: &Path, mut f: impl FnMut(&Path, Vec<u8>)) -> io::Result<()> {
+    let file = File::open(archive)?;
+    if is_zst(archive) {
+        let mut tar = tar::Archive::new(zstd::Decoder::new(file)?);
+        visit_members(tar.entries()?, &mut f)
+    } else {
+        let mut tar = tar::Archive::new(BufReader::new(file));
+        visit_members(tar.entries()?, &mut f)
+    }
+}
+
+fn visit_members<R: Read>(entries: tar::Entries<'_, R>, f: &mut impl FnMut(&Path, Vec<u8>)) -> io::Result<()> {
+    for entry in entries {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = normalize(&entry.path()?);
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf)?;
+        f(&path, buf);
+    }
+    Ok(())
+}
+
+fn find_member<R: Read>(entries: tar::Entries<'_, R>, member: &Path) -> io::Result<Vec<u8>> {
+    for entry in entries {
+        let mut entry = entry?;
+        if normalize(&entry.path()?) == member {
+            let mut buf = Vec::new();
+            entry.read_to_end(&mut buf)?;
+            return Ok(buf);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} not found in archive", member.display()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::LogTree;
+
+    fn build_tar(path: &Path, members: &[(&str, &str)]) {
+        let file = File::create(path).unwrap();
+        let mut builder = tar::Builder::new(file);
+        for (name, content) in members {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, content.as_bytes()).unwrap();
+        }
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn test_archive_stem() {
+        assert_eq!(archive_stem("#chan.tar.zst"), Some("#chan"));
+        assert_eq!(archive_stem("logs.tar"), Some("logs"));
+        assert_eq!(archive_stem("2025-01-01.log.zst"), None);
+    }
+
+    #[test]
+    fn test_list_and_read_members() {
+        let dir = LogTree::new("archive");
+        let archive = dir.join("#chan.tar");
+        build_tar(&archive, &[
+            ("./2025-01-01.log", "[00:00:01] <a> one\n"),
+            ("sub/2025-01-02.log", "[00:00:02] <b> two\n"),
+        ]);
+
+        let members = list_members(&archive).unwrap();
+        assert_eq!(members[Path::new("")], vec!["2025-01-01.log"]);
+        assert_eq!(members[Path::new("sub")], vec!["2025-01-02.log"]);
+
+        let data = read_member(&archive, Path::new("sub/2025-01-02.log")).unwrap();
+        assert_eq!(data, b"[00:00:02] <b> two\n");
+
+        let mut seen = Vec::new();
+        for_each_member(&archive, |path, data| seen.push((path.to_path_buf(), data.len()))).unwrap();
+        assert_eq!(seen, vec![(PathBuf::from("2025-01-01.log"), 19), (PathBuf::from("sub/2025-01-02.log"), 19)]);
+
+        let virtual_path = archive.join("2025-01-01.log");
+        let (a, m) = split_member_path(&virtual_path).unwrap();
+        assert_eq!((a, m), (archive.as_path(), Path::new("2025-01-01.log")));
+    }
+
+    #[test]
+    fn test_read_member_zst() {
+        let dir = LogTree::new("archive-zst");
+        let plain = dir.join("plain.tar");
+        build_tar(&plain, &[("#chan/2025-01-01.log", "[00:00:01] <a> one\n")]);
+        let archive = dir.join("#chan.tar.zst");
+        let compressed = zstd::encode_all(File::open(&plain).unwrap(), 3).unwrap();
+        std::fs::write(&archive, compressed).unwrap();
+
+        let members = list_members(&archive).unwrap();
+        assert_eq!(members[Path::new("#chan")], vec!["2025-01-01.log"]);
+        let data = read_member(&archive, Path::new("#chan/2025-01-01.log")).unwrap();
+        assert_eq!(data, b"[00:00:01] <a> one\n");
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use axum::extract::{Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use crate::{AppState, AuthConfig};
+
+/// Past this many remembered credentials the set is cleared, so a client
+/// cycling through wrong-but-valid-looking headers cannot grow it forever.
+const MAX_VERIFIED: usize = 1024;
+
+/// Rejects requests without valid `auth` credentials with 401. Streams and
+/// AI routes are covered like any other page, and what is let through is
+/// marked private so shared caches never hand it to someone else.
+pub async fn require_auth(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let Some(auth) = &state.config.auth else {
+        return next.run(request).await;
+    };
+    let given = request.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok()).map(str::to_owned);
+    let Some(given) = given else {
+        return unauthorized(auth, &state.config.title);
+    };
+    if state.auth_verified.lock().unwrap().contains(&given) {
+        return private(next.run(request).await);
+    }
+    // bcrypt is deliberately slow; keep it off the single runtime thread
+    let checked = {
+        let state = Arc::clone(&state);
+        let given = given.clone();
+        tokio::task::spawn_blocking(move || state.config.auth.as_ref().is_some_and(|a| accepts(a, &given))).await
+    };
+    if !checked.unwrap_or(false) {
+        return unauthorized(auth, &state.config.title);
+    }
+    {
+        let mut verified = state.auth_verified.lock().unwrap();
+        if verified.len() >= MAX_VERIFIED {
+            verified.clear();
+        }
+        verified.insert(given);
+    }
+    private(next.run(request).await)
+}
+
+/// Rewrites a `public` Cache-Control to `private`, dropping `s-maxage`,
+/// which only shared caches read. The browser may still keep its copy.
+fn private(mut response: Response) -> Response {
+    let Some(value) = response.headers().get(header::CACHE_CONTROL).and_then(|v| v.to_str().ok()) else {
+        return response;
+    };
+    let kept = value.split(',').map(str::trim).filter(|d| {
+        let d = d.to_ascii_lowercase();
+        d != "public" && d != "private" && !d.starts_with("s-maxage")
+    });
+    let value = std::iter::once("private").chain(kept).collect::<Vec<_>>().join(", ");
+    response.headers_mut().insert(header::CACHE_CONTROL, value.parse().unwrap());
+    response
+}
+
+/// Whether an `Authorization` header value matches a configured user or
+/// the token.
+pub fn accepts(auth: &AuthConfig, value: &str) -> bool {
+    let Some((scheme, credentials)) = value.trim().split_once(' ') else { return false };
+    let credentials = credentials.trim();
+    if scheme.eq_ignore_ascii_case("bearer") {
+        return auth.token.as_deref().is_some_and(|token| constant_time_eq(token.as_bytes(), credentials.as_bytes()));
+    }
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return false;
+    }
+    let Some(decoded) = STANDARD.decode(credentials).ok().and_then(|b| String::from_utf8(b).ok()) else {
+        return false;
+    };
+    let Some((user, password)) = decoded.split_once(':') else { return false };
+    auth.users.get(user).is_some_and(|stored| password_matches(stored, password))
+}
+
+fn password_matches(stored: &str, password: &str) -> bool {
+    if ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|p| stored.starts_with(p)) {
+        return bcrypt::verify(password, stored).unwrap_or(false);
+    }
+    constant_time_eq(stored.as_bytes(), password.as_bytes())
+}
+
+/// Compares without stopping at the first differing byte, so response time
+/// does not reveal how much of a guess was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn unauthorized(auth: &AuthConfig, title: &str) -> Response {
+    let realm = auth.realm.as_deref().unwrap_or(title).replace(['"', '\\'], "");
+    let challenge = if auth.users.is_empty() {
+        format!("Bearer realm=\"{realm}\"")
+    } else {
+        format!("Basic realm=\"{realm}\", charset=\"UTF-8\"")
+    };
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, challenge), (header::CACHE_CONTROL, "no-store".into())],
+        "authentication required",
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(user: &str, password: &str) -> String {
+        format!("Basic {}", STANDARD.encode(format!("{user}:{password}")))
+    }
+
+    #[test]
+    fn test_accepts() {
+        let hash = bcrypt::hash("hunter2", 4).unwrap();
+        let auth = AuthConfig {
+            users: [("alice".into(), "secret".into()), ("bob".into(), hash)].into(),
+            token: Some("tok3n".into()),
+            realm: None,
+        };
+        assert!(accepts(&auth, &basic("alice", "secret")));
+        assert!(accepts(&auth, &basic("bob", "hunter2")));
+        assert!(accepts(&auth, "Bearer tok3n"));
+        assert!(accepts(&auth, "bearer tok3n"));
+        assert!(!accepts(&auth, &basic("alice", "secre")));
+        assert!(!accepts(&auth, &basic("bob", "hunter3")));
+        assert!(!accepts(&auth, &basic("carol", "secret")));
+        assert!(!accepts(&auth, "Bearer tok3"));
+        assert!(!accepts(&auth, "Basic !!!"));
+        assert!(!accepts(&auth, "tok3n"));
+
+        let no_token = AuthConfig { token: None, ..auth };
+        assert!(!accepts(&no_token, "Bearer "));
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::{ChannelNode, Config};
+
+/// Puts between eviction sweeps.
+const EVICT_EVERY: usize = 100;
+
+/// Read-through disk cache for rendered pages of immutable days.
+///
+/// Entries live under `cache_dir/<tag>/`, where the tag hashes the config,
+/// the channel list and the binary, so anything that changes the rendered
+/// HTML (sidebar, templates, options) starts a fresh namespace; stale
+/// namespaces age out through normal eviction.
+pub struct PageCache {
+    root: PathBuf,
+    dir: PathBuf,
+    max_bytes: u64,
+    max_age: Duration,
+    puts: AtomicUsize,
+}
+
+impl PageCache {
+    pub fn new(config: &Config, channels: &ChannelNode) -> Option<Self> {
+        let root = config.cache_dir.clone()?;
+        let mut hasher = DefaultHasher::new();
+        serde_yaml::to_string(config).unwrap_or_default().hash(&mut hasher);
+        channel_keys(channels, &mut hasher);
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        std::env::current_exe()
+            .and_then(std::fs::metadata)
+            .and_then(|m| m.modified())
+            .ok()
+            .hash(&mut hasher);
+        let dir = root.join(format!("{:016x}", hasher.finish()));
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            tracing::warn!("cache: cannot create {}: {e}", dir.display());
+            return None;
+        }
+        let cache = Self {
+            root,
+            dir,
+            max_bytes: config.cache_max_size_mb.saturating_mul(1024 * 1024),
+            max_age: Duration::from_secs(config.cache_max_age_days.saturating_mul(86400)),
+            puts: AtomicUsize::new(0),
+        };
+        cache.evict();
+        Some(cache)
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        let name: String = key
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || "-_.#".contains(c) { c } else { '_' })
+            .collect();
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.dir.join(format!("{name}-{:08x}.html", hasher.finish() as u32))
+    }
+
+    /// Cached page for `key`, unless it is older than any of the `sources`
+    /// it was rendered from, or expired.
+    pub fn get(&self, key: &str, sources: &[PathBuf]) -> Option<String> {
+        let path = self.entry_path(key);
+        let cached = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+        let stale = sources.iter().any(|s| source_mtime(s).is_none_or(|src| src > cached));
+        if stale || age(cached) > self.max_age {
+            return None;
+        }
+        std::fs::read_to_string(path).ok()
+    }
+
+    pub fn put(&self, key: &str, html: &str) {
+        let path = self.entry_path(key);
+        let tmp = path.with_extension("tmp");
+        let written = std::fs::write(&tmp, html).and_then(|_| std::fs::rename(&tmp, &path));
+        if let Err(e) = written {
+            tracing::warn!("cache: cannot write {}: {e}", path.display());
+            let _ = std::fs::remove_file(&tmp);
+            return;
+        }
+        if self.puts.fetch_add(1, Ordering::Relaxed) % EVICT_EVERY == EVICT_EVERY - 1 {
+            self.evict();
+        }
+    }
+
+    /// Drops expired entries, then the oldest ones until the cache fits
+    /// `max_bytes`. Only `.html` files under the cache root are touched.
+    fn evict(&self) {
+        let mut entries = Vec::new();
+        collect_entries(&self.root, &mut entries);
+        entries.retain(|(path, mtime, _)| {
+            if age(*mtime) > self.max_age {
+                let _ = std::fs::remove_file(path);
+                false
+            } else {
+                true
+            }
+        });
+        entries.sort_by_key(|(_, mtime, _)| *mtime);
+        let mut total: u64 = entries.iter().map(|(_, _, len)| len).sum();
+        for (path, _, len) in &entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            if std::fs::remove_file(path).is_ok() {
+                total -= len;
+            }
+        }
+        if let Ok(dirs) = std::fs::read_dir(&self.root) {
+            for dir in dirs.flatten() {
+                if dir.path() != self.dir {
+                    // Only succeeds once a stale namespace is empty
+                    let _ = std::fs::remove_dir(dir.path());
+                }
+            }
+        }
+    }
+}
+
+/// Files written to this recently bypass [`LogCache`]; a logger may still be
+/// appending within the same mtime tick.
+const SETTLE_TIME: Duration = Duration::from_secs(5);
+
+/// In-memory LRU of decompressed log files, so repeated page views and
+/// searches skip the disk and zstd. Entries are checked against the
+/// source's mtime and size on every read, and the live tail drops a file's
+/// entry as soon as it grows.
+pub struct LogCache {
+    max_bytes: usize,
+    inner: Mutex<LogCacheInner>,
+    /// Reads that went to disk.
+    misses: AtomicUsize,
+}
+
+#[derive(Default)]
+struct LogCacheInner {
+    entries: HashMap<PathBuf, CachedLog>,
+    bytes: usize,
+    clock: u64,
+}
+
+struct CachedLog {
+    mtime: SystemTime,
+    len: u64,
+    content: Arc<str>,
+    last_used: u64,
+}
+
+impl LogCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self { max_bytes, inner: Mutex::default(), misses: AtomicUsize::new(0) }
+    }
+
+    pub fn for_config(config: &Config) -> Option<Self> {
+        (config.log_cache_size_mb > 0).then(|| Self::new(config.log_cache_size_mb.saturating_mul(1024 * 1024)))
+    }
+
+    /// Content of the log file at `path`, as [`crate::server::read_log_file`].
+    pub fn read(&self, path: &Path) -> io::Result<Arc<str>> {
+        let source = crate::archive::split_member_path(path).map_or(path, |(archive, _)| archive);
+        let meta = std::fs::metadata(source)?;
+        let (mtime, len) = (meta.modified()?, meta.len());
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.clock += 1;
+            let clock = inner.clock;
+            if let Some(entry) = inner.entries.get_mut(path).filter(|e| (e.mtime, e.len) == (mtime, len)) {
+                entry.last_used = clock;
+                return Ok(Arc::clone(&entry.content));
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let content: Arc<str> = crate::server::read_log_file(path)?.into();
+        if content.len() <= self.max_bytes && age(mtime) >= SETTLE_TIME {
+            self.insert(path, CachedLog { mtime, len, content: Arc::clone(&content), last_used: 0 });
+        }
+        Ok(content)
+    }
+
+    fn insert(&self, path: &Path, mut entry: CachedLog) {
+        let mut inner = self.inner.lock().unwrap();
+        entry.last_used = inner.clock;
+        inner.bytes += entry.content.len();
+        if let Some(old) = inner.entries.insert(path.to_path_buf(), entry) {
+            inner.bytes -= old.content.len();
+        }
+        while inner.bytes > self.max_bytes {
+            let Some(oldest) = inner.entries.iter().min_by_key(|(_, e)| e.last_used).map(|(p, _)| p.clone()) else {
+                break;
+            };
+            let evicted = inner.entries.remove(&oldest).unwrap();
+            inner.bytes -= evicted.content.len();
+        }
+    }
+
+    /// Forgets `path`, for files the live tail saw change.
+    pub fn invalidate(&self, path: &Path) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(old) = inner.entries.remove(path) {
+            inner.bytes -= old.content.len();
+        }
+    }
+
+    #[cfg(test)]
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+/// Reads through `cache` when there is one.
+pub fn read_log(cache: Option<&LogCache>, path: &Path) -> io::Result<Arc<str>> {
+    match cache {
+        Some(cache) => cache.read(path),
+        None => crate::server::read_log_file(path).map(Arc::from),
+    }
+}
+
+fn channel_keys(node: &ChannelNode, hasher: &mut DefaultHasher) {
+    if let Some(channel) = &node.channel {
+        channel.path_segments.hash(hasher);
+    }
+    for child in node.children.values() {
+        channel_keys(child, hasher);
+    }
+}
+
+fn collect_entries(dir: &Path, out: &mut Vec<(PathBuf, SystemTime, u64)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(meta) = entry.metadata() else { continue };
+        if meta.is_dir() {
+            collect_entries(&path, out);
+        } else if path.extension().is_some_and(|e| e == "html") {
+            if let Ok(mtime) = meta.modified() {
+                out.push((path, mtime, meta.len()));
+            }
+        }
+    }
+}
+
+/// Modification time of a log file, or of its archive for archive members.
+pub fn source_mtime(path: &Path) -> Option<SystemTime> {
+    let path = crate::archive::split_member_path(path).map_or(path, |(archive, _)| archive);
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn age(t: SystemTime) -> Duration {
+    SystemTime::now().duration_since(t).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::LogTree;
+
+    fn test_cache(name: &str, max_bytes: u64) -> (LogTree, PageCache) {
+        let root = LogTree::new(name);
+        let config = Config { cache_dir: Some(root.to_path_buf()), ..Config::default() };
+        let mut cache = PageCache::new(&config, &ChannelNode::default()).unwrap();
+        cache.max_bytes = max_bytes;
+        (root, cache)
+    }
+
+    #[test]
+    fn test_cache_invalidated_by_newer_source() {
+        let (root, cache) = test_cache("cache-mtime", u64::MAX);
+        let source = [root.join("2025-01-01.log")];
+        std::fs::write(&source[0], "x").unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(cache.get("OFTC/#a/2025-01-01", &source), None);
+        cache.put("OFTC/#a/2025-01-01", "<html>");
+        assert_eq!(cache.get("OFTC/#a/2025-01-01", &source).as_deref(), Some("<html>"));
+
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(&source[0], "y").unwrap();
+        assert_eq!(cache.get("OFTC/#a/2025-01-01", &source), None);
+    }
+
+    /// A log written long enough ago to be cached.
+    fn settled_log(path: &Path, content: &str) {
+        std::fs::write(path, content).unwrap();
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(60)).unwrap();
+    }
+
+    #[test]
+    fn test_log_cache_second_read_skips_disk() {
+        let root = LogTree::new("logcache");
+        let (plain, zst) = (root.join("2025-01-01.log"), root.join("2025-01-02.log.zst"));
+        let day: String = (0..5000).map(|i| format!("[12:00:00] <n> line {i}\n")).collect();
+        settled_log(&plain, "[00:00:01] <a> hi\n");
+        std::fs::write(&zst, zstd::encode_all(day.as_bytes(), 3).unwrap()).unwrap();
+        let file = std::fs::File::options().write(true).open(&zst).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(60)).unwrap();
+        let cache = LogCache::new(1024 * 1024);
+
+        assert_eq!(&*cache.read(&zst).unwrap(), day);
+        assert_eq!(&*cache.read(&zst).unwrap(), day);
+        assert_eq!(cache.misses(), 1);
+
+        // A changed file is read again, and so is one the tail invalidated
+        cache.read(&plain).unwrap();
+        settled_log(&plain, "[00:00:01] <a> hi\n[00:00:02] <b> more\n");
+        assert!(cache.read(&plain).unwrap().contains("more"));
+        assert_eq!(cache.misses(), 3);
+        cache.invalidate(&plain);
+        cache.read(&plain).unwrap();
+        assert_eq!(cache.misses(), 4);
+
+        // Over the cap, the least recently used file goes; a fresh file is
+        // never cached
+        let small = LogCache::new(day.len() + 10);
+        small.read(&zst).unwrap();
+        small.read(&plain).unwrap();
+        small.read(&zst).unwrap();
+        assert_eq!(small.misses(), 3);
+        std::fs::write(&plain, "[00:00:03] <c> now\n").unwrap();
+        small.read(&plain).unwrap();
+        small.read(&plain).unwrap();
+        assert_eq!(small.misses(), 5);
+    }
+
+    #[test]
+    fn test_cache_evicts_oldest_over_size() {
+        let (root, cache) = test_cache("cache-evict", 10);
+        let source = [root.join("src.log")];
+        std::fs::write(&source[0], "x").unwrap();
+        cache.put("a", "123456");
+        std::thread::sleep(Duration::from_millis(20));
+        cache.put("b", "123456");
+        cache.evict();
+        assert_eq!(cache.get("a", &source), None);
+        assert!(cache.get("b", &source).is_some());
+    }
+}
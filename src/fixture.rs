@@ -0,0 +1,56 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use crate::{AppState, Config};
+
+/// A log tree under the temp dir for one test, removed on drop so a
+/// failing assert does not leave it behind.
+pub struct LogTree(PathBuf);
+
+impl LogTree {
+    /// An empty tree; `name` keeps tests running in parallel apart.
+    pub fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("irc-log-viewer-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        Self(root)
+    }
+
+    /// Writes `rel`, creating its parent directories.
+    pub fn write(&self, rel: impl AsRef<Path>, content: impl AsRef<[u8]>) -> &Self {
+        let path = self.0.join(rel);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+        self
+    }
+
+    pub fn mkdir(&self, rel: impl AsRef<Path>) -> &Self {
+        std::fs::create_dir_all(self.0.join(rel)).unwrap();
+        self
+    }
+
+    /// State over this tree as the only logs dir.
+    pub fn state(&self, config: Config) -> AppState {
+        AppState::for_tests(Config { logs_dirs: vec![self.0.clone().into()], ..config })
+    }
+}
+
+impl Deref for LogTree {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for LogTree {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for LogTree {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
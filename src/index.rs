@@ -0,0 +1,279 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::ChannelNode;
+use crate::cache::source_mtime;
+use crate::server::{channel_dates, day_files, read_log_file};
+
+const MAGIC: &[u8; 8] = b"IRCIDX1\n";
+
+/// Trigram index of every log file, used to skip files that cannot contain
+/// a literal search query. An entry is only trusted while the file's mtime
+/// matches the one it was built from, so a stale or missing entry means the
+/// file is scanned as if there were no index.
+pub struct SearchIndex {
+    path: PathBuf,
+    files: RwLock<HashMap<PathBuf, FileEntry>>,
+}
+
+struct FileEntry {
+    mtime: SystemTime,
+    /// Sorted, deduplicated.
+    trigrams: Vec<u32>,
+}
+
+/// Sorted, deduplicated trigrams of `text`, ASCII-lowercased so one index
+/// serves both case-sensitive and case-insensitive queries.
+pub fn trigrams(text: &str) -> Vec<u32> {
+    let mut out: Vec<u32> = text
+        .as_bytes()
+        .windows(3)
+        .filter(|w| !w.contains(&b'\n'))
+        .map(|w| {
+            let [a, b, c] = [w[0], w[1], w[2]].map(|b| u32::from(b.to_ascii_lowercase()));
+            a << 16 | b << 8 | c
+        })
+        .collect();
+    out.sort_unstable();
+    out.dedup();
+    out
+}
+
+impl SearchIndex {
+    /// Index persisted under `dir`, loaded from a previous run if present.
+    pub fn open(dir: &Path) -> Option<Self> {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            tracing::warn!("index: cannot create {}: {e}", dir.display());
+            return None;
+        }
+        let path = dir.join("trigrams.idx");
+        let files = match std::fs::File::open(&path) {
+            Ok(file) => load(BufReader::new(file)).unwrap_or_else(|e| {
+                tracing::warn!("index: ignoring unreadable {}: {e}", path.display());
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Some(Self { path, files: RwLock::new(files) })
+    }
+
+    /// Indexes every file not already indexed at its current mtime, drops
+    /// files that are gone, and saves the result. Archive members are read
+    /// in one pass per archive.
+    pub fn build(&self, root: &ChannelNode) {
+        let mut wanted = Vec::new();
+        collect_files(root, &mut wanted);
+        let mut stale: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+        let mut indexed = 0;
+        {
+            let files = self.files.read().unwrap();
+            for path in &wanted {
+                let current = files.get(path).map(|e| e.mtime);
+                if current.is_some() && current == source_mtime(path) {
+                    continue;
+                }
+                match crate::archive::split_member_path(path) {
+                    Some((archive, _)) => stale.entry(archive.to_path_buf()).or_default().push(path.clone()),
+                    None => stale.entry(PathBuf::new()).or_default().push(path.clone()),
+                }
+            }
+        }
+        for path in stale.remove(Path::new("")).unwrap_or_default() {
+            let (Some(mtime), Ok(content)) = (source_mtime(&path), read_log_file(&path)) else { continue };
+            self.files.write().unwrap().insert(path, FileEntry { mtime, trigrams: trigrams(&content) });
+            indexed += 1;
+        }
+        for (archive, members) in stale {
+            let Some(mtime) = source_mtime(&archive) else { continue };
+            let result = crate::archive::for_each_member(&archive, |member, data| {
+                let path = archive.join(member);
+                if members.contains(&path) {
+                    let entry = FileEntry { mtime, trigrams: trigrams(&String::from_utf8_lossy(&data)) };
+                    self.files.write().unwrap().insert(path, entry);
+                    indexed += 1;
+                }
+            });
+            if let Err(e) = result {
+                tracing::warn!("index: cannot read {}: {e}", archive.display());
+            }
+        }
+        let mut files = self.files.write().unwrap();
+        let wanted: std::collections::HashSet<&PathBuf> = wanted.iter().collect();
+        files.retain(|path, _| wanted.contains(path));
+        tracing::info!(files = files.len(), indexed, "index built");
+        if let Err(e) = save(&self.path, &files) {
+            tracing::warn!("index: cannot write {}: {e}", self.path.display());
+        }
+    }
+
+    /// False only when `path` is indexed at its current mtime and lacks one
+    /// of the `needle` trigrams, i.e. it certainly has no match.
+    pub fn may_contain(&self, path: &Path, needle: &[u32]) -> bool {
+        if needle.is_empty() {
+            return true;
+        }
+        let files = self.files.read().unwrap();
+        let Some(entry) = files.get(path) else { return true };
+        if source_mtime(path) != Some(entry.mtime) {
+            return true;
+        }
+        needle.iter().all(|t| entry.trigrams.binary_search(t).is_ok())
+    }
+
+    /// Adds lines the live tail read from `path`. `from_start` says `text` is
+    /// the whole file, so an unindexed file can be indexed from it; otherwise
+    /// only an existing entry is extended.
+    pub fn append(&self, path: &Path, text: &str, from_start: bool) {
+        let Some(mtime) = source_mtime(path) else { return };
+        let mut files = self.files.write().unwrap();
+        match files.get_mut(path) {
+            Some(entry) if !from_start => {
+                entry.trigrams.extend(trigrams(text));
+                entry.trigrams.sort_unstable();
+                entry.trigrams.dedup();
+                entry.mtime = mtime;
+            }
+            _ if from_start => {
+                files.insert(path.to_path_buf(), FileEntry { mtime, trigrams: trigrams(text) });
+            }
+            _ => {}
+        }
+    }
+}
+
+fn collect_files(node: &ChannelNode, out: &mut Vec<PathBuf>) {
+    // Merged channels only point at files their sources already cover
+    if let Some(channel) = node.channel.as_ref().filter(|c| c.sources.is_empty()) {
+        for date in channel_dates(channel) {
+            out.extend(day_files(channel, &date).into_iter().map(|(path, _)| path));
+        }
+    }
+    for child in node.children.values() {
+        collect_files(child, out);
+    }
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Format: magic, then per file its path, mtime and trigrams, all
+/// length-prefixed little-endian.
+fn load(mut r: impl Read) -> io::Result<HashMap<PathBuf, FileEntry>> {
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an index file"));
+    }
+    let mut files = HashMap::new();
+    loop {
+        let len = match read_u32(&mut r) {
+            Ok(len) => len as usize,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(files),
+            Err(e) => return Err(e),
+        };
+        let mut path = vec![0; len];
+        r.read_exact(&mut path)?;
+        let secs = read_u64(&mut r)?;
+        let nanos = read_u32(&mut r)?;
+        let count = read_u32(&mut r)? as usize;
+        let trigrams = (0..count).map(|_| read_u32(&mut r)).collect::<io::Result<Vec<_>>>()?;
+        let path = PathBuf::from(String::from_utf8_lossy(&path).into_owned());
+        let mtime = UNIX_EPOCH + Duration::new(secs, nanos);
+        files.insert(path, FileEntry { mtime, trigrams });
+    }
+}
+
+fn save(path: &Path, files: &HashMap<PathBuf, FileEntry>) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut w = BufWriter::new(std::fs::File::create(&tmp)?);
+    w.write_all(MAGIC)?;
+    for (file, entry) in files {
+        let name = file.to_string_lossy();
+        let since = entry.mtime.duration_since(UNIX_EPOCH).unwrap_or_default();
+        w.write_all(&(name.len() as u32).to_le_bytes())?;
+        w.write_all(name.as_bytes())?;
+        w.write_all(&since.as_secs().to_le_bytes())?;
+        w.write_all(&since.subsec_nanos().to_le_bytes())?;
+        w.write_all(&(entry.trigrams.len() as u32).to_le_bytes())?;
+        for t in &entry.trigrams {
+            w.write_all(&t.to_le_bytes())?;
+        }
+    }
+    w.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::LogTree;
+    use crate::parser::UtcOffset;
+    use crate::search::{Matcher, Sources, search_channel};
+
+    #[test]
+    fn test_trigrams() {
+        assert_eq!(trigrams("abcab"), trigrams("ABCAB"));
+        assert_eq!(trigrams("abcab").len(), 3);
+        assert!(trigrams("ab").is_empty());
+        assert!(trigrams("ab\ncd").is_empty());
+    }
+
+    /// A year of busy days with one rare word; the index has to give the
+    /// same answer as the linear scan while reading a single file.
+    #[test]
+    fn test_index_year_archive() {
+        let root = LogTree::new("index");
+        let filler: String = (0..200).map(|i| format!("[12:{:02}:{:02}] <nick{i}> the quick brown fox {i}\n", i / 60, i % 60)).collect();
+        let months = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+        for (month, days) in months.into_iter().enumerate() {
+            for day in 1..=days {
+                let rare = if (month, day) == (3, 10) { "[23:00:00] <x> xylophone spotted\n" } else { "" };
+                root.write(format!("logs/OFTC/#chan/2024-{:02}-{day:02}.log", month + 1), format!("{filler}{rare}"));
+            }
+        }
+        let config = crate::Config { logs_dirs: vec![root.join("logs").into()], ..Default::default() };
+        let state = crate::AppState::for_tests(config);
+        let channel = state.channels.children["OFTC"].children["#chan"].channel.as_ref().unwrap();
+
+        let index = SearchIndex::open(&root.join("index")).unwrap();
+        index.build(&state.channels);
+        let matcher = Matcher::new("XYLOPHONE", false, false).unwrap();
+
+        let linear = search_channel(channel, &matcher, 100, UtcOffset::default(), Sources::default());
+        let indexed = search_channel(channel, &matcher, 100, UtcOffset::default(), Sources { index: Some(&index), cache: None });
+        assert_eq!(linear.len(), 1);
+        assert_eq!(linear, indexed);
+
+        let candidates = channel_dates(channel)
+            .iter()
+            .flat_map(|d| day_files(channel, d))
+            .filter(|(p, _)| index.may_contain(p, matcher.index_needle()))
+            .count();
+        assert_eq!(candidates, 1);
+
+        // Reloaded from disk, and extended by the live tail
+        let reloaded = SearchIndex::open(&root.join("index")).unwrap();
+        let first = day_files(channel, &channel_dates(channel)[0]).remove(0).0;
+        assert!(!reloaded.may_contain(&first, matcher.index_needle()));
+        std::thread::sleep(Duration::from_millis(20));
+        let mut content = std::fs::read_to_string(&first).unwrap();
+        content.push_str("[23:59:00] <y> another xylophone\n");
+        std::fs::write(&first, &content).unwrap();
+        assert!(reloaded.may_contain(&first, matcher.index_needle()));
+        reloaded.append(&first, "[23:59:00] <y> another xylophone\n", false);
+        assert!(reloaded.may_contain(&first, matcher.index_needle()));
+        assert!(!reloaded.may_contain(&first, Matcher::new("zebra", false, false).unwrap().index_needle()));
+    }
+}
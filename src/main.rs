@@ -1,21 +1,100 @@
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use axum::Router;
 use clap::Parser;
 use serde::{Deserialize, Serialize};
 use tokio::sync::{RwLock, Semaphore};
-use tokio::sync::broadcast;
+use tracing_subscriber::EnvFilter;
 
 mod ai;
+mod archive;
+mod auth;
+mod cache;
+#[cfg(test)]
+mod fixture;
+mod index;
+mod metrics;
 mod parser;
+mod proxy;
 mod search;
 mod server;
+mod stats;
 mod tail;
 mod templates;
 
-use parser::LogFormat;
+use parser::{LogFormat, UtcOffset};
+
+/// Commented-out optional keys appended to a freshly generated config.
+const EXAMPLE_CONFIG: &str = concat!(
+    "#base_path: /irc\n",
+    "#timezone: \"+02:00\"\n",
+    "#max_connections: 64\n",
+    "#max_sse_connections: 256\n",
+    "#trusted_proxies:\n",
+    "#- 127.0.0.1\n",
+    "#- unix\n",
+    "#forwarded_headers: true\n",
+    "#auth:\n",
+    "#  users:\n",
+    "#    alice: $2b$12$...\n",
+    "#  token: long-random-string\n",
+    "#line_numbers: true\n",
+    "#infinite_scroll: true\n",
+    "#media_previews: true\n",
+    "#page_lines: 2000\n",
+    "#cache_dir: /var/cache/irc-log-viewer\n",
+    "#search_index_dir: /var/cache/irc-log-viewer/index\n",
+    "#sidebar_open_depth: 1\n",
+    "#favorite_channels:\n",
+    "#- OFTC/#bcachefs\n",
+    "#include_channels:\n",
+    "#- \"OFTC/#*\"\n",
+    "#- OFTC/bridge\n",
+    "#exclude_channels:\n",
+    "#- \"*/#secret*\"\n",
+    "#merged_channels:\n",
+    "#  all/#bcachefs: [OFTC/#bcachefs, Libera/#bcachefs]\n",
+    "#channels:\n",
+    "#  OFTC/#busy:\n",
+    "#    hide_events_default: true\n",
+    "#    format: znc\n",
+    "#    ai_enabled: false\n",
+    "#    display_name: \"#busy (OFTC)\"\n",
+    "#    media_previews: false\n",
+    "#    code_blocks: true\n",
+    "#nick_aliases:\n",
+    "#  alice_irc: Alice\n",
+    "#nick_colors:\n",
+    "#  palette: [0, 40, 120, 200, 280, \"#c678dd\"]\n",
+    "#  pinned:\n",
+    "#    ChanServ: gray\n",
+    "#ai:\n",
+    "#  provider: anthropic\n",
+    "#  api_key: sk-ant-api03-...\n",
+    "#  model: claude-haiku-4-5-20251001\n",
+    "#  output_dir: /var/lib/irc-logs/ask\n",
+    "#  max_concurrent: 1\n",
+    "#  max_tool_calls: 100\n",
+    "#  max_retries: 3\n",
+    "#  max_tokens: 4096\n",
+    "#  max_context_bytes: 150000\n",
+    "#  search_output_bytes: 8000\n",
+    "#  max_dates_scanned: 365\n",
+    "#  search_time_ms: 5000\n",
+    "#  output_buffer_bytes: 100000\n",
+    "#  prices:\n",
+    "#    input: 1.0\n",
+    "#    output: 5.0\n",
+    "#    cache_read: 0.1\n",
+    "#    cache_write: 1.25\n",
+    "#  verify_quotes: true\n",
+    "#  broadcast_results: true\n",
+    "#  system_prompt: |\n",
+    "#    Custom system prompt text here.\n",
+    "#    The channel list is always appended automatically.\n",
+);
 
 #[derive(Parser)]
 #[command(version, about = "IRC log viewer")]
@@ -25,6 +104,7 @@ struct Cli {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     #[serde(default = "default_bind")]
     pub bind: String,
@@ -32,19 +112,124 @@ pub struct Config {
     pub title: String,
     #[serde(default = "default_search_limit")]
     pub search_limit: usize,
-    pub logs_dirs: Vec<PathBuf>,
+    /// Log files a search across all channels, or a count per day, may read.
+    #[serde(default = "default_search_all_max_files")]
+    pub search_all_max_files: usize,
+    #[serde(default = "default_sse_buffer_lines")]
+    pub sse_buffer_lines: usize,
+    /// Lines the live tail reads within this window go out as one event.
+    #[serde(default = "default_sse_batch_ms")]
+    pub sse_batch_ms: u64,
+    /// Live events held for a stream that has not read them yet; one that
+    /// falls further behind is told to reload.
+    #[serde(default = "default_sse_channel_capacity")]
+    pub sse_channel_capacity: usize,
+    #[serde(default = "default_ignore_suffixes")]
+    pub ignore_suffixes: Vec<String>,
+    #[serde(default = "default_fold_reasons_at")]
+    pub fold_reasons_at: usize,
+    #[serde(default = "default_irc_formatting")]
+    pub irc_formatting: bool,
+    /// URL prefixes linked in messages, separator included (`https://`, `mailto:`).
+    #[serde(default = "default_link_schemes")]
+    pub link_schemes: Vec<String>,
+    pub logs_dirs: Vec<LogsDir>,
     #[serde(default)]
     pub base_path: String,
+    #[serde(default, skip_serializing_if = "UtcOffset::is_utc")]
+    pub timezone: UtcOffset,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub ai: Option<AiConfig>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub nick_aliases: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "NickColors::is_empty")]
+    pub nick_colors: NickColors,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub favorite_channels: Vec<String>,
+    /// Sidebar tree levels shown expanded; deeper nodes start collapsed and
+    /// their children are fetched when opened. Unset expands everything,
+    /// unless there are more than [`SIDEBAR_FULL_TREE_MAX`] channels.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sidebar_open_depth: Option<usize>,
+    /// Channel path globs (`*` any run, `?` one character) to serve; when
+    /// set, nothing else is, and directories without a `#` are no longer
+    /// skipped next to ones with it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include_channels: Vec<String>,
+    /// Channel path globs never served, winning over `include_channels`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_channels: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub merged_channels: BTreeMap<String, Vec<String>>,
+    /// Per-channel settings keyed by channel path, e.g. `OFTC/#bcachefs`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub channels: BTreeMap<String, ChannelOverrides>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_proxies: Vec<proxy::TrustedProxy>,
+    /// Build absolute URLs from `X-Forwarded-Proto`/`X-Forwarded-Host`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub forwarded_headers: bool,
+    /// Requires credentials on every route when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AuthConfig>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub debug: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub line_numbers: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub infinite_scroll: bool,
+    /// Shows image and video links as thumbnails, which the viewer's
+    /// browser then loads from the linked host.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub media_previews: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_sse_connections: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_lines: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<PathBuf>,
+    /// Where the trigram search index is kept; unset disables the index.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search_index_dir: Option<PathBuf>,
+    #[serde(default = "default_cache_max_size_mb")]
+    pub cache_max_size_mb: u64,
+    #[serde(default = "default_cache_max_age_days")]
+    pub cache_max_age_days: u64,
+    /// Memory for decompressed log files; 0 disables the cache.
+    #[serde(default = "default_log_cache_size_mb")]
+    pub log_cache_size_mb: usize,
 }
 
 fn default_bind() -> String { "0.0.0.0:8080".into() }
 fn default_title() -> String { "IRC Logs".into() }
 fn default_search_limit() -> usize { 10000 }
+fn default_search_all_max_files() -> usize { 20000 }
+fn default_sse_buffer_lines() -> usize { 100 }
+fn default_sse_batch_ms() -> u64 { 100 }
+fn default_sse_channel_capacity() -> usize { 256 }
+fn default_fold_reasons_at() -> usize { 200 }
+fn default_irc_formatting() -> bool { true }
+fn default_cache_max_size_mb() -> u64 { 1024 }
+fn default_cache_max_age_days() -> u64 { 30 }
+fn default_log_cache_size_mb() -> usize { 64 }
+fn default_link_schemes() -> Vec<String> {
+    ["http://", "https://", "ftp://", "irc://", "ircs://", "mailto:"].map(String::from).to_vec()
+}
+fn default_ignore_suffixes() -> Vec<String> {
+    [".tmp", ".part", ".swp", "~"].map(String::from).to_vec()
+}
 fn default_ai_model() -> String { "claude-haiku-4-5-20251001".into() }
 fn default_ai_max_concurrent() -> usize { 1 }
 fn default_ai_max_tool_calls() -> usize { 100 }
+fn default_ai_max_retries() -> u32 { 3 }
+fn default_ai_max_tokens() -> u32 { 4096 }
+fn default_ai_max_context_bytes() -> usize { 150_000 }
+fn default_ai_search_output_bytes() -> usize { 8000 }
+fn default_ai_max_dates_scanned() -> usize { 365 }
+fn default_ai_search_time_ms() -> u64 { 5000 }
+fn default_ai_output_buffer_bytes() -> usize { 100_000 }
 
 impl Default for Config {
     fn default() -> Self {
@@ -52,15 +237,194 @@ impl Default for Config {
             bind: default_bind(),
             title: default_title(),
             search_limit: default_search_limit(),
-            logs_dirs: vec![PathBuf::from("./logs")],
+            search_all_max_files: default_search_all_max_files(),
+            sse_buffer_lines: default_sse_buffer_lines(),
+            sse_batch_ms: default_sse_batch_ms(),
+            sse_channel_capacity: default_sse_channel_capacity(),
+            ignore_suffixes: default_ignore_suffixes(),
+            fold_reasons_at: default_fold_reasons_at(),
+            irc_formatting: default_irc_formatting(),
+            link_schemes: default_link_schemes(),
+            logs_dirs: vec![PathBuf::from("./logs").into()],
             base_path: String::new(),
+            timezone: UtcOffset::default(),
             ai: None,
+            nick_aliases: BTreeMap::new(),
+            nick_colors: NickColors::default(),
+            favorite_channels: Vec::new(),
+            sidebar_open_depth: None,
+            include_channels: Vec::new(),
+            exclude_channels: Vec::new(),
+            merged_channels: BTreeMap::new(),
+            channels: BTreeMap::new(),
+            trusted_proxies: Vec::new(),
+            forwarded_headers: false,
+            auth: None,
+            debug: false,
+            line_numbers: false,
+            infinite_scroll: false,
+            media_previews: false,
+            max_connections: None,
+            max_sse_connections: None,
+            page_lines: None,
+            cache_dir: None,
+            search_index_dir: None,
+            cache_max_size_mb: default_cache_max_size_mb(),
+            cache_max_age_days: default_cache_max_age_days(),
+            log_cache_size_mb: default_log_cache_size_mb(),
+        }
+    }
+}
+
+/// A `logs_dirs` entry: a bare path, or `{path, format}` to force the log
+/// format of every channel under it instead of detecting it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LogsDir {
+    Path(PathBuf),
+    Entry(LogsDirEntry),
+}
+
+/// Nick colors chosen by the operator; nicks otherwise get a hue from
+/// their hash.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NickColors {
+    /// Colors nicks are spread over, each always getting the same one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub palette: Vec<NickColor>,
+    /// Colors for given nicks, matched ignoring ASCII case.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pinned: BTreeMap<String, NickColor>,
+}
+
+/// A hue, drawn at the theme's nick lightness, or any CSS color.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum NickColor {
+    Hue(u16),
+    Css(String),
+}
+
+impl NickColors {
+    pub fn is_empty(&self) -> bool {
+        self.palette.is_empty() && self.pinned.is_empty()
+    }
+
+    /// Rejects hues past 359 and CSS colors that could carry other style
+    /// properties into the attribute they end up in.
+    pub fn validate(&self) -> Result<(), String> {
+        for color in self.palette.iter().chain(self.pinned.values()) {
+            match color {
+                NickColor::Hue(hue) if *hue >= 360 => {
+                    return Err(format!("nick_colors: hue {hue} must be below 360"));
+                }
+                NickColor::Css(css) if !is_css_color(css) => {
+                    return Err(format!("nick_colors: invalid color {css:?}"));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Color names, `#rgb` and functions like `rgb(1, 2, 3)`, but no `;` or
+/// quotes.
+fn is_css_color(css: &str) -> bool {
+    !css.is_empty() && css.chars().all(|c| c.is_ascii_alphanumeric() || "#(),.% -".contains(c))
+}
+
+/// Settings for one channel under `channels`; unset keys keep the global
+/// behavior.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelOverrides {
+    /// Log pages open with events hidden unless `?view=` says otherwise.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hide_events_default: bool,
+    /// Forced format, winning over the `logs_dirs` entry and detection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<LogFormat>,
+    /// `false` hides the channel from AI search.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ai_enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// Wins over the global `media_previews`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_previews: Option<bool>,
+    /// Shows backtick-quoted and indented code in messages in monospace.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub code_blocks: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogsDirEntry {
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<LogFormat>,
+}
+
+impl LogsDir {
+    pub fn path(&self) -> &Path {
+        match self {
+            LogsDir::Path(p) => p,
+            LogsDir::Entry(e) => &e.path,
+        }
+    }
+
+    pub fn format(&self) -> Option<LogFormat> {
+        match self {
+            LogsDir::Path(_) => None,
+            LogsDir::Entry(e) => e.format,
         }
     }
 }
 
+impl From<PathBuf> for LogsDir {
+    fn from(path: PathBuf) -> Self {
+        LogsDir::Path(path)
+    }
+}
+
+/// API shape spoken by the `ask` backend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AiProvider {
+    #[default]
+    Anthropic,
+    /// Chat completions with function calling, as served by OpenAI,
+    /// Ollama, vLLM or LiteLLM.
+    #[serde(rename = "openai")]
+    OpenAi,
+}
+
+/// Credentials for a private archive: HTTP basic users, a bearer token, or
+/// both. Passwords are plain text or bcrypt hashes (`$2b$...`).
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub users: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Shown by browsers in the login prompt; defaults to `title`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub realm: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct AiConfig {
+    #[serde(default)]
+    pub provider: AiProvider,
+    /// API root; defaults to the provider's public endpoint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    /// May be empty for local `openai` servers that take no key.
+    #[serde(default)]
     pub api_key: String,
     #[serde(default = "default_ai_model")]
     pub model: String,
@@ -69,14 +433,137 @@ pub struct AiConfig {
     pub max_concurrent: usize,
     #[serde(default = "default_ai_max_tool_calls")]
     pub max_tool_calls: usize,
+    /// Retries of an API call answered with 429, 500 or 503.
+    #[serde(default = "default_ai_max_retries")]
+    pub max_retries: u32,
     #[serde(default)]
     pub system_prompt: Option<String>,
+    #[serde(default)]
+    pub verify_quotes: bool,
+    #[serde(default)]
+    pub broadcast_results: bool,
+    /// Output tokens requested per model turn.
+    #[serde(default = "default_ai_max_tokens")]
+    pub max_tokens: u32,
+    /// Serialized conversation size at which a session stops.
+    #[serde(default = "default_ai_max_context_bytes")]
+    pub max_context_bytes: usize,
+    /// Size at which one `search` call stops adding matches.
+    #[serde(default = "default_ai_search_output_bytes")]
+    pub search_output_bytes: usize,
+    /// Days one `search` call reads across all its channels.
+    #[serde(default = "default_ai_max_dates_scanned")]
+    pub max_dates_scanned: usize,
+    /// Wall-clock time one `search` call may spend reading logs.
+    #[serde(default = "default_ai_search_time_ms")]
+    pub search_time_ms: u64,
+    /// Size the report built by `copy` and `output` is truncated to.
+    #[serde(default = "default_ai_output_buffer_bytes")]
+    pub output_buffer_bytes: usize,
+    /// Enables a cost estimate next to the token tally on the ask page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prices: Option<TokenPrices>,
+}
+
+/// Dollars per million tokens of each kind.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenPrices {
+    #[serde(default)]
+    pub input: f64,
+    #[serde(default)]
+    pub output: f64,
+    #[serde(default)]
+    pub cache_read: f64,
+    #[serde(default)]
+    pub cache_write: f64,
+}
+
+impl AiConfig {
+    /// Rejects limits that would stop every session before it starts.
+    pub fn validate(&self) -> Result<(), String> {
+        let limits = [
+            ("max_tokens", self.max_tokens as usize),
+            ("max_context_bytes", self.max_context_bytes),
+            ("search_output_bytes", self.search_output_bytes),
+            ("max_dates_scanned", self.max_dates_scanned),
+            ("search_time_ms", self.search_time_ms as usize),
+            ("output_buffer_bytes", self.output_buffer_bytes),
+        ];
+        if let Some((name, _)) = limits.iter().find(|(_, value)| *value == 0) {
+            return Err(format!("ai.{name} must be greater than 0"));
+        }
+        if self.search_output_bytes >= self.max_context_bytes {
+            return Err("ai.search_output_bytes must be smaller than ai.max_context_bytes".into());
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ChannelDir {
     pub path: PathBuf,
     pub format: LogFormat,
+    pub source: LogSource,
+    /// Channel name the file names start with (`#chan` in
+    /// `#chan.2025-01-01.log`), or empty for bare dates. Files naming another
+    /// channel belong to that channel, not this one.
+    pub prefix: String,
+}
+
+#[derive(Debug, Clone)]
+pub enum LogSource {
+    Dir,
+    /// Directory inside a tar archive: `ChannelDir::path` is the archive path
+    /// joined with the member directory, and the file names are indexed at
+    /// startup since archives don't change.
+    Archive(Arc<Vec<String>>),
+}
+
+impl ChannelDir {
+    pub fn file_names(&self) -> Vec<String> {
+        match &self.source {
+            LogSource::Dir => {
+                let Ok(entries) = std::fs::read_dir(&self.path) else { return Vec::new() };
+                entries
+                    .flatten()
+                    .filter(|e| !e.file_type().is_ok_and(|t| t.is_dir()))
+                    .filter_map(|e| Some((e.file_name().into_string().ok()?, e.file_type().ok()?)))
+                    .filter(|(name, _)| !name.starts_with('.'))
+                    .filter(|(name, t)| !t.is_symlink() || contained_path(&self.path, name).is_some())
+                    .map(|(name, _)| name)
+                    .collect()
+            }
+            LogSource::Archive(files) => files.to_vec(),
+        }
+    }
+
+    pub fn has_file(&self, name: &str) -> bool {
+        match &self.source {
+            LogSource::Dir => contained_path(&self.path, name).is_some_and(|p| p.is_file()),
+            LogSource::Archive(files) => files.iter().any(|f| f == name),
+        }
+    }
+
+    /// Normalized date of `name` if it is one of this directory's daily logs.
+    pub fn file_date(&self, name: &str) -> Option<String> {
+        server::parse_log_file_name(name).filter(|(prefix, _)| *prefix == self.prefix).map(|(_, date)| date)
+    }
+}
+
+/// `dir/name` unless `name` is not a plain file name, or is a symlink
+/// resolving outside `dir`. A missing file passes, so callers can create it.
+pub fn contained_path(dir: &Path, name: &str) -> Option<PathBuf> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        return None;
+    }
+    let path = dir.join(name);
+    if std::fs::symlink_metadata(&path).is_err() {
+        return Some(path);
+    }
+    let real = std::fs::canonicalize(&path).ok()?;
+    let base = std::fs::canonicalize(dir).ok()?;
+    real.starts_with(&base).then_some(path)
 }
 
 #[derive(Debug, Clone)]
@@ -84,56 +571,177 @@ pub struct Channel {
     pub name: String,
     pub path_segments: Vec<String>,
     pub dirs: Vec<ChannelDir>,
+    /// Listed in `favorite_channels`, pinned at the top of the sidebar.
+    pub favorite: bool,
+    /// Real channels combined into this one by `merged_channels`; `dirs`
+    /// then holds all of their directories. Empty for real channels.
+    pub sources: Vec<Channel>,
+    /// From `channels`.
+    pub overrides: ChannelOverrides,
+    /// Date of the newest log, set at discovery and moved forward by the
+    /// live tail; shared with the clones `merged_channels` keeps.
+    pub latest: Arc<std::sync::Mutex<Option<String>>>,
 }
 
+impl Channel {
+    /// Name shown on pages: the `display_name` override, or the directory name.
+    pub fn display_name(&self) -> &str {
+        self.overrides.display_name.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn ai_enabled(&self) -> bool {
+        self.overrides.ai_enabled != Some(false)
+    }
+
+    /// Date of the newest log, of any source for a merged channel.
+    pub fn latest_date(&self) -> Option<String> {
+        if self.sources.is_empty() {
+            self.latest.lock().unwrap().clone()
+        } else {
+            self.sources.iter().filter_map(Channel::latest_date).max()
+        }
+    }
+
+    /// Notes a log for `date`, which becomes the latest if newer.
+    pub fn saw_date(&self, date: &str) {
+        let mut latest = self.latest.lock().unwrap();
+        if latest.as_deref().is_none_or(|l| l < date) {
+            *latest = Some(date.to_string());
+        }
+    }
+
+    pub fn media_previews(&self, config: &Config) -> bool {
+        self.overrides.media_previews.unwrap_or(config.media_previews)
+    }
+}
+
+/// Channels the sidebar lists in full when `sidebar_open_depth` is unset;
+/// beyond this only the top level is shipped with each page.
+pub const SIDEBAR_FULL_TREE_MAX: usize = 500;
+
 #[derive(Debug, Default)]
 pub struct ChannelNode {
     pub channel: Option<Channel>,
     pub children: BTreeMap<String, ChannelNode>,
+    /// Past `sidebar_open_depth`: the sidebar leaves the children out and
+    /// loads them when the node is opened.
+    pub collapsed: bool,
 }
 
 pub struct AppState {
     pub config: Config,
     pub logs_dirs: Vec<PathBuf>,
     pub channels: ChannelNode,
-    pub sse_senders: RwLock<HashMap<String, broadcast::Sender<String>>>,
+    pub sse_feeds: RwLock<HashMap<String, tail::ChannelFeed>>,
+    pub page_cache: Option<cache::PageCache>,
+    /// Set with `search_index_dir`; built in the background at startup.
+    pub search_index: Option<Arc<index::SearchIndex>>,
+    /// Unset when `log_cache_size_mb` is 0.
+    pub log_cache: Option<cache::LogCache>,
+    /// Messages per log file for the calendar, keyed by path and checked
+    /// against the file's mtime.
+    pub message_counts: std::sync::Mutex<HashMap<PathBuf, (std::time::SystemTime, usize)>>,
+    /// Per-file activity for the stats page, checked the same way.
+    pub day_stats: stats::StatsCache,
+    /// `Authorization` values that already passed, so bcrypt runs once per
+    /// credential rather than once per request.
+    pub auth_verified: std::sync::Mutex<HashSet<String>>,
+    /// Caps concurrent non-streaming requests (`max_connections`).
+    pub request_limit: Option<Arc<Semaphore>>,
+    /// Caps open live streams (`max_sse_connections`).
+    pub stream_limit: Option<Arc<Semaphore>>,
     pub ai_semaphore: Option<Arc<Semaphore>>,
+    pub metrics: metrics::Metrics,
+    /// Becomes true once the server starts shutting down.
+    pub shutdown: tokio::sync::watch::Sender<bool>,
     pub reqwest_client: Option<reqwest::Client>,
 }
 
+impl AppState {
+    /// The search index and log cache, for searches.
+    pub fn sources(&self) -> search::Sources<'_> {
+        search::Sources { index: self.search_index.as_deref(), cache: self.log_cache.as_ref() }
+    }
+
+    /// Tells AI sessions to stop and ends every live stream by dropping the
+    /// feeds' senders, since graceful shutdown waits for open responses.
+    pub async fn shut_down(&self) {
+        self.shutdown.send_replace(true);
+        self.sse_feeds.write().await.clear();
+    }
+
+    /// State over the channels found in `logs_dirs`, with the merged
+    /// channels added and the sidebar laid out.
+    pub fn new(config: Config, logs_dirs: Vec<PathBuf>, mut channels: ChannelNode) -> Self {
+        record_latest_dates(&channels);
+        insert_merged_channels(&mut channels, &config);
+        mark_favorites(&mut channels, &config.favorite_channels);
+        collapse_sidebar(&mut channels, config.sidebar_open_depth);
+        apply_channel_overrides(&mut channels, &config.channels);
+        Self {
+            page_cache: cache::PageCache::new(&config, &channels),
+            search_index: config.search_index_dir.as_deref().and_then(index::SearchIndex::open).map(Arc::new),
+            log_cache: cache::LogCache::for_config(&config),
+            request_limit: config.max_connections.map(|n| Arc::new(Semaphore::new(n))),
+            stream_limit: config.max_sse_connections.map(|n| Arc::new(Semaphore::new(n))),
+            ai_semaphore: config.ai.as_ref().map(|ai| Arc::new(Semaphore::new(ai.max_concurrent))),
+            reqwest_client: config.ai.as_ref().map(|_| reqwest::Client::new()),
+            config,
+            logs_dirs,
+            channels,
+            sse_feeds: RwLock::new(HashMap::new()),
+            message_counts: Default::default(),
+            day_stats: Default::default(),
+            auth_verified: Default::default(),
+            metrics: Default::default(),
+            shutdown: Default::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State over `config.logs_dirs`, without the watcher.
+    pub fn for_tests(config: Config) -> Self {
+        let logs_dirs: Vec<PathBuf> = config.logs_dirs.iter()
+            .map(|d| std::fs::canonicalize(d.path()).unwrap())
+            .collect();
+        let channels = discover_logs_dirs(&logs_dirs, &config);
+        Self::new(config, logs_dirs, channels)
+    }
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
     let cli = Cli::parse();
+    // `RUST_LOG` picks what is logged, e.g. `warn` to drop the access log
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt().with_env_filter(filter).with_writer(std::io::stderr).init();
 
     let mut config: Config = if cli.config.exists() {
         let content = std::fs::read_to_string(&cli.config).unwrap_or_else(|e| {
-            eprintln!("cannot read config {:?}: {e}", cli.config);
+            tracing::error!("cannot read config {:?}: {e}", cli.config);
             std::process::exit(1);
         });
-        serde_yaml::from_str(&content).unwrap_or_else(|e| {
-            eprintln!("invalid config {:?}: {e}", cli.config);
+        let config: Config = serde_yaml::from_str(&content).unwrap_or_else(|e| {
+            tracing::error!("invalid config {:?}: {e}", cli.config);
             std::process::exit(1);
-        })
+        });
+        let valid = config.ai.as_ref().map_or(Ok(()), AiConfig::validate).and_then(|()| config.nick_colors.validate());
+        if let Err(e) = valid {
+            tracing::error!("invalid config {:?}: {e}", cli.config);
+            std::process::exit(1);
+        }
+        config
     } else {
         let config = Config::default();
         let mut yaml = serde_yaml::to_string(&config).unwrap();
-        yaml.push_str(concat!(
-            "#base_path: /irc\n",
-            "#ai:\n",
-            "#  api_key: sk-ant-api03-...\n",
-            "#  model: claude-haiku-4-5-20251001\n",
-            "#  output_dir: /var/lib/irc-logs/ask\n",
-            "#  max_concurrent: 1\n",
-            "#  max_tool_calls: 100\n",
-            "#  system_prompt: |\n",
-            "#    Custom system prompt text here.\n",
-            "#    The channel list is always appended automatically.\n",
-        ));
+        yaml.push_str(EXAMPLE_CONFIG);
         std::fs::write(&cli.config, &yaml).unwrap_or_else(|e| {
-            eprintln!("cannot write default config {:?}: {e}", cli.config);
+            tracing::error!("cannot write default config {:?}: {e}", cli.config);
             std::process::exit(1);
         });
-        eprintln!("created default config {:?}, edit and restart", cli.config);
+        tracing::info!("created default config {:?}, edit and restart", cli.config);
         std::process::exit(0);
     };
 
@@ -146,109 +754,284 @@ async fn main() {
     };
 
     let logs_dirs: Vec<PathBuf> = config.logs_dirs.iter().map(|d| {
-        std::fs::canonicalize(d).unwrap_or_else(|e| {
-            eprintln!("cannot access logs dir {d:?}: {e}");
+        std::fs::canonicalize(d.path()).unwrap_or_else(|e| {
+            tracing::error!("cannot access logs dir {:?}: {e}", d.path());
             std::process::exit(1);
         })
     }).collect();
 
-    let mut root = ChannelNode::default();
-    for dir in &logs_dirs {
-        discover_channels(dir, &[], &mut root);
-    }
-
+    let root = discover_logs_dirs(&logs_dirs, &config);
     let bind = config.bind.clone();
-    let (ai_semaphore, reqwest_client) = match &config.ai {
-        Some(ai) => {
-            eprintln!("ai: enabled, model={}, max_concurrent={}", ai.model, ai.max_concurrent);
-            (
-                Some(Arc::new(Semaphore::new(ai.max_concurrent))),
-                Some(reqwest::Client::new()),
-            )
-        }
-        None => (None, None),
-    };
-    let state = Arc::new(AppState {
-        config,
-        logs_dirs,
-        channels: root,
-        sse_senders: RwLock::new(HashMap::new()),
-        ai_semaphore,
-        reqwest_client,
-    });
+    if let Some(ai) = &config.ai {
+        tracing::info!(model = %ai.model, max_concurrent = ai.max_concurrent, "ai enabled");
+    }
+    let state = Arc::new(AppState::new(config, logs_dirs, root));
 
     tail::start_watcher(Arc::clone(&state));
+    if let Some(index) = state.search_index.clone() {
+        let state = Arc::clone(&state);
+        tokio::task::spawn_blocking(move || index.build(&state.channels));
+    }
 
-    let app = if state.config.base_path.is_empty() {
-        server::router().with_state(Arc::clone(&state))
-    } else {
-        Router::new()
-            .nest(&state.config.base_path, server::router())
-            .with_state(Arc::clone(&state))
+    let app = server::app(Arc::clone(&state));
+    let shutdown = async move {
+        shutdown_signal().await;
+        tracing::info!("shutting down");
+        state.shut_down().await;
     };
-    let listener = tokio::net::TcpListener::bind(&bind).await.unwrap_or_else(|e| {
-        eprintln!("cannot bind {bind}: {e}");
+    let cannot_bind = |e: std::io::Error| -> ! {
+        tracing::error!("cannot bind {bind}: {e}");
         std::process::exit(1);
-    });
-    eprintln!("listening on {bind}");
-    axum::serve(listener, app).await.unwrap();
+    };
+    match bind.strip_prefix("unix:") {
+        #[cfg(unix)]
+        Some(path) => {
+            let listener = server::bind_unix(Path::new(path)).unwrap_or_else(|e| cannot_bind(e));
+            tracing::info!("listening on {bind}");
+            axum::serve(listener, server::unix_app(app)).with_graceful_shutdown(shutdown).await.unwrap();
+            let _ = std::fs::remove_file(path);
+        }
+        _ => {
+            let listener = tokio::net::TcpListener::bind(&bind).await.unwrap_or_else(|e| cannot_bind(e));
+            tracing::info!("listening on {bind}");
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(shutdown)
+                .await
+                .unwrap();
+        }
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM from a service manager.
+async fn shutdown_signal() {
+    let interrupt = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+/// Dotfiles (`.DS_Store`, editor swap files, sync tool state) and names
+/// ending in one of the configured temp suffixes are never logs or channels.
+fn is_ignored_name(name: &str, ignore_suffixes: &[String]) -> bool {
+    name.starts_with('.') || ignore_suffixes.iter().any(|s| name.ends_with(s.as_str()))
+}
+
+/// Settings applied while walking one `logs_dirs` entry.
+struct Discovery<'a> {
+    ignore_suffixes: &'a [String],
+    /// Forced format; detected per channel directory when unset.
+    format: Option<LogFormat>,
+    /// Per-channel `format` overrides win over both.
+    overrides: &'a BTreeMap<String, ChannelOverrides>,
+    include: &'a [String],
+    exclude: &'a [String],
+}
+
+impl Discovery<'_> {
+    fn format_of(&self, segments: &[String], dir: &ChannelDir) -> LogFormat {
+        self.overrides
+            .get(&segments.join("/"))
+            .and_then(|o| o.format)
+            .or(self.format)
+            .unwrap_or_else(|| detect_channel_format(dir))
+    }
+
+    fn publishes(&self, path: &str) -> bool {
+        is_published(path, self.include, self.exclude)
+    }
+}
+
+impl Config {
+    /// Whether `include_channels` names the channel at `path`, which makes
+    /// it available to the AI even without a `#`.
+    pub fn lists_channel(&self, path: &str) -> bool {
+        self.include_channels.iter().any(|p| glob_match(p, path))
+    }
+}
+
+/// Whether `path` matches one of `include` (or `include` is empty) and none
+/// of `exclude`.
+fn is_published(path: &str, include: &[String], exclude: &[String]) -> bool {
+    (include.is_empty() || include.iter().any(|p| glob_match(p, path))) && !exclude.iter().any(|p| glob_match(p, path))
+}
+
+/// Whether `text` matches `pattern` in full, where `*` stands for any run
+/// of characters, `/` included, and `?` for one character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and the text position it currently covers up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((sp, st)) => {
+                    star = Some((sp, st + 1));
+                    p = sp + 1;
+                    t = st + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The channel tree under `logs_dirs`, the canonical paths of
+/// `config.logs_dirs` in the same order.
+fn discover_logs_dirs(logs_dirs: &[PathBuf], config: &Config) -> ChannelNode {
+    let mut root = ChannelNode::default();
+    for (dir, entry) in logs_dirs.iter().zip(&config.logs_dirs) {
+        let discovery = Discovery {
+            ignore_suffixes: &config.ignore_suffixes,
+            format: entry.format(),
+            overrides: &config.channels,
+            include: &config.include_channels,
+            exclude: &config.exclude_channels,
+        };
+        discover_channels(dir, &[], &mut root, &discovery);
+    }
+    root
 }
 
 fn discover_channels(
     dir: &Path,
     segments: &[String],
     root: &mut ChannelNode,
+    discovery: &Discovery,
 ) {
+    if archive::is_archive(dir) {
+        discover_archive(dir, segments, root, discovery);
+        return;
+    }
     let Ok(entries) = std::fs::read_dir(dir) else { return };
 
     let mut subdirs = Vec::new();
-    let mut has_logs = false;
+    let mut logs = Vec::new();
 
     for entry in entries.flatten() {
         let name = entry.file_name().to_string_lossy().to_string();
+        if is_ignored_name(&name, discovery.ignore_suffixes) {
+            continue;
+        }
         if entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false) {
             subdirs.push((name, entry.path()));
-        } else if name.ends_with(".log") || name.ends_with(".log.zst") {
-            let date_part = name.strip_suffix(".log")
-                .or_else(|| name.strip_suffix(".log.zst"))
-                .unwrap_or("");
-            if date_part.len() == 10 {
-                has_logs = true;
-            }
+        } else if let Some(stem) = archive::archive_stem(&name) {
+            // Archives behave like a directory named after the archive
+            subdirs.push((stem.to_string(), entry.path()));
+        } else if server::log_file_date(&name).is_some() {
+            logs.push(name);
         }
     }
 
-    if has_logs && !segments.is_empty() {
-        let format = detect_channel_format(dir);
-        let channel_dir = ChannelDir {
-            path: dir.to_path_buf(),
-            format,
-        };
-        insert_channel(root, segments, channel_dir);
-    }
+    insert_log_dir(root, segments, logs, discovery, |_| (dir.to_path_buf(), LogSource::Dir));
 
     // If any sibling subdir starts with #, only recurse into #-prefixed dirs
-    // (filters out ZNC private query logs like "qwebirc56163")
-    let has_hash_sibling = subdirs.iter().any(|(name, _)| name.starts_with('#'));
+    // (filters out ZNC private query logs like "qwebirc56163"), unless
+    // `include_channels` says what to serve
+    let has_hash_sibling = discovery.include.is_empty() && subdirs.iter().any(|(name, _)| name.starts_with('#'));
     for (name, path) in subdirs {
         if has_hash_sibling && !name.starts_with('#') {
             continue;
         }
         let mut child_segments = segments.to_vec();
         child_segments.push(name);
-        discover_channels(&path, &child_segments, root);
+        discover_channels(&path, &child_segments, root, discovery);
     }
 }
 
-fn detect_channel_format(dir: &Path) -> LogFormat {
-    let Ok(entries) = std::fs::read_dir(dir) else {
-        return LogFormat::Iso8601;
+/// Indexes a tar archive once and inserts a channel for every member
+/// directory holding daily logs. `segments` already ends with the archive
+/// stem; a single top-level member directory of the same name is skipped so
+/// `#chan.tar.zst` containing `#chan/…` doesn't nest twice.
+fn discover_archive(path: &Path, segments: &[String], root: &mut ChannelNode, discovery: &Discovery) {
+    let members = match archive::list_members(path) {
+        Ok(m) => m,
+        Err(e) => {
+            tracing::warn!("cannot read archive {path:?}: {e}");
+            return;
+        }
     };
+    let redundant_top = segments.last().is_some_and(|stem| {
+        members.keys().all(|dir| dir.components().next().is_some_and(|c| c.as_os_str() == stem.as_str()))
+    });
 
-    for entry in entries.flatten() {
-        let name = entry.file_name().to_string_lossy().to_string();
-        if name.ends_with(".log") || name.ends_with(".log.zst") {
-            if let Ok(content) = server::read_log_file(&entry.path()) {
+    for (member_dir, files) in members {
+        let files: Vec<String> = files
+            .into_iter()
+            .filter(|f| !is_ignored_name(f, discovery.ignore_suffixes) && server::log_file_date(f).is_some())
+            .collect();
+        let mut channel_segments = segments.to_vec();
+        let skip = usize::from(redundant_top);
+        channel_segments.extend(
+            member_dir.components().skip(skip).map(|c| c.as_os_str().to_string_lossy().to_string()),
+        );
+        insert_log_dir(root, &channel_segments, files, discovery, |files| {
+            (path.join(&member_dir), LogSource::Archive(Arc::new(files)))
+        });
+    }
+}
+
+/// Inserts the channels whose daily logs are `names`, all in one directory.
+/// When the names carry a single channel prefix, or none, the directory is
+/// the channel; otherwise each prefix becomes a channel below it and bare
+/// dates stay with the directory. `source` gives the directory's path and
+/// source for the files of one channel.
+fn insert_log_dir(
+    root: &mut ChannelNode,
+    segments: &[String],
+    names: Vec<String>,
+    discovery: &Discovery,
+    source: impl Fn(Vec<String>) -> (PathBuf, LogSource),
+) {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for name in names {
+        if let Some((prefix, _)) = server::parse_log_file_name(&name) {
+            groups.entry(prefix.to_string()).or_default().push(name);
+        }
+    }
+    let split = groups.len() > 1 || segments.is_empty();
+    for (prefix, files) in groups {
+        let mut channel_segments = segments.to_vec();
+        if split && !prefix.is_empty() {
+            channel_segments.push(prefix.clone());
+        }
+        if channel_segments.is_empty() || !discovery.publishes(&channel_segments.join("/")) {
+            continue;
+        }
+        let (path, source) = source(files);
+        let mut channel_dir = ChannelDir { path, format: LogFormat::Iso8601, source, prefix };
+        channel_dir.format = discovery.format_of(&channel_segments, &channel_dir);
+        insert_channel(root, &channel_segments, channel_dir);
+    }
+}
+
+fn detect_channel_format(dir: &ChannelDir) -> LogFormat {
+    for name in dir.file_names() {
+        if dir.file_date(&name).is_some() {
+            if let Ok(content) = server::read_log_file(&dir.path.join(&name)) {
                 if let Some(first_line) = content.lines().next() {
                     return parser::detect_format(first_line);
                 }
@@ -259,6 +1042,87 @@ fn detect_channel_format(dir: &Path) -> LogFormat {
     LogFormat::Iso8601
 }
 
+fn find_channel_mut<'a>(root: &'a mut ChannelNode, path: &str) -> Option<&'a mut Channel> {
+    let mut node = root;
+    for seg in path.split('/') {
+        node = node.children.get_mut(seg)?;
+    }
+    node.channel.as_mut()
+}
+
+/// Adds a synthetic channel at each `merged_channels` path whose `dirs` are
+/// the union of its sources' directories.
+fn insert_merged_channels(root: &mut ChannelNode, config: &Config) {
+    for (path, source_paths) in &config.merged_channels {
+        if !is_published(path, &config.include_channels, &config.exclude_channels) {
+            continue;
+        }
+        let mut sources = Vec::new();
+        for source in source_paths {
+            match find_channel_mut(root, source) {
+                Some(channel) => sources.push(channel.clone()),
+                None => tracing::warn!("merged channel {path:?}: source {source:?} not found"),
+            }
+        }
+        let segments: Vec<String> = path.split('/').map(String::from).collect();
+        if sources.is_empty() || find_channel_mut(root, path).is_some() {
+            tracing::warn!("merged channel {path:?} skipped");
+            continue;
+        }
+        let mut node = &mut *root;
+        for seg in &segments {
+            node = node.children.entry(seg.clone()).or_default();
+        }
+        node.channel = Some(Channel {
+            name: segments.last().unwrap().clone(),
+            path_segments: segments,
+            dirs: sources.iter().flat_map(|c| c.dirs.clone()).collect(),
+            favorite: false,
+            sources,
+            overrides: ChannelOverrides::default(),
+            latest: Default::default(),
+        });
+    }
+}
+
+fn apply_channel_overrides(root: &mut ChannelNode, overrides: &BTreeMap<String, ChannelOverrides>) {
+    for (path, settings) in overrides {
+        match find_channel_mut(root, path) {
+            Some(channel) => channel.overrides = settings.clone(),
+            None => tracing::warn!("channels: {path:?} not found"),
+        }
+    }
+}
+
+fn mark_favorites(root: &mut ChannelNode, favorites: &[String]) {
+    for path in favorites {
+        match find_channel_mut(root, path) {
+            Some(channel) => channel.favorite = true,
+            None => tracing::warn!("favorite channel {path:?} not found"),
+        }
+    }
+}
+
+/// Collapses the sidebar past `sidebar_open_depth`, or past the top level
+/// for a tree too big to ship whole when it is unset.
+fn collapse_sidebar(root: &mut ChannelNode, open_depth: Option<usize>) {
+    let open_depth = match open_depth {
+        Some(depth) => depth,
+        None if server::count_channels(root) > SIDEBAR_FULL_TREE_MAX => 0,
+        None => return,
+    };
+    mark_collapsed(root, 0, open_depth);
+}
+
+/// Marks nodes with children `open_depth` levels below the root, or
+/// deeper, as collapsed; `depth` is that of `node`'s children.
+fn mark_collapsed(node: &mut ChannelNode, depth: usize, open_depth: usize) {
+    for child in node.children.values_mut() {
+        child.collapsed = depth >= open_depth && !child.children.is_empty();
+        mark_collapsed(child, depth + 1, open_depth);
+    }
+}
+
 fn insert_channel(root: &mut ChannelNode, segments: &[String], dir: ChannelDir) {
     let mut node = root;
     for seg in segments {
@@ -271,6 +1135,110 @@ fn insert_channel(root: &mut ChannelNode, segments: &[String], dir: ChannelDir)
             name: segments.last().unwrap().clone(),
             path_segments: segments.to_vec(),
             dirs: vec![dir],
+            favorite: false,
+            sources: Vec::new(),
+            overrides: ChannelOverrides::default(),
+            latest: Default::default(),
         });
     }
 }
+
+/// Records each discovered channel's newest log date, once every logs dir
+/// has added its files.
+fn record_latest_dates(node: &ChannelNode) {
+    if let Some(channel) = &node.channel {
+        if let Some(date) = server::channel_dates(channel).last() {
+            channel.saw_date(date);
+        }
+    }
+    for child in node.children.values() {
+        record_latest_dates(child);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::LogTree;
+
+    #[test]
+    fn test_example_config_keys_are_accepted() {
+        let mut yaml = String::from("logs_dirs: [./logs]\n");
+        for line in EXAMPLE_CONFIG.lines() {
+            yaml.push_str(line.strip_prefix('#').unwrap());
+            yaml.push('\n');
+        }
+        let config: Config = serde_yaml::from_str(&yaml).unwrap();
+        assert!(config.ai.is_some());
+    }
+
+    #[test]
+    fn test_discovery_skips_junk_files() {
+        let dir = LogTree::new("junk");
+        dir.mkdir("OFTC/#chan/2025-01-07.log");
+        for f in ["2025-01-01.log", ".2025-01-02.log", "2025-01-0x.log", "2025-01-03.log.tmp", ".DS_Store"] {
+            dir.write(Path::new("OFTC/#chan").join(f), "[00:00:01] <a> hi\n");
+        }
+        dir.write("OFTC/#chan.tmp/2025-01-04.log", "[00:00:01] <a> hi\n");
+        dir.write("OFTC/.trash/#old/2025-01-05.log", "[00:00:01] <a> hi\n");
+
+        let state = dir.state(Config::default());
+        let oftc = &state.channels.children["OFTC"];
+        assert_eq!(oftc.children.keys().collect::<Vec<_>>(), vec!["#chan"]);
+        let channel = oftc.children["#chan"].channel.as_ref().unwrap();
+        assert_eq!(server::channel_dates(channel), vec!["2025-01-01"]);
+        assert!(server::resolve_log_path(channel, "2025-01-07").is_none());
+    }
+
+    #[test]
+    fn test_channel_overrides() {
+        let dir = LogTree::new("overrides");
+        dir.write("OFTC/#a/2025-01-01.log", "[00:00:01] <a> hi\n");
+        dir.write("OFTC/#b/2025-01-01.log", "[00:00:01] <a> hi\n");
+        let yaml = format!(
+            "logs_dirs: [{}]\nchannels:\n  OFTC/#b:\n    format: weechat\n    ai_enabled: false\n    display_name: B\n  OFTC/#gone: {{}}\n",
+            dir.display()
+        );
+        let state = AppState::for_tests(serde_yaml::from_str(&yaml).unwrap());
+        let oftc = &state.channels.children["OFTC"];
+        let (a, b) = (oftc.children["#a"].channel.as_ref().unwrap(), oftc.children["#b"].channel.as_ref().unwrap());
+
+        assert_eq!(detect_channel_format(&b.dirs[0]), LogFormat::Znc);
+        assert_eq!(a.dirs[0].format, LogFormat::Znc);
+        assert_eq!(b.dirs[0].format, LogFormat::Weechat);
+        assert_eq!((a.display_name(), b.display_name()), ("#a", "B"));
+        assert!(a.ai_enabled() && !b.ai_enabled());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("OFTC/#*", "OFTC/#chan"));
+        assert!(glob_match("*/#secret*", "Libera/#secret-ops"));
+        assert!(glob_match("OFTC/#ch?n", "OFTC/#chan"));
+        assert!(glob_match("*", "OFTC/#chan"));
+        assert!(!glob_match("OFTC/#*", "Libera/#chan"));
+        assert!(!glob_match("OFTC/#ch?n", "OFTC/#chn"));
+        assert!(!glob_match("OFTC", "OFTC/#chan"));
+    }
+
+    #[test]
+    fn test_logs_dirs_accept_paths_and_entries() {
+        let yaml = "logs_dirs:\n- /a\n- path: /b\n  format: znc\n";
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.logs_dirs[0].path(), Path::new("/a"));
+        assert_eq!(config.logs_dirs[0].format(), None);
+        assert_eq!(config.logs_dirs[1].path(), Path::new("/b"));
+        assert_eq!(config.logs_dirs[1].format(), Some(LogFormat::Znc));
+
+        assert!(serde_yaml::from_str::<Config>("logs_dirs:\n- path: /b\n  format: irssi\n").is_err());
+    }
+
+    #[test]
+    fn test_unknown_config_key_is_rejected() {
+        let err = serde_yaml::from_str::<Config>("logs_dirs: [/tmp]\nsearch_limt: 5\n").unwrap_err();
+        assert!(err.to_string().contains("search_limt"));
+
+        let yaml = "logs_dirs: [/tmp]\nai:\n  api_key: x\n  output_dir: /tmp\n  modle: y\n";
+        assert!(serde_yaml::from_str::<Config>(yaml).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tokio_stream::StreamExt;
+
+use crate::AppState;
+
+/// Counters behind `/metrics`, updated by [`track`].
+#[derive(Default)]
+pub struct Metrics {
+    /// Responses by status class, 1xx to 5xx.
+    responses: [AtomicU64; 5],
+    sse_connections: AtomicU64,
+}
+
+/// Lowers the open SSE count once the response body is dropped, i.e. when
+/// the client goes away or the stream ends.
+struct SseGuard(Arc<AppState>);
+
+impl Drop for SseGuard {
+    fn drop(&mut self) {
+        self.0.metrics.sse_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts every response by status class, and keeps the number of open
+/// live streams.
+pub async fn track(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let stream = crate::server::is_stream_path(request.uri().path(), &state);
+    let response = next.run(request).await;
+    let class = usize::from(response.status().as_u16() / 100).clamp(1, 5) - 1;
+    state.metrics.responses[class].fetch_add(1, Ordering::Relaxed);
+    if !stream || !response.status().is_success() {
+        return response;
+    }
+    state.metrics.sse_connections.fetch_add(1, Ordering::Relaxed);
+    let guard = SseGuard(state);
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _ = &guard;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// Prometheus text exposition of the counters and of what discovery found.
+pub fn render(state: &AppState) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, u64)]| {
+        let _ = writeln!(out, "# HELP irc_log_viewer_{name} {help}\n# TYPE irc_log_viewer_{name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(out, "irc_log_viewer_{name}{labels} {value}");
+        }
+    };
+    let responses: Vec<(String, u64)> = state
+        .metrics
+        .responses
+        .iter()
+        .enumerate()
+        .map(|(i, n)| (format!("{{status=\"{}xx\"}}", i + 1), n.load(Ordering::Relaxed)))
+        .collect();
+    let responses: Vec<(&str, u64)> = responses.iter().map(|(l, n)| (l.as_str(), *n)).collect();
+    metric("requests_total", "counter", "Requests answered, by response status class.", &responses);
+    let sse = state.metrics.sse_connections.load(Ordering::Relaxed);
+    metric("sse_connections", "gauge", "Live tail, search and AI streams open.", &[("", sse)]);
+    if let (Some(ai), Some(semaphore)) = (&state.config.ai, &state.ai_semaphore) {
+        let running = ai.max_concurrent.saturating_sub(semaphore.available_permits()) as u64;
+        metric("ai_sessions", "gauge", "AI sessions running.", &[("", running)]);
+        metric("ai_sessions_max", "gauge", "AI sessions allowed at once.", &[("", ai.max_concurrent as u64)]);
+    }
+    let channels = crate::server::count_channels(&state.channels) as u64;
+    metric("channels", "gauge", "Channels discovered at startup.", &[("", channels)]);
+    metric("logs_dirs", "gauge", "Configured log directories.", &[("", state.logs_dirs.len() as u64)]);
+    out
+}
+
+pub async fn serve_metrics(State(state): State<Arc<AppState>>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        render(&state),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let state = AppState::for_tests(crate::Config { logs_dirs: Vec::new(), ..Default::default() });
+        state.metrics.responses[1].fetch_add(3, Ordering::Relaxed);
+        state.metrics.sse_connections.fetch_add(2, Ordering::Relaxed);
+        let out = render(&state);
+        assert!(out.contains("# TYPE irc_log_viewer_requests_total counter\n"), "{out}");
+        assert!(out.contains("irc_log_viewer_requests_total{status=\"2xx\"} 3\n"), "{out}");
+        assert!(out.contains("irc_log_viewer_requests_total{status=\"5xx\"} 0\n"), "{out}");
+        assert!(out.contains("irc_log_viewer_sse_connections 2\n"), "{out}");
+        assert!(out.contains("irc_log_viewer_channels 0\n"), "{out}");
+        assert!(!out.contains("ai_sessions"), "{out}");
+    }
+}
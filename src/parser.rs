@@ -1,9 +1,15 @@
 use std::fmt;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use serde::{Deserialize, Serialize, Serializer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum LogFormat {
     Iso8601,
     Znc,
+    Weechat,
+    /// Supybot/Limnoria `ChannelLogger`: `2025-02-01T12:18:17  <nick> msg`.
+    Supybot,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -21,6 +27,68 @@ impl Time {
     pub fn to_anchor(self) -> String {
         format!("T{:02}{:02}{:02}", self.hour, self.minute, self.second)
     }
+
+    pub fn to_seconds(self) -> u32 {
+        u32::from(self.hour) * 3600 + u32::from(self.minute) * 60 + u32::from(self.second)
+    }
+
+    /// Moves the time by `seconds`, wrapping around midnight.
+    pub fn shifted(self, seconds: i32) -> Time {
+        let t = (i64::from(self.to_seconds()) + i64::from(seconds)).rem_euclid(86400) as u32;
+        Time { hour: (t / 3600) as u8, minute: (t / 60 % 60) as u8, second: (t % 60) as u8 }
+    }
+}
+
+/// Fixed offset from UTC used for "today" and for showing ISO 8601 (UTC)
+/// timestamps; ZNC, WeeChat and Limnoria logs are already in the logger's
+/// local time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct UtcOffset {
+    pub seconds: i32,
+}
+
+impl UtcOffset {
+    pub fn is_utc(&self) -> bool {
+        self.seconds == 0
+    }
+
+    /// Shifts lines of UTC formats into the configured offset.
+    pub fn localize(self, line: &mut LogLine, format: LogFormat) {
+        if format == LogFormat::Iso8601 && !self.is_utc() {
+            line.time = line.time.shifted(self.seconds);
+        }
+    }
+}
+
+impl TryFrom<String> for UtcOffset {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, String> {
+        let err = || format!("unsupported timezone {s:?}: use UTC or a fixed offset like +02:00");
+        if matches!(s.as_str(), "UTC" | "utc" | "Z") {
+            return Ok(Self::default());
+        }
+        let (sign, rest) = match s.as_bytes().first() {
+            Some(b'+') => (1, &s[1..]),
+            Some(b'-') => (-1, &s[1..]),
+            _ => return Err(err()),
+        };
+        let (h, m) = rest.split_once(':').unwrap_or((rest, "0"));
+        let (h, m): (i32, i32) = (h.parse().map_err(|_| err())?, m.parse().map_err(|_| err())?);
+        if h > 14 || m >= 60 {
+            return Err(err());
+        }
+        Ok(Self { seconds: sign * (h * 3600 + m * 60) })
+    }
+}
+
+impl From<UtcOffset> for String {
+    fn from(offset: UtcOffset) -> String {
+        let sign = if offset.seconds < 0 { '-' } else { '+' };
+        let abs = offset.seconds.unsigned_abs();
+        format!("{sign}{:02}:{:02}", abs / 3600, abs / 60 % 60)
+    }
 }
 
 impl fmt::Display for Time {
@@ -29,7 +97,14 @@ impl fmt::Display for Time {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl Serialize for Time {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub enum LineKind {
     Message { nick: String, text: String },
     Action { nick: String, text: String },
@@ -37,11 +112,16 @@ pub enum LineKind {
     Quit { nick: String, userhost: String, reason: String },
     Part { nick: String, userhost: String, reason: String },
     NickChange { old_nick: String, new_nick: String },
+    Kick { nick: String, by: String, reason: String },
+    Topic { nick: String, topic: String },
+    /// A line none of the patterns above understood, shown as-is.
+    Raw { text: String },
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct LogLine {
     pub time: Time,
+    #[serde(flatten)]
     pub kind: LineKind,
 }
 
@@ -49,41 +129,159 @@ impl LogLine {
     pub fn is_event(&self) -> bool {
         !matches!(self.kind, LineKind::Message { .. } | LineKind::Action { .. })
     }
+
+    fn clean_nicks(&mut self) {
+        match &mut self.kind {
+            LineKind::Message { nick, .. }
+            | LineKind::Action { nick, .. }
+            | LineKind::Join { nick, .. }
+            | LineKind::Quit { nick, .. }
+            | LineKind::Part { nick, .. }
+            | LineKind::Topic { nick, .. } => clean_nick(nick),
+            LineKind::NickChange { old_nick, new_nick } => {
+                clean_nick(old_nick);
+                clean_nick(new_nick);
+            }
+            LineKind::Kick { nick, by, .. } => {
+                clean_nick(nick);
+                clean_nick(by);
+            }
+            LineKind::Raw { .. } => {}
+        }
+    }
+
+    /// Nicks the line is about: the speaker, or the subjects of an event.
+    pub fn nicks(&self) -> Vec<&str> {
+        match &self.kind {
+            LineKind::Message { nick, .. }
+            | LineKind::Action { nick, .. }
+            | LineKind::Join { nick, .. }
+            | LineKind::Quit { nick, .. }
+            | LineKind::Part { nick, .. }
+            | LineKind::Topic { nick, .. } => vec![nick],
+            LineKind::NickChange { old_nick, new_nick } => vec![old_nick, new_nick],
+            LineKind::Kick { nick, by, .. } => vec![nick, by],
+            LineKind::Raw { .. } => Vec::new(),
+        }
+    }
+
+    /// The line as ZNC would log it, whatever format it was read from.
+    pub fn to_log_line(&self) -> String {
+        let time = self.time;
+        match &self.kind {
+            LineKind::Message { nick, text } => format!("[{time}] <{nick}> {text}"),
+            LineKind::Action { nick, text } => format!("[{time}] * {nick} {text}"),
+            LineKind::Join { nick, userhost } => format!("[{time}] *** Joins: {nick} ({userhost})"),
+            LineKind::Quit { nick, userhost, reason } => format!("[{time}] *** Quits: {nick} ({userhost}) ({reason})"),
+            LineKind::Part { nick, userhost, reason } => format!("[{time}] *** Parts: {nick} ({userhost}) ({reason})"),
+            LineKind::NickChange { old_nick, new_nick } => {
+                format!("[{time}] *** {old_nick} is now known as {new_nick}")
+            }
+            LineKind::Kick { nick, by, reason } => format!("[{time}] *** {nick} was kicked by {by} ({reason})"),
+            LineKind::Topic { nick, topic } => format!("[{time}] *** {nick} changes topic to '{topic}'"),
+            LineKind::Raw { text } => format!("[{time}] {text}"),
+        }
+    }
 }
 
 pub fn detect_format(first_line: &str) -> LogFormat {
     if first_line.starts_with('[') {
         LogFormat::Znc
+    } else if first_line.as_bytes().get(10) == Some(&b' ') && first_line.as_bytes().get(19) == Some(&b'\t') {
+        // "YYYY-MM-DD HH:MM:SS\t"
+        LogFormat::Weechat
+    } else if first_line.as_bytes().get(10) == Some(&b'T') && first_line.as_bytes().get(19..21) == Some(b"  ") {
+        // "YYYY-MM-DDTHH:MM:SS  ", where ISO 8601 lines have "Z "
+        LogFormat::Supybot
     } else {
         LogFormat::Iso8601
     }
 }
 
+/// The format to parse one file with: `stored`, the channel's, unless the
+/// file's first line is unreadable with it yet parses under the format it
+/// looks like, as in archives moved from one logger to another.
+pub fn file_format(content: &str, stored: LogFormat) -> LogFormat {
+    let Some(first) = content.lines().find(|l| !l.trim().is_empty()) else { return stored };
+    let sniffed = detect_format(first);
+    let parses = |format| parse_line(first, format).is_some_and(|l| !matches!(l.kind, LineKind::Raw { .. }));
+    if sniffed != stored && !parses(stored) && parses(sniffed) { sniffed } else { stored }
+}
+
+/// `HH?MM?SS` from the start of `s`, read as bytes so a corrupt line with
+/// a multibyte character in that span is rejected rather than sliced.
 fn parse_time(s: &str) -> Option<Time> {
-    if s.len() < 8 {
-        return None;
-    }
-    let hour = s[0..2].parse().ok()?;
-    let minute = s[3..5].parse().ok()?;
-    let second = s[6..8].parse().ok()?;
-    Some(Time { hour, minute, second })
+    let b = s.as_bytes();
+    let two = |i: usize| match b.get(i..i + 2)? {
+        [h, l] if h.is_ascii_digit() && l.is_ascii_digit() => Some((h - b'0') * 10 + (l - b'0')),
+        _ => None,
+    };
+    Some(Time { hour: two(0)?, minute: two(3)?, second: two(6)? })
 }
 
+/// Parses one log line. Only blank lines and ZNC playback markers yield
+/// `None`; anything else unrecognized comes back as `LineKind::Raw`.
 pub fn parse_line(line: &str, format: LogFormat) -> Option<LogLine> {
-    match format {
+    // `str::lines` drops the CR of CRLF, but lines split any other way may
+    // still carry it
+    let line = line.trim_end_matches(['\r', '\n']);
+    if line.trim().is_empty() {
+        return None;
+    }
+    let parsed = match format {
         LogFormat::Iso8601 => parse_iso8601(line),
         LogFormat::Znc => parse_znc(line),
+        LogFormat::Weechat => parse_weechat(line),
+        LogFormat::Supybot => parse_supybot(line),
+    };
+    let mut parsed = parsed.or_else(|| parse_raw(line, format))?;
+    parsed.clean_nicks();
+    Some(parsed)
+}
+
+/// Characters that show as nothing: controls, zero-width spaces and joiners,
+/// direction marks, the BOM and the soft hyphen.
+fn is_invisible(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{00ad}' | '\u{034f}' | '\u{180e}' | '\u{200b}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2060}'..='\u{2064}' | '\u{feff}'
+        )
+}
+
+/// Drops invisible characters, so a nick padded with them looks, colors and
+/// links like the plain nick. A nick made only of them is kept as it is.
+fn clean_nick(nick: &mut String) {
+    if nick.chars().any(is_invisible) && !nick.chars().all(is_invisible) {
+        nick.retain(|c| !is_invisible(c));
+    }
+}
+
+/// Keeps the format's timestamp when there is one; a line without any is
+/// shown verbatim at 00:00:00.
+fn parse_raw(line: &str, format: LogFormat) -> Option<LogLine> {
+    let stamped = match format {
+        LogFormat::Znc => line.get(1..9).filter(|_| line.starts_with('[')).zip(line.get(10..)),
+        LogFormat::Iso8601 => line.get(11..19).zip(line.get(20..)),
+        LogFormat::Weechat => line.get(11..19).zip(line.get(19..).filter(|r| r.starts_with('\t'))),
+        LogFormat::Supybot => line.get(11..19).zip(line.get(19..).filter(|r| r.starts_with("  "))),
+    };
+    let (time, text) = match stamped.and_then(|(t, rest)| Some((parse_time(t)?, rest))) {
+        Some((time, rest)) => (time, rest.trim_start()),
+        None => (Time { hour: 0, minute: 0, second: 0 }, line),
+    };
+    if format == LogFormat::Znc && text.strip_prefix("*** ").is_some_and(is_znc_playback_marker) {
+        return None;
     }
+    let text = text.replace('\t', " ");
+    Some(LogLine { time, kind: LineKind::Raw { text } })
 }
 
 fn parse_iso8601(line: &str) -> Option<LogLine> {
     // Format: 2025-02-01T12:18:17Z <nick> msg
     // Time is at bytes [11..19]
-    if line.len() < 21 {
-        return None;
-    }
-    let time = parse_time(&line[11..19])?;
-    let rest = &line[21..]; // skip "YYYY-MM-DDTHH:MM:SSZ "
+    let time = parse_time(line.get(11..19)?)?;
+    let rest = line.get(21..)?; // skip "YYYY-MM-DDTHH:MM:SSZ "
 
     if let Some(rest) = rest.strip_prefix('<') {
         let end = rest.find('>')?;
@@ -103,15 +301,16 @@ fn parse_iso8601(line: &str) -> Option<LogLine> {
 fn parse_znc(line: &str) -> Option<LogLine> {
     // Format: [HH:MM:SS] <nick> msg
     // Time is at bytes [1..9]
-    if line.len() < 11 || !line.starts_with('[') {
+    if !line.starts_with('[') {
         return None;
     }
-    let time = parse_time(&line[1..9])?;
-    let rest = &line[11..]; // skip "[HH:MM:SS] "
+    let time = parse_time(line.get(1..9)?)?;
+    let rest = line.get(11..)?; // skip "[HH:MM:SS] "
 
     if let Some(rest) = rest.strip_prefix('<') {
         let end = rest.find('>')?;
-        let nick = rest[..end].to_string();
+        // Some ZNC setups log their own messages as <*nick>
+        let nick = rest[..end].strip_prefix('*').unwrap_or(&rest[..end]).to_string();
         let text = rest[end + 1..].strip_prefix(' ').unwrap_or(&rest[end + 1..]).to_string();
         Some(LogLine { time, kind: LineKind::Message { nick, text } })
     } else if let Some(event_rest) = rest.strip_prefix("*** ") {
@@ -127,6 +326,9 @@ fn parse_znc(line: &str) -> Option<LogLine> {
 }
 
 fn parse_znc_event(time: Time, rest: &str) -> Option<LogLine> {
+    if is_znc_playback_marker(rest) {
+        return None;
+    }
     if let Some(rest) = rest.strip_prefix("Joins: ") {
         // nick (~user@host)
         let paren = rest.find(" (")?;
@@ -140,6 +342,27 @@ fn parse_znc_event(time: Time, rest: &str) -> Option<LogLine> {
     } else if let Some(rest) = rest.strip_prefix("Parts: ") {
         // nick (~user@host) (reason)
         parse_quit_or_part(time, rest, false)
+    } else if let Some(pos) = rest.find(" changes topic to") {
+        // nick changes topic to: text / nick changes topic to 'text'
+        let nick = rest[..pos].to_string();
+        let topic = &rest[pos + 17..];
+        let topic = match topic.strip_prefix(": ") {
+            Some(t) => t,
+            None => topic.strip_prefix(" '").and_then(|t| t.strip_suffix('\'')).unwrap_or(topic.trim_start()),
+        };
+        Some(LogLine { time, kind: LineKind::Topic { nick, topic: topic.to_string() } })
+    } else if let Some(pos) = rest.find(" was kicked by ") {
+        // nick was kicked by op (reason)
+        let nick = rest[..pos].to_string();
+        let rest = &rest[pos + 15..];
+        let (by, reason) = match rest.split_once(" (") {
+            Some((by, reason)) => (by, reason.strip_suffix(')').unwrap_or(reason)),
+            None => (rest, ""),
+        };
+        Some(LogLine {
+            time,
+            kind: LineKind::Kick { nick, by: by.to_string(), reason: reason.to_string() },
+        })
     } else if let Some(pos) = rest.find(" is now known as ") {
         let old_nick = rest[..pos].to_string();
         let new_nick = rest[pos + 17..].to_string();
@@ -152,6 +375,145 @@ fn parse_znc_event(time: Time, rest: &str) -> Option<LogLine> {
     }
 }
 
+fn parse_weechat(line: &str) -> Option<LogLine> {
+    // Format: 2025-02-01 12:18:17\tprefix\tmessage
+    // Time is at bytes [11..19]
+    let mut fields = line.splitn(3, '\t');
+    let stamp = fields.next()?;
+    let (prefix, text) = (fields.next()?, fields.next()?);
+    if stamp.len() != 19 {
+        return None;
+    }
+    let time = parse_time(stamp.get(11..19)?)?;
+
+    let kind = match prefix.trim() {
+        "-->" => {
+            // nick (~user@host) has joined #chan
+            let (nick, userhost, _) = split_weechat_event(text)?;
+            LineKind::Join { nick, userhost }
+        }
+        "<--" if text.contains(" has kicked ") => {
+            // op has kicked nick (reason)
+            let (by, rest) = text.split_once(" has kicked ")?;
+            let (nick, reason) = match rest.split_once(" (") {
+                Some((nick, reason)) => (nick, reason.strip_suffix(')').unwrap_or(reason)),
+                None => (rest, ""),
+            };
+            LineKind::Kick { nick: nick.to_string(), by: by.to_string(), reason: reason.to_string() }
+        }
+        "<--" => {
+            // nick (~user@host) has left #chan (reason) / has quit (reason)
+            let (nick, userhost, rest) = split_weechat_event(text)?;
+            let reason = rest
+                .find(" (")
+                .map(|p| rest[p + 2..].strip_suffix(')').unwrap_or(&rest[p + 2..]).to_string())
+                .unwrap_or_default();
+            if rest.starts_with("has quit") {
+                LineKind::Quit { nick, userhost, reason }
+            } else {
+                LineKind::Part { nick, userhost, reason }
+            }
+        }
+        "--" if text.contains(" has changed topic for ") => {
+            // nick has changed topic for #chan from "old" to "new"
+            let (nick, rest) = text.split_once(" has changed topic for ")?;
+            let (_, topic) = rest.rsplit_once(" to \"")?;
+            let topic = topic.strip_suffix('"').unwrap_or(topic);
+            LineKind::Topic { nick: nick.to_string(), topic: topic.to_string() }
+        }
+        "--" => {
+            let pos = text.find(" is now known as ")?;
+            LineKind::NickChange { old_nick: text[..pos].to_string(), new_nick: text[pos + 17..].to_string() }
+        }
+        "*" => {
+            let space = text.find(' ')?;
+            LineKind::Action { nick: text[..space].to_string(), text: text[space + 1..].to_string() }
+        }
+        "" => return None,
+        // Other prefixes ("=!=", "-- Notice", server names) have no
+        // counterpart in the other formats
+        nick if nick.starts_with(['-', '<', '=']) => return None,
+        nick => {
+            // Drop the channel mode sigil WeeChat shows before the nick
+            let nick = nick.trim_start_matches(['@', '+', '%', '~', '&']);
+            LineKind::Message { nick: nick.to_string(), text: text.to_string() }
+        }
+    };
+    Some(LogLine { time, kind })
+}
+
+/// Splits `nick (~user@host) rest` from WeeChat join/part/quit lines.
+fn split_weechat_event(text: &str) -> Option<(String, String, &str)> {
+    let paren = text.find(" (")?;
+    let close = paren + text[paren..].find(')')?;
+    let rest = text[close + 1..].trim_start();
+    Some((text[..paren].to_string(), text[paren + 2..close].to_string(), rest))
+}
+
+fn parse_supybot(line: &str) -> Option<LogLine> {
+    // Format: 2025-02-01T12:18:17  <nick> msg
+    // Time is at bytes [11..19], in the bot's local time
+    let time = parse_time(line.get(11..19)?)?;
+    let rest = line.get(19..)?.strip_prefix("  ")?;
+
+    let kind = if let Some(rest) = rest.strip_prefix('<') {
+        let end = rest.find('>')?;
+        let text = rest[end + 1..].strip_prefix(' ').unwrap_or(&rest[end + 1..]);
+        LineKind::Message { nick: rest[..end].to_string(), text: text.to_string() }
+    } else if let Some(rest) = rest.strip_prefix("*** ") {
+        parse_supybot_event(rest)?
+    } else if let Some(rest) = rest.strip_prefix("* ") {
+        let space = rest.find(' ')?;
+        LineKind::Action { nick: rest[..space].to_string(), text: rest[space + 1..].to_string() }
+    } else {
+        // Notices (`-nick- text`) have no counterpart in the other formats
+        return None;
+    };
+    Some(LogLine { time, kind })
+}
+
+fn parse_supybot_event(rest: &str) -> Option<LineKind> {
+    if let Some(pos) = rest.find(" is now known as ") {
+        return Some(LineKind::NickChange { old_nick: rest[..pos].to_string(), new_nick: rest[pos + 17..].to_string() });
+    }
+    if let Some((nick, topic)) = rest.split_once(" changes topic to ") {
+        let topic = topic.strip_prefix('"').and_then(|t| t.strip_suffix('"')).unwrap_or(topic);
+        return Some(LineKind::Topic { nick: nick.to_string(), topic: topic.to_string() });
+    }
+    if let Some((nick, rest)) = rest.split_once(" was kicked by ") {
+        let (by, reason) = match rest.split_once(" (") {
+            Some((by, reason)) => (by, reason.strip_suffix(')').unwrap_or(reason)),
+            None => (rest, ""),
+        };
+        return Some(LineKind::Kick { nick: nick.to_string(), by: by.to_string(), reason: reason.to_string() });
+    }
+    // nick <nick!user@host> has joined #chan; older versions omit the <prefix>
+    let (nick, rest) = rest.split_once(' ')?;
+    let (userhost, rest) = match rest.strip_prefix('<').and_then(|r| r.split_once("> ")) {
+        Some((prefix, rest)) => (prefix.split_once('!').map_or(prefix, |(_, uh)| uh), rest),
+        None => ("", rest),
+    };
+    let reason = || {
+        rest.split_once(" (").map(|(_, r)| r.strip_suffix(')').unwrap_or(r).to_string()).unwrap_or_default()
+    };
+    let (nick, userhost) = (nick.to_string(), userhost.to_string());
+    if rest.starts_with("has joined ") {
+        Some(LineKind::Join { nick, userhost })
+    } else if rest.starts_with("has left ") {
+        Some(LineKind::Part { nick, userhost, reason: reason() })
+    } else if rest.starts_with("has quit") {
+        Some(LineKind::Quit { nick, userhost, reason: reason() })
+    } else {
+        None
+    }
+}
+
+/// `*** Buffer Playback...` / `*** Playback Complete.` bracket replayed
+/// buffers and carry no content of their own.
+fn is_znc_playback_marker(rest: &str) -> bool {
+    rest.starts_with("Buffer Playback") || rest.starts_with("Playback Complete")
+}
+
 fn parse_quit_or_part(time: Time, rest: &str, is_quit: bool) -> Option<LogLine> {
     // nick (~user@host) (reason)
     let paren = rest.find(" (")?;
@@ -182,6 +544,111 @@ mod tests {
     fn test_detect_format() {
         assert_eq!(detect_format("[12:34:56] <nick> hi"), LogFormat::Znc);
         assert_eq!(detect_format("2025-02-01T12:18:17Z <nick> hi"), LogFormat::Iso8601);
+        assert_eq!(detect_format("2025-02-01 12:18:17\tnick\thi"), LogFormat::Weechat);
+    }
+
+    #[test]
+    fn test_detect_supybot() {
+        assert_eq!(detect_format("2025-02-01T12:18:17  <nick> hi"), LogFormat::Supybot);
+        assert_eq!(detect_format("2025-02-01T12:18:17  *** nick <nick!~u@host> has joined #chan"), LogFormat::Supybot);
+        assert_eq!(detect_format("2025-02-01T12:18:17Z <nick> hi"), LogFormat::Iso8601);
+    }
+
+    /// Lines as written by Limnoria's ChannelLogger.
+    #[test]
+    fn test_supybot_lines() {
+        let parse = |line: &str| parse_line(line, LogFormat::Supybot).unwrap();
+        let parsed = parse("2025-02-01T12:18:17  <py1hon> is the fsck done yet?");
+        assert_eq!(parsed.time, Time { hour: 12, minute: 18, second: 17 });
+        assert_eq!(parsed.kind, LineKind::Message { nick: "py1hon".into(), text: "is the fsck done yet?".into() });
+        assert_eq!(parse("2025-02-01T12:18:18  * py1hon waves").kind, LineKind::Action {
+            nick: "py1hon".into(),
+            text: "waves".into(),
+        });
+        assert_eq!(parse("2025-02-01T12:18:19  *** dza <dza!~dza@host.oftc.net> has joined #bcachefs").kind, LineKind::Join {
+            nick: "dza".into(),
+            userhost: "~dza@host.oftc.net".into(),
+        });
+        assert_eq!(parse("2025-02-01T12:18:19  *** dza has joined #bcachefs").kind, LineKind::Join {
+            nick: "dza".into(),
+            userhost: "".into(),
+        });
+        assert_eq!(parse("2025-02-01T12:18:20  *** dza <dza!~dza@host.oftc.net> has left #bcachefs (see you)").kind, LineKind::Part {
+            nick: "dza".into(),
+            userhost: "~dza@host.oftc.net".into(),
+            reason: "see you".into(),
+        });
+        assert_eq!(parse("2025-02-01T12:18:21  *** dza <dza!~dza@host.oftc.net> has quit IRC (Quit: Leaving)").kind, LineKind::Quit {
+            nick: "dza".into(),
+            userhost: "~dza@host.oftc.net".into(),
+            reason: "Quit: Leaving".into(),
+        });
+        assert_eq!(parse("2025-02-01T12:18:22  *** dza is now known as dza_").kind, LineKind::NickChange {
+            old_nick: "dza".into(),
+            new_nick: "dza_".into(),
+        });
+        assert_eq!(parse("2025-02-01T12:18:23  *** py1hon changes topic to \"bcachefs: 1.20 released\"").kind, LineKind::Topic {
+            nick: "py1hon".into(),
+            topic: "bcachefs: 1.20 released".into(),
+        });
+        assert_eq!(parse("2025-02-01T12:18:24  *** spammer was kicked by py1hon (spam)").kind, LineKind::Kick {
+            nick: "spammer".into(),
+            by: "py1hon".into(),
+            reason: "spam".into(),
+        });
+        let parsed = parse("2025-02-01T12:18:25  *** ChanServ sets mode: +o py1hon");
+        assert_eq!(parsed.time, Time { hour: 12, minute: 18, second: 25 });
+        assert_eq!(parsed.kind, LineKind::Raw { text: "*** ChanServ sets mode: +o py1hon".into() });
+        assert_eq!(parse("2025-02-01T12:18:26  -NickServ- This nickname is registered.").kind, LineKind::Raw {
+            text: "-NickServ- This nickname is registered.".into(),
+        });
+    }
+
+    #[test]
+    fn test_weechat_message_and_action() {
+        let parsed = parse_line("2025-02-01 12:18:17\t@py1hon\thello\tworld", LogFormat::Weechat).unwrap();
+        assert_eq!(parsed.time, Time { hour: 12, minute: 18, second: 17 });
+        assert_eq!(parsed.kind, LineKind::Message {
+            nick: "py1hon".into(),
+            text: "hello\tworld".into(),
+        });
+        let parsed = parse_line("2025-02-01 12:18:18\t *\tpy1hon waves", LogFormat::Weechat).unwrap();
+        assert_eq!(parsed.kind, LineKind::Action {
+            nick: "py1hon".into(),
+            text: "waves".into(),
+        });
+    }
+
+    #[test]
+    fn test_weechat_join_part_quit() {
+        let line = "2025-02-01 12:00:00\t-->\tdza (~dza@host.oftc.net) has joined #bcachefs";
+        assert_eq!(parse_line(line, LogFormat::Weechat).unwrap().kind, LineKind::Join {
+            nick: "dza".into(),
+            userhost: "~dza@host.oftc.net".into(),
+        });
+        let line = "2025-02-01 12:00:01\t<--\tdza (~dza@host.oftc.net) has quit (Remote host closed the connection)";
+        assert_eq!(parse_line(line, LogFormat::Weechat).unwrap().kind, LineKind::Quit {
+            nick: "dza".into(),
+            userhost: "~dza@host.oftc.net".into(),
+            reason: "Remote host closed the connection".into(),
+        });
+        let line = "2025-02-01 12:00:02\t<--\tdza (~dza@host.oftc.net) has left #bcachefs";
+        assert_eq!(parse_line(line, LogFormat::Weechat).unwrap().kind, LineKind::Part {
+            nick: "dza".into(),
+            userhost: "~dza@host.oftc.net".into(),
+            reason: String::new(),
+        });
+        let line = "2025-02-01 12:00:04\t<--\tticpu has kicked spammer (spam)";
+        assert_eq!(parse_line(line, LogFormat::Weechat).unwrap().kind, LineKind::Kick {
+            nick: "spammer".into(),
+            by: "ticpu".into(),
+            reason: "spam".into(),
+        });
+        let line = "2025-02-01 12:00:03\t--\ttherobin is now known as Guest2176";
+        assert_eq!(parse_line(line, LogFormat::Weechat).unwrap().kind, LineKind::NickChange {
+            old_nick: "therobin".into(),
+            new_nick: "Guest2176".into(),
+        });
     }
 
     #[test]
@@ -258,6 +725,45 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_znc_kick() {
+        let line = "[04:43:20] *** spammer was kicked by ticpu (spam (again))";
+        let parsed = parse_line(line, LogFormat::Znc).unwrap();
+        assert!(parsed.is_event());
+        assert_eq!(parsed.kind, LineKind::Kick {
+            nick: "spammer".into(),
+            by: "ticpu".into(),
+            reason: "spam (again)".into(),
+        });
+        let parsed = parse_line("[04:43:21] *** spammer was kicked by ticpu", LogFormat::Znc).unwrap();
+        assert_eq!(parsed.kind, LineKind::Kick {
+            nick: "spammer".into(),
+            by: "ticpu".into(),
+            reason: String::new(),
+        });
+    }
+
+    #[test]
+    fn test_znc_topic() {
+        let line = "[04:43:20] *** ticpu changes topic to: bcachefs: 1.2 (released) | logs: https://example.org/";
+        let parsed = parse_line(line, LogFormat::Znc).unwrap();
+        assert!(parsed.is_event());
+        assert_eq!(parsed.kind, LineKind::Topic {
+            nick: "ticpu".into(),
+            topic: "bcachefs: 1.2 (released) | logs: https://example.org/".into(),
+        });
+        let line = "[04:43:21] *** ticpu changes topic to 'a: b (c)'";
+        assert_eq!(parse_line(line, LogFormat::Znc).unwrap().kind, LineKind::Topic {
+            nick: "ticpu".into(),
+            topic: "a: b (c)".into(),
+        });
+        let line = "2025-02-01 12:00:00\t--\tticpu has changed topic for #chan from \"old\" to \"new: (topic)\"";
+        assert_eq!(parse_line(line, LogFormat::Weechat).unwrap().kind, LineKind::Topic {
+            nick: "ticpu".into(),
+            topic: "new: (topic)".into(),
+        });
+    }
+
     #[test]
     fn test_znc_action() {
         let line = "[12:34:56] * nick does something";
@@ -268,6 +774,75 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_znc_self_message() {
+        let parsed = parse_line("[12:34:56] <*ticpu> my own line", LogFormat::Znc).unwrap();
+        assert_eq!(parsed.kind, LineKind::Message {
+            nick: "ticpu".into(),
+            text: "my own line".into(),
+        });
+    }
+
+    #[test]
+    fn test_znc_playback_markers_ignored() {
+        assert_eq!(parse_line("[12:00:00] *** Buffer Playback...", LogFormat::Znc), None);
+        assert_eq!(parse_line("[12:00:01] *** Playback Complete.", LogFormat::Znc), None);
+    }
+
+    #[test]
+    fn test_to_log_line_round_trips() {
+        for line in [
+            "[12:00:00] <alice> hi there",
+            "[12:00:01] * bob waves",
+            "[12:00:02] *** Joins: carol (~c@host)",
+            "[12:00:03] *** Quits: carol (~c@host) (Ping timeout)",
+            "[12:00:04] *** Parts: dave (~d@host) ()",
+            "[12:00:05] *** bob is now known as bobby",
+            "[12:00:06] *** eve was kicked by op (spam)",
+            "[12:00:07] *** op changes topic to 'new topic'",
+            "[12:00:08] -NickServ- This nickname is registered.",
+        ] {
+            assert_eq!(parse_line(line, LogFormat::Znc).unwrap().to_log_line(), line);
+        }
+        let weechat = parse_line("2025-02-01 12:18:17\talice\thello", LogFormat::Weechat).unwrap();
+        assert_eq!(weechat.to_log_line(), "[12:18:17] <alice> hello");
+    }
+
+    #[test]
+    fn test_unparsed_lines_kept_raw() {
+        let parsed = parse_line("[12:00:00] -NickServ- This nickname is registered.", LogFormat::Znc).unwrap();
+        assert!(parsed.is_event());
+        assert_eq!(parsed.time, Time { hour: 12, minute: 0, second: 0 });
+        assert_eq!(parsed.kind, LineKind::Raw { text: "-NickServ- This nickname is registered.".into() });
+
+        let parsed = parse_line("2025-02-01T12:18:17Z -!- netsplit", LogFormat::Iso8601).unwrap();
+        assert_eq!(parsed.kind, LineKind::Raw { text: "-!- netsplit".into() });
+
+        let parsed = parse_line("garbage without a timestamp", LogFormat::Znc).unwrap();
+        assert_eq!(parsed.time, Time { hour: 0, minute: 0, second: 0 });
+        assert_eq!(parsed.kind, LineKind::Raw { text: "garbage without a timestamp".into() });
+
+        assert_eq!(parse_line("  ", LogFormat::Znc), None);
+    }
+
+    #[test]
+    fn test_utc_offset() {
+        let parse = |s: &str| UtcOffset::try_from(s.to_string());
+        assert_eq!(parse("+02:00"), Ok(UtcOffset { seconds: 7200 }));
+        assert_eq!(parse("-05:30"), Ok(UtcOffset { seconds: -19800 }));
+        assert_eq!(parse("UTC"), Ok(UtcOffset::default()));
+        assert!(parse("Europe/Paris").is_err());
+        assert_eq!(String::from(UtcOffset { seconds: -19800 }), "-05:30");
+
+        let t = Time { hour: 23, minute: 30, second: 0 };
+        assert_eq!(t.shifted(7200), Time { hour: 1, minute: 30, second: 0 });
+        assert_eq!(Time { hour: 0, minute: 10, second: 5 }.shifted(-3600), Time { hour: 23, minute: 10, second: 5 });
+
+        let mut line = parse_line("2025-02-01T23:30:00Z <a> b", LogFormat::Iso8601).unwrap();
+        parse("+02:00").unwrap().localize(&mut line, LogFormat::Iso8601);
+        assert_eq!(line.time, Time { hour: 1, minute: 30, second: 0 });
+    }
+
     #[test]
     fn test_time_display() {
         let t = Time { hour: 5, minute: 6, second: 7 };
@@ -290,6 +865,85 @@ mod tests {
         assert!(join.is_event());
     }
 
+    /// Timestamps straddling a multibyte character used to panic on slicing.
+    #[test]
+    fn test_multibyte_in_timestamp() {
+        let lines = [
+            "[12:0é:00] <a> hi",
+            "[12:00:0é] <a> hi",
+            "[é",
+            "2025-02-01T12:1é:17Z <a> hi",
+            "2025-02-01é12:18:17Z <a> hi",
+            "2025-02-01 12:18:é\t<a>\thi",
+            "2025-02-01 12:1é\t<a>\thi",
+        ];
+        for line in lines {
+            for format in [LogFormat::Znc, LogFormat::Iso8601, LogFormat::Weechat, LogFormat::Supybot] {
+                let parsed = parse_line(line, format).unwrap();
+                assert!(matches!(parsed.kind, LineKind::Raw { .. }), "{line:?} as {format:?}: {parsed:?}");
+            }
+        }
+        assert_eq!(parse_time("+1:02:03"), None);
+        assert_eq!(parse_time("01:02:03"), Some(Time { hour: 1, minute: 2, second: 3 }));
+    }
+
+    #[test]
+    fn test_file_format() {
+        let iso = "\n2025-02-01T12:18:17Z <a> hi\n";
+        assert_eq!(file_format(iso, LogFormat::Znc), LogFormat::Iso8601);
+        assert_eq!(file_format(iso, LogFormat::Iso8601), LogFormat::Iso8601);
+        // Unrecognizable either way: the channel's format stands
+        assert_eq!(file_format("garbage\n", LogFormat::Znc), LogFormat::Znc);
+        assert_eq!(file_format("", LogFormat::Weechat), LogFormat::Weechat);
+    }
+
+    #[test]
+    fn test_crlf_line_endings() {
+        let parsed = parse_line("[05:06:37] <LordKitsuna> hello world\r", LogFormat::Znc).unwrap();
+        assert_eq!(parsed.kind, LineKind::Message {
+            nick: "LordKitsuna".into(),
+            text: "hello world".into(),
+        });
+        let parsed = parse_line("[00:07:04] *** Quits: a (~a@host) (Ping timeout)\r\n", LogFormat::Znc).unwrap();
+        assert_eq!(parsed.kind, LineKind::Quit {
+            nick: "a".into(),
+            userhost: "~a@host".into(),
+            reason: "Ping timeout".into(),
+        });
+        let lf = "2025-02-01 12:18:17\tpy1hon\thi\n2025-02-01 12:18:18\t-->\tb (b@host) has joined #chan\n";
+        let crlf = lf.replace('\n', "\r\n");
+        let parse_all =
+            |content: &str| content.split('\n').filter_map(|l| parse_line(l, LogFormat::Weechat)).collect::<Vec<_>>();
+        assert_eq!(parse_all(lf), parse_all(&crlf));
+        assert_eq!(parse_line("\r", LogFormat::Znc), None);
+    }
+
+    #[test]
+    fn test_invisible_chars_stripped_from_nicks() {
+        let line = "[05:06:37] <\u{200b}Lord\u{200d}Kitsuna\u{feff}> hi\u{200b}there";
+        let parsed = parse_line(line, LogFormat::Znc).unwrap();
+        assert_eq!(parsed.kind, LineKind::Message {
+            nick: "LordKitsuna".into(),
+            text: "hi\u{200b}there".into(),
+        });
+        let parsed = parse_line("[05:06:38] *** a\u{2060} is now known as b\u{7}", LogFormat::Znc).unwrap();
+        assert_eq!(parsed.kind, LineKind::NickChange { old_nick: "a".into(), new_nick: "b".into() });
+        let parsed = parse_line("[05:06:39] <\u{200b}> only invisible", LogFormat::Znc).unwrap();
+        assert_eq!(parsed.kind, LineKind::Message { nick: "\u{200b}".into(), text: "only invisible".into() });
+    }
+
+    #[test]
+    fn test_serialize_line() {
+        let line = parse_line("[04:43:20] *** therobin is now known as Guest2176", LogFormat::Znc).unwrap();
+        let json = serde_json::to_value(&line).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "time": "04:43:20",
+            "kind": "nick_change",
+            "old_nick": "therobin",
+            "new_nick": "Guest2176",
+        }));
+    }
+
     #[test]
     fn test_iso8601_url_message() {
         let line = "2026-02-10T00:06:39Z <py1hon> is anyone here a cachyos user? https://discuss.cachyos.org/t/bcachefs-nvme-drive-fstab-no-longer-exists-wont-boot/22666";
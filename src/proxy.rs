@@ -0,0 +1,282 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::Config;
+
+/// An IP network in CIDR notation (`10.0.0.0/8`, `::1/128`, or a bare address).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // Dual-stack sockets report IPv4 peers as ::ffff:a.b.c.d
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix)).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let (addr_s, prefix_s) = match s.split_once('/') {
+            Some((a, p)) => (a, Some(p)),
+            None => (s.as_str(), None),
+        };
+        let addr: IpAddr = addr_s.parse().map_err(|_| format!("invalid address in {s:?}"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix_s {
+            Some(p) => p.parse().ok().filter(|p| *p <= max).ok_or_else(|| format!("invalid prefix in {s:?}"))?,
+            None => max,
+        };
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(c: Cidr) -> String {
+        c.to_string()
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// A `trusted_proxies` entry: a network, or `unix` for any process that
+/// connects over the Unix socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TrustedProxy {
+    Unix,
+    Net(Cidr),
+}
+
+impl TryFrom<String> for TrustedProxy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s == "unix" { Ok(TrustedProxy::Unix) } else { Cidr::try_from(s).map(TrustedProxy::Net) }
+    }
+}
+
+impl From<TrustedProxy> for String {
+    fn from(p: TrustedProxy) -> String {
+        match p {
+            TrustedProxy::Unix => "unix".into(),
+            TrustedProxy::Net(c) => c.to_string(),
+        }
+    }
+}
+
+/// Peer address given to requests on the Unix socket, which only local
+/// processes such as the reverse proxy can reach. No TCP peer has port 0.
+pub const UNIX_PEER: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+
+fn is_trusted(config: &Config, ip: IpAddr) -> bool {
+    config.trusted_proxies.iter().any(|p| matches!(p, TrustedProxy::Net(c) if c.contains(ip)))
+}
+
+/// Whether `peer` may describe the original request in `X-Forwarded-*`
+/// headers: one of `trusted_proxies`, where `unix` stands for the Unix
+/// socket. With none configured, no peer is.
+fn is_trusted_proxy(config: &Config, peer: Option<SocketAddr>) -> bool {
+    match peer {
+        Some(UNIX_PEER) => config.trusted_proxies.contains(&TrustedProxy::Unix),
+        Some(peer) => is_trusted(config, peer.ip()),
+        None => false,
+    }
+}
+
+/// The first value of a forwarded header: the one the client-facing proxy
+/// added.
+fn first_forwarded<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    Some(headers.get(name)?.to_str().ok()?.split(',').next()?.trim())
+}
+
+/// `X-Forwarded-Proto` from a trusted proxy, if it is http or https.
+fn forwarded_scheme<'a>(headers: &'a HeaderMap, peer: Option<SocketAddr>, config: &Config) -> Option<&'a str> {
+    if !is_trusted_proxy(config, peer) {
+        return None;
+    }
+    first_forwarded(headers, "x-forwarded-proto").filter(|s| matches!(*s, "http" | "https"))
+}
+
+/// Client address for a request. `X-Forwarded-For` is only honored when the
+/// socket peer is a trusted proxy; the header is then walked right to left,
+/// skipping further `trusted_proxies` hops, so a client can't spoof its
+/// address by prepending entries.
+pub fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>, config: &Config) -> Option<IpAddr> {
+    let trusted = is_trusted_proxy(config, peer);
+    let peer = peer?.ip();
+    if !trusted {
+        return Some(peer);
+    }
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect();
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else { break };
+        client = ip;
+        if !is_trusted(config, ip) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+/// `scheme://host` the client used to reach the viewer, from
+/// `X-Forwarded-Proto` and `X-Forwarded-Host`. Only with
+/// `forwarded_headers` set and a trusted proxy as the socket peer; the
+/// headers are otherwise client-controlled.
+pub fn forwarded_origin(headers: &HeaderMap, peer: Option<SocketAddr>, config: &Config) -> Option<String> {
+    if !config.forwarded_headers {
+        return None;
+    }
+    let scheme = forwarded_scheme(headers, peer, config)?;
+    let host = first_forwarded(headers, "x-forwarded-host").or_else(|| first_forwarded(headers, "host"))?;
+    is_host(host).then(|| format!("{scheme}://{host}"))
+}
+
+/// A host name, IP address or `[IPv6]`, with an optional port: nothing
+/// that could break out of a URL or an HTML attribute.
+fn is_host(host: &str) -> bool {
+    !host.is_empty() && host.chars().all(|c| c.is_ascii_alphanumeric() || "-.:[]".contains(c))
+}
+
+/// Absolute URL of `path`, which already includes `base_path`. Without a
+/// forwarded origin the `Host` header is taken (`localhost` if it is not a
+/// valid host), over https when a trusted proxy says so in
+/// `X-Forwarded-Proto` and over http otherwise.
+pub fn external_url(headers: &HeaderMap, peer: Option<SocketAddr>, config: &Config, path: &str) -> String {
+    let origin = forwarded_origin(headers, peer, config).unwrap_or_else(|| {
+        let scheme = forwarded_scheme(headers, peer, config).unwrap_or("http");
+        let host = headers.get("host").and_then(|v| v.to_str().ok()).filter(|h| is_host(h)).unwrap_or("localhost");
+        format!("{scheme}://{host}")
+    });
+    format!("{origin}{path}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        Cidr::try_from(s.to_string()).unwrap()
+    }
+
+    fn net(s: &str) -> TrustedProxy {
+        TrustedProxy::Net(cidr(s))
+    }
+
+    #[test]
+    fn test_cidr_contains() {
+        assert!(cidr("10.0.0.0/8").contains("10.1.2.3".parse().unwrap()));
+        assert!(!cidr("10.0.0.0/8").contains("11.0.0.1".parse().unwrap()));
+        assert!(cidr("127.0.0.1").contains("127.0.0.1".parse().unwrap()));
+        assert!(cidr("0.0.0.0/0").contains("8.8.8.8".parse().unwrap()));
+        assert!(cidr("fd00::/8").contains("fd12::1".parse().unwrap()));
+        assert!(cidr("127.0.0.1").contains("::ffff:127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_cidr_invalid() {
+        assert!(Cidr::try_from("10.0.0.0/33".to_string()).is_err());
+        assert!(Cidr::try_from("nope".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_client_ip_untrusted_peer_ignores_header() {
+        let config = Config { trusted_proxies: vec![net("127.0.0.1")], ..Config::default() };
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.2.3.4".parse().unwrap());
+        let peer = "192.0.2.1:5000".parse().ok();
+        assert_eq!(client_ip(&headers, peer, &config), "192.0.2.1".parse().ok());
+    }
+
+    #[test]
+    fn test_client_ip_trusted_peer_uses_header() {
+        let config = Config { trusted_proxies: vec![net("127.0.0.1"), net("10.0.0.0/8")], ..Config::default() };
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "6.6.6.6, 1.2.3.4, 10.0.0.5".parse().unwrap());
+        let peer = "127.0.0.1:5000".parse().ok();
+        assert_eq!(client_ip(&headers, peer, &config), "1.2.3.4".parse().ok());
+    }
+
+    #[test]
+    fn test_unix_peer_is_trusted_when_listed() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "1.2.3.4".parse().unwrap());
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        headers.insert("host", "logs.example.org".parse().unwrap());
+        // Any local process may connect to the socket, and 127.0.0.1 does not cover it
+        let config = Config { trusted_proxies: vec![net("127.0.0.1")], ..Config::default() };
+        assert_eq!(client_ip(&headers, Some(UNIX_PEER), &config), "127.0.0.1".parse().ok());
+        assert_eq!(external_url(&headers, Some(UNIX_PEER), &config, "/x"), "http://logs.example.org/x");
+
+        let config = Config { trusted_proxies: vec![TrustedProxy::Unix, net("10.0.0.0/8")], ..Config::default() };
+        assert_eq!(client_ip(&headers, Some(UNIX_PEER), &config), "1.2.3.4".parse().ok());
+        assert_eq!(external_url(&headers, Some(UNIX_PEER), &config, "/x"), "https://logs.example.org/x");
+        let tcp: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        assert_eq!(client_ip(&headers, Some(tcp), &config), "127.0.0.1".parse().ok());
+    }
+
+    #[test]
+    fn test_forwarded_origin() {
+        let mut headers = HeaderMap::new();
+        headers.insert("host", "127.0.0.1:8080".parse().unwrap());
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        headers.insert("x-forwarded-host", "logs.example.org, inner".parse().unwrap());
+        let proxy: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let other: SocketAddr = "192.0.2.1:5000".parse().unwrap();
+
+        // No proxy is trusted until listed, not even a local one
+        assert_eq!(external_url(&headers, Some(proxy), &Config::default(), "/irc/x"), "http://127.0.0.1:8080/irc/x");
+
+        // Without the flag, only the scheme is taken
+        let off = Config { trusted_proxies: vec![net("127.0.0.1")], ..Config::default() };
+        assert_eq!(forwarded_origin(&headers, Some(proxy), &off), None);
+        assert_eq!(external_url(&headers, Some(proxy), &off, "/irc/x"), "https://127.0.0.1:8080/irc/x");
+        assert_eq!(external_url(&headers, Some(other), &off, "/irc/x"), "http://127.0.0.1:8080/irc/x");
+        assert_eq!(external_url(&headers, None, &off, "/irc/x"), "http://127.0.0.1:8080/irc/x");
+
+        let on = Config { forwarded_headers: true, ..off };
+        assert_eq!(forwarded_origin(&headers, Some(proxy), &on).as_deref(), Some("https://logs.example.org"));
+        assert_eq!(forwarded_origin(&headers, Some(other), &on), None);
+        assert_eq!(forwarded_origin(&headers, None, &on), None);
+        let gated = Config { trusted_proxies: vec![net("10.0.0.0/8")], ..on };
+        let proxy: SocketAddr = "10.0.0.5:5000".parse().unwrap();
+        assert_eq!(external_url(&headers, Some(proxy), &gated, "/irc/x"), "https://logs.example.org/irc/x");
+        assert_eq!(forwarded_origin(&headers, Some(other), &gated), None);
+        let local: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        assert_eq!(forwarded_origin(&headers, Some(local), &gated), None);
+
+        headers.insert("x-forwarded-host", "evil.org/\"><".parse().unwrap());
+        assert_eq!(forwarded_origin(&headers, Some(proxy), &gated), None);
+        headers.insert("x-forwarded-host", "logs.example.org".parse().unwrap());
+        headers.insert("x-forwarded-proto", "javascript".parse().unwrap());
+        assert_eq!(forwarded_origin(&headers, Some(proxy), &gated), None);
+    }
+}
@@ -1,32 +1,475 @@
-use crate::Channel;
-use crate::parser::{LogLine, parse_line};
-use crate::server::{channel_dates, resolve_log_path, read_log_file};
+use std::ops::{ControlFlow, Range};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{Channel, ChannelNode};
+use crate::cache::{LogCache, read_log};
+use crate::index::SearchIndex;
+use crate::parser::{LogLine, UtcOffset, file_format, parse_line};
+use crate::server::{channel_dates, day_files};
+
+/// Consecutive search results from the same day, shown under one header.
+pub struct ResultGroup {
+    pub date: String,
+    pub lines: Vec<LogLine>,
+}
+
+/// How a query is matched against raw log lines: a literal substring or a
+/// regex, case-insensitive unless asked otherwise, then narrowed by a
+/// [`Filter`] on the parsed line.
+pub struct Matcher {
+    regex: Regex,
+    /// Trigrams every matching line contains, for the search index. Empty
+    /// for regex queries, which the index cannot narrow.
+    needle: Vec<u32>,
+    filter: Filter,
+}
+
+/// What a channel search keeps besides lines matching the query.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter {
+    /// Keeps lines involving a nick containing this, ignoring case.
+    pub nick: Option<String>,
+    /// Drops joins, quits and other events, also from context lines.
+    pub messages_only: bool,
+    /// First and last day searched, as `YYYY-MM-DD`; both inclusive.
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+impl Filter {
+    pub fn keeps_date(&self, date: &str) -> bool {
+        self.from.as_deref().is_none_or(|from| date >= from) && self.to.as_deref().is_none_or(|to| date <= to)
+    }
+
+    pub fn keeps(&self, line: &LogLine) -> bool {
+        if self.messages_only && line.is_event() {
+            return false;
+        }
+        let Some(wanted) = &self.nick else { return true };
+        let wanted = wanted.to_lowercase();
+        line.nicks().iter().any(|nick| nick.to_lowercase().contains(&wanted))
+    }
+}
+
+impl Matcher {
+    pub fn new(query: &str, regex: bool, case: bool) -> Result<Self, regex::Error> {
+        let pattern = if regex { query.to_string() } else { regex::escape(query) };
+        let mut needle = if regex { Vec::new() } else { crate::index::trigrams(query) };
+        if !case {
+            // The index only folds ASCII case, and case-insensitive k and s
+            // also match the Kelvin and long s signs
+            needle.retain(|t| t.to_be_bytes()[1..].iter().all(|b| b.is_ascii() && !matches!(b, b'k' | b's')));
+        }
+        let regex = RegexBuilder::new(&pattern).case_insensitive(!case).build()?;
+        Ok(Matcher { regex, needle, filter: Filter::default() })
+    }
+
+    pub fn with_filter(self, filter: Filter) -> Self {
+        Self { filter, ..self }
+    }
+
+    /// Whether the raw line matches the query; the filter is not applied.
+    pub fn is_match(&self, line: &str) -> bool {
+        self.regex.is_match(line)
+    }
+
+    pub fn index_needle(&self) -> &[u32] {
+        &self.needle
+    }
+
+    /// Non-empty, non-overlapping match ranges in `text`, for highlighting.
+    pub fn find_ranges(&self, text: &str) -> Vec<Range<usize>> {
+        self.regex.find_iter(text).map(|m| m.range()).filter(|r| !r.is_empty()).collect()
+    }
+}
+
+/// Where a search gets its files: the trigram index rules files out and the
+/// log cache serves the rest. Either may be absent.
+#[derive(Clone, Copy, Default)]
+pub struct Sources<'a> {
+    pub index: Option<&'a SearchIndex>,
+    pub cache: Option<&'a LogCache>,
+}
 
 pub fn search_channel(
     channel: &Channel,
-    query: &str,
+    matcher: &Matcher,
     limit: usize,
+    tz: UtcOffset,
+    sources: Sources,
 ) -> Vec<(String, LogLine)> {
-    let query_lower = query.to_lowercase();
     let mut results = Vec::new();
+    scan_channel(channel, matcher, limit, tz, sources, |date, line| {
+        results.push((date.to_string(), line));
+        ControlFlow::Continue(())
+    });
+    results
+}
+
+/// Matches per date over the history the filter keeps, oldest first, for
+/// seeing which days a topic came up most. Reads at most `max_files` log
+/// files, newest first; the flag tells whether older ones were left out.
+pub fn count_by_date(
+    channel: &Channel,
+    matcher: &Matcher,
+    max_files: usize,
+    tz: UtcOffset,
+    sources: Sources,
+) -> (Vec<(String, usize)>, bool) {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    let mut files_left = max_files;
+    let (_, truncated) = scan_bounded(channel, matcher, usize::MAX, tz, sources, &mut files_left, |date, _| {
+        match counts.last_mut() {
+            Some((last, n)) if last == date => *n += 1,
+            _ => counts.push((date.to_string(), 1)),
+        }
+        ControlFlow::Continue(())
+    });
+    counts.reverse();
+    (counts, truncated)
+}
+
+/// Feeds matches to `on_match`, newest date first, until `limit` matches
+/// were found, the history is exhausted, or `on_match` breaks. Files the
+/// index rules out are not read.
+pub fn scan_channel(
+    channel: &Channel,
+    matcher: &Matcher,
+    limit: usize,
+    tz: UtcOffset,
+    sources: Sources,
+    on_match: impl FnMut(&str, LogLine) -> ControlFlow<()>,
+) {
+    let mut unbounded = usize::MAX;
+    scan_bounded(channel, matcher, limit, tz, sources, &mut unbounded, on_match);
+}
+
+/// `scan_channel` that also stops once `files_left` log files were read,
+/// counting them down. Returns the number of matches, and whether a file
+/// was left unread for want of `files_left`.
+fn scan_bounded(
+    channel: &Channel,
+    matcher: &Matcher,
+    limit: usize,
+    tz: UtcOffset,
+    sources: Sources,
+    files_left: &mut usize,
+    mut on_match: impl FnMut(&str, LogLine) -> ControlFlow<()>,
+) -> (usize, bool) {
+    let mut found = 0;
+    if limit == 0 {
+        return (found, false);
+    }
     let mut dates = channel_dates(channel);
+    dates.retain(|date| matcher.filter.keeps_date(date));
     dates.reverse();
 
     for date in dates {
-        let Some((path, format)) = resolve_log_path(channel, &date) else { continue };
-        let Ok(content) = read_log_file(&path) else { continue };
-
-        for raw_line in content.lines() {
-            if raw_line.to_lowercase().contains(&query_lower) {
-                if let Some(parsed) = parse_line(raw_line, format) {
-                    results.push((date.clone(), parsed));
-                    if results.len() >= limit {
-                        return results;
+        for (path, format) in day_files(channel, &date) {
+            if sources.index.is_some_and(|i| !i.may_contain(&path, matcher.index_needle())) {
+                continue;
+            }
+            if *files_left == 0 {
+                return (found, true);
+            }
+            *files_left -= 1;
+            let Ok(content) = read_log(sources.cache, &path) else { continue };
+            let format = file_format(&content, format);
+
+            for raw_line in content.lines() {
+                if matcher.is_match(raw_line) {
+                    if let Some(mut parsed) = parse_line(raw_line, format).filter(|l| matcher.filter.keeps(l)) {
+                        tz.localize(&mut parsed, format);
+                        found += 1;
+                        if on_match(&date, parsed).is_break() || found >= limit {
+                            return (found, false);
+                        }
                     }
                 }
             }
         }
     }
+    (found, false)
+}
 
-    results
+/// Matches of one day with `context` lines before and after each; matches
+/// whose context touches or overlaps share a block, like `grep -C`.
+pub struct ContextBlock {
+    pub date: String,
+    /// Each line, and whether it is a match rather than context.
+    pub lines: Vec<(LogLine, bool)>,
+}
+
+/// `search_channel` returning matches with the lines around them, newest
+/// date first, stopping after the block holding the `limit`th match.
+pub fn search_context(
+    channel: &Channel,
+    matcher: &Matcher,
+    limit: usize,
+    context: usize,
+    tz: UtcOffset,
+    sources: Sources,
+) -> Vec<ContextBlock> {
+    let mut blocks = Vec::new();
+    let mut found = 0;
+    let mut dates = channel_dates(channel);
+    dates.retain(|date| matcher.filter.keeps_date(date));
+    dates.reverse();
+
+    for date in dates {
+        for (path, format) in day_files(channel, &date) {
+            if found >= limit {
+                return blocks;
+            }
+            if sources.index.is_some_and(|i| !i.may_contain(&path, matcher.index_needle())) {
+                continue;
+            }
+            let Ok(content) = read_log(sources.cache, &path) else { continue };
+            let format = file_format(&content, format);
+            let mut lines: Vec<(LogLine, bool)> = Vec::new();
+            for raw_line in content.lines() {
+                let Some(mut parsed) = parse_line(raw_line, format) else { continue };
+                if matcher.filter.messages_only && parsed.is_event() {
+                    continue;
+                }
+                let matched = matcher.is_match(raw_line) && matcher.filter.keeps(&parsed);
+                tz.localize(&mut parsed, format);
+                lines.push((parsed, matched));
+            }
+
+            let mut block: Option<Range<usize>> = None;
+            for i in (0..lines.len()).filter(|&i| lines[i].1) {
+                if found >= limit {
+                    break;
+                }
+                found += 1;
+                let window = i.saturating_sub(context)..(i + context + 1).min(lines.len());
+                block = match block {
+                    Some(open) if window.start <= open.end => Some(open.start..window.end),
+                    Some(open) => {
+                        blocks.push(ContextBlock { date: date.clone(), lines: lines[open].to_vec() });
+                        Some(window)
+                    }
+                    None => Some(window),
+                };
+            }
+            if let Some(open) = block {
+                blocks.push(ContextBlock { date: date.clone(), lines: lines[open].to_vec() });
+            }
+        }
+    }
+    blocks
+}
+
+/// Matches of one channel in a tree-wide search.
+pub struct ChannelResults {
+    pub channel: Channel,
+    pub results: Vec<(String, LogLine)>,
+}
+
+/// Searches every channel under `node` in sidebar order, newest date first
+/// within each. `limit` matches and `max_files` files are shared by the
+/// whole search; the flag is set when either ran out, so results may be
+/// missing. Merged channels are skipped since their sources are searched.
+pub fn search_all(
+    node: &ChannelNode,
+    matcher: &Matcher,
+    limit: usize,
+    max_files: usize,
+    tz: UtcOffset,
+    sources: Sources,
+) -> (Vec<ChannelResults>, bool) {
+    let mut channels = Vec::new();
+    collect_channels(node, &mut channels);
+    let (mut remaining, mut files_left) = (limit, max_files);
+    let mut out = Vec::new();
+    let mut skipped = false;
+    for channel in channels {
+        if remaining == 0 || skipped {
+            break;
+        }
+        let mut results = Vec::new();
+        let on_match = |date: &str, line| {
+            results.push((date.to_string(), line));
+            ControlFlow::Continue(())
+        };
+        let (found, out_of_files) = scan_bounded(channel, matcher, remaining, tz, sources, &mut files_left, on_match);
+        remaining -= found;
+        skipped = out_of_files;
+        if !results.is_empty() {
+            out.push(ChannelResults { channel: channel.clone(), results });
+        }
+    }
+    (out, remaining == 0 || skipped)
+}
+
+fn collect_channels<'a>(node: &'a ChannelNode, out: &mut Vec<&'a Channel>) {
+    if let Some(channel) = node.channel.as_ref().filter(|c| c.sources.is_empty()) {
+        out.push(channel);
+    }
+    for child in node.children.values() {
+        collect_channels(child, out);
+    }
+}
+
+/// Groups results whose time is within `window_secs` of the previous result
+/// on the same date. A window of 0 yields one group per result.
+pub fn group_results(results: Vec<(String, LogLine)>, window_secs: u32) -> Vec<ResultGroup> {
+    let mut groups: Vec<ResultGroup> = Vec::new();
+    for (date, line) in results {
+        if let Some(group) = groups.last_mut() {
+            let prev = group.lines.last().map(|l| l.time.to_seconds()).unwrap_or(0);
+            if window_secs > 0 && group.date == date && line.time.to_seconds().abs_diff(prev) <= window_secs {
+                group.lines.push(line);
+                continue;
+            }
+        }
+        groups.push(ResultGroup { date, lines: vec![line] });
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::LogTree;
+    use crate::parser::{LineKind, Time};
+
+    fn result(date: &str, hour: u8, minute: u8) -> (String, LogLine) {
+        let line = LogLine {
+            time: Time { hour, minute, second: 0 },
+            kind: LineKind::Message { nick: "a".into(), text: "b".into() },
+        };
+        (date.to_string(), line)
+    }
+
+    #[test]
+    fn test_group_results_by_window() {
+        let results = vec![
+            result("2025-01-02", 10, 0),
+            result("2025-01-02", 10, 3),
+            result("2025-01-02", 11, 0),
+            result("2025-01-01", 11, 1),
+        ];
+        let groups = group_results(results, 5 * 60);
+        let sizes: Vec<_> = groups.iter().map(|g| g.lines.len()).collect();
+        assert_eq!(sizes, vec![2, 1, 1]);
+        assert_eq!(groups[2].date, "2025-01-01");
+    }
+
+    #[test]
+    fn test_matcher_modes() {
+        let line = "[10:00:00] <Alice> see Bug#42";
+        assert!(Matcher::new("bug#42", false, false).unwrap().is_match(line));
+        assert!(!Matcher::new("bug#42", false, true).unwrap().is_match(line));
+        assert!(Matcher::new(r"<alice> .*#\d+$", true, false).unwrap().is_match(line));
+        assert!(!Matcher::new(r"<alice>", true, true).unwrap().is_match(line));
+        assert!(Matcher::new("(unclosed", true, false).is_err());
+    }
+
+    #[test]
+    fn test_search_spans_all_channel_dirs() {
+        let root = LogTree::new("search-dirs");
+        root.write("archive/OFTC/#chan/2025-01-01.log", "[10:00:00] <a> hello\n");
+        root.write("live/OFTC/#chan/2025-01-02.log", "[10:00:00] <a> hello\n");
+        let (archive, live) = (root.join("archive"), root.join("live"));
+        let config = crate::Config { logs_dirs: vec![archive.into(), live.into()], ..Default::default() };
+        let state = crate::AppState::for_tests(config);
+        let channel = state.channels.children["OFTC"].children["#chan"].channel.as_ref().unwrap();
+        assert_eq!(channel.dirs.len(), 2);
+
+        let matcher = Matcher::new("hello", false, false).unwrap();
+        let results = search_channel(channel, &matcher, 10, UtcOffset::default(), Sources::default());
+        let dates: Vec<_> = results.iter().map(|(d, _)| d.as_str()).collect();
+        assert_eq!(dates, vec!["2025-01-02", "2025-01-01"]);
+    }
+
+    #[test]
+    fn test_count_by_date() {
+        let root = LogTree::new("search-counts");
+        // Day n of March has n mentions, except the 4th with none
+        for day in 1..=5 {
+            let mentions = if day == 4 { 0 } else { day };
+            let lines: String = (0..10).map(|i| {
+                let text = if i < mentions { "the RELEASE is out" } else { "chatter" };
+                format!("[10:{i:02}:00] <n{i}> {text}\n")
+            }).collect();
+            root.write(format!("OFTC/#chan/2025-03-0{day}.log"), lines);
+        }
+        let state = root.state(Default::default());
+        let channel = state.channels.children["OFTC"].children["#chan"].channel.as_ref().unwrap();
+        let count_in = |matcher: &Matcher, max_files| {
+            count_by_date(channel, matcher, max_files, UtcOffset::default(), Sources::default())
+        };
+        let count = |matcher: &Matcher| count_in(matcher, 5).0;
+        let day = |d: &str, n| (format!("2025-03-{d}"), n);
+
+        let release = Matcher::new("release", false, false).unwrap();
+        assert_eq!(count(&release), [day("01", 1), day("02", 2), day("03", 3), day("05", 5)]);
+        assert!(!count_in(&release, 5).1);
+        // Out of files after the newest two, the day without mentions included
+        assert_eq!(count_in(&release, 2), (vec![day("05", 5)], true));
+        let days = Filter { from: Some("2025-03-02".into()), to: Some("2025-03-03".into()), ..Default::default() };
+        let bounded = release.with_filter(days);
+        assert_eq!(count(&bounded), [day("02", 2), day("03", 3)]);
+        let none = Matcher::new("nothing", false, false).unwrap();
+        assert!(count(&none).is_empty());
+    }
+
+    #[test]
+    fn test_filter_and_context() {
+        let root = LogTree::new("search-filter");
+        root.write("OFTC/#chan/2025-01-01.log", "[09:00:00] <Alice> bug in the old day\n");
+        let day = [
+            "[10:00:00] <Alice> first bug",
+            "[10:01:00] <bob> one",
+            "[10:02:00] *** Joins: carol (c@host)",
+            "[10:03:00] <bob> two",
+            "[10:04:00] <Bob> bug again",
+            "[10:05:00] <carol> three",
+            "[10:06:00] <dave> four",
+            "[10:07:00] <erin> five",
+            "[10:08:00] <alice> last bug",
+        ];
+        root.write("OFTC/#chan/2025-01-02.log", day.join("\n") + "\n");
+        let state = root.state(Default::default());
+        let channel = state.channels.children["OFTC"].children["#chan"].channel.as_ref().unwrap();
+        let search = |filter: Filter| {
+            let matcher = Matcher::new("bug", false, false).unwrap().with_filter(filter);
+            let results = search_channel(channel, &matcher, 10, UtcOffset::default(), Sources::default());
+            results.into_iter().map(|(date, line)| format!("{date} {}", line.time.to_hms())).collect::<Vec<_>>()
+        };
+
+        let alice = Filter { nick: Some("ALI".into()), ..Default::default() };
+        assert_eq!(search(alice.clone()), ["2025-01-02 10:00:00", "2025-01-02 10:08:00", "2025-01-01 09:00:00"]);
+        let recent = Filter { from: Some("2025-01-02".into()), ..alice };
+        assert_eq!(search(recent), ["2025-01-02 10:00:00", "2025-01-02 10:08:00"]);
+        let old = Filter { to: Some("2025-01-01".into()), ..Default::default() };
+        assert_eq!(search(old), ["2025-01-01 09:00:00"]);
+
+        // One line of context: the first two matches are a line apart
+        // until the join between them is dropped, then share a block
+        let context = |filter: Filter| {
+            let matcher = Matcher::new("bug", false, false).unwrap().with_filter(filter);
+            search_context(channel, &matcher, 10, 1, UtcOffset::default(), Sources::default())
+                .iter()
+                .map(|b| {
+                    let mark = |matched: bool| if matched { "*" } else { "" };
+                    let lines: Vec<String> = b.lines.iter().map(|(l, m)| format!("{}{}", l.time.minute, mark(*m))).collect();
+                    format!("{} {}", b.date, lines.join(" "))
+                })
+                .collect::<Vec<_>>()
+        };
+        let spaced = Filter { from: Some("2025-01-02".into()), ..Default::default() };
+        assert_eq!(context(spaced.clone()), ["2025-01-02 0* 1", "2025-01-02 3 4* 5", "2025-01-02 7 8*"]);
+        let quiet = Filter { messages_only: true, ..spaced };
+        assert_eq!(context(quiet), ["2025-01-02 0* 1 3 4* 5", "2025-01-02 7 8*"]);
+    }
+
+    #[test]
+    fn test_group_results_flat() {
+        let results = vec![result("2025-01-02", 10, 0), result("2025-01-02", 10, 0)];
+        assert_eq!(group_results(results, 0).len(), 2);
+    }
 }
@@ -1,21 +1,33 @@
 use std::io::{self, BufReader, Read};
+use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::Arc;
 
 use axum::Router;
-use axum::extract::{Query, State};
-use axum::http::{StatusCode, Uri, header};
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Query, Request, State};
+use axum::http::{HeaderMap, StatusCode, Uri, header};
 use axum::response::sse::{Event, KeepAlive, Sse};
-use axum::response::{IntoResponse, Redirect, Response};
-use axum::routing::get;
-use serde::Deserialize;
-use tokio::sync::broadcast;
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::middleware::{self, Next};
+use axum::routing::{get, post};
+use axum::Extension;
+use serde::{Deserialize, Serialize};
 use tokio_stream::StreamExt;
-use tokio_stream::wrappers::BroadcastStream;
+use tower_http::compression::CompressionLayer;
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::classify::{ServerErrorsAsFailures, SharedClassifier};
+use tower_http::trace::{DefaultOnResponse, MakeSpan, TraceLayer};
+use tower_http::LatencyUnit;
+use tracing::Instrument;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, ReceiverStream};
 
 use crate::AppState;
-use crate::parser::{LogFormat, parse_line};
-use crate::search::search_channel;
+use crate::cache::{LogCache, read_log};
+use crate::parser::{LineKind, LogFormat, LogLine, Time, UtcOffset, detect_format, file_format, parse_line};
+use crate::search::{Filter, Matcher, count_by_date, group_results, search_all, search_channel, search_context};
+use crate::tail::ChannelFeed;
 use crate::templates;
 
 static CSS: &str = include_str!("../static/style.css");
@@ -24,11 +36,148 @@ fn cache_control(value: &str) -> (header::HeaderName, &str) {
     (header::CACHE_CONTROL, value)
 }
 
+/// Themed 404 for HTML routes; API, raw and stream routes keep plain text.
+fn not_found(state: &AppState, message: &str) -> Response {
+    let page = templates::not_found_page(&state.config.title, &state.channels, &state.config.base_path, message);
+    (StatusCode::NOT_FOUND, [cache_control("private, no-cache")], page).into_response()
+}
+
+/// The full application, nested under `base_path` when one is set. The
+/// health and metrics probes answer both at the root and under `base_path`,
+/// without credentials or a connection permit.
+pub fn app(state: Arc<AppState>) -> Router {
+    let limit = middleware::from_fn_with_state(Arc::clone(&state), limit_concurrency);
+    // Outside the limit, so unauthenticated requests never take a permit
+    let auth = middleware::from_fn_with_state(Arc::clone(&state), crate::auth::require_auth);
+    let track = middleware::from_fn_with_state(Arc::clone(&state), crate::metrics::track);
+    let (router, probes) = if state.config.base_path.is_empty() {
+        (router(), probes())
+    } else {
+        let bp = &state.config.base_path;
+        (Router::new().nest(bp, router()), probes().merge(Router::new().nest(bp, probes())))
+    };
+    let trace = trace(&state);
+    router.layer(limit).layer(auth).layer(track).merge(probes).layer(compression()).layer(trace).with_state(state)
+}
+
+/// One `request` span per request, with the method, path and client
+/// address (see [`crate::proxy::client_ip`]), closed by an event giving the
+/// status and latency. Shown at the `info` level.
+fn trace(state: &Arc<AppState>) -> TraceLayer<SharedClassifier<ServerErrorsAsFailures>, impl MakeSpan<Body> + Clone> {
+    let state = Arc::clone(state);
+    let span = move |req: &Request<Body>| {
+        let (method, path) = (req.method(), req.uri().path());
+        let span = tracing::info_span!("request", %method, %path, client = tracing::field::Empty);
+        let peer = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|c| c.0);
+        if let Some(client) = crate::proxy::client_ip(req.headers(), peer, &state.config) {
+            span.record("client", tracing::field::display(client));
+        }
+        span
+    };
+    TraceLayer::new_for_http()
+        .make_span_with(span)
+        .on_response(DefaultOnResponse::new().level(tracing::Level::INFO).latency_unit(LatencyUnit::Millis))
+}
+
+/// Binds a Unix socket at `path` that its owner and group may connect to,
+/// replacing a socket an earlier run left behind. Anything else already at
+/// `path` is an error.
+#[cfg(unix)]
+pub fn bind_unix(path: &Path) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+    Ok(listener)
+}
+
+/// `app` served on the Unix socket, whose connections have no peer address:
+/// requests get [`crate::proxy::UNIX_PEER`], whose forwarded headers are
+/// trusted with `unix` in `trusted_proxies`.
+#[cfg(unix)]
+pub fn unix_app(app: Router) -> Router {
+    app.layer(Extension(ConnectInfo(crate::proxy::UNIX_PEER)))
+}
+
+fn probes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/metrics", get(crate::metrics::serve_metrics))
+}
+
+/// gzip or brotli, as the client's `Accept-Encoding` allows. SSE streams are
+/// left alone since the encoder would buffer events, and tiny bodies are not
+/// worth it.
+fn compression() -> CompressionLayer<impl Predicate> {
+    let predicate = SizeAbove::new(256)
+        .and(NotForContentType::SSE)
+        .and(NotForContentType::IMAGES)
+        .and(NotForContentType::GRPC);
+    CompressionLayer::new().compress_when(predicate)
+}
+
+/// Paths of the SSE streams: a channel's live tail (`/latest`), AI sessions
+/// (`/ask/stream`, `/ask/live`) and streamed search (`/search/stream`).
+/// They stay open, so they count against `max_sse_connections` rather than
+/// `max_connections` and are tracked as open streams in the metrics;
+/// compression already leaves them alone by content type. The channel has
+/// to exist, as in `wildcard`, so a channel named `latest` is not a stream.
+pub fn is_stream_path(path: &str, state: &AppState) -> bool {
+    let bp = state.config.base_path.as_str();
+    let Some(path) = path.strip_prefix(bp).filter(|p| p.is_empty() || p.starts_with('/')) else {
+        return false;
+    };
+    let path = percent_decode(path.trim_matches('/'));
+    let segments: Vec<&str> = path.split('/').collect();
+    match segments.as_slice() {
+        [channel @ .., "latest"] | [channel @ .., "ask", "stream" | "live"] | [channel @ .., "search", "stream"] => {
+            find_channel(&state.channels, channel).is_some()
+        }
+        _ => false,
+    }
+}
+
+/// Rejects requests with 503 once `max_connections` are in flight, or
+/// once `max_sse_connections` streams are open for a stream request.
+async fn limit_concurrency(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let stream = is_stream_path(request.uri().path(), &state);
+    let limit = if stream { &state.stream_limit } else { &state.request_limit };
+    let Some(limit) = limit else {
+        return next.run(request).await;
+    };
+    let Ok(permit) = Arc::clone(limit).try_acquire_owned() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, "1")],
+            "server busy, try again later",
+        )
+            .into_response();
+    };
+    let response = next.run(request).await;
+    if !stream {
+        return response;
+    }
+    // A stream holds its permit until the client goes away or it ends
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _ = &permit;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
 pub fn router() -> Router<Arc<AppState>> {
     Router::new()
         .route("/", get(index))
         .route("/static/style.css", get(serve_css))
+        .route("/about.json", get(about))
+        .route("/latest.json", get(serve_latest_json))
+        .route("/search", get(serve_search_all))
+        .route("/sidebar/{*path}", get(serve_sidebar))
         .route("/ask/output/{filename}", get(serve_ask_output))
+        .route("/debug/parse", post(debug_parse))
         .fallback(get(wildcard))
 }
 
@@ -51,21 +200,187 @@ async fn serve_css() -> impl IntoResponse {
     )
 }
 
+pub fn count_channels(node: &crate::ChannelNode) -> usize {
+    node.channel.is_some() as usize + node.children.values().map(count_channels).sum::<usize>()
+}
+
+/// Each channel's newest log date by path, for the sidebar's freshness
+/// badges. Kept out of the pages themselves, which may be cached for good.
+async fn serve_latest_json(State(state): State<Arc<AppState>>) -> Response {
+    fn collect(node: &crate::ChannelNode, out: &mut serde_json::Map<String, serde_json::Value>) {
+        if let Some(channel) = &node.channel {
+            if let Some(date) = channel.latest_date() {
+                out.insert(channel.path_segments.join("/"), date.into());
+            }
+        }
+        for child in node.children.values() {
+            collect(child, out);
+        }
+    }
+    let mut dates = serde_json::Map::new();
+    collect(&state.channels, &mut dates);
+    (
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            cache_control("no-cache"),
+        ],
+        serde_json::Value::Object(dates).to_string(),
+    )
+        .into_response()
+}
+
+/// Children of a collapsed sidebar node, as the list the sidebar script
+/// inserts under it.
+async fn serve_sidebar(
+    State(state): State<Arc<AppState>>,
+    axum::extract::Path(path): axum::extract::Path<String>,
+) -> Response {
+    let node = path.split('/').try_fold(&state.channels, |node, seg| node.children.get(seg));
+    let Some(node) = node.filter(|n| !n.children.is_empty()) else {
+        return (StatusCode::NOT_FOUND, "no such node").into_response();
+    };
+    let tree = templates::render_channel_tree(node, &path, &state.config.base_path);
+    ([cache_control("private, no-cache")], Html(tree.0)).into_response()
+}
+
+/// Deployment summary for monitoring; nothing secret goes in here.
+async fn about(State(state): State<Arc<AppState>>) -> Response {
+    let config = &state.config;
+    let json = serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "title": config.title,
+        "base_path": config.base_path,
+        "ai_enabled": config.ai.is_some(),
+        "channels": count_channels(&state.channels),
+    });
+    (
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            cache_control("no-store"),
+        ],
+        json.to_string(),
+    )
+        .into_response()
+}
+
+/// Liveness check for load balancers: answering at all means the server is
+/// up, and the counts show what discovery found.
+async fn healthz(State(state): State<Arc<AppState>>) -> Response {
+    let json = serde_json::json!({
+        "status": "ok",
+        "channels": count_channels(&state.channels),
+        "logs_dirs": state.logs_dirs.len(),
+        "ai_enabled": state.config.ai.is_some(),
+    });
+    (
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            cache_control("no-store"),
+        ],
+        json.to_string(),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct DebugParseQuery {
+    format: Option<LogFormat>,
+}
+
+#[derive(Serialize)]
+struct DebugParsedLine<'a> {
+    input: &'a str,
+    parsed: Option<LogLine>,
+}
+
+/// Parses a pasted log snippet (request body) and returns what the parser
+/// made of each line. Only available with `debug: true`.
+async fn debug_parse(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DebugParseQuery>,
+    body: String,
+) -> Response {
+    if !state.config.debug {
+        return (StatusCode::NOT_FOUND, "not found").into_response();
+    }
+    let format = query
+        .format
+        .unwrap_or_else(|| detect_format(body.lines().next().unwrap_or("")));
+    let lines: Vec<_> = body
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| DebugParsedLine { input: l, parsed: parse_line(l, format) })
+        .collect();
+    let json = serde_json::json!({"format": format, "lines": lines});
+    (
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            cache_control("no-store"),
+        ],
+        json.to_string(),
+    )
+        .into_response()
+}
+
 #[derive(Deserialize)]
 struct SearchQuery {
     q: Option<String>,
+    group: Option<u32>,
+    view: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    pos: Option<u64>,
+    wrap: Option<String>,
+    regex: Option<String>,
+    case: Option<String>,
+    limit: Option<usize>,
+    events: Option<String>,
+    scope: Option<String>,
+    format: Option<String>,
+    append_to: Option<String>,
+    /// Context lines shown around each search match; a string since the
+    /// search form sends the field even when it is left empty.
+    #[serde(rename = "C")]
+    context: Option<String>,
+    nick: Option<String>,
+    msg: Option<String>,
+    counts: Option<String>,
+}
+
+impl SearchQuery {
+    fn flag(value: &Option<String>) -> bool {
+        value.as_deref() == Some("1")
+    }
+
+    /// The channel search filter from `nick`, `msg`, `from` and `to`; the
+    /// search form sends the fields left empty too.
+    fn filter(&self) -> Filter {
+        let given =
+            |value: &Option<String>| value.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(str::to_owned);
+        Filter {
+            nick: given(&self.nick),
+            messages_only: Self::flag(&self.msg),
+            from: given(&self.from).filter(|d| looks_like_date(d)),
+            to: given(&self.to).filter(|d| looks_like_date(d)),
+        }
+    }
 }
 
+/// Most context lines a channel search shows on each side of a match.
+const MAX_SEARCH_CONTEXT: usize = 20;
+
 async fn wildcard(
     State(state): State<Arc<AppState>>,
     uri: Uri,
+    headers: HeaderMap,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
     Query(search): Query<SearchQuery>,
 ) -> Response {
-    let path = percent_decode(uri.path().trim_start_matches('/'));
+    let path = percent_decode(uri.path().trim_matches('/'));
     let segments: Vec<&str> = path.split('/').collect();
 
     if segments.is_empty() {
-        return (StatusCode::NOT_FOUND, "not found").into_response();
+        return not_found(&state, "No such page.");
     }
 
     let last = *segments.last().unwrap();
@@ -78,18 +393,46 @@ async fn wildcard(
         let channel_segments = &segments[..segments.len() - 2];
         if let Some(channel) = find_channel(&state.channels, channel_segments).cloned() {
             let query = search.q.unwrap_or_default();
-            return serve_ask_stream(state, channel, &query).await.into_response();
+            let peer = connect_info.map(|Extension(ConnectInfo(addr))| addr);
+            let client = crate::proxy::client_ip(&headers, peer, &state.config);
+            let origin = crate::proxy::forwarded_origin(&headers, peer, &state.config).unwrap_or_default();
+            let ask = AskRequest { query: &query, append_to: search.append_to.as_deref(), client, origin };
+            return serve_ask_stream(state, channel, ask).await.into_response();
+        }
+    }
+
+    // "ask/live": finished reports from other viewers of the channel
+    if segments.len() >= 3
+        && segments[segments.len() - 2] == "ask"
+        && segments[segments.len() - 1] == "live"
+    {
+        let channel_segments = &segments[..segments.len() - 2];
+        if let Some(channel) = find_channel(&state.channels, channel_segments).cloned() {
+            return serve_ask_live(state, &channel).await;
+        }
+    }
+
+    // "search/stream": matches as they are found
+    if segments.len() >= 3
+        && segments[segments.len() - 2] == "search"
+        && segments[segments.len() - 1] == "stream"
+    {
+        let channel_segments = &segments[..segments.len() - 2];
+        if let Some(channel) = find_channel(&state.channels, channel_segments).cloned() {
+            let (regex, case) = (SearchQuery::flag(&search.regex), SearchQuery::flag(&search.case));
+            let filter = search.filter();
+            return serve_search_stream(state, channel, search.q.unwrap_or_default(), regex, case, filter);
         }
     }
 
     // Try to find channel with all segments vs. all-but-last
-    if last == "today" || last == "latest" || last == "search" || last == "ask" || looks_like_date(last) || is_date_raw(last, segments.len()) {
+    if last == "today" || last == "random" || last == "latest" || last == "search" || last == "ask" || last == "export.ndjson" || last == "export" || last == "dates.json" || last == "nicks.json" || last == "feed.xml" || last == "calendar" || last == "stats" || looks_like_date(last) || is_date_suffix(last, segments.len()) {
         let channel_segments = &segments[..segments.len() - 1];
-        // Handle YYYY-MM-DD/raw
-        let (action, channel_segments) = if last == "raw" && segments.len() >= 2 {
+        // Handle YYYY-MM-DD/raw and YYYY-MM-DD/json
+        let (action, channel_segments) = if is_date_suffix(last, segments.len()) {
             let date_seg = segments[segments.len() - 2];
             if looks_like_date(date_seg) {
-                ("raw", &segments[..segments.len() - 2])
+                (last, &segments[..segments.len() - 2])
             } else {
                 (last, channel_segments)
             }
@@ -102,23 +445,56 @@ async fn wildcard(
                 "today" => {
                     let bp = &state.config.base_path;
                     let encoded = channel.path_segments.join("/").replace('#', "%23");
-                    let date = latest_date(&channel);
+                    let date = latest_date(&channel, state.config.timezone);
                     Redirect::temporary(&format!("{bp}/{encoded}/{date}")).into_response()
                 }
-                "latest" => serve_sse(state, &channel).await.into_response(),
-                "search" => {
-                    let query = search.q.unwrap_or_default();
-                    serve_search(&state, &channel, &query).into_response()
+                "random" => serve_random_date(&state, &channel),
+                "latest" => {
+                    let last_id = headers.get("last-event-id").and_then(|v| v.to_str().ok()?.parse().ok());
+                    serve_sse(state, &channel, search.pos, last_id).await.into_response()
                 }
+                "search" => serve_search(state, &channel, &search).await,
                 "ask" => serve_ask_page(&state, &channel).into_response(),
+                "dates.json" => serve_dates_json(&channel),
+                "nicks.json" => serve_nicks_json(state, channel).await,
+                "feed.xml" => {
+                    let peer = connect_info.map(|Extension(ConnectInfo(addr))| addr);
+                    serve_feed(&state, &channel, &headers, peer, SearchQuery::flag(&search.events))
+                }
+                "calendar" => serve_calendar(state, channel).await,
+                "stats" => serve_stats(state, channel, search.from, search.to).await,
+                "export" => {
+                    let zst = search.format.as_deref() == Some("zst");
+                    serve_export_raw(channel, search.from.as_deref(), search.to.as_deref(), zst)
+                }
+                "export.ndjson" => {
+                    let tz = state.config.timezone;
+                    serve_export_ndjson(channel, search.from.as_deref(), search.to.as_deref(), tz)
+                }
+                "json" if looks_like_date(segments[segments.len() - 2]) => {
+                    serve_day_json(&state, &channel, segments[segments.len() - 2])
+                }
+                "fragment" if looks_like_date(segments[segments.len() - 2]) => {
+                    let view = event_view(&channel, search.view.as_deref());
+                    serve_day_fragment(&state, &channel, segments[segments.len() - 2], view)
+                }
                 "raw" => {
                     let date = segments[segments.len() - 2];
-                    serve_raw(&channel, date).await.into_response()
+                    let nowrap = search.wrap.as_deref() == Some("0");
+                    serve_raw(&state, &channel, date, nowrap, &headers).await.into_response()
                 }
                 date if looks_like_date(date) => {
-                    serve_log_page(&state, &channel, date).into_response()
+                    if date == today_date(state.config.timezone) {
+                        expect_subscriber(&state, &channel).await;
+                    }
+                    let view = event_view(&channel, search.view.as_deref());
+                    let from = search.from.as_deref().and_then(parse_hms);
+                    let limit = search.limit.or(state.config.page_lines).filter(|&n| n > 0);
+                    let peer = connect_info.map(|Extension(ConnectInfo(addr))| addr);
+                    let origin = crate::proxy::external_url(&headers, peer, &state.config, "");
+                    serve_log_page(&state, &channel, date, view, from, limit, &origin).into_response()
                 }
-                _ => (StatusCode::NOT_FOUND, "not found").into_response(),
+                _ => not_found(&state, "No such page."),
             };
         }
     }
@@ -127,18 +503,26 @@ async fn wildcard(
     if let Some(channel) = find_channel(&state.channels, &segments) {
         let bp = &state.config.base_path;
         let encoded = channel.path_segments.join("/").replace('#', "%23");
-        let date = latest_date(channel);
+        let date = latest_date(channel, state.config.timezone);
         return Redirect::temporary(&format!("{bp}/{encoded}/{date}")).into_response();
     }
 
-    (StatusCode::NOT_FOUND, "not found").into_response()
+    not_found(&state, "No such channel or page.")
+}
+
+/// `?view=`, or the channel's default when absent.
+fn event_view(channel: &crate::Channel, param: Option<&str>) -> templates::EventView {
+    match param {
+        None if channel.overrides.hide_events_default => templates::EventView::Messages,
+        param => templates::EventView::from_param(param),
+    }
 }
 
-fn is_date_raw(last: &str, len: usize) -> bool {
-    last == "raw" && len >= 2
+fn is_date_suffix(last: &str, len: usize) -> bool {
+    (last == "raw" || last == "json" || last == "fragment") && len >= 2
 }
 
-fn looks_like_date(s: &str) -> bool {
+pub fn looks_like_date(s: &str) -> bool {
     let b = s.as_bytes();
     b.len() == 10
         && b[4] == b'-'
@@ -148,13 +532,30 @@ fn looks_like_date(s: &str) -> bool {
         && b[8..10].iter().all(u8::is_ascii_digit)
 }
 
-fn today_date() -> String {
+fn today_date(tz: UtcOffset) -> String {
+    date_days_ago(0, tz)
+}
+
+fn date_days_ago(n: u64, tz: UtcOffset) -> String {
     let now = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
         .as_secs();
-    let days = now / 86400;
-    epoch_days_to_date(days)
+    local_date(now, tz, n)
+}
+
+/// Date `days_ago` days before the local day containing `epoch_secs`.
+fn local_date(epoch_secs: u64, tz: UtcOffset, days_ago: u64) -> String {
+    let local = epoch_secs.saturating_add_signed(i64::from(tz.seconds));
+    epoch_days_to_date((local / 86400).saturating_sub(days_ago))
+}
+
+/// Logs older than this many days are assumed to never change again.
+const IMMUTABLE_AFTER_DAYS: u64 = 7;
+const IMMUTABLE_CACHE: &str = "public, max-age=31536000, immutable";
+
+fn is_immutable_date(date: &str, tz: UtcOffset) -> bool {
+    date < date_days_ago(IMMUTABLE_AFTER_DAYS, tz).as_str()
 }
 
 fn epoch_days_to_date(days: u64) -> String {
@@ -172,25 +573,51 @@ fn epoch_days_to_date(days: u64) -> String {
     format!("{y:04}-{m:02}-{d:02}")
 }
 
+/// Days since the epoch of a `YYYY-MM-DD` date, the inverse of
+/// `epoch_days_to_date`.
+fn date_to_epoch_days(date: &str) -> Option<i64> {
+    let y: i64 = date.get(..4)?.parse().ok()?;
+    let m: i64 = date.get(5..7)?.parse().ok()?;
+    let d: i64 = date.get(8..10)?.parse().ok()?;
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Some(era * 146097 + doe - 719468)
+}
+
+/// The date in sorted `dates` closest to `date`, the earlier one on a tie.
+fn nearest_date<'a>(dates: &'a [String], date: &str) -> Option<&'a str> {
+    let i = dates.partition_point(|d| d.as_str() < date);
+    let before = i.checked_sub(1).map(|i| dates[i].as_str());
+    let after = dates.get(i).map(String::as_str);
+    let distance = |d: &str| Some((date_to_epoch_days(d)? - date_to_epoch_days(date)?).abs());
+    match (before, after) {
+        (Some(b), Some(a)) if distance(a) < distance(b) => Some(a),
+        (Some(b), _) => Some(b),
+        (None, a) => a,
+    }
+}
+
 fn percent_decode(s: &str) -> String {
-    let mut result = String::with_capacity(s.len());
     let bytes = s.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
     let mut i = 0;
     while i < bytes.len() {
-        if bytes[i] == b'%' && i + 2 < bytes.len() {
-            if let Ok(byte) = u8::from_str_radix(
-                std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or(""),
-                16,
-            ) {
-                result.push(byte as char);
+        if bytes[i] == b'%' && i + 3 <= bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                result.push(byte);
                 i += 3;
                 continue;
             }
         }
-        result.push(bytes[i] as char);
+        result.push(bytes[i]);
         i += 1;
     }
-    result
+    // Decoded bytes form UTF-8 sequences only once they are all collected
+    String::from_utf8(result).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
 }
 
 fn find_channel<'a>(
@@ -204,180 +631,1014 @@ fn find_channel<'a>(
     current.channel.as_ref()
 }
 
-fn serve_log_page(state: &AppState, channel: &crate::Channel, date: &str) -> Response {
-    let (path, format) = match resolve_log_path(channel, date) {
-        Some(r) => r,
-        None => {
-            return (StatusCode::NOT_FOUND, format!("no log for {date}")).into_response();
+/// Today's page subscribes to the live tail once loaded. Until then the
+/// tail keeps rendering the channel, so lines logged in between are
+/// replayed rather than lost.
+async fn expect_subscriber(state: &AppState, channel: &crate::Channel) {
+    let key = channel.path_segments.join("/");
+    let capacity = state.config.sse_channel_capacity;
+    state.sse_feeds.write().await.entry(key).or_insert_with(|| ChannelFeed::new(capacity)).watch();
+}
+
+fn serve_log_page(
+    state: &AppState,
+    channel: &crate::Channel,
+    date: &str,
+    view: templates::EventView,
+    from: Option<Time>,
+    limit: Option<usize>,
+    origin: &str,
+) -> Response {
+    let tz = state.config.timezone;
+    let files = day_files(channel, date);
+    if files.is_empty() {
+        // A picked day without a log goes to the closest one that has one;
+        // the page says so from `missing`
+        let dates = channel_dates(channel);
+        let Some(nearest) = nearest_date(&dates, date) else {
+            return not_found(state, &format!("No log for {} on {date}.", channel.display_name()));
+        };
+        let encoded = channel.path_segments.join("/").replace('#', "%23");
+        let mut url = format!("{}/{encoded}/{nearest}?missing={date}", state.config.base_path);
+        if view != event_view(channel, None) {
+            url.push_str(&format!("&view={}", view.param()));
         }
-    };
-    let content = match read_log_file(&path) {
-        Ok(c) => c,
+        return Redirect::temporary(&url).into_response();
+    }
+    let mut cache_key = format!("{}/{date}/{}", channel.path_segments.join("/"), view.param());
+    if let Some(limit) = limit {
+        cache_key.push_str(&format!("/{}/{limit}", from.map(Time::to_anchor).unwrap_or_default()));
+    }
+    let page_cache = state.page_cache.as_ref().filter(|_| is_immutable_date(date, tz));
+    let sources: Vec<_> = files.iter().map(|(p, _)| p.clone()).collect();
+    if let Some(html) = page_cache.and_then(|c| c.get(&cache_key, &sources)) {
+        return ([cache_control(IMMUTABLE_CACHE)], Html(with_origin(&html, origin))).into_response();
+    }
+
+    let day = match read_day(&files, tz, state.log_cache.as_ref()) {
+        Ok(d) => d,
         Err(e) => {
             return (StatusCode::INTERNAL_SERVER_ERROR, format!("read error: {e}")).into_response();
         }
     };
 
-    let lines: Vec<_> = content
-        .lines()
-        .filter_map(|l| parse_line(l, format))
-        .collect();
-
     let dates = channel_dates(channel);
     let idx = dates.iter().position(|d| d == date);
     let prev = idx.and_then(|i| if i > 0 { dates.get(i - 1) } else { None }).map(|s| s.as_str());
     let next = idx.and_then(|i| dates.get(i + 1)).map(|s| s.as_str());
-    let is_today = date == today_date();
+    let (range, window) = match limit {
+        Some(limit) => {
+            let (range, window) = page_window(&day.lines, from, limit);
+            (range, Some(window))
+        }
+        None => (0..day.lines.len(), None),
+    };
+    // Live lines only make sense appended to the end of the day
+    let is_today = date == today_date(tz) && range.end == day.lines.len();
+    let participants = templates::participants(day.lines.iter().map(|(_, l)| l));
+    let channel_refs = templates::channel_refs(&state.channels, channel, &state.config.base_path);
+    let encoded = channel.path_segments.join("/").replace('#', "%23");
+    let url = format!("{ORIGIN_PLACEHOLDER}{}/{encoded}/{date}", state.config.base_path);
 
-    let mut resp = templates::log_page(&templates::LogPageContext {
+    let markup = templates::log_page(&templates::LogPageContext {
         title: &state.config.title,
         tree: &state.channels,
         channel,
         date,
-        lines: &lines,
+        lines: &day.lines[range],
         prev_date: prev,
         next_date: next,
         is_today,
-        ai_enabled: state.config.ai.is_some(),
+        file_len: day.bytes,
+        ai_enabled: state.config.ai.is_some() && channel.ai_enabled(),
         base_path: &state.config.base_path,
-    }).into_response();
-    let cc = if is_today {
-        "public, max-age=30, s-maxage=120"
-    } else {
-        "public, max-age=3600, s-maxage=86400"
-    };
+        render: templates::RenderOptions {
+            participants: Some(&participants),
+            channel_refs: Some(&channel_refs),
+            ..templates::RenderOptions::for_channel(&state.config, channel)
+        },
+        view,
+        default_view: event_view(channel, None),
+        window,
+        infinite_scroll: state.config.infinite_scroll,
+        url: &url,
+    });
+    if let Some(cache) = page_cache {
+        cache.put(&cache_key, &markup.0);
+    }
+    let mut resp = Html(with_origin(&markup.0, origin)).into_response();
+    let cc = day_cache_control(date, tz);
     resp.headers_mut().insert(header::CACHE_CONTROL, cc.parse().unwrap());
     resp
 }
 
-fn serve_search(state: &AppState, channel: &crate::Channel, query: &str) -> Response {
-    let results = search_channel(channel, query, state.config.search_limit);
-    let mut resp = templates::search_page(&state.config.title, &state.channels, channel, query, &results, &state.config.base_path)
-        .into_response();
-    resp.headers_mut().insert(header::CACHE_CONTROL, "private, no-cache".parse().unwrap());
-    resp
+/// Stands in for the request's origin in the absolute `og:url` of a log
+/// page, so cached pages are shared by every `Host` a client may send.
+const ORIGIN_PLACEHOLDER: &str = "irc-log-viewer:origin";
+
+/// `html` with its origin placeholder filled in. `og:url` comes before any
+/// log text in the page, so the first occurrence is the one to replace.
+fn with_origin(html: &str, origin: &str) -> String {
+    html.replacen(ORIGIN_PLACEHOLDER, origin, 1)
 }
 
-async fn serve_raw(channel: &crate::Channel, date: &str) -> Response {
-    let Some((path, _)) = resolve_log_path(channel, date) else {
-        return (StatusCode::NOT_FOUND, format!("no log for {date}")).into_response();
+/// 302 to a random day of the channel, seeded from the clock.
+fn serve_random_date(state: &AppState, channel: &crate::Channel) -> Response {
+    let dates = channel_dates(channel);
+    if dates.is_empty() {
+        return not_found(state, &format!("No logs for {}.", channel.display_name()));
+    }
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos() as usize;
+    let date = &dates[seed % dates.len()];
+    let bp = &state.config.base_path;
+    let encoded = channel.path_segments.join("/").replace('#', "%23");
+    (
+        StatusCode::FOUND,
+        [
+            (header::LOCATION, format!("{bp}/{encoded}/{date}")),
+            (header::CACHE_CONTROL, "no-store".into()),
+        ],
+    )
+        .into_response()
+}
+
+async fn serve_search(state: Arc<AppState>, channel: &crate::Channel, search: &SearchQuery) -> Response {
+    let query = search.q.as_deref().unwrap_or_default();
+    let group_minutes = search.group.unwrap_or(0);
+    let (regex, case) = (SearchQuery::flag(&search.regex), SearchQuery::flag(&search.case));
+    let filter = search.filter();
+    let context = search.context.as_deref().and_then(|c| c.trim().parse().ok()).unwrap_or(0).min(MAX_SEARCH_CONTEXT);
+    let counting = SearchQuery::flag(&search.counts);
+    let matcher = match Matcher::new(query, regex, case) {
+        // Counting every line of the history says no more than the calendar
+        Ok(_) if counting && query.is_empty() => Err("a search term is required".to_string()),
+        m => m.map(|m| m.with_filter(filter.clone())).map_err(|e| e.to_string()),
     };
-    let cc = if date == today_date() {
-        "public, max-age=60, s-maxage=300"
-    } else {
-        "public, max-age=86400, s-maxage=604800"
+    let (limit, tz) = (state.config.search_limit, state.config.timezone);
+    // Up to the whole history of the channel; keep it off the runtime thread
+    let scanned = match matcher {
+        Ok(matcher) => {
+            let (state, channel) = (Arc::clone(&state), channel.clone());
+            let scan = tokio::task::spawn_blocking(move || {
+                let sources = state.sources();
+                let (mut counts, mut blocks, mut results) = (None, Vec::new(), Vec::new());
+                if counting {
+                    let max_files = state.config.search_all_max_files;
+                    counts = Some(count_by_date(&channel, &matcher, max_files, tz, sources));
+                } else if context > 0 {
+                    blocks = search_context(&channel, &matcher, limit, context, tz, sources);
+                } else {
+                    results = search_channel(&channel, &matcher, limit, tz, sources);
+                }
+                (counts, blocks, results, matcher)
+            });
+            scan.await.map_err(|_| "search failed".to_string())
+        }
+        Err(e) => Err(e),
     };
-    match read_log_file(&path) {
-        Ok(content) => {
-            (
-                [
-                    (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
-                    cache_control(cc),
-                ],
-                content,
-            )
-                .into_response()
+    let (counts, counts_truncated, groups, blocks, matcher, error) = match scanned {
+        Ok((counts, blocks, results, matcher)) => {
+            let (counts, truncated) = counts.map_or((None, false), |(c, t)| (Some(c), t));
+            let groups = group_results(results, group_minutes.saturating_mul(60));
+            (counts, truncated, groups, blocks, Some(matcher), None)
         }
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("read error: {e}")).into_response(),
-    }
+        Err(e) => (counting.then(Vec::new), false, Vec::new(), Vec::new(), None, Some(e)),
+    };
+    let mut resp = templates::search_page(&templates::SearchPageContext {
+        title: &state.config.title,
+        tree: &state.channels,
+        channel,
+        query,
+        groups: &groups,
+        blocks: &blocks,
+        counts: counts.as_deref(),
+        counts_truncated,
+        group_minutes,
+        context,
+        filter: &filter,
+        regex,
+        case,
+        error: error.as_deref(),
+        matcher: matcher.as_ref(),
+        base_path: &state.config.base_path,
+        render: templates::RenderOptions::new(&state.config),
+    })
+    .into_response();
+    resp.headers_mut().insert(header::CACHE_CONTROL, "private, no-cache".parse().unwrap());
+    resp
 }
 
-async fn serve_sse(
-    state: Arc<AppState>,
-    channel: &crate::Channel,
-) -> Sse<impl tokio_stream::Stream<Item = Result<Event, std::convert::Infallible>>> {
-    let key = channel.path_segments.join("/");
-    let rx = {
-        let mut senders = state.sse_senders.write().await;
-        let sender = senders
-            .entry(key)
-            .or_insert_with(|| broadcast::channel(256).0);
-        sender.subscribe()
+/// The node `scope` (`OFTC`, `OFTC/sub`) names, or the root when empty.
+fn scope_node<'a>(root: &'a crate::ChannelNode, scope: &str) -> Option<&'a crate::ChannelNode> {
+    scope.split('/').filter(|s| !s.is_empty()).try_fold(root, |node, seg| node.children.get(seg))
+}
+
+/// Search over every channel, or those under `scope` (e.g. a network).
+async fn serve_search_all(State(state): State<Arc<AppState>>, Query(search): Query<SearchQuery>) -> Response {
+    let query = search.q.unwrap_or_default();
+    let scope = search.scope.unwrap_or_default();
+    let (regex, case) = (SearchQuery::flag(&search.regex), SearchQuery::flag(&search.case));
+    if scope_node(&state.channels, &scope).is_none() {
+        return not_found(&state, &format!("No channels under {scope}."));
+    }
+    let (results, truncated, error, matcher) = match Matcher::new(&query, regex, case) {
+        Ok(matcher) if query.is_empty() => (Vec::new(), false, None, Some(matcher)),
+        // Up to `search_all_max_files` files; keep them off the runtime thread
+        Ok(matcher) => {
+            let (state, scope) = (Arc::clone(&state), scope.clone());
+            let scan = tokio::task::spawn_blocking(move || {
+                let (config, sources) = (&state.config, state.sources());
+                let node = scope_node(&state.channels, &scope).unwrap();
+                let (limit, max_files) = (config.search_limit, config.search_all_max_files);
+                let (results, truncated) = search_all(node, &matcher, limit, max_files, config.timezone, sources);
+                (results, truncated, matcher)
+            });
+            match scan.await {
+                Ok((results, truncated, matcher)) => (results, truncated, None, Some(matcher)),
+                Err(_) => (Vec::new(), false, Some("search failed".to_string()), None),
+            }
+        }
+        Err(e) => (Vec::new(), false, Some(e.to_string()), None),
     };
+    let mut resp = templates::search_all_page(&templates::SearchAllContext {
+        title: &state.config.title,
+        tree: &state.channels,
+        scope: &scope,
+        query: &query,
+        results: &results,
+        truncated,
+        regex,
+        case,
+        error: error.as_deref(),
+        matcher: matcher.as_ref(),
+        base_path: &state.config.base_path,
+        render: templates::RenderOptions::new(&state.config),
+    })
+    .into_response();
+    resp.headers_mut().insert(header::CACHE_CONTROL, "private, no-cache".parse().unwrap());
+    resp
+}
 
-    let stream = BroadcastStream::new(rx).filter_map(|result| {
-        result
-            .ok()
-            .map(|html| Ok::<_, std::convert::Infallible>(Event::default().data(html)))
+/// Search results as SSE: one `message` event of rendered HTML per match,
+/// then a `done` event with the match count, or a single `search_error`
+/// event for an invalid regex. Scanning runs on a blocking thread and stops
+/// as soon as the client disconnects.
+fn serve_search_stream(
+    state: Arc<AppState>,
+    channel: crate::Channel,
+    query: String,
+    regex: bool,
+    case: bool,
+    filter: Filter,
+) -> Response {
+    if query.is_empty() {
+        return (StatusCode::BAD_REQUEST, "query is required").into_response();
+    }
+    let (tx, rx) = tokio::sync::mpsc::channel::<Event>(16);
+    tokio::task::spawn_blocking(move || {
+        let matcher = match Matcher::new(&query, regex, case) {
+            Ok(m) => m.with_filter(filter),
+            Err(e) => {
+                let _ = tx.blocking_send(Event::default().event("search_error").data(e.to_string()));
+                return;
+            }
+        };
+        let opts = templates::RenderOptions::new(&state.config);
+        let encoded = channel.path_segments.join("/").replace('#', "%23");
+        let mut count = 0;
+        let (limit, tz) = (state.config.search_limit, state.config.timezone);
+        crate::search::scan_channel(&channel, &matcher, limit, tz, state.sources(), |date, line| {
+            count += 1;
+            let html = templates::search_result_line(&state.config.base_path, &encoded, date, &line, &opts, Some(&matcher));
+            match tx.blocking_send(Event::default().data(html.into_string())) {
+                Ok(()) => std::ops::ControlFlow::Continue(()),
+                // Receiver dropped: the client stopped the search
+                Err(_) => std::ops::ControlFlow::Break(()),
+            }
+        });
+        let _ = tx.blocking_send(Event::default().event("done").data(count.to_string()));
     });
-
-    Sse::new(stream).keep_alive(KeepAlive::default())
+    let stream = ReceiverStream::new(rx).map(Ok::<_, std::convert::Infallible>);
+    (
+        [cache_control("no-store")],
+        Sse::new(stream).keep_alive(KeepAlive::default()),
+    )
+        .into_response()
 }
 
-pub fn read_log_file(path: &Path) -> io::Result<String> {
-    let file = std::fs::File::open(path)?;
-    let mut content = String::new();
-
-    if path.extension().and_then(|e| e.to_str()) == Some("zst") {
-        let mut decoder = zstd::Decoder::new(BufReader::new(file))?;
-        decoder.read_to_string(&mut content)?;
+/// Cache-Control for a rendered day: short while it is still being
+/// written, long once it can no longer change.
+fn day_cache_control(date: &str, tz: UtcOffset) -> &'static str {
+    if date == today_date(tz) {
+        "public, max-age=30, s-maxage=120"
+    } else if is_immutable_date(date, tz) {
+        IMMUTABLE_CACHE
     } else {
-        let mut reader = BufReader::new(file);
-        reader.read_to_string(&mut content)?;
+        "public, max-age=3600, s-maxage=86400"
     }
-
-    Ok(content)
 }
 
-pub fn resolve_log_path(channel: &crate::Channel, date: &str) -> Option<(std::path::PathBuf, LogFormat)> {
-    for dir in &channel.dirs {
-        let plain = dir.path.join(format!("{date}.log"));
-        if plain.exists() {
-            return Some((plain, dir.format));
+/// The parsed lines of a day as a JSON array, in page order.
+fn serve_day_json(state: &AppState, channel: &crate::Channel, date: &str) -> Response {
+    let json_response = |status, cc, body: String| {
+        (status, [(header::CONTENT_TYPE, "application/json"), cache_control(cc)], body).into_response()
+    };
+    let files = day_files(channel, date);
+    if files.is_empty() {
+        let body = serde_json::json!({"error": format!("no log for {date}")}).to_string();
+        return json_response(StatusCode::NOT_FOUND, "private, no-cache", body);
+    }
+    let tz = state.config.timezone;
+    match read_day(&files, tz, state.log_cache.as_ref()) {
+        Ok(day) => {
+            let lines: Vec<&LogLine> = day.lines.iter().map(|(_, l)| l).collect();
+            let body = serde_json::to_string(&lines).unwrap_or_default();
+            json_response(StatusCode::OK, day_cache_control(date, tz), body)
         }
-        let zst = dir.path.join(format!("{date}.log.zst"));
-        if zst.exists() {
-            return Some((zst, dir.format));
+        Err(e) => {
+            let body = serde_json::json!({"error": format!("read error: {e}")}).to_string();
+            json_response(StatusCode::INTERNAL_SERVER_ERROR, "no-store", body)
         }
     }
-    None
 }
 
-fn latest_date(channel: &crate::Channel) -> String {
+/// The rendered lines of one day, without the page around them. The
+/// `X-Prev-Date` header gives the day before, if any, so a client can keep
+/// loading earlier days.
+fn serve_day_fragment(state: &AppState, channel: &crate::Channel, date: &str, view: templates::EventView) -> Response {
+    let files = day_files(channel, date);
+    if files.is_empty() {
+        return (StatusCode::NOT_FOUND, format!("no log for {date}")).into_response();
+    }
+    let tz = state.config.timezone;
+    let day = match read_day(&files, tz, state.log_cache.as_ref()) {
+        Ok(d) => d,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("read error: {e}")).into_response(),
+    };
     let dates = channel_dates(channel);
-    dates.last().cloned().unwrap_or_else(today_date)
+    let prev = dates.iter().position(|d| d == date).filter(|&i| i > 0).map(|i| dates[i - 1].clone());
+    let participants = templates::participants(day.lines.iter().map(|(_, l)| l));
+    let channel_refs = templates::channel_refs(&state.channels, channel, &state.config.base_path);
+    let opts = templates::RenderOptions {
+        participants: Some(&participants),
+        channel_refs: Some(&channel_refs),
+        ..templates::RenderOptions::for_channel(&state.config, channel)
+    };
+    let markup = templates::log_fragment(&day.lines, &opts, view);
+    let mut resp = ([cache_control(day_cache_control(date, tz))], Html(markup.into_string())).into_response();
+    if let Some(prev) = prev.and_then(|p| header::HeaderValue::from_str(&p).ok()) {
+        resp.headers_mut().insert("x-prev-date", prev);
+    }
+    resp
 }
 
-pub fn channel_dates(channel: &crate::Channel) -> Vec<String> {
-    let mut dates = std::collections::BTreeSet::new();
-    for dir in &channel.dirs {
-        let Ok(entries) = std::fs::read_dir(&dir.path) else { continue };
-        for entry in entries.flatten() {
-            let Ok(name) = entry.file_name().into_string() else { continue };
-            let date = name.strip_suffix(".log")
-                .or_else(|| name.strip_suffix(".log.zst"));
-            if let Some(d) = date {
-                if d.len() == 10 {
-                    dates.insert(d.to_string());
-                }
-            }
+/// Plain text by default; `nowrap` wraps it in a minimal HTML page with
+/// unwrapped lines for side-scrolling analysis. Plain text honors a single
+/// `Range`, counted in bytes of the decompressed log.
+async fn serve_raw(state: &AppState, channel: &crate::Channel, date: &str, nowrap: bool, headers: &HeaderMap) -> Response {
+    let files = day_files(channel, date);
+    if files.is_empty() {
+        return (StatusCode::NOT_FOUND, format!("no log for {date}")).into_response();
+    }
+    let tz = state.config.timezone;
+    let cc = if date == today_date(tz) {
+        "public, max-age=60, s-maxage=300"
+    } else if is_immutable_date(date, tz) {
+        IMMUTABLE_CACHE
+    } else {
+        "public, max-age=86400, s-maxage=604800"
+    };
+    // Merged channels show each source's file in turn
+    let content: io::Result<String> = files.iter().map(|(path, _)| read_log_file(path)).collect();
+    let content = match content {
+        Ok(content) => content,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, format!("read error: {e}")).into_response(),
+    };
+    if nowrap {
+        let page = templates::raw_page(&state.config.title, channel, date, &content, &state.config.base_path);
+        return ([cache_control(cc)], page).into_response();
+    }
+    let len = content.len();
+    let plain = [
+        (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+        cache_control(cc),
+        (header::ACCEPT_RANGES, "bytes"),
+    ];
+    match requested_range(headers, len) {
+        None => (plain, content).into_response(),
+        Some(Ok(range)) => {
+            let content_range = format!("bytes {}-{}/{len}", range.start, range.end - 1);
+            let body = axum::body::Bytes::from(content).slice(range);
+            (StatusCode::PARTIAL_CONTENT, plain, [(header::CONTENT_RANGE, content_range)], body).into_response()
+        }
+        Some(Err(())) => {
+            (StatusCode::RANGE_NOT_SATISFIABLE, [(header::CONTENT_RANGE, format!("bytes */{len}"))]).into_response()
         }
     }
-    dates.into_iter().collect()
 }
 
-fn serve_ask_page(state: &AppState, channel: &crate::Channel) -> Response {
-    if state.config.ai.is_none() {
-        return (StatusCode::NOT_FOUND, "not found").into_response();
+/// The byte range of a `len`-byte body asked for by a `Range` header.
+/// `None` sends the whole body: no header, an unparsable one, several
+/// ranges, or an `If-Range` (there are no validators to check it against).
+/// `Err` when the range starts past the end.
+fn requested_range(headers: &HeaderMap, len: usize) -> Option<Result<std::ops::Range<usize>, ()>> {
+    if headers.contains_key(header::IF_RANGE) {
+        return None;
     }
-    let mut resp = templates::ask_page(&state.config.title, &state.channels, channel, &state.config.base_path).into_response();
-    resp.headers_mut().insert(header::CACHE_CONTROL, "private, no-cache".parse().unwrap());
-    resp
+    let spec = headers.get(header::RANGE)?.to_str().ok()?.strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let range = match spec.trim().split_once('-')? {
+        ("", suffix) => len.saturating_sub(suffix.parse().ok()?)..len,
+        (start, "") => start.parse().ok()?..len,
+        (start, end) => {
+            let (start, end): (usize, usize) = (start.parse().ok()?, end.parse().ok()?);
+            if end < start {
+                return None;
+            }
+            start..len.min(end + 1)
+        }
+    };
+    Some(if range.start < range.end { Ok(range) } else { Err(()) })
+}
+
+#[derive(Serialize)]
+struct ExportLine<'a> {
+    date: &'a str,
+    #[serde(flatten)]
+    line: &'a LogLine,
+}
+
+/// Streams every parsed line in `[from, to]` (whole history when unset) as
+/// NDJSON, one day at a time so memory stays bounded by the largest day.
+fn serve_export_ndjson(channel: crate::Channel, from: Option<&str>, to: Option<&str>, tz: UtcOffset) -> Response {
+    let Some(dates) = export_dates(&channel, from, to) else {
+        return (StatusCode::BAD_REQUEST, "from/to must be YYYY-MM-DD").into_response();
+    };
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<io::Result<String>>(4);
+    tokio::task::spawn_blocking(move || {
+        for date in dates {
+            // Bypasses the log cache, which a whole history would only churn
+            let day = match read_day(&day_files(&channel, &date), tz, None) {
+                Ok(d) => d,
+                Err(e) => {
+                    let _ = tx.blocking_send(Err(e));
+                    return;
+                }
+            };
+            let mut chunk = String::new();
+            for (_, line) in &day.lines {
+                let record = ExportLine { date: &date, line };
+                chunk.push_str(&serde_json::to_string(&record).unwrap_or_default());
+                chunk.push('\n');
+            }
+            // Receiver dropped: the client went away
+            if tx.blocking_send(Ok(chunk)).is_err() {
+                return;
+            }
+        }
+    });
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson"),
+            cache_control("private, no-cache"),
+        ],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
+
+/// Dates of `channel` within `[from, to]`, or `None` if a bound is not a date.
+fn export_dates(channel: &crate::Channel, from: Option<&str>, to: Option<&str>) -> Option<Vec<String>> {
+    if [from, to].into_iter().flatten().any(|d| !looks_like_date(d)) {
+        return None;
+    }
+    let mut dates = channel_dates(channel);
+    dates.retain(|d| from.is_none_or(|f| d.as_str() >= f) && to.is_none_or(|t| d.as_str() <= t));
+    Some(dates)
+}
+
+/// Download name for a channel export, e.g. `OFTC_bcachefs.log`.
+fn export_filename(channel: &crate::Channel, extension: &str) -> String {
+    let name: String = channel
+        .path_segments
+        .join("_")
+        .chars()
+        .filter(|&c| c != '#')
+        .map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect();
+    format!("{name}.{extension}")
+}
+
+/// The raw daily logs in `[from, to]` (whole history when unset) concatenated
+/// into one download, zstd-compressed on the fly with `zst`. Archived and
+/// compressed days are decompressed first. Days are read one at a time so
+/// memory stays bounded by the largest day.
+fn serve_export_raw(channel: crate::Channel, from: Option<&str>, to: Option<&str>, zst: bool) -> Response {
+    let Some(dates) = export_dates(&channel, from, to) else {
+        return (StatusCode::BAD_REQUEST, "from/to must be YYYY-MM-DD").into_response();
+    };
+    let filename = export_filename(&channel, if zst { "log.zst" } else { "log" });
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<io::Result<Vec<u8>>>(4);
+    tokio::task::spawn_blocking(move || {
+        let mut encoder = match zst.then(|| zstd::stream::write::Encoder::new(Vec::new(), 3)).transpose() {
+            Ok(e) => e,
+            Err(e) => {
+                let _ = tx.blocking_send(Err(e));
+                return;
+            }
+        };
+        for date in dates {
+            for (path, _) in day_files(&channel, &date) {
+                let mut content = match read_log_file(&path) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        let _ = tx.blocking_send(Err(e));
+                        return;
+                    }
+                };
+                if !content.is_empty() && !content.ends_with('\n') {
+                    content.push('\n');
+                }
+                let chunk = match encoder.as_mut() {
+                    // Only what the encoder already emitted; the rest
+                    // follows with later days or at finish
+                    Some(enc) => match io::Write::write_all(enc, content.as_bytes()) {
+                        Ok(()) => std::mem::take(enc.get_mut()),
+                        Err(e) => {
+                            let _ = tx.blocking_send(Err(e));
+                            return;
+                        }
+                    },
+                    None => content.into_bytes(),
+                };
+                // Receiver dropped: the client went away
+                if !chunk.is_empty() && tx.blocking_send(Ok(chunk)).is_err() {
+                    return;
+                }
+            }
+        }
+        if let Some(enc) = encoder {
+            let _ = tx.blocking_send(enc.finish());
+        }
+    });
+
+    let content_type = if zst { "application/zstd" } else { "text/plain; charset=utf-8" };
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{filename}\"")),
+            (header::CACHE_CONTROL, "private, no-cache".to_string()),
+        ],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response()
 }
 
-async fn serve_ask_stream(
+/// Live tail of today's log. `pos` is the size of the file the page was
+/// rendered from; buffered lines past it are replayed before live ones. A
+/// reconnecting `EventSource` sends `last_id` instead, which takes priority.
+async fn serve_sse(
     state: Arc<AppState>,
-    channel: crate::Channel,
-    query: &str,
+    channel: &crate::Channel,
+    pos: Option<u64>,
+    last_id: Option<u64>,
+) -> Sse<impl tokio_stream::Stream<Item = Result<Event, std::convert::Infallible>>> {
+    let key = channel.path_segments.join("/");
+    let today = today_date(state.config.timezone);
+    let today_path = resolve_log_path(channel, &today).map(|(p, _)| p);
+    let (replay, rx) = {
+        let mut feeds = state.sse_feeds.write().await;
+        let feed = feeds.entry(key).or_insert_with(|| ChannelFeed::new(state.config.sse_channel_capacity));
+        feed.watch();
+        let replay = match (today_path, last_id, pos) {
+            (Some(path), Some(id), _) => feed.after(&path, id),
+            (Some(path), None, Some(pos)) => feed.since(&path, pos),
+            _ => Vec::new(),
+        };
+        (replay, feed.sender.subscribe())
+    };
+
+    // Once the logger starts the next day's file, the page is told to move
+    // there rather than having that day's lines appended to this one. A
+    // client too slow to keep up is told how many events it missed, and
+    // reloads rather than show a gap
+    let live = BroadcastStream::new(rx).filter_map(move |result| {
+        let batch = match result {
+            Ok(batch) => batch,
+            Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                return Some(Event::default().event("lagged").data(missed.to_string()));
+            }
+        };
+        let date = crate::tail::file_date(&batch.path)?;
+        match date.as_str().cmp(today.as_str()) {
+            std::cmp::Ordering::Equal => Some(Event::default().id(batch.id.to_string()).data(batch.html())),
+            std::cmp::Ordering::Greater => Some(Event::default().event("rollover").data(date)),
+            std::cmp::Ordering::Less => None,
+        }
+    });
+    let stream = tokio_stream::iter(replay)
+        .map(|(id, html)| Event::default().id(id.to_string()).data(html))
+        .chain(live)
+        .map(Ok::<_, std::convert::Infallible>);
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+pub fn read_log_file(path: &Path) -> io::Result<String> {
+    if let Some((archive, member)) = crate::archive::split_member_path(path) {
+        let data = crate::archive::read_member(archive, member)?;
+        return decode_log(path, &data[..]);
+    }
+    let file = std::fs::File::open(path)?;
+    decode_log(path, BufReader::new(file))
+}
+
+fn decode_log(path: &Path, reader: impl Read) -> io::Result<String> {
+    let mut content = String::new();
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("zst") => {
+            zstd::Decoder::new(reader)?.read_to_string(&mut content)?;
+        }
+        Some("gz") => {
+            flate2::read::MultiGzDecoder::new(reader).read_to_string(&mut content)?;
+        }
+        _ => {
+            let mut reader = reader;
+            reader.read_to_string(&mut content)?;
+        }
+    }
+
+    // Old Mac line endings: a lone CR ends each line. Swapping it for LF
+    // keeps byte offsets, which the live tail and `?pos=` rely on.
+    if content.contains('\r') && !content.contains('\n') {
+        content = content.replace('\r', "\n");
+    }
+    Ok(content)
+}
+
+/// Endings of daily log files: plain, zstd- or gzip-compressed.
+const LOG_SUFFIXES: [&str; 3] = [".log", ".log.zst", ".log.gz"];
+
+/// A daily log file name split into the channel name it may start with
+/// (empty when it doesn't) and its date, normalized to `YYYY-MM-DD`. Before
+/// one of the `LOG_SUFFIXES`, the name is `2025-02-01` or `20250201`, optionally
+/// after a channel name and `.`, `_` or `-`: `#chan.2025-02-01.log`,
+/// `chan_20250201.log.zst`.
+pub fn parse_log_file_name(name: &str) -> Option<(&str, String)> {
+    let stem = LOG_SUFFIXES.iter().find_map(|suffix| name.strip_suffix(suffix))?;
+    for len in [10, 8] {
+        let Some(split) = stem.len().checked_sub(len) else { continue };
+        let (Some(prefix), Some(date)) = (stem.get(..split), stem.get(split..)) else { continue };
+        let Some(date) = normalize_date(date) else { continue };
+        if prefix.is_empty() {
+            return Some(("", date));
+        }
+        if let Some(channel) = prefix.strip_suffix(['.', '_', '-']).filter(|c| !c.is_empty()) {
+            return Some((channel, date));
+        }
+    }
+    None
+}
+
+/// `YYYY-MM-DD` as is, or `YYYYMMDD` with a plausible month and day.
+fn normalize_date(s: &str) -> Option<String> {
+    if looks_like_date(s) {
+        return Some(s.to_string());
+    }
+    let b = s.as_bytes();
+    if b.len() != 8 || !b.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let (month, day) = (&s[4..6], &s[6..8]);
+    (("01"..="12").contains(&month) && ("01"..="31").contains(&day)).then(|| format!("{}-{month}-{day}", &s[..4]))
+}
+
+/// Normalized date of a daily log file name, whatever channel it names.
+pub fn log_file_date(name: &str) -> Option<String> {
+    parse_log_file_name(name).map(|(_, date)| date)
+}
+
+pub fn resolve_log_path(channel: &crate::Channel, date: &str) -> Option<(std::path::PathBuf, LogFormat)> {
+    if !looks_like_date(date) {
+        return None;
+    }
+    let compact = date.replace('-', "");
+    for dir in &channel.dirs {
+        let prefixes: Vec<String> = if dir.prefix.is_empty() {
+            vec![String::new()]
+        } else {
+            ['.', '_', '-'].iter().map(|sep| format!("{}{sep}", dir.prefix)).collect()
+        };
+        for prefix in &prefixes {
+            for day in [date, compact.as_str()] {
+                for ext in LOG_SUFFIXES {
+                    let name = format!("{prefix}{day}{ext}");
+                    if dir.has_file(&name) {
+                        return Some((dir.path.join(name), dir.format));
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Files holding `date`: at most one for a real channel, one per source
+/// that has the day for a merged channel.
+pub fn day_files(channel: &crate::Channel, date: &str) -> Vec<(std::path::PathBuf, LogFormat)> {
+    if channel.sources.is_empty() {
+        resolve_log_path(channel, date).into_iter().collect()
+    } else {
+        channel.sources.iter().filter_map(|s| resolve_log_path(s, date)).collect()
+    }
+}
+
+pub struct Day {
+    /// Parsed lines with their 1-based number in the file. Lines from
+    /// several files are interleaved by time and carry no number.
+    pub lines: Vec<(Option<usize>, LogLine)>,
+    /// Bytes read, i.e. the file size when there is a single file.
+    pub bytes: u64,
+}
+
+pub fn read_day(files: &[(std::path::PathBuf, LogFormat)], tz: UtcOffset, cache: Option<&LogCache>) -> io::Result<Day> {
+    let mut lines = Vec::new();
+    let mut bytes = 0;
+    for (path, format) in files {
+        let content = read_log(cache, path)?;
+        let format = &file_format(&content, *format);
+        bytes += content.len() as u64;
+        // Lines without a timestamp, such as pasted output, are parsed as
+        // raw lines at midnight; they take the previous line's time so the
+        // day stays in time order
+        let mut last_time = None;
+        for (i, l) in content.lines().enumerate() {
+            let Some(mut p) = parse_line(l, *format) else { continue };
+            match last_time {
+                Some(time) if matches!(p.kind, LineKind::Raw { .. }) && p.time.to_seconds() == 0 => p.time = time,
+                _ => tz.localize(&mut p, *format),
+            }
+            last_time = Some(p.time);
+            lines.push((Some(i + 1), p));
+        }
+    }
+    if files.len() > 1 {
+        lines.iter_mut().for_each(|(n, _)| *n = None);
+        // Stable, so same-second lines keep their per-file order
+        lines.sort_by_key(|(_, l)| l.time.to_seconds());
+    }
+    Ok(Day { lines, bytes })
+}
+
+/// `HH:MM:SS` or `HH:MM`, as in `?from=`.
+fn parse_hms(s: &str) -> Option<Time> {
+    let mut parts = s.splitn(3, ':').map(|p| p.parse::<u8>().ok());
+    let (hour, minute) = (parts.next()??, parts.next()??);
+    let second = parts.next().map_or(Some(0), |p| p)?;
+    (hour < 24 && minute < 60 && second < 60).then_some(Time { hour, minute, second })
+}
+
+/// The `limit` lines starting at the first one at or after `from`, and the
+/// times the neighbouring windows start at.
+fn page_window(
+    lines: &[(Option<usize>, LogLine)],
+    from: Option<Time>,
+    limit: usize,
+) -> (std::ops::Range<usize>, templates::PageWindow) {
+    let start = from.map_or(0, |t| lines.partition_point(|(_, l)| l.time.to_seconds() < t.to_seconds()));
+    let start = start.min(lines.len().saturating_sub(1));
+    let end = (start + limit).min(lines.len());
+    let earlier = (start > 0).then(|| lines[start.saturating_sub(limit)].1.time);
+    let later = lines.get(end).map(|(_, l)| l.time).map(|t| {
+        // More than `limit` lines in one second would never move forward
+        if t == lines[start].1.time { t.shifted(1) } else { t }
+    });
+    (start..end, templates::PageWindow { limit, from, earlier, later })
+}
+
+fn latest_date(channel: &crate::Channel, tz: UtcOffset) -> String {
+    let dates = channel_dates(channel);
+    dates.last().cloned().unwrap_or_else(|| today_date(tz))
+}
+
+pub fn channel_dates(channel: &crate::Channel) -> Vec<String> {
+    let mut dates = std::collections::BTreeSet::new();
+    for dir in &channel.dirs {
+        for name in dir.file_names() {
+            if let Some(d) = dir.file_date(&name) {
+                dates.insert(d);
+            }
+        }
+    }
+    dates.into_iter().collect()
+}
+
+fn serve_dates_json(channel: &crate::Channel) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            cache_control("public, max-age=60"),
+        ],
+        serde_json::to_string(&channel_dates(channel)).unwrap_or_default(),
+    )
+        .into_response()
+}
+
+/// Nicks that wrote a message or action in the channel's most recent days
+/// of logs, most active first. Counts come from the stats cache, so only
+/// files that changed are read again.
+async fn serve_nicks_json(state: Arc<AppState>, channel: crate::Channel) -> Response {
+    let nicks = tokio::task::spawn_blocking(move || {
+        let mut dates = channel_dates(&channel);
+        dates.drain(..dates.len().saturating_sub(crate::stats::DEFAULT_DAYS));
+        let stats = crate::stats::channel_stats(&state, &channel, &dates);
+        stats.total.top_nicks().into_iter().map(|(nick, _)| nick.to_string()).collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+    (
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            cache_control("public, max-age=300"),
+        ],
+        serde_json::to_string(&nicks).unwrap_or_default(),
+    )
+        .into_response()
+}
+
+/// Messages in a channel's Atom feed, newest first.
+const FEED_ENTRIES: usize = 50;
+/// Log days read, newest first, to fill the feed of a quiet channel.
+const FEED_DAYS: usize = 7;
+
+/// Atom feed of the latest messages, read back over the most recent days.
+/// Joins, quits and other events are left out unless `events` is set.
+fn serve_feed(
+    state: &AppState,
+    channel: &crate::Channel,
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+    events: bool,
 ) -> Response {
-    if state.config.ai.is_none() {
+    let tz = state.config.timezone;
+    let mut entries = Vec::new();
+    for date in channel_dates(channel).into_iter().rev().take(FEED_DAYS) {
+        let Ok(day) = read_day(&day_files(channel, &date), tz, state.log_cache.as_ref()) else { continue };
+        for (i, (n, line)) in day.lines.into_iter().enumerate().rev() {
+            if events || !line.is_event() {
+                entries.push((date.clone(), n.unwrap_or(i + 1), line));
+            }
+            if entries.len() == FEED_ENTRIES {
+                break;
+            }
+        }
+        if entries.len() == FEED_ENTRIES {
+            break;
+        }
+    }
+
+    // Atom wants absolute links; build them from the request since the
+    // viewer does not know its public address
+    let site = crate::proxy::external_url(headers, peer, &state.config, &state.config.base_path);
+    let offset = if tz.is_utc() { "Z".to_string() } else { String::from(tz) };
+    let opts = templates::RenderOptions::new(&state.config);
+    (
+        [
+            (header::CONTENT_TYPE, "application/atom+xml; charset=utf-8"),
+            cache_control("public, max-age=60"),
+        ],
+        templates::atom_feed(channel, &site, &offset, &entries, &opts),
+    )
+        .into_response()
+}
+
+/// Messages and actions logged on `date`. Counts are cached per file and
+/// recomputed only when the file (or its archive) changes.
+fn day_message_count(state: &AppState, channel: &crate::Channel, date: &str) -> usize {
+    let mut total = 0;
+    for (path, format) in day_files(channel, date) {
+        let Some(mtime) = crate::cache::source_mtime(&path) else { continue };
+        let cached = state.message_counts.lock().unwrap().get(&path).copied();
+        total += match cached {
+            Some((at, count)) if at == mtime => count,
+            _ => {
+                let count = read_log_file(&path).map_or(0, |content| {
+                    let format = file_format(&content, format);
+                    content.lines().filter_map(|l| parse_line(l, format)).filter(|l| !l.is_event()).count()
+                });
+                state.message_counts.lock().unwrap().insert(path, (mtime, count));
+                count
+            }
+        };
+    }
+    total
+}
+
+async fn serve_calendar(state: Arc<AppState>, channel: crate::Channel) -> Response {
+    // Counting reads every uncached day of the history
+    let days: Vec<(String, usize)> = {
+        let (state, channel) = (Arc::clone(&state), channel.clone());
+        tokio::task::spawn_blocking(move || {
+            let dates = channel_dates(&channel).into_iter();
+            dates
+                .map(|date| {
+                    let count = day_message_count(&state, &channel, &date);
+                    (date, count)
+                })
+                .collect()
+        })
+        .await
+        .unwrap_or_default()
+    };
+    let page = templates::calendar_page(&state.config.title, &state.channels, &channel, &state.config.base_path, &days);
+    ([cache_control("public, max-age=300")], page).into_response()
+}
+
+/// Activity between `from` and `to`, or over the last
+/// [`stats::DEFAULT_DAYS`](crate::stats::DEFAULT_DAYS) days with logs when
+/// neither is given.
+async fn serve_stats(state: Arc<AppState>, channel: crate::Channel, from: Option<String>, to: Option<String>) -> Response {
+    let (from, to) = (from.filter(|d| !d.is_empty()), to.filter(|d| !d.is_empty()));
+    let Some(mut dates) = export_dates(&channel, from.as_deref(), to.as_deref()) else {
+        return (StatusCode::BAD_REQUEST, "from/to must be YYYY-MM-DD").into_response();
+    };
+    if from.is_none() && to.is_none() {
+        dates.drain(..dates.len().saturating_sub(crate::stats::DEFAULT_DAYS));
+    }
+    let stats = {
+        let (state, channel) = (Arc::clone(&state), channel.clone());
+        tokio::task::spawn_blocking(move || crate::stats::channel_stats(&state, &channel, &dates)).await.unwrap_or_default()
+    };
+    let opts = templates::RenderOptions::new(&state.config);
+    let page = templates::stats_page(&state.config.title, &state.channels, &channel, &state.config.base_path, &opts, &stats);
+    ([cache_control("public, max-age=300")], page).into_response()
+}
+
+fn serve_ask_page(state: &AppState, channel: &crate::Channel) -> Response {
+    let Some(ai) = state.config.ai.as_ref().filter(|_| channel.ai_enabled()) else {
+        return not_found(state, "No such page.");
+    };
+    let live_results = ai.broadcast_results;
+    let mut resp = templates::ask_page(&state.config.title, &state.channels, channel, &state.config.base_path, live_results).into_response();
+    resp.headers_mut().insert(header::CACHE_CONTROL, "private, no-cache".parse().unwrap());
+    resp
+}
+
+async fn serve_ask_live(state: Arc<AppState>, channel: &crate::Channel) -> Response {
+    if !state.config.ai.as_ref().is_some_and(|ai| ai.broadcast_results) || !channel.ai_enabled() {
         return (StatusCode::NOT_FOUND, "not found").into_response();
     }
+    let rx = {
+        let mut feeds = state.sse_feeds.write().await;
+        let capacity = state.config.sse_channel_capacity;
+        let feed = feeds.entry(crate::ai::ask_feed_key(channel)).or_insert_with(|| ChannelFeed::new(capacity));
+        feed.sender.subscribe()
+    };
+    let stream = BroadcastStream::new(rx).filter_map(|result| {
+        result
+            .ok()
+            .map(|batch| Ok::<_, std::convert::Infallible>(Event::default().event("report").data(batch.html())))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+/// An `ask/stream` request: the query, an optional earlier report to
+/// continue, and who asked.
+struct AskRequest<'a> {
+    query: &'a str,
+    append_to: Option<&'a str>,
+    client: Option<std::net::IpAddr>,
+    origin: String,
+}
+
+async fn serve_ask_stream(state: Arc<AppState>, channel: crate::Channel, ask: AskRequest<'_>) -> Response {
+    let Some(ai_config) = state.config.ai.as_ref().filter(|_| channel.ai_enabled()) else {
+        return (StatusCode::NOT_FOUND, "not found").into_response();
+    };
+    let AskRequest { query, append_to, client, origin } = ask;
 
     let query = query.to_string();
     if query.is_empty() {
         return (StatusCode::BAD_REQUEST, "query is required").into_response();
     }
+    let prior = match append_to.filter(|s| !s.is_empty()) {
+        Some(slug) => match crate::ai::prior_output(ai_config, slug) {
+            Ok(report) => Some(report),
+            Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        },
+        None => None,
+    };
+    let span = tracing::info_span!("ai_session", channel = %channel.path_segments.join("/"), client = ?client);
+    tracing::info!(parent: &span, query = ?query, "ai query");
 
     let semaphore = state.ai_semaphore.as_ref().unwrap();
     let permit = match semaphore.clone().try_acquire_owned() {
@@ -394,7 +1655,7 @@ async fn serve_ask_stream(
     let (tx, rx) = tokio::sync::mpsc::unbounded_channel::<crate::ai::SseEvent>();
 
     tokio::spawn(async move {
-        crate::ai::run_ai_session(query, channel, state, tx).await;
+        crate::ai::run_ai_session(query, channel, state, origin, prior, tx).instrument(span).await;
         drop(permit);
     });
 
@@ -417,10 +1678,24 @@ async fn serve_ask_stream(
             crate::ai::SseEvent::Display(text) => {
                 ("display", serde_json::json!({"text": text}).to_string())
             }
+            crate::ai::SseEvent::Text(text) => {
+                ("text", serde_json::json!({"text": text}).to_string())
+            }
             crate::ai::SseEvent::Done { url, output } => (
                 "done",
                 serde_json::json!({"url": url, "output": output}).to_string(),
             ),
+            crate::ai::SseEvent::Usage { input, output, cache_read, cache_create, cost } => (
+                "usage",
+                serde_json::json!({
+                    "input": input,
+                    "output": output,
+                    "cache_read": cache_read,
+                    "cache_create": cache_create,
+                    "cost": cost,
+                })
+                .to_string(),
+            ),
             crate::ai::SseEvent::Error(msg) => {
                 ("ask_error", serde_json::json!({"error": msg}).to_string())
             }
@@ -439,17 +1714,17 @@ async fn serve_ask_output(
 ) -> Response {
     let ai_config = match &state.config.ai {
         Some(c) => c,
+        None if filename.ends_with(".html") => return not_found(&state, "No such page."),
         None => return (StatusCode::NOT_FOUND, "not found").into_response(),
     };
 
-    if !filename
-        .bytes()
-        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'.')
-    {
+    if !crate::ai::is_output_name(&filename) {
         return (StatusCode::BAD_REQUEST, "invalid filename").into_response();
     }
     if filename.ends_with(".md") {
-        let path = ai_config.output_dir.join(&filename);
+        let Some(path) = crate::ai::output_path(ai_config, &filename) else {
+            return (StatusCode::NOT_FOUND, "not found").into_response();
+        };
         return match std::fs::read_to_string(&path) {
             Ok(content) => {
                 (
@@ -467,16 +1742,1085 @@ async fn serve_ask_output(
 
     if filename.ends_with(".html") {
         let md_name = format!("{}.md", filename.strip_suffix(".html").unwrap());
-        let path = ai_config.output_dir.join(&md_name);
+        let Some(path) = crate::ai::output_path(ai_config, &md_name) else {
+            return not_found(&state, "No such ask result.");
+        };
         return match std::fs::read_to_string(&path) {
             Ok(content) => {
                 let mut resp = templates::ask_output_page(&state.config.title, &md_name, &content, &state.config.base_path).into_response();
                 resp.headers_mut().insert(header::CACHE_CONTROL, "public, max-age=3600, s-maxage=86400".parse().unwrap());
                 resp
             }
-            Err(_) => (StatusCode::NOT_FOUND, "not found").into_response(),
+            Err(_) => not_found(&state, "No such ask result."),
         };
     }
 
     (StatusCode::BAD_REQUEST, "invalid filename").into_response()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::LogTree;
+    use tower::ServiceExt;
+
+    fn test_state(name: &str, channels: &[&str], config: crate::Config) -> (LogTree, Arc<AppState>) {
+        let dir = LogTree::new(name);
+        for chan in channels {
+            dir.write(Path::new(chan).join("2025-01-01.log"), "[00:00:01] <a> hi\n");
+        }
+        let state = Arc::new(dir.state(config));
+        (dir, state)
+    }
+
+    fn test_app(name: &str, channels: &[&str], config: crate::Config) -> (LogTree, Router) {
+        let (dir, state) = test_state(name, channels, config);
+        (dir, app(state))
+    }
+
+    async fn get(app: &Router, uri: &str) -> (StatusCode, String) {
+        let resp = app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    fn ai_config() -> crate::Config {
+        let yaml = "logs_dirs: []\nbase_path: /irc\nai:\n  api_key: x\n  output_dir: /nonexistent\n";
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_local_date_crosses_midnight() {
+        // 2025-01-01T23:30:00Z
+        let t = 1_735_774_200;
+        let utc = UtcOffset::default();
+        let east = UtcOffset::try_from("+02:00".to_string()).unwrap();
+        let west = UtcOffset::try_from("-05:00".to_string()).unwrap();
+        assert_eq!(local_date(t, utc, 0), "2025-01-01");
+        assert_eq!(local_date(t, east, 0), "2025-01-02");
+        assert_eq!(local_date(t, east, 2), "2024-12-31");
+        assert_eq!(local_date(t - 23 * 3600, west, 0), "2024-12-31");
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("OFTC/%23caf%C3%A9"), "OFTC/#café");
+        assert_eq!(percent_decode("OFTC/#chan/2025-01-01"), "OFTC/#chan/2025-01-01");
+        assert_eq!(percent_decode("a%23"), "a#");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%C3"), "%zz\u{fffd}");
+    }
+
+    #[tokio::test]
+    async fn test_non_ascii_channel_route() {
+        let (_dir, app) = test_app("utf8", &["OFTC/#café"], crate::Config::default());
+        let (status, body) = get(&app, "/OFTC/%23caf%C3%A9/2025-01-01").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("#café — 2025-01-01"));
+    }
+
+    #[tokio::test]
+    async fn test_ask_stream_under_base_path() {
+        let (_dir, app) = test_app("ask-base", &["OFTC/#chan"], ai_config());
+        // An empty query is rejected by the ask handler itself, proving the route matched
+        let (status, body) = get(&app, "/irc/OFTC/%23chan/ask/stream?q=&extra=1").await;
+        assert_eq!((status, body.as_str()), (StatusCode::BAD_REQUEST, "query is required"));
+        let (status, _) = get(&app, "/irc/OFTC/%23chan/ask/stream/").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = get(&app, "/OFTC/%23chan/ask/stream").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_ask_stream_channel_named_ask() {
+        let (_dir, app) = test_app("ask-named", &["net/ask"], ai_config());
+        let (status, body) = get(&app, "/irc/net/ask/ask/stream").await;
+        assert_eq!((status, body.as_str()), (StatusCode::BAD_REQUEST, "query is required"));
+        // "net" is not a channel, so this is not the ask stream of anything
+        let (status, _) = get(&app, "/irc/net/ask/stream").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = get(&app, "/irc/net/ask").await;
+        assert_eq!(status, StatusCode::TEMPORARY_REDIRECT);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit_exempts_streams() {
+        let config = crate::Config { max_connections: Some(1), ..crate::Config::default() };
+        let (_dir, state) = test_state("limit", &["OFTC/#chan", "net/latest"], config);
+        let app = app(Arc::clone(&state));
+        let (status, _) = get(&app, "/OFTC/%23chan/2025-01-01").await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Hold the only permit, as a slow in-flight request would
+        let _permit = Arc::clone(state.request_limit.as_ref().unwrap()).try_acquire_owned().unwrap();
+        let (status, _) = get(&app, "/OFTC/%23chan/2025-01-01").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+        assert!(is_stream_path("/OFTC/%23chan/latest", &state));
+        assert!(is_stream_path("/OFTC/%23chan/ask/stream/", &state));
+        assert!(is_stream_path("/OFTC/%23chan/ask/live", &state));
+        assert!(is_stream_path("/OFTC/%23chan/search/stream", &state));
+        assert!(!is_stream_path("/OFTC/%23chan/2025-01-01", &state));
+        // The page of a channel named "latest", and streams of no channel
+        assert!(!is_stream_path("/net/latest", &state));
+        assert!(is_stream_path("/net/latest/latest", &state));
+        assert!(!is_stream_path("/OFTC/%23nope/latest", &state));
+        assert!(!is_stream_path("/OFTC/ask/stream", &state));
+        let (status, _) = get(&app, "/net/latest").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_sse_connection_limit() {
+        let config = crate::Config { max_sse_connections: Some(1), ..crate::Config::default() };
+        let (_dir, app) = test_app("sse-limit", &["OFTC/#chan"], config);
+        let open = || app.clone().oneshot(Request::get("/OFTC/%23chan/latest").body(Body::empty()).unwrap());
+        let first = open().await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        let second = open().await.unwrap();
+        assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(second.headers()[header::RETRY_AFTER], "1");
+        // Pages are not counted against streams
+        assert_eq!(get(&app, "/OFTC/%23chan/2025-01-01").await.0, StatusCode::OK);
+
+        drop(first);
+        assert_eq!(open().await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_sse_reports_lag() {
+        let config = crate::Config { sse_channel_capacity: 1, ..crate::Config::default() };
+        let (dir, state) = test_state("sse-lag", &["OFTC/#chan"], config);
+        let resp = app(Arc::clone(&state))
+            .oneshot(Request::get("/OFTC/%23chan/latest").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Three batches before the client reads any: it keeps only the last
+        let today = today_date(state.config.timezone);
+        let path = dir.join(format!("OFTC/#chan/{today}.log"));
+        {
+            let feeds = state.sse_feeds.read().await;
+            for end in 1..=3 {
+                feeds["OFTC/#chan"].publish(path.clone(), vec![(end, format!("line {end}"))]);
+            }
+        }
+        let mut body = resp.into_body().into_data_stream();
+        let lagged = body.next().await.unwrap().unwrap();
+        assert_eq!(String::from_utf8_lossy(&lagged), "event: lagged\ndata: 2\n\n");
+        let last = body.next().await.unwrap().unwrap();
+        assert!(String::from_utf8_lossy(&last).contains("data: line 3"), "{last:?}");
+    }
+
+    #[tokio::test]
+    async fn test_merged_channel_interleaves_sources() {
+        let mut merged = std::collections::BTreeMap::new();
+        merged.insert("all/#chan".to_string(), vec!["OFTC/#chan".to_string(), "Libera/#chan".to_string()]);
+        let config = crate::Config { merged_channels: merged, ..crate::Config::default() };
+        let (dir, state) = test_state("merged", &["OFTC/#chan", "Libera/#chan"], config);
+        std::fs::write(dir.join("Libera/#chan/2025-01-01.log"), "[00:00:00] <b> first\n[00:00:02] <b> last\n").unwrap();
+
+        let channel = find_channel(&state.channels, &["all", "#chan"]).unwrap();
+        let day = read_day(&day_files(channel, "2025-01-01"), UtcOffset::default(), None).unwrap();
+        let texts: Vec<_> = day.lines.iter().map(|(n, l)| (*n, l.time.to_seconds())).collect();
+        assert_eq!(texts, vec![(None, 0), (None, 1), (None, 2)]);
+
+        let (status, body) = get(&app(Arc::clone(&state)), "/all/%23chan/2025-01-01").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.find(">first<").unwrap() < body.find(">hi<").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_about_json() {
+        let (_dir, app) = test_app("about", &["OFTC/#a", "OFTC/#b"], ai_config());
+        let (status, body) = get(&app, "/irc/about.json").await;
+        assert_eq!(status, StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["base_path"], "/irc");
+        assert_eq!(json["ai_enabled"], true);
+        assert_eq!(json["channels"], 2);
+        assert!(!body.contains("api_key"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket() {
+        use std::os::unix::fs::PermissionsExt;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let trusted_proxies = vec![crate::proxy::TrustedProxy::Unix];
+        let config = crate::Config { forwarded_headers: true, trusted_proxies, ..crate::Config::default() };
+        let (dir, state) = test_state("unix-socket", &["OFTC/#chan"], config);
+        let path = dir.join("viewer.sock");
+        // Left behind by a run that did not exit cleanly
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let listener = bind_unix(&path).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o660);
+        tokio::spawn(async move { axum::serve(listener, unix_app(app(state))).await.unwrap() });
+
+        let request = |head: &'static str| {
+            let path = path.clone();
+            async move {
+                let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+                stream.write_all(head.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                response
+            }
+        };
+        let response = request("GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains(r#""channels":1"#), "{response}");
+        // nginx in front of the socket: its forwarded headers are trusted
+        let response = request(concat!(
+            "GET /OFTC/%23chan/feed.xml HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n",
+            "X-Forwarded-Proto: https\r\nX-Forwarded-Host: logs.example.org\r\n\r\n",
+        ))
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("https://logs.example.org/OFTC/%23chan"), "{response}");
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::write(&path, "not a socket").unwrap();
+        assert!(bind_unix(&path).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+    }
+
+    #[tokio::test]
+    async fn test_healthz_and_metrics() {
+        let config = crate::Config {
+            auth: Some(crate::AuthConfig { users: Default::default(), token: Some("tok3n".into()), realm: None }),
+            ..ai_config()
+        };
+        let (_dir, app) = test_app("healthz", &["OFTC/#a", "OFTC/#b"], config);
+        for uri in ["/healthz", "/irc/healthz"] {
+            let (status, body) = get(&app, uri).await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+            let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(json["status"], "ok");
+            assert_eq!(json["channels"], 2);
+            assert_eq!(json["logs_dirs"], 1);
+            assert_eq!(json["ai_enabled"], true);
+        }
+
+        let (status, _) = get(&app, "/irc/OFTC/%23a/2025-01-01").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, body) = get(&app, "/metrics").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("irc_log_viewer_requests_total{status=\"4xx\"} 1\n"), "{body}");
+        assert!(body.contains("irc_log_viewer_ai_sessions 0\n") && body.contains("irc_log_viewer_channels 2\n"), "{body}");
+        let (_, body) = get(&app, "/irc/metrics").await;
+        assert!(body.contains("irc_log_viewer_logs_dirs 1\n"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_search_stream_emits_matches_then_done() {
+        let (dir, app) = test_app("search-stream", &["OFTC/#chan"], crate::Config::default());
+        std::fs::write(dir.join("OFTC/#chan/2025-01-02.log"), "[00:00:01] <a> hi again\n").unwrap();
+        let (status, body) = get(&app, "/OFTC/%23chan/search/stream?q=HI").await;
+        assert_eq!(status, StatusCode::OK);
+        let newest = body.find("2025-01-02").unwrap();
+        assert!(newest < body.find("2025-01-01").unwrap());
+        assert!(body.ends_with("event: done\ndata: 2\n\n"));
+        let (status, _) = get(&app, "/OFTC/%23chan/search/stream").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_search_invalid_regex_is_reported() {
+        let (_dir, app) = test_app("search-regex", &["OFTC/#chan"], crate::Config::default());
+        let (status, body) = get(&app, "/OFTC/%23chan/search?q=(hi&regex=1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("invalid search: "));
+        let (_, body) = get(&app, "/OFTC/%23chan/search?q=^.00:00:01.%20%3Ca%3E&regex=1").await;
+        assert!(body.contains("2025-01-01"));
+        let (_, body) = get(&app, "/OFTC/%23chan/search/stream?q=(hi&regex=1").await;
+        assert!(body.starts_with("event: search_error\n"));
+    }
+
+    #[tokio::test]
+    async fn test_search_filters_and_context() {
+        let (dir, app) = test_app("search-filters", &["OFTC/#chan"], crate::Config::default());
+        let log = "[10:00:00] <alice> hi there\n[10:01:00] <bob> between\n\
+                   [10:02:00] <bob> hi back\n[10:03:00] <carol> after\n";
+        std::fs::write(dir.join("OFTC/#chan/2025-01-01.log"), log).unwrap();
+
+        let (_, body) = get(&app, "/OFTC/%23chan/search?q=hi&nick=ALI&C=&msg=").await;
+        assert!(body.contains("there") && !body.contains("back"), "{body}");
+        assert!(body.contains(r#"name="nick" value="ALI""#));
+        let (_, body) = get(&app, "/OFTC/%23chan/search/stream?q=hi&nick=bob").await;
+        assert!(body.contains("back") && !body.contains("there"), "{body}");
+        let (_, body) = get(&app, "/OFTC/%23chan/search?q=hi&from=2025-01-02").await;
+        assert!(body.contains("no results"), "{body}");
+
+        // One line of context joins both matches into one block
+        let (_, body) = get(&app, "/OFTC/%23chan/search?q=hi&C=1").await;
+        assert_eq!(body.matches(r#"class="result-group""#).count(), 1, "{body}");
+        assert!(body.contains("2 matches"));
+        assert_eq!(body.matches(r#"class="line context""#).count(), 2, "{body}");
+        assert!(body.contains("after"));
+    }
+
+    #[tokio::test]
+    async fn test_log_page_window_is_bounded() {
+        let (dir, app) = test_app("window", &["OFTC/#chan"], crate::Config::default());
+        let log: String = (0..50_000).map(|i| format!("[{:02}:{:02}:{:02}] <a> line {i}\n", i / 3600 % 24, i / 60 % 60, i % 60)).collect();
+        std::fs::write(dir.join("OFTC/#chan/2025-01-01.log"), log).unwrap();
+
+        let (status, body) = get(&app, "/OFTC/%23chan/2025-01-01?from=01:00:00&limit=100").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.matches("class=\"line\"").count(), 100);
+        assert!(body.len() < 100_000);
+        assert!(body.contains("id=\"T010000\""));
+        assert!(body.contains("?from=00:58:20&amp;limit=100"));
+        assert!(body.contains("?from=01:01:40&amp;limit=100"));
+
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-01").await;
+        assert_eq!(body.matches("class=\"line\"").count(), 50_000);
+    }
+
+    #[tokio::test]
+    async fn test_untimed_lines_keep_time_order() {
+        let (dir, app) = test_app("untimed", &["OFTC/#chan"], crate::Config::default());
+        let log = "[10:00:00] <a> one\nuntimed\n[10:05:00] <a> two\nalso untimed\n[10:06:00] <a> three\n";
+        std::fs::write(dir.join("OFTC/#chan/2025-01-01.log"), log).unwrap();
+
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-01?from=10:05:00&limit=2").await;
+        assert!(body.contains("two") && body.contains("also untimed"), "{body}");
+        assert!(!body.contains(r#"<span class="msg">one"#) && !body.contains("three"), "{body}");
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-01/json").await;
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json[1]["time"], "10:00:00");
+        assert_eq!(json[3]["time"], "10:05:00");
+    }
+
+    #[tokio::test]
+    async fn test_day_json() {
+        let (_dir, app) = test_app("day-json", &["OFTC/#chan"], crate::Config::default());
+        let (status, body) = get(&app, "/OFTC/%23chan/2025-01-01/json").await;
+        assert_eq!(status, StatusCode::OK);
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json, serde_json::json!([{"time": "00:00:01", "kind": "message", "nick": "a", "text": "hi"}]));
+
+        let (status, body) = get(&app, "/OFTC/%23chan/2025-01-02/json").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, r#"{"error":"no log for 2025-01-02"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_dates_json_and_calendar() {
+        let (dir, state) = test_state("dates", &["OFTC/#chan"], crate::Config::default());
+        std::fs::write(dir.join("OFTC/#chan/2025-02-03.log"), "[00:00:01] <a> one\n[00:00:02] <b> two\n").unwrap();
+        let app = app(Arc::clone(&state));
+        let channel = find_channel(&state.channels, &["OFTC", "#chan"]).unwrap();
+
+        let (status, body) = get(&app, "/OFTC/%23chan/dates.json").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_str::<Vec<String>>(&body).unwrap(), channel_dates(channel));
+
+        let (status, body) = get(&app, "/OFTC/%23chan/calendar").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("2025-02"));
+        assert!(body.contains("title=\"2025-02-03: 2 messages\""));
+        assert_eq!(state.message_counts.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_search_counts_per_day() {
+        let (dir, app) = test_app("search-counts", &["OFTC/#chan"], crate::Config::default());
+        std::fs::write(dir.join("OFTC/#chan/2025-01-02.log"), "[10:00:00] <b> hi\n[10:01:00] <c> hi hi\n").unwrap();
+
+        let (status, body) = get(&app, "/OFTC/%23chan/search?q=hi+there&counts=1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("no results"), "{body}");
+        let (_, body) = get(&app, "/OFTC/%23chan/search?q=hi&counts=1&case=1").await;
+        assert!(body.contains(r#"<p class="search-summary">3 matches on 2 days</p>"#), "{body}");
+        assert!(body.contains(r#"title="2025-01-02: 2 matches" style="height: 100%""#));
+        assert!(body.contains(r#"title="2025-01-01: 1 matches" style="height: 50%""#));
+        // Busiest day first, with a link to the same search on that day
+        let first = body.find("2025-01-02&amp;to=2025-01-02\">2 matches").unwrap();
+        assert!(first < body.find("2025-01-01&amp;to=2025-01-01\">1 matches").unwrap());
+        assert!(body.contains("search?q=hi&amp;case=1&amp;from=2025-01-02"), "{body}");
+        assert!(!body.contains(r#"<div class="line">"#));
+        assert!(!body.contains("search stopped early"));
+
+        let (_, body) = get(&app, "/OFTC/%23chan/search?q=&counts=1").await;
+        assert!(body.contains("invalid search: a search term is required"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_search_counts_file_budget() {
+        let config = crate::Config { search_all_max_files: 1, ..Default::default() };
+        let (dir, app) = test_app("search-counts-budget", &["OFTC/#chan"], config);
+        std::fs::write(dir.join("OFTC/#chan/2025-01-02.log"), "[10:00:00] <b> hi\n").unwrap();
+
+        let (_, body) = get(&app, "/OFTC/%23chan/search?q=hi&counts=1").await;
+        assert!(body.contains("1 matches on 1 days (search stopped early"), "{body}");
+        assert!(body.contains("2025-01-02: 1 matches"));
+        assert!(!body.contains("2025-01-01: 1 matches"));
+    }
+
+    #[tokio::test]
+    async fn test_nicks_json() {
+        let (dir, app) = test_app("nicks", &["OFTC/#chan"], crate::Config::default());
+        let day = "[10:00:00] <Alice> one\n[10:01:00] * Bob waves\n[10:02:00] *** Joins: carol (c@host)\n\
+                   [10:03:00] <Alice> two\n[10:04:00] *** bob is now known as bobby\n";
+        std::fs::write(dir.join("OFTC/#chan/2025-01-02.log"), day).unwrap();
+
+        let (status, body) = get(&app, "/OFTC/%23chan/nicks.json").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_str::<Vec<String>>(&body).unwrap(), ["Alice", "Bob", "a"]);
+        let (_, body) = get(&app, "/OFTC/%23chan/search").await;
+        assert!(body.contains(r#"list="search-nicks""#) && body.contains(r#"<datalist id="search-nicks">"#));
+    }
+
+    #[tokio::test]
+    async fn test_sidebar_collapses_past_open_depth() {
+        let channels = ["OFTC/#chan", "Libera/team/#deep"];
+        let deep = r#"href="/Libera/team/%23deep/today""#;
+        let (_dir, app) = test_app("sidebar-full", &channels, crate::Config::default());
+        let (_, body) = get(&app, "/").await;
+        assert!(body.contains(deep) && !body.contains("data-subtree="), "{body}");
+
+        let config = crate::Config { sidebar_open_depth: Some(1), ..Default::default() };
+        let (_dir, app) = test_app("sidebar-depth", &channels, config);
+        let (_, body) = get(&app, "/").await;
+        assert!(body.contains(r#"<details class="tree-node" open><summary><span class="tree-label">Libera</span>"#));
+        let collapsed = r#"<details class="tree-node" data-path="/Libera/team/" data-subtree="/sidebar/Libera/team">"#;
+        let label = r#"<summary><span class="tree-label">team</span></summary>"#;
+        assert!(body.contains(&format!("{collapsed}{label}</details>")), "{body}");
+        assert!(!body.contains(deep), "{body}");
+
+        let (status, subtree) = get(&app, "/sidebar/Libera/team").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(subtree.starts_with("<ul><li>") && subtree.contains(deep), "{subtree}");
+        assert_eq!(get(&app, "/sidebar/Libera/nope").await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_channel_refs_link_same_network() {
+        let (dir, app) = test_app("channel-refs", &["OFTC/#chan", "OFTC/#other", "Libera/#elsewhere"], ai_config());
+        std::fs::write(dir.join("OFTC/#chan/2025-01-01.log"), "[00:00:01] <a> see #other, not #elsewhere\n").unwrap();
+
+        let (_, body) = get(&app, "/irc/OFTC/%23chan/2025-01-01").await;
+        let link = r#"<a class="channel-ref" href="/irc/OFTC/%23other/today">#other</a>"#;
+        assert!(body.contains(&format!("see {link}, not #elsewhere")), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_sidebar_latest_dates() {
+        let config = crate::Config {
+            merged_channels: [("all/#chan".into(), vec!["OFTC/#chan".into(), "Libera/#chan".into()])].into(),
+            ..Default::default()
+        };
+        let dir = LogTree::new("latest");
+        dir.write("OFTC/#chan/2025-01-01.log", "[00:00:01] <a> hi\n");
+        dir.write("Libera/#chan/2025-01-01.log", "[00:00:01] <a> hi\n");
+        dir.write("Libera/#chan/2025-03-04.log", "[00:00:01] <b> yo\n");
+        let app = app(Arc::new(dir.state(config)));
+
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-01").await;
+        // Cached pages only carry a placeholder the sidebar script fills in
+        let placeholder = r#"<time class="tree-latest" data-channel="OFTC/#chan"></time>"#;
+        assert!(body.contains(&format!(r#"href="/OFTC/%23chan/today">#chan</a> {placeholder}"#)), "{body}");
+        assert!(!body.contains("2025-03-04"));
+
+        let resp = app.clone().oneshot(Request::get("/latest.json").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(resp.headers()[header::CACHE_CONTROL], "no-cache");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let dates: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(dates["OFTC/#chan"], "2025-01-01");
+        assert_eq!(dates["Libera/#chan"], "2025-03-04");
+        // A merged channel is as recent as its newest source
+        assert_eq!(dates["all/#chan"], "2025-03-04");
+    }
+
+    #[tokio::test]
+    async fn test_excluded_channels_are_unreachable() {
+        let channels = ["OFTC/#chan", "OFTC/#secret", "OFTC/bridge", "OFTC/query42"];
+        let config = crate::Config { exclude_channels: vec!["*/#secret".into()], ..Default::default() };
+        let (dir, state) = test_state("exclude", &channels, config);
+        assert!(find_channel(&state.channels, &["OFTC", "#chan"]).is_some());
+        assert!(find_channel(&state.channels, &["OFTC", "#secret"]).is_none());
+        // The `#` heuristic still hides query logs when only excluding
+        assert!(find_channel(&state.channels, &["OFTC", "bridge"]).is_none());
+        let app = app(state);
+        assert_eq!(get(&app, "/OFTC/%23secret/2025-01-01").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(get(&app, "/OFTC/%23secret/2025-01-01/raw").await.0, StatusCode::NOT_FOUND);
+        let (_, body) = get(&app, "/search?q=hi").await;
+        assert!(body.contains("#chan") && !body.contains("#secret"), "{body}");
+
+        let config = crate::Config {
+            include_channels: vec!["OFTC/#*".into(), "OFTC/bridge".into()],
+            exclude_channels: vec!["*/#secret".into()],
+            ..Default::default()
+        };
+        let state = dir.state(config);
+        let oftc = &state.channels.children["OFTC"];
+        assert_eq!(oftc.children.keys().collect::<Vec<_>>(), ["#chan", "bridge"]);
+    }
+
+    #[tokio::test]
+    async fn test_log_path_stays_in_channel_dir() {
+        let (dir, state) = test_state("traversal", &["OFTC/#chan"], crate::Config::default());
+        std::fs::write(dir.join("OFTC/secret.log"), "[00:00:01] <a> secret\n").unwrap();
+        let channel = find_channel(&state.channels, &["OFTC", "#chan"]).unwrap();
+        assert!(resolve_log_path(channel, "../secret").is_none());
+        assert!(resolve_log_path(channel, "2025-01-01").is_some());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_log_symlinks_stay_in_channel_dir() {
+        let (dir, state) = test_state("symlinks", &["OFTC/#chan"], crate::Config::default());
+        std::fs::write(dir.join("OFTC/secret.log"), "[00:00:01] <a> secret\n").unwrap();
+        let chan = dir.join("OFTC/#chan");
+        std::os::unix::fs::symlink("../secret.log", chan.join("2025-01-02.log")).unwrap();
+        std::os::unix::fs::symlink("2025-01-01.log", chan.join("2025-01-03.log")).unwrap();
+        let channel = find_channel(&state.channels, &["OFTC", "#chan"]).unwrap();
+        assert!(resolve_log_path(channel, "2025-01-02").is_none());
+        assert!(resolve_log_path(channel, "2025-01-03").is_some());
+        assert_eq!(channel_dates(channel), ["2025-01-01", "2025-01-03"]);
+        let app = app(state);
+        assert_eq!(get(&app, "/OFTC/%23chan/2025-01-02/raw").await.0, StatusCode::NOT_FOUND);
+    }
+
+    /// Shares what a subscriber writes with the test.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_traced() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::INFO)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let (_dir, app) = test_app("trace", &["OFTC/#chan"], crate::Config::default());
+        assert_eq!(get(&app, "/OFTC/%23chan/2025-01-01?view=plain").await.0, StatusCode::OK);
+        assert_eq!(get(&app, "/nope").await.0, StatusCode::NOT_FOUND);
+        let log = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = log.lines().filter(|l| l.contains("finished processing request")).collect();
+        assert_eq!(lines.len(), 2, "{log}");
+        assert!(lines[0].contains("request{method=GET path=/OFTC/%23chan/2025-01-01}"), "{log}");
+        assert!(lines[0].contains("status=200") && lines[0].contains("latency="), "{log}");
+        assert!(lines[1].contains("path=/nope") && lines[1].contains("status=404"), "{log}");
+
+        // The client address comes from X-Forwarded-For when the peer is a trusted proxy
+        let proxy = "127.0.0.1".to_string().try_into().unwrap();
+        let config = crate::Config { trusted_proxies: vec![proxy], ..Default::default() };
+        let (_dir, app) = test_app("trace-client", &["OFTC/#chan"], config);
+        for peer in ["127.0.0.1:5000", "192.0.2.7:5000"] {
+            let request = Request::get("/healthz").header("x-forwarded-for", "203.0.113.9");
+            let mut request = request.body(Body::empty()).unwrap();
+            request.extensions_mut().insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+            app.clone().oneshot(request).await.unwrap();
+        }
+        let log = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(log.contains("path=/healthz client=203.0.113.9}"), "{log}");
+        assert!(log.contains("path=/healthz client=192.0.2.7}"), "{log}");
+    }
+
+    #[tokio::test]
+    async fn test_log_page_opengraph() {
+        let (_dir, app) = test_app("opengraph", &["OFTC/#chan"], crate::Config::default());
+        let (status, body) = get(&app, "/OFTC/%23chan/2025-01-01").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r##"<meta property="og:title" content="#chan — 2025-01-01">"##), "{body}");
+        assert!(body.contains(r#"<meta property="og:description" content="&lt;a&gt; hi">"#), "{body}");
+        let url = r#"<meta property="og:url" content="http://localhost/OFTC/%23chan/2025-01-01">"#;
+        assert!(body.contains(url), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_page_cache_ignores_host() {
+        let (dir, state) = test_state("cache-host", &["OFTC/#chan"], crate::Config::default());
+        let mut state = Arc::try_unwrap(state).ok().unwrap();
+        state.config.cache_dir = Some(dir.join("cache"));
+        state.page_cache = crate::cache::PageCache::new(&state.config, &state.channels);
+        let app = app(Arc::new(state));
+        let with_host = |host: &'static str| {
+            let req = Request::get("/OFTC/%23chan/2025-01-01").header(header::HOST, host).body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                String::from_utf8(axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+            }
+        };
+        for host in ["a.example", "b.example", "a.example", "evil\"><x"] {
+            let body = with_host(host).await;
+            let host = if host.starts_with("evil") { "localhost" } else { host };
+            let url = format!(r#"<meta property="og:url" content="http://{host}/OFTC/%23chan/2025-01-01">"#);
+            assert!(body.contains(&url), "{body}");
+        }
+        let entries: Vec<_> = std::fs::read_dir(dir.join("cache"))
+            .unwrap()
+            .flat_map(|tag| std::fs::read_dir(tag.unwrap().path()).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_log_page_day_links_for_keyboard() {
+        let (dir, app) = test_app("day-links", &["OFTC/#chan"], crate::Config::default());
+        std::fs::write(dir.join("OFTC/#chan/2025-01-02.log"), "[00:00:01] <b> next\n").unwrap();
+
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-02").await;
+        assert!(body.contains(r#"<a rel="prev" href="/OFTC/%23chan/2025-01-01""#));
+        assert!(!body.contains(r#"rel="next""#));
+        assert!(body.contains(r#"id="today-link""#) && body.contains(r#"id="log-search""#));
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-01").await;
+        assert!(body.contains(r#"<a rel="next" href="/OFTC/%23chan/2025-01-02""#));
+    }
+
+    #[tokio::test]
+    async fn test_log_page_line_anchors_for_ranges() {
+        let (dir, app) = test_app("range-anchors", &["OFTC/#chan"], crate::Config::default());
+        std::fs::write(dir.join("OFTC/#chan/2025-01-02.log"), "[12:00:00] <a> one\n[12:05:30] <b> two\n").unwrap();
+
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-02").await;
+        // The range script compares these ids as strings, so they must sort by time
+        let first =
+            r##"id="T120000" data-nick="a" data-raw="[12:00:00] &lt;a&gt; one"><a class="ts" href="#T120000">"##;
+        let second =
+            r##"id="T120530" data-nick="b" data-raw="[12:05:30] &lt;b&gt; two"><a class="ts" href="#T120530">"##;
+        assert!(body.contains(first), "{body}");
+        assert!(body.contains(second));
+        assert!(body.contains("range-hl"));
+    }
+
+    #[tokio::test]
+    async fn test_ask_page_tallies_usage() {
+        let (_dir, app) = test_app("ask-usage", &["OFTC/#chan"], ai_config());
+        let (status, body) = get(&app, "/irc/OFTC/%23chan/ask").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#"<div id="ask-usage" style="display:none">"#), "{body}");
+        let tally = body.find("var tally").unwrap();
+        let listener = body.find("src.addEventListener('usage'").unwrap();
+        assert!(tally < listener && body[listener..].contains("usage.textContent = 'tokens: '"), "{body}");
+        let (_, body) = get(&app, "/irc/OFTC/%23chan/search?q=hi").await;
+        assert!(!body.contains("'usage'") && !body.contains("tally"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_channel_overrides_in_pages() {
+        let mut config = ai_config();
+        let settings = crate::ChannelOverrides { hide_events_default: true, ai_enabled: Some(false), ..Default::default() };
+        config.channels.insert("OFTC/#chan".into(), settings);
+        let (_dir, app) = test_app("overrides", &["OFTC/#chan"], config);
+
+        let (_, body) = get(&app, "/irc/OFTC/%23chan/2025-01-01").await;
+        assert!(body.contains(r#"<div id="log" class="hide-events""#));
+        assert!(!body.contains("/ask\""));
+        let (_, body) = get(&app, "/irc/OFTC/%23chan/2025-01-01?view=all").await;
+        assert!(!body.contains("hide-events\""));
+        let (status, _) = get(&app, "/irc/OFTC/%23chan/ask").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_window_links_keep_view() {
+        let mut config = crate::Config::default();
+        let settings = crate::ChannelOverrides { hide_events_default: true, ..Default::default() };
+        config.channels.insert("OFTC/#chan".into(), settings);
+        let (dir, app) = test_app("window-view", &["OFTC/#chan"], config);
+        let log = "[00:00:01] <a> one\n[00:00:02] *** Joins: b (b@host)\n[00:00:03] <a> three\n";
+        std::fs::write(dir.join("OFTC/#chan/2025-01-01.log"), log).unwrap();
+
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-01?from=00:00:02&limit=1&view=all").await;
+        assert!(body.contains(r#"href="?from=00:00:01&amp;limit=1&amp;view=all">↑ load earlier"#), "{body}");
+        assert!(body.contains(r#"href="?from=00:00:03&amp;limit=1&amp;view=all">↓ load later"#), "{body}");
+        // The channel's default view needs no parameter
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-01?from=00:00:02&limit=1").await;
+        assert!(body.contains(r#"href="?from=00:00:03&amp;limit=1">↓ load later"#), "{body}");
+    }
+
+    #[test]
+    fn test_gzip_log() {
+        let (dir, state) = test_state("gzip", &["OFTC/#chan"], crate::Config::default());
+        let day = "[00:00:01] <a> one\n[00:00:02] * b waves\n[00:00:03] *** Joins: c (c@host)\n";
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        io::Write::write_all(&mut gz, day.as_bytes()).unwrap();
+        std::fs::write(dir.join("OFTC/#chan/2025-01-02.log.gz"), gz.finish().unwrap()).unwrap();
+        std::fs::write(dir.join("OFTC/#chan/2025-01-03.log"), day).unwrap();
+        let channel = state.channels.children["OFTC"].children["#chan"].channel.clone().unwrap();
+
+        assert_eq!(channel_dates(&channel), ["2025-01-01", "2025-01-02", "2025-01-03"]);
+        let (path, _) = resolve_log_path(&channel, "2025-01-02").unwrap();
+        assert_eq!(read_log_file(&path).unwrap(), day);
+        let lines = |date| read_day(&day_files(&channel, date), UtcOffset::default(), None).unwrap().lines;
+        assert_eq!(lines("2025-01-02").len(), 3);
+        assert_eq!(lines("2025-01-02"), lines("2025-01-03"));
+
+        std::fs::write(dir.join("OFTC/#chan/2025-01-04.log"), day.replace('\n', "\r\n")).unwrap();
+        std::fs::write(dir.join("OFTC/#chan/2025-01-05.log"), day.replace('\n', "\r")).unwrap();
+        assert_eq!(lines("2025-01-04"), lines("2025-01-03"));
+        assert_eq!(lines("2025-01-05"), lines("2025-01-03"));
+    }
+
+    #[tokio::test]
+    async fn test_media_previews_per_channel() {
+        let mut config = crate::Config { media_previews: true, ..Default::default() };
+        config.channels.insert("OFTC/#quiet".into(), crate::ChannelOverrides { media_previews: Some(false), ..Default::default() });
+        let (dir, app) = test_app("media-previews", &["OFTC/#chan", "OFTC/#quiet"], config);
+        for chan in ["#chan", "#quiet"] {
+            std::fs::write(dir.join("OFTC").join(chan).join("2025-01-02.log"), "[00:00:01] <a> https://x.org/cat.jpg\n").unwrap();
+        }
+
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-02").await;
+        assert!(body.contains(r#"<img src="https://x.org/cat.jpg" loading="lazy""#), "{body}");
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-02/fragment").await;
+        assert!(body.contains(r#"loading="lazy""#), "{body}");
+        let (_, body) = get(&app, "/OFTC/%23quiet/2025-01-02").await;
+        assert!(body.contains(r#"<a href="https://x.org/cat.jpg""#) && !body.contains(r#"<img src="https://x.org/cat.jpg""#), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_raw_range() {
+        let (dir, app) = test_app("raw-range", &["OFTC/#chan"], crate::Config::default());
+        let day: String = (0..20).map(|i| format!("[00:00:{i:02}] <a> line {i}\n")).collect();
+        std::fs::write(dir.join("OFTC/#chan/2025-01-02.log.zst"), zstd::encode_all(day.as_bytes(), 3).unwrap()).unwrap();
+        let ranged = |range: &str| {
+            let req = Request::get("/OFTC/%23chan/2025-01-02/raw").header(header::RANGE, range).body(Body::empty()).unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let resp = ranged("bytes=0-99").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers()[header::CONTENT_RANGE], format!("bytes 0-99/{}", day.len()));
+        assert!(!resp.headers().contains_key(header::CONTENT_ENCODING));
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], &day.as_bytes()[..100]);
+
+        let resp = ranged("bytes=-10").await.unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], &day.as_bytes()[day.len() - 10..]);
+        let resp = ranged(&format!("bytes={}-", day.len() - 5)).await.unwrap();
+        assert_eq!(resp.headers()[header::CONTENT_RANGE], format!("bytes {}-{1}/{2}", day.len() - 5, day.len() - 1, day.len()));
+
+        let resp = ranged(&format!("bytes={}-", day.len())).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(resp.headers()[header::CONTENT_RANGE], format!("bytes */{}", day.len()));
+        let resp = ranged("bytes=0-1,5-9").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::ACCEPT_RANGES], "bytes");
+    }
+
+    #[tokio::test]
+    async fn test_auth_required() {
+        let config = crate::Config {
+            auth: Some(crate::AuthConfig {
+                users: [("alice".into(), "secret".into())].into(),
+                token: Some("tok3n".into()),
+                realm: None,
+            }),
+            ..Default::default()
+        };
+        let (dir, app) = test_app("auth", &["OFTC/#chan"], config);
+        let with = |uri: &str, authorization: &str| {
+            let req = Request::get(uri).header(header::AUTHORIZATION, authorization).body(Body::empty()).unwrap();
+            app.clone().oneshot(req)
+        };
+
+        for uri in ["/", "/OFTC/%23chan/2025-01-01", "/OFTC/%23chan/latest", "/static/style.css"] {
+            let resp = app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{uri}");
+            assert_eq!(resp.headers()[header::WWW_AUTHENTICATE], r#"Basic realm="IRC Logs", charset="UTF-8""#);
+        }
+        // "alice:secret"
+        let resp = with("/OFTC/%23chan/2025-01-01", "Basic YWxpY2U6c2VjcmV0").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        // Never left in a shared cache, which would serve it without credentials
+        assert_eq!(resp.headers()[header::CACHE_CONTROL], "private, max-age=31536000, immutable");
+        let recent = date_days_ago(2, UtcOffset::default());
+        std::fs::write(dir.join(format!("OFTC/#chan/{recent}.log")), "[00:00:01] <a> hi\n").unwrap();
+        let resp = with(&format!("/OFTC/%23chan/{recent}"), "Bearer tok3n").await.unwrap();
+        assert_eq!(resp.headers()[header::CACHE_CONTROL], "private, max-age=3600");
+        let resp = with("/OFTC/%23chan/2025-01-01", "Bearer tok3n").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        // "alice:wrong"
+        let resp = with("/OFTC/%23chan/2025-01-01", "Basic YWxpY2U6d3Jvbmc=").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_missing_date_redirects_to_nearest() {
+        let (dir, app) = test_app("nearest-date", &["OFTC/#chan"], crate::Config::default());
+        for date in ["2025-01-10", "2025-02-01"] {
+            std::fs::write(dir.join(format!("OFTC/#chan/{date}.log")), "[00:00:01] <a> hi\n").unwrap();
+        }
+        let location = |resp: &Response| resp.headers()[header::LOCATION].to_str().unwrap().to_string();
+
+        let resp = app.clone().oneshot(Request::get("/OFTC/%23chan/2025-01-04").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(location(&resp), "/OFTC/%23chan/2025-01-01?missing=2025-01-04");
+        let resp = app.clone().oneshot(Request::get("/OFTC/%23chan/2025-01-07?view=events").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(location(&resp), "/OFTC/%23chan/2025-01-10?missing=2025-01-07&view=events");
+        // Equally far: the earlier day wins
+        let resp = app.clone().oneshot(Request::get("/OFTC/%23chan/2025-01-21").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(location(&resp), "/OFTC/%23chan/2025-01-10?missing=2025-01-21");
+        let resp = app.clone().oneshot(Request::get("/OFTC/%23chan/2026-01-01").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(location(&resp), "/OFTC/%23chan/2025-02-01?missing=2026-01-01");
+        let resp = app.clone().oneshot(Request::get("/OFTC/%23chan/2024-06-01").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(location(&resp), "/OFTC/%23chan/2025-01-01?missing=2024-06-01");
+
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-01?missing=2025-01-04").await;
+        assert!(body.contains(r#"<div id="date-notice" hidden></div>"#));
+    }
+
+    #[test]
+    fn test_date_to_epoch_days() {
+        for days in [0, 59, 60, 365, 10_957, 20_089, 20_148] {
+            assert_eq!(date_to_epoch_days(&epoch_days_to_date(days)), Some(days as i64));
+        }
+    }
+
+    #[test]
+    fn test_parse_log_file_name() {
+        let date = |p: &str| Some(("", p.to_string()));
+        assert_eq!(parse_log_file_name("2025-02-01.log"), date("2025-02-01"));
+        assert_eq!(parse_log_file_name("2025-02-01.log.zst"), date("2025-02-01"));
+        assert_eq!(parse_log_file_name("20250201.log"), date("2025-02-01"));
+        assert_eq!(parse_log_file_name("#chan.2025-02-01.log"), Some(("#chan", "2025-02-01".into())));
+        assert_eq!(parse_log_file_name("chan_2025-02-01.log"), Some(("chan", "2025-02-01".into())));
+        assert_eq!(parse_log_file_name("#chan-20250201.log.zst"), Some(("#chan", "2025-02-01".into())));
+        assert_eq!(parse_log_file_name("#café.2025-02-01.log"), Some(("#café", "2025-02-01".into())));
+        for name in ["2025-02-01.txt", "20251301.log", "20250232.log", "x20250201.log", ".2025-02-01.log", "é2025-02-01.log", "12345678901.log"] {
+            assert_eq!(parse_log_file_name(name), None, "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_log_file_name_patterns() {
+        let dir = LogTree::new("file-names");
+        for (path, line) in [
+            ("OFTC/#compact/20250201.log", "[00:00:01] <a> compact\n"),
+            ("OFTC/#supy/#supy.2025-02-01.log", "2025-02-01T00:00:01  <a> dotted\n"),
+            ("OFTC/#supy/#supy.2025-02-02.log", "2025-02-02T00:00:01  <a> dotted again\n"),
+            ("Libera/shared/one_2025-02-01.log", "[00:00:01] <a> first channel\n"),
+            ("Libera/shared/two_2025-02-01.log", "[00:00:01] <b> second channel\n"),
+        ] {
+            dir.write(path, line);
+        }
+        let state = Arc::new(dir.state(Default::default()));
+        let channel = |segments: &[&str]| {
+            let node = segments.iter().fold(&state.channels, |node, s| &node.children[*s]);
+            node.channel.clone().unwrap()
+        };
+        assert_eq!(channel_dates(&channel(&["OFTC", "#compact"])), ["2025-02-01"]);
+        assert_eq!(channel_dates(&channel(&["OFTC", "#supy"])), ["2025-02-01", "2025-02-02"]);
+        assert_eq!(channel_dates(&channel(&["Libera", "shared", "one"])), ["2025-02-01"]);
+        assert!(state.channels.children["Libera"].children["shared"].channel.is_none());
+        assert!(resolve_log_path(&channel(&["Libera", "shared", "two"]), "2025-02-01").unwrap().0.ends_with("two_2025-02-01.log"));
+
+        let app = app(Arc::clone(&state));
+        for (uri, text) in [
+            ("/OFTC/%23compact/2025-02-01", "compact"),
+            ("/OFTC/%23supy/2025-02-02", "dotted again"),
+            ("/Libera/shared/one/2025-02-01", "first channel"),
+            ("/Libera/shared/two/2025-02-01", "second channel"),
+        ] {
+            let (status, body) = get(&app, uri).await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+            assert!(body.contains(&format!(r#"<span class="msg">{text}</span>"#)), "{uri}: {body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mixed_formats_in_one_channel() {
+        let (dir, app) = test_app("mixed-formats", &["OFTC/#chan"], crate::Config::default());
+        std::fs::write(dir.join("OFTC/#chan/2025-01-02.log"), "2025-01-02T10:00:00Z <b> after the move\n").unwrap();
+
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-01").await;
+        assert!(body.contains(r#"<span class="msg">hi</span>"#), "{body}");
+        let (_, body) = get(&app, "/OFTC/%23chan/2025-01-02").await;
+        assert!(body.contains(r#"id="T100000""#) && body.contains(r#"<span class="msg">after the move</span>"#), "{body}");
+        let (_, body) = get(&app, "/OFTC/%23chan/search/stream?q=move").await;
+        assert!(body.contains("after the <mark>move</mark>"), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_stats() {
+        let (dir, state) = test_state("stats", &["OFTC/#chan"], crate::Config::default());
+        let day = "[09:00:00] <alice> one\n[09:30:00] * alice waves\n[10:00:00] <bob> two\n\
+                   [10:01:00] *** Joins: carol (c@host)\n[10:02:00] *** Quits: carol (c@host) (bye)\n";
+        std::fs::write(dir.join("OFTC/#chan/2025-01-02.log"), day).unwrap();
+        let channel = state.channels.children["OFTC"].children["#chan"].channel.clone().unwrap();
+
+        let stats = crate::stats::channel_stats(&state, &channel, &channel_dates(&channel));
+        assert_eq!(stats.days, 2);
+        assert_eq!(stats.total.top_nicks(), [("alice", 2), ("a", 1), ("bob", 1)]);
+        assert_eq!((stats.total.hours[0], stats.total.hours[9], stats.total.hours[10]), (1, 2, 1));
+        assert_eq!((stats.total.joins, stats.total.quits), (1, 1));
+
+        let app = app(state);
+        let (status, body) = get(&app, "/OFTC/%23chan/stats").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("4 messages from 3 nicks over 2 days (2025-01-01 to 2025-01-02)"), "{body}");
+        let (_, body) = get(&app, "/OFTC/%23chan/stats?from=2025-01-02&to=").await;
+        assert!(body.contains("3 messages from 2 nicks over 1 day"), "{body}");
+        let (status, _) = get(&app, "/OFTC/%23chan/stats?from=yesterday").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_day_fragment() {
+        let (dir, app) = test_app("fragment", &["OFTC/#chan"], crate::Config::default());
+        std::fs::write(dir.join("OFTC/#chan/2025-01-02.log"), "[00:00:01] <b> next\n").unwrap();
+
+        let resp = app.clone().oneshot(Request::get("/OFTC/%23chan/2025-01-02/fragment").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["x-prev-date"], "2025-01-01");
+        let body = String::from_utf8(axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap();
+        assert!(body.starts_with(r#"<div class="line" id="T000001""#), "{body}");
+        assert!(body.contains("next"));
+        for chrome in ["<html", "<head", "sidebar", "log-header", "<script"] {
+            assert!(!body.contains(chrome), "{chrome} in fragment");
+        }
+
+        let resp = app.clone().oneshot(Request::get("/OFTC/%23chan/2025-01-01/fragment").body(Body::empty()).unwrap()).await.unwrap();
+        assert!(!resp.headers().contains_key("x-prev-date"));
+        let (status, _) = get(&app, "/OFTC/%23chan/2024-12-31/fragment").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    /// Checks that tags nest and close properly and that every `&` starts
+    /// an entity, which is what makes the feed well-formed XML.
+    fn assert_well_formed(xml: &str) {
+        let body = xml.strip_prefix("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n").expect("xml declaration");
+        let mut stack = Vec::new();
+        let mut rest = body;
+        while let Some(start) = rest.find('<') {
+            let end = rest[start..].find('>').expect("unclosed tag") + start;
+            let tag = &rest[start + 1..end];
+            assert!(!tag.contains('<'), "stray < in {tag}");
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(stack.pop(), Some(name), "mismatched </{name}>");
+            } else if !tag.ends_with('/') {
+                stack.push(tag.split_whitespace().next().unwrap());
+            }
+            rest = &rest[end + 1..];
+        }
+        assert!(stack.is_empty(), "unclosed {stack:?}");
+        for (i, _) in body.match_indices('&') {
+            let entity = &body[i..body[i..].find(';').map_or(body.len(), |e| i + e + 1)];
+            assert!(["&amp;", "&lt;", "&gt;", "&quot;"].contains(&entity), "bad entity {entity}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_atom_feed() {
+        let (dir, app) = test_app("feed", &["OFTC/#chan"], ai_config());
+        std::fs::write(
+            dir.join("OFTC/#chan/2025-01-02.log"),
+            "[00:00:01] <a> see https://x.test/?a=1&b=<2>\n[00:00:02] *** Joins: b (b@host)\n\
+             [00:00:03] <b> bye & thanks\n[00:00:04] <c> \x0304,01red\x03 \x02alert\x02\x07\n",
+        )
+        .unwrap();
+
+        let (status, body) = get(&app, "/irc/OFTC/%23chan/feed.xml").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_well_formed(&body);
+        assert_eq!(body.matches("<entry>").count(), 4);
+        assert!(body.contains("<title>&lt;c&gt; red alert</title>"), "{body}");
+        assert!(body.contains("<content type=\"html\">red alert</content>"), "{body}");
+        assert!(!body.contains(|c: char| c.is_control() && c != '\n'));
+        assert!(body.find("bye &amp;amp; thanks").unwrap() < body.find("see https").unwrap());
+        assert!(body.contains("<link href=\"http://localhost/irc/OFTC/%23chan/2025-01-02#T000003\"/>"));
+        assert!(!body.contains("joined"));
+
+        let (_, body) = get(&app, "/irc/OFTC/%23chan/feed.xml?events=1").await;
+        assert_well_formed(&body);
+        assert!(body.contains("<title>b joined</title>"));
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        let (_dir, app) = test_app("compression", &["OFTC/#chan"], crate::Config::default());
+        let request = |uri: &str| Request::get(uri).header(header::ACCEPT_ENCODING, "gzip").body(Body::empty()).unwrap();
+
+        let resp = app.clone().oneshot(request("/OFTC/%23chan/2025-01-01")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_ENCODING], "gzip");
+
+        let resp = app.clone().oneshot(Request::get("/OFTC/%23chan/2025-01-01").body(Body::empty()).unwrap()).await.unwrap();
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+
+        let resp = app.clone().oneshot(request("/OFTC/%23chan/search/stream?q=hi")).await.unwrap();
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/event-stream");
+        assert!(resp.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_search_all_channels() {
+        let (_dir, app) = test_app("search-all", &["OFTC/#a", "Libera/#b"], crate::Config::default());
+
+        let (status, body) = get(&app, "/search?q=hi").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("href=\"/OFTC/%23a/2025-01-01#T000001\""));
+        assert!(body.contains("href=\"/Libera/%23b/2025-01-01#T000001\""));
+
+        let (_, body) = get(&app, "/search?q=hi&scope=OFTC").await;
+        assert!(body.contains("/OFTC/%23a/2025-01-01#T000001"));
+        assert!(!body.contains("/Libera/%23b/2025-01-01#T000001"));
+
+        let (status, _) = get(&app, "/search?q=hi&scope=EFnet").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_search_all_file_budget() {
+        let stopped = "search stopped early";
+        // Exactly enough files for both channels: nothing was left out
+        let config = crate::Config { search_all_max_files: 2, ..crate::Config::default() };
+        let (_dir, app) = test_app("search-all-budget", &["OFTC/#a", "Libera/#b"], config);
+        let (_, body) = get(&app, "/search?q=hi").await;
+        assert!(body.contains("/OFTC/%23a/2025-01-01#T000001") && !body.contains(stopped), "{body}");
+
+        let config = crate::Config { search_all_max_files: 1, ..crate::Config::default() };
+        let (_dir, app) = test_app("search-all-budget", &["OFTC/#a", "Libera/#b"], config);
+        let (_, body) = get(&app, "/search?q=hi").await;
+        assert!(body.contains(stopped), "{body}");
+    }
+
+    #[tokio::test]
+    async fn test_export_raw() {
+        let (dir, app) = test_app("export-raw", &["OFTC/#chan"], crate::Config::default());
+        let chan = dir.join("OFTC/#chan");
+        std::fs::write(chan.join("2025-01-02.log.zst"), zstd::encode_all(&b"[00:00:02] <b> two"[..], 3).unwrap()).unwrap();
+        std::fs::write(chan.join("2025-01-03.log"), "[00:00:03] <c> three\n").unwrap();
+
+        let resp = app.clone().oneshot(Request::get("/OFTC/%23chan/export?to=2025-01-02").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_DISPOSITION], "attachment; filename=\"OFTC_chan.log\"");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"[00:00:01] <a> hi\n[00:00:02] <b> two\n");
+
+        let resp = app.clone().oneshot(Request::get("/OFTC/%23chan/export?format=zst").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(resp.headers()[header::CONTENT_DISPOSITION], "attachment; filename=\"OFTC_chan.log.zst\"");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let decoded = zstd::decode_all(&body[..]).unwrap();
+        assert_eq!(decoded, b"[00:00:01] <a> hi\n[00:00:02] <b> two\n[00:00:03] <c> three\n");
+
+        let (status, _) = get(&app, "/OFTC/%23chan/export?from=yesterday").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
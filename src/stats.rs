@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::parser::{LineKind, LogFormat, file_format, parse_line};
+use crate::server::{day_files, read_log_file};
+use crate::{AppState, Channel};
+
+/// Most recent days of logs read when neither `from` nor `to` is given.
+pub const DEFAULT_DAYS: usize = 90;
+
+/// Per-file stats keyed by path and checked against the file's mtime.
+pub type StatsCache = Mutex<HashMap<PathBuf, (SystemTime, Arc<DayStats>)>>;
+
+/// Activity in one log file, or summed over several.
+#[derive(Debug, Default, PartialEq)]
+pub struct DayStats {
+    /// Messages and actions per nick.
+    pub messages: HashMap<String, usize>,
+    /// Messages and actions per hour of the day, as logged.
+    pub hours: [usize; 24],
+    pub joins: usize,
+    pub parts: usize,
+    pub quits: usize,
+    pub kicks: usize,
+}
+
+impl DayStats {
+    pub fn from_log(content: &str, format: LogFormat) -> Self {
+        let mut stats = Self::default();
+        for line in content.lines().filter_map(|l| parse_line(l, format)) {
+            match line.kind {
+                LineKind::Message { nick, .. } | LineKind::Action { nick, .. } => {
+                    *stats.messages.entry(nick).or_default() += 1;
+                    stats.hours[usize::from(line.time.hour) % 24] += 1;
+                }
+                LineKind::Join { .. } => stats.joins += 1,
+                LineKind::Part { .. } => stats.parts += 1,
+                LineKind::Quit { .. } => stats.quits += 1,
+                LineKind::Kick { .. } => stats.kicks += 1,
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    fn add(&mut self, other: &DayStats) {
+        for (nick, n) in &other.messages {
+            *self.messages.entry(nick.clone()).or_default() += n;
+        }
+        for (total, n) in self.hours.iter_mut().zip(other.hours) {
+            *total += n;
+        }
+        self.joins += other.joins;
+        self.parts += other.parts;
+        self.quits += other.quits;
+        self.kicks += other.kicks;
+    }
+
+    pub fn total_messages(&self) -> usize {
+        self.messages.values().sum()
+    }
+
+    /// Nicks by message count, most active first.
+    pub fn top_nicks(&self) -> Vec<(&str, usize)> {
+        let mut nicks: Vec<(&str, usize)> = self.messages.iter().map(|(nick, &n)| (nick.as_str(), n)).collect();
+        nicks.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        nicks
+    }
+}
+
+/// Activity of a channel over `dates`.
+#[derive(Debug, Default)]
+pub struct ChannelStats {
+    pub first: Option<String>,
+    pub last: Option<String>,
+    pub days: usize,
+    pub total: DayStats,
+}
+
+/// Sums the stats of every file on `dates`, reading only files that changed
+/// since they were last counted. Reads bypass the log cache, which a range
+/// of days would only churn.
+pub fn channel_stats(state: &AppState, channel: &Channel, dates: &[String]) -> ChannelStats {
+    let mut stats = ChannelStats {
+        first: dates.first().cloned(),
+        last: dates.last().cloned(),
+        days: dates.len(),
+        total: DayStats::default(),
+    };
+    for date in dates {
+        for (path, format) in day_files(channel, date) {
+            if let Some(day) = file_stats(&state.day_stats, &path, format) {
+                stats.total.add(&day);
+            }
+        }
+    }
+    stats
+}
+
+fn file_stats(cache: &StatsCache, path: &Path, format: LogFormat) -> Option<Arc<DayStats>> {
+    let mtime = crate::cache::source_mtime(path)?;
+    if let Some((at, stats)) = cache.lock().unwrap().get(path) {
+        if *at == mtime {
+            return Some(Arc::clone(stats));
+        }
+    }
+    let content = read_log_file(path).ok()?;
+    let stats = Arc::new(DayStats::from_log(&content, file_format(&content, format)));
+    cache.lock().unwrap().insert(path.to_path_buf(), (mtime, Arc::clone(&stats)));
+    Some(stats)
+}
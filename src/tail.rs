@@ -1,12 +1,135 @@
-use std::collections::HashMap;
-use std::io::{Read, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use notify::{Event, EventKind, RecursiveMode, Watcher};
+use tokio::sync::broadcast;
 use crate::AppState;
-use crate::parser::{LogFormat, parse_line};
-use crate::templates::render_line;
+use crate::parser::{LogFormat, file_format, parse_line};
+use crate::templates::{RenderOptions, render_line};
+
+/// Rendered lines sent to SSE subscribers as one event, tagged with its
+/// event ID and the file they came from.
+#[derive(Clone)]
+pub struct FeedBatch {
+    pub id: u64,
+    pub path: PathBuf,
+    /// Each line with the byte offset just past it, oldest first.
+    pub lines: Vec<(u64, String)>,
+}
+
+impl FeedBatch {
+    pub fn html(&self) -> String {
+        self.lines.iter().map(|(_, html)| html.as_str()).collect()
+    }
+}
+
+/// Date of a daily log file, used to tell a new day's lines apart from the
+/// day a subscriber is watching.
+pub fn file_date(path: &Path) -> Option<String> {
+    crate::server::log_file_date(path.file_name()?.to_str()?)
+}
+
+/// How long a feed outlives its last subscriber, and how long after today's
+/// page is served its lines are rendered while the page connects.
+pub const FEED_GRACE: Duration = Duration::from_secs(60);
+
+/// Live-tail state for one channel. The most recent lines are kept even
+/// without subscribers so a page that connects a moment after it was
+/// rendered, or an `EventSource` reconnecting with `Last-Event-ID`, can
+/// catch up on what it missed. Feeds nobody follows for `FEED_GRACE` are
+/// dropped by [`prune_feeds`], and lines of channels without a feed are not
+/// rendered at all.
+pub struct ChannelFeed {
+    pub sender: broadcast::Sender<FeedBatch>,
+    recent: VecDeque<FeedBatch>,
+    /// Lines in `recent`, which is trimmed to `sse_buffer_lines` of them.
+    recent_lines: usize,
+    next_id: AtomicU64,
+    watched_at: Instant,
+}
+
+impl ChannelFeed {
+    /// `capacity` batches are held for each subscriber before the slowest
+    /// one lags and misses some.
+    pub fn new(capacity: usize) -> Self {
+        // Seeded from the clock so IDs keep increasing across restarts and
+        // a client resuming against a fresh process is not handed old lines
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+        Self {
+            sender: broadcast::channel(capacity.max(1)).0,
+            recent: VecDeque::new(),
+            recent_lines: 0,
+            next_id: AtomicU64::new(seed),
+            watched_at: Instant::now(),
+        }
+    }
+
+    /// Notes that someone follows this feed, or is about to.
+    pub fn watch(&mut self) {
+        self.watched_at = Instant::now();
+    }
+
+    /// Subscribed to, or was within `FEED_GRACE`.
+    pub fn is_watched(&self) -> bool {
+        self.sender.receiver_count() > 0 || self.watched_at.elapsed() < FEED_GRACE
+    }
+
+    /// Broadcasts lines under a fresh event ID and returns them. Messages
+    /// not tied to a log file pass an empty `path`.
+    pub fn publish(&self, path: PathBuf, lines: Vec<(u64, String)>) -> FeedBatch {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let batch = FeedBatch { id, path, lines };
+        let _ = self.sender.send(batch.clone());
+        batch
+    }
+
+    fn push(&mut self, batch: FeedBatch, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        self.recent_lines += batch.lines.len();
+        self.recent.push_back(batch);
+        while self.recent_lines > capacity && self.recent.len() > 1 {
+            let dropped = self.recent.pop_front().unwrap();
+            self.recent_lines -= dropped.lines.len();
+        }
+    }
+
+    /// Buffered lines of `path` past byte offset `pos`, as one HTML chunk
+    /// per batch, oldest first.
+    pub fn since(&self, path: &Path, pos: u64) -> Vec<(u64, String)> {
+        self.recent
+            .iter()
+            .filter(|b| b.path == path)
+            .filter_map(|b| {
+                let html: String = b.lines.iter().filter(|(end, _)| *end > pos).map(|(_, html)| html.as_str()).collect();
+                (!html.is_empty()).then_some((b.id, html))
+            })
+            .collect()
+    }
+
+    /// Buffered batches of `path` with an event ID above `last_id`, oldest
+    /// first. Lines that already fell out of the buffer are lost.
+    pub fn after(&self, path: &Path, last_id: u64) -> Vec<(u64, String)> {
+        self.recent.iter().filter(|b| b.path == path && b.id > last_id).map(|b| (b.id, b.html())).collect()
+    }
+}
+
+/// Drops the feeds nobody followed for `FEED_GRACE`, along with their
+/// buffered lines. Run every `FEED_GRACE / 2`, so a feed lasts between one
+/// and one and a half `FEED_GRACE` past its last subscriber.
+pub fn prune_feeds(feeds: &mut HashMap<String, ChannelFeed>) {
+    feeds.retain(|_, feed| {
+        if feed.sender.receiver_count() > 0 {
+            feed.watch();
+        }
+        feed.is_watched()
+    });
+}
 
 pub fn start_watcher(state: Arc<AppState>) {
     let (tx, rx) = std::sync::mpsc::channel::<notify::Result<Event>>();
@@ -18,95 +141,231 @@ pub fn start_watcher(state: Arc<AppState>) {
             .expect("failed to watch logs directory");
     }
 
+    let pruned = Arc::clone(&state);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(FEED_GRACE / 2);
+        loop {
+            interval.tick().await;
+            prune_feeds(&mut *pruned.sse_feeds.write().await);
+        }
+    });
+
     tokio::task::spawn_blocking(move || {
+        let _span = tracing::info_span!("tail", dirs = state.logs_dirs.len()).entered();
         let _watcher = watcher;
-        let mut positions: HashMap<PathBuf, u64> = HashMap::new();
+        let mut positions: HashMap<PathBuf, Position> = HashMap::new();
         tail_loop(rx, &mut positions, &state);
     });
 }
 
+/// How far the tail read a file, and which file that was: a logger that
+/// renames a new file over the old one gives the path a new inode.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Position {
+    offset: u64,
+    inode: u64,
+}
+
+#[cfg(unix)]
+fn inode(meta: &std::fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(meta)
+}
+
+#[cfg(not(unix))]
+fn inode(_: &std::fs::Metadata) -> u64 {
+    0
+}
+
+/// Rendered lines waiting for the batch window to close, per channel feed
+/// key and file, in the order they were read.
+type Pending = Vec<(String, PathBuf, Vec<(u64, String)>)>;
+
 fn tail_loop(
     rx: std::sync::mpsc::Receiver<notify::Result<Event>>,
-    positions: &mut HashMap<PathBuf, u64>,
+    positions: &mut HashMap<PathBuf, Position>,
     state: &AppState,
 ) {
-    for event in rx {
-        let Ok(event) = event else { continue };
-        if !matches!(event.kind, EventKind::Modify(_)) {
+    // Per file, sniffed once from its first line
+    let mut formats: HashMap<PathBuf, LogFormat> = HashMap::new();
+    let window = Duration::from_millis(state.config.sse_batch_ms);
+    let mut pending = Pending::new();
+    // When the lines read first in `pending` are due
+    let mut due: Option<Instant> = None;
+    loop {
+        let event = match due {
+            None => match rx.recv() {
+                Ok(event) => event,
+                Err(_) => break,
+            },
+            Some(at) => match rx.recv_timeout(at.saturating_duration_since(Instant::now())) {
+                Ok(event) => event,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                    flush(&mut pending, state);
+                    due = None;
+                    continue;
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+            },
+        };
+        if let Ok(event) = event {
+            read_event(&event, positions, &mut formats, state, &mut pending);
+        }
+        if !pending.is_empty() {
+            let at = *due.get_or_insert_with(|| Instant::now() + window);
+            if at <= Instant::now() {
+                flush(&mut pending, state);
+                due = None;
+            }
+        }
+    }
+    flush(&mut pending, state);
+}
+
+/// Reads what was appended to the files of `event` and renders it into
+/// `pending` for the channels someone follows.
+fn read_event(
+    event: &Event,
+    positions: &mut HashMap<PathBuf, Position>,
+    formats: &mut HashMap<PathBuf, LogFormat>,
+    state: &AppState,
+    pending: &mut Pending,
+) {
+    let created = matches!(event.kind, EventKind::Create(_));
+    if !created && !matches!(event.kind, EventKind::Modify(_)) {
+        return;
+    }
+
+    for path in &event.paths {
+        let Some(ext) = path.extension() else { continue };
+        if ext != "log" {
             continue;
         }
 
-        for path in &event.paths {
-            let Some(ext) = path.extension() else { continue };
-            if ext != "log" {
-                continue;
-            }
+        if let Some(cache) = &state.log_cache {
+            cache.invalidate(path);
+        }
+        let Some((channel, format)) = resolve_channel(path, state) else {
+            continue;
+        };
 
-            let Some((channel_key, format)) = resolve_channel(path, state) else {
-                continue;
-            };
+        if created {
+            // A new day's file: anything already in it is new, and any
+            // offset left over from a file of the same name is stale
+            positions.remove(path);
+        }
+        let (start, new_lines) = read_new_bytes(path, positions);
+        if start == 0 {
+            formats.remove(path);
+        }
+        if new_lines.is_empty() {
+            continue;
+        }
 
-            let new_lines = read_new_bytes(path, positions);
-            if new_lines.is_empty() {
-                continue;
-            }
+        if let Some(index) = &state.search_index {
+            index.append(path, &new_lines, start == 0);
+        }
+        if let Some(date) = file_date(path) {
+            channel.saw_date(&date);
+        }
 
-            let senders = state.sse_senders.blocking_read();
-            let Some(sender) = senders.get(&channel_key) else {
+        let format = *formats.entry(path.clone()).or_insert_with(|| match first_line(path) {
+            Some(line) => file_format(&line, format),
+            None => format,
+        });
+        let key = channel.path_segments.join("/");
+        // Nobody follows the channel, so nobody would see the lines
+        if !state.sse_feeds.blocking_read().get(&key).is_some_and(ChannelFeed::is_watched) {
+            continue;
+        }
+        let opts = RenderOptions::for_channel(&state.config, channel);
+        let mut rendered = Vec::new();
+        let mut end = start;
+        for raw_line in new_lines.split_inclusive('\n') {
+            end += raw_line.len() as u64;
+            let raw_line = raw_line.trim_end_matches(['\n', '\r']);
+            if raw_line.is_empty() {
                 continue;
-            };
-
-            for raw_line in new_lines.lines() {
-                if raw_line.is_empty() {
-                    continue;
-                }
-                if let Some(parsed) = parse_line(raw_line, format) {
-                    let html = render_line(&parsed).into_string();
-                    let _ = sender.send(html);
-                }
             }
+            if let Some(mut parsed) = parse_line(raw_line, format) {
+                state.config.timezone.localize(&mut parsed, format);
+                rendered.push((end, render_line(&parsed, &opts, None).into_string()));
+            }
+        }
+        match pending.iter_mut().find(|(k, p, _)| *k == key && p == path) {
+            Some((_, _, lines)) => lines.extend(rendered),
+            None if !rendered.is_empty() => pending.push((key, path.clone(), rendered)),
+            None => {}
         }
     }
 }
 
-fn read_new_bytes(path: &PathBuf, positions: &mut HashMap<PathBuf, u64>) -> String {
+/// Publishes each file's pending lines as one event.
+fn flush(pending: &mut Pending, state: &AppState) {
+    if pending.is_empty() {
+        return;
+    }
+    let mut feeds = state.sse_feeds.blocking_write();
+    for (key, path, lines) in pending.drain(..) {
+        if let Some(feed) = feeds.get_mut(&key) {
+            tracing::debug!(feed = %key, path = %path.display(), lines = lines.len(), "publishing");
+            let batch = feed.publish(path, lines);
+            feed.push(batch, state.config.sse_buffer_lines);
+        }
+    }
+}
+
+fn first_line(path: &Path) -> Option<String> {
+    let mut line = String::new();
+    std::io::BufReader::new(std::fs::File::open(path).ok()?).read_line(&mut line).ok()?;
+    Some(line)
+}
+
+/// What was appended to `path` since it was last read, and the offset it
+/// starts at. A file that shrank was truncated and one with another inode
+/// was replaced; either is read again from the start.
+fn read_new_bytes(path: &PathBuf, positions: &mut HashMap<PathBuf, Position>) -> (u64, String) {
     let Ok(mut file) = std::fs::File::open(path) else {
-        return String::new();
+        return (0, String::new());
     };
     let Ok(meta) = file.metadata() else {
-        return String::new();
+        return (0, String::new());
     };
     let size = meta.len();
-    let pos = positions.get(path).copied().unwrap_or(0);
-
-    if size <= pos {
-        positions.insert(path.clone(), size);
-        return String::new();
-    }
+    let inode = inode(&meta);
+    let pos = match positions.get(path) {
+        Some(last) if last.inode == inode && last.offset <= size => last.offset,
+        _ => 0,
+    };
+    positions.insert(path.clone(), Position { offset: size, inode });
 
-    if file.seek(SeekFrom::Start(pos)).is_err() {
-        return String::new();
+    if size == pos || file.seek(SeekFrom::Start(pos)).is_err() {
+        return (pos, String::new());
     }
-
     let mut buf = String::new();
     let _ = file.read_to_string(&mut buf);
-    positions.insert(path.clone(), size);
-    buf
+    (pos, buf)
 }
 
-fn resolve_channel(path: &PathBuf, state: &AppState) -> Option<(String, LogFormat)> {
+fn resolve_channel<'a>(path: &PathBuf, state: &'a AppState) -> Option<(&'a crate::Channel, LogFormat)> {
     let abs = std::fs::canonicalize(path).ok()?;
     let parent = abs.parent()?;
+    let prefix = abs.file_name()?.to_str().and_then(crate::server::parse_log_file_name).map(|(p, _)| p.to_string());
     for logs_dir in &state.logs_dirs {
         if let Ok(rel) = parent.strip_prefix(logs_dir) {
-            let segments: Vec<String> = rel.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+            let mut segments: Vec<String> = rel.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+            // A directory shared by several channels has one below it per prefix
+            if let Some(prefix) = prefix.clone().filter(|p| !p.is_empty()) {
+                segments.push(prefix);
+                if find_channel_in_tree(&state.channels, &segments, 0).is_none() {
+                    segments.pop();
+                }
+            }
             if let Some(channel) = find_channel_in_tree(&state.channels, &segments, 0) {
-                let key = channel.path_segments.join("/");
                 let format = channel.dirs.iter()
                     .find(|d| parent.starts_with(&d.path))
                     .map(|d| d.format)
                     .unwrap_or(channel.dirs[0].format);
-                return Some((key, format));
+                return Some((channel, format));
             }
         }
     }
@@ -125,3 +384,202 @@ fn find_channel_in_tree<'a>(
     let child = node.children.get(&segments[depth])?;
     find_channel_in_tree(child, segments, depth + 1)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::LogTree;
+
+    /// A receiver on the feed for `key`, created if needed.
+    fn subscribe(state: &AppState, key: &str) -> broadcast::Receiver<FeedBatch> {
+        let capacity = state.config.sse_channel_capacity;
+        let mut feeds = state.sse_feeds.blocking_write();
+        feeds.entry(key.into()).or_insert_with(|| ChannelFeed::new(capacity)).sender.subscribe()
+    }
+
+    fn line(feed: &ChannelFeed, path: &str, end: u64) -> FeedBatch {
+        feed.publish(PathBuf::from(path), vec![(end, format!("{path}:{end}"))])
+    }
+
+    fn htmls(lines: Vec<(u64, String)>) -> Vec<String> {
+        lines.into_iter().map(|(_, html)| html).collect()
+    }
+
+    #[test]
+    fn test_feed_replays_lines_past_position() {
+        let mut feed = ChannelFeed::new(256);
+        for end in [10, 20, 30] {
+            let l = line(&feed, "/a.log", end);
+            feed.push(l, 2);
+        }
+        let l = line(&feed, "/b.log", 5);
+        feed.push(l, 2);
+        assert_eq!(htmls(feed.since(Path::new("/a.log"), 0)), vec!["/a.log:30"]);
+        assert_eq!(htmls(feed.since(Path::new("/a.log"), 25)), vec!["/a.log:30"]);
+        assert_eq!(htmls(feed.since(Path::new("/a.log"), 30)), Vec::<String>::new());
+        assert_eq!(htmls(feed.since(Path::new("/b.log"), 0)), vec!["/b.log:5"]);
+
+        // Only the part of a batch past the position
+        let batch = feed.publish(PathBuf::from("/c.log"), vec![(10, "x".into()), (20, "y".into()), (30, "z".into())]);
+        feed.push(batch, 2);
+        assert_eq!(htmls(feed.since(Path::new("/c.log"), 15)), vec!["yz"]);
+        assert_eq!(htmls(feed.since(Path::new("/b.log"), 0)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_feed_resumes_after_event_id() {
+        let mut feed = ChannelFeed::new(256);
+        let mut rx = feed.sender.subscribe();
+        let ids: Vec<u64> = [10, 20, 30]
+            .into_iter()
+            .map(|end| {
+                let l = line(&feed, "/a.log", end);
+                let id = l.id;
+                feed.push(l, 10);
+                id
+            })
+            .collect();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(rx.try_recv().unwrap().id, ids[0]);
+        assert_eq!(htmls(feed.after(Path::new("/a.log"), ids[0])), vec!["/a.log:20", "/a.log:30"]);
+        assert_eq!(htmls(feed.after(Path::new("/a.log"), ids[2])), Vec::<String>::new());
+        assert_eq!(htmls(feed.after(Path::new("/b.log"), 0)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_prune_feeds() {
+        let long_ago = Instant::now() - FEED_GRACE * 2;
+        let feeds = ["followed", "left", "fresh"].map(|k| (k.into(), ChannelFeed::new(256)));
+        let mut feeds: HashMap<String, ChannelFeed> = feeds.into();
+        let rx = feeds.get_mut("followed").unwrap().sender.subscribe();
+        drop(feeds.get_mut("left").unwrap().sender.subscribe());
+        for feed in feeds.values_mut() {
+            feed.watched_at = long_ago;
+        }
+        feeds.get_mut("fresh").unwrap().watch();
+
+        prune_feeds(&mut feeds);
+        let mut kept: Vec<&str> = feeds.keys().map(String::as_str).collect();
+        kept.sort();
+        assert_eq!(kept, ["followed", "fresh"]);
+
+        // The last subscriber leaving starts the grace period
+        drop(rx);
+        assert!(feeds["followed"].is_watched());
+        for feed in feeds.values_mut() {
+            feed.watched_at = long_ago;
+        }
+        prune_feeds(&mut feeds);
+        assert!(feeds.is_empty());
+    }
+
+    #[test]
+    fn test_tail_skips_unfollowed_channels() {
+        let dir = LogTree::new("tail-unfollowed");
+        dir.mkdir("OFTC/#chan");
+        let path = std::fs::canonicalize(dir.join("OFTC/#chan")).unwrap().join("2025-01-01.log");
+        std::fs::write(&path, "[00:00:01] <a> nobody sees this\n").unwrap();
+        let state = dir.state(crate::Config::default());
+
+        let (tx, events) = std::sync::mpsc::channel();
+        tx.send(Ok(Event::new(EventKind::Modify(notify::event::ModifyKind::Any)).add_path(path.clone()))).unwrap();
+        drop(tx);
+        let mut positions = HashMap::new();
+        tail_loop(events, &mut positions, &state);
+
+        assert!(state.sse_feeds.blocking_read().is_empty());
+        assert_eq!(positions[&path].offset, std::fs::metadata(&path).unwrap().len());
+    }
+
+    #[test]
+    fn test_tail_follows_new_day_file() {
+        let dir = LogTree::new("tail-rollover");
+        let old = dir.write("OFTC/#chan/2025-01-01.log", "[23:59:59] <a> late\n").join("OFTC/#chan/2025-01-01.log");
+        let state = dir.state(crate::Config::default());
+        let mut rx = subscribe(&state, "OFTC/#chan");
+        let mut positions = HashMap::from([(std::fs::canonicalize(&old).unwrap(), Position { offset: 20, inode: 0 })]);
+        let channel = state.channels.children["OFTC"].children["#chan"].channel.as_ref().unwrap();
+        assert_eq!(channel.latest_date().as_deref(), Some("2025-01-01"));
+
+        let new = std::fs::canonicalize(dir.join("OFTC/#chan")).unwrap().join("2025-01-02.log");
+        let (tx, events) = std::sync::mpsc::channel();
+        std::fs::write(&new, "[00:00:01] <a> first\n").unwrap();
+        tx.send(Ok(Event::new(EventKind::Create(notify::event::CreateKind::File)).add_path(new.clone()))).unwrap();
+        std::fs::write(&new, "[00:00:01] <a> first\n[00:00:02] <b> second\n").unwrap();
+        tx.send(Ok(Event::new(EventKind::Modify(notify::event::ModifyKind::Any)).add_path(new.clone()))).unwrap();
+        drop(tx);
+        tail_loop(events, &mut positions, &state);
+
+        let first = rx.try_recv().unwrap();
+        assert_eq!(file_date(&first.path).as_deref(), Some("2025-01-02"));
+        assert!(first.html().contains("first") && first.html().contains("second"));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(positions[&new].offset, std::fs::metadata(&new).unwrap().len());
+        assert_eq!(channel.latest_date().as_deref(), Some("2025-01-02"));
+    }
+
+    #[test]
+    fn test_tail_rereads_truncated_and_replaced_files() {
+        let dir = LogTree::new("tail-truncate");
+        dir.mkdir("OFTC/#chan");
+        let path = std::fs::canonicalize(dir.join("OFTC/#chan")).unwrap().join("2025-01-01.log");
+        std::fs::write(&path, "[00:00:01] <a> a long first line\n[00:00:02] <a> and another\n").unwrap();
+        let state = dir.state(crate::Config::default());
+        let mut rx = subscribe(&state, "OFTC/#chan");
+        let mut positions = HashMap::new();
+        let mut modified = || {
+            let (tx, events) = std::sync::mpsc::channel();
+            tx.send(Ok(Event::new(EventKind::Modify(notify::event::ModifyKind::Any)).add_path(path.clone()))).unwrap();
+            drop(tx);
+            tail_loop(events, &mut positions, &state);
+            rx.try_recv().map(|batch| batch.html()).unwrap_or_default()
+        };
+        assert!(modified().contains("another"));
+
+        // Truncated and rewritten shorter: everything in it is new
+        std::fs::write(&path, "[00:00:03] <b> short\n").unwrap();
+        let html = modified();
+        assert!(html.contains("short") && !html.contains("another"), "{html}");
+        assert_eq!(modified(), "");
+
+        // Replaced by renaming a file of the same size over it
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, "[00:00:04] <c> swap!\n").unwrap();
+        std::fs::rename(&tmp, &path).unwrap();
+        let html = modified();
+        if cfg!(unix) {
+            assert!(html.contains("swap"), "{html}");
+        }
+    }
+
+    #[test]
+    fn test_tail_batches_bursts() {
+        let dir = LogTree::new("tail-batch");
+        dir.mkdir("OFTC/#chan");
+        let path = std::fs::canonicalize(dir.join("OFTC/#chan")).unwrap().join("2025-01-01.log");
+        std::fs::write(&path, "").unwrap();
+        let state = dir.state(crate::Config::default());
+        let mut rx = subscribe(&state, "OFTC/#chan");
+        let modified = || Ok(Event::new(EventKind::Modify(notify::event::ModifyKind::Any)).add_path(path.clone()));
+
+        // 200 lines in one write, then 2 more within the batch window
+        let mut content: String = (0..200).map(|i| format!("[00:00:{:02}] <bot> line {i}\n", i % 60)).collect();
+        std::fs::write(&path, &content).unwrap();
+        let (tx, events) = std::sync::mpsc::channel();
+        tx.send(modified()).unwrap();
+        content.push_str("[00:01:00] <a> after\n[00:01:01] <b> the bot\n");
+        std::fs::write(&path, &content).unwrap();
+        tx.send(modified()).unwrap();
+        drop(tx);
+        tail_loop(events, &mut HashMap::new(), &state);
+
+        let batch = rx.try_recv().unwrap();
+        assert!(rx.try_recv().is_err());
+        assert_eq!(batch.lines.len(), 202);
+        assert!(batch.lines.windows(2).all(|w| w[0].0 < w[1].0));
+        let html = batch.html();
+        assert_eq!(html.matches(r#"<div class="line"#).count(), 202);
+        assert!(html.find("line 199").unwrap() < html.find("the bot").unwrap());
+        assert_eq!(batch.lines.last().unwrap().0, content.len() as u64);
+    }
+}
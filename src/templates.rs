@@ -1,121 +1,665 @@
+use std::collections::{BTreeMap, HashMap};
+
 use maud::{DOCTYPE, Markup, PreEscaped, html};
 
-use crate::parser::{LineKind, LogLine};
-use crate::{ChannelNode, Channel};
+use crate::parser::{LineKind, LogLine, Time};
+use crate::search::{ChannelResults, ContextBlock, Filter, Matcher, ResultGroup};
+use crate::stats::ChannelStats;
+use crate::{ChannelNode, Channel, Config, NickColor, NickColors};
 
-fn nick_hue(nick: &str) -> u16 {
+/// Rendering settings taken from the config, shared by every line renderer.
+pub struct RenderOptions<'a> {
+    pub nick_aliases: &'a BTreeMap<String, String>,
+    pub nick_colors: &'a NickColors,
+    pub line_numbers: bool,
+    pub fold_reasons_at: usize,
+    pub irc_formatting: bool,
+    pub link_schemes: &'a [String],
+    /// Nicks whose mentions are linked, from [`participants`]; `None` for
+    /// lines rendered without their day, such as the live tail.
+    pub participants: Option<&'a Participants>,
+    /// Channels whose `#name` is linked, from [`channel_refs`]; `None` for
+    /// lines rendered outside a channel's log.
+    pub channel_refs: Option<&'a ChannelRefs>,
+    /// Image and video links get a preview, per the channel's
+    /// `media_previews`; off for lines shown outside a channel's log.
+    pub media_previews: bool,
+    /// Backtick-quoted and indented code shown in monospace, per the
+    /// channel's `code_blocks`.
+    pub code_blocks: bool,
+}
+
+impl<'a> RenderOptions<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            nick_aliases: &config.nick_aliases,
+            nick_colors: &config.nick_colors,
+            line_numbers: config.line_numbers,
+            fold_reasons_at: config.fold_reasons_at,
+            irc_formatting: config.irc_formatting,
+            link_schemes: &config.link_schemes,
+            participants: None,
+            channel_refs: None,
+            media_previews: false,
+            code_blocks: false,
+        }
+    }
+
+    /// Settings for lines of `channel`'s log, with its overrides applied.
+    pub fn for_channel(config: &'a Config, channel: &Channel) -> Self {
+        Self {
+            media_previews: channel.media_previews(config),
+            code_blocks: channel.overrides.code_blocks,
+            ..Self::new(config)
+        }
+    }
+}
+
+fn nick_hash(nick: &str) -> u32 {
     let mut hash: u32 = 5381;
     for b in nick.bytes() {
         hash = hash.wrapping_mul(33).wrapping_add(u32::from(b));
     }
-    (hash % 360) as u16
+    hash
+}
+
+fn nick_hue(nick: &str) -> u16 {
+    (nick_hash(nick) % 360) as u16
+}
+
+/// A pinned color, else one from the palette, else a hue from the nick's
+/// hash. Hue lightness comes from the stylesheet so nicks stay readable in
+/// both themes.
+fn nick_color_style(nick: &str, colors: &NickColors) -> String {
+    let pinned = colors.pinned.iter().find(|(pinned, _)| pinned.eq_ignore_ascii_case(nick)).map(|(_, color)| color);
+    let palette = || colors.palette.get(nick_hash(nick) as usize % colors.palette.len().max(1));
+    match pinned.or_else(palette) {
+        Some(NickColor::Css(css)) => format!("color:{css}"),
+        Some(NickColor::Hue(hue)) => format!("color:hsl({hue},70%,var(--nick-lightness))"),
+        None => format!("color:hsl({},70%,var(--nick-lightness))", nick_hue(nick)),
+    }
 }
 
-fn nick_color_style(nick: &str) -> String {
-    format!("color:hsl({},70%,65%)", nick_hue(nick))
+/// Nick label colored from the real nick, showing the configured alias if any.
+fn nick_span(nick: &str, opts: &RenderOptions, brackets: bool) -> Markup {
+    let alias = opts.nick_aliases.get(nick);
+    let label = alias.map(String::as_str).unwrap_or(nick);
+    html! {
+        span.nick style=(nick_color_style(nick, opts.nick_colors)) title=[alias.map(|_| nick)] {
+            @if brackets { "<" (label) ">" } @else { (label) }
+        }
+    }
 }
 
-pub fn linkify(text: &str) -> Markup {
+/// `text` escaped, with the ranges `matcher` finds wrapped in `<mark>`.
+fn mark_matches(text: &str, matcher: Option<&Matcher>) -> String {
+    let Some(matcher) = matcher else {
+        return html! { (text) }.into_string();
+    };
     let mut result = String::new();
     let mut last = 0;
+    for range in matcher.find_ranges(text) {
+        result.push_str(&html! { (&text[last..range.start]) mark { (&text[range.clone()]) } }.into_string());
+        last = range.end;
+    }
+    result.push_str(&html! { (&text[last..]) }.into_string());
+    result
+}
 
-    for (i, _) in text.match_indices("http") {
-        let rest = &text[i..];
-        let scheme_end = if rest.starts_with("https://") {
-            8
-        } else if rest.starts_with("http://") {
-            7
-        } else {
+/// Nicks seen in a day by lowercased nick, with the nick as written and
+/// the anchor of the first line involving it.
+pub type Participants = HashMap<String, (String, String)>;
+
+pub fn participants<'l>(lines: impl IntoIterator<Item = &'l LogLine>) -> Participants {
+    let mut out = Participants::new();
+    for line in lines {
+        for nick in line.nicks() {
+            out.entry(nick.to_lowercase()).or_insert_with(|| (nick.to_string(), line.time.to_anchor()));
+        }
+    }
+    out
+}
+
+/// Channels on the same network as the one shown, by lowercased name, with
+/// the URL of their today page.
+pub type ChannelRefs = HashMap<String, String>;
+
+pub fn channel_refs(tree: &ChannelNode, channel: &Channel, base_path: &str) -> ChannelRefs {
+    let network = &channel.path_segments[..channel.path_segments.len().saturating_sub(1)];
+    let Some(node) = network.iter().try_fold(tree, |node, seg| node.children.get(seg)) else {
+        return ChannelRefs::new();
+    };
+    node.children
+        .iter()
+        .filter(|(name, child)| name.starts_with('#') && child.channel.is_some())
+        .map(|(name, _)| {
+            let path = network.iter().chain([name]).cloned().collect::<Vec<_>>().join("/");
+            (name.to_lowercase(), format!("{base_path}/{}/today", path.replace('#', "%23")))
+        })
+        .collect()
+}
+
+/// Byte ranges of known `#channel` names in `text`, starting a word and
+/// ending before a space, a comma or trailing punctuation. Text between
+/// backticks is left alone.
+fn channel_mentions(text: &str, refs: &ChannelRefs) -> Vec<std::ops::Range<usize>> {
+    let mut out = Vec::new();
+    for (i, _) in text.match_indices('#') {
+        let before = &text[..i];
+        if before.matches('`').count() % 2 == 1
+            || before.chars().next_back().is_some_and(|p| !p.is_whitespace() && !"(\"'".contains(p))
+        {
             continue;
+        }
+        let word = text[i..].split(|c: char| c.is_whitespace() || c == ',').next().unwrap_or_default();
+        let name = word.trim_end_matches(|c| ".;:!?)'\"".contains(c));
+        if name.len() > 1 && refs.contains_key(&name.to_lowercase()) {
+            out.push(i..i + name.len());
+        }
+    }
+    out
+}
+
+/// Backtick is a valid nick character but left out so `code` spans stay
+/// recognizable.
+fn is_nick_char(c: char) -> bool {
+    c.is_alphanumeric() || "-_[]\\^{}|".contains(c)
+}
+
+/// Byte ranges of participant nicks addressed in `text`: `@nick` anywhere,
+/// `nick:` followed by a space or the end, and `nick,` as the first word
+/// when `leading`. Text between backticks is left alone.
+fn mentions(text: &str, participants: &Participants, leading: bool) -> Vec<std::ops::Range<usize>> {
+    let mut out = Vec::new();
+    let mut in_code = false;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        if c == '`' {
+            in_code = !in_code;
+        }
+        if in_code || !is_nick_char(c) {
+            i += c.len_utf8();
+            continue;
+        }
+        let end = text[i..].find(|c| !is_nick_char(c)).map_or(text.len(), |n| i + n);
+        let before = &text[..i];
+        let after = &text[end..];
+        let at = before.strip_suffix('@').filter(|b| !b.chars().next_back().is_some_and(is_nick_char));
+        let starts_word = before.chars().next_back().is_none_or(|p| p.is_whitespace() || "(\"'".contains(p));
+        let first_word = leading && before.trim_start().is_empty();
+        let addressed = after.strip_prefix(':').is_some_and(|r| r.is_empty() || r.starts_with(' '))
+            || (first_word && after.starts_with(','));
+        if (at.is_some() || (starts_word && addressed)) && participants.contains_key(&text[i..end].to_lowercase()) {
+            out.push(i..end);
+        }
+        i = end;
+    }
+    out
+}
+
+/// `text` escaped and marked like [`mark_matches`], with participant
+/// mentions linked to the nick's first line of the day and `#channel`
+/// references to that channel. The two never overlap, since `#` is not a
+/// nick character.
+fn mark_mentions(text: &str, opts: &RenderOptions, matcher: Option<&Matcher>, leading: bool) -> String {
+    let mut links: Vec<(std::ops::Range<usize>, Markup)> = Vec::new();
+    if let Some(participants) = opts.participants {
+        for range in mentions(text, participants, leading) {
+            let (nick, anchor) = &participants[&text[range.clone()].to_lowercase()];
+            let style = nick_color_style(nick, opts.nick_colors);
+            let link = html! {
+                a.mention href=(format!("#{anchor}")) data-nick=(nick) style=(style) {
+                    (PreEscaped(mark_matches(&text[range.clone()], matcher)))
+                }
+            };
+            links.push((range, link));
+        }
+    }
+    if let Some(refs) = opts.channel_refs {
+        for range in channel_mentions(text, refs) {
+            let href = &refs[&text[range.clone()].to_lowercase()];
+            let link = html! {
+                a.channel-ref href=(href) { (PreEscaped(mark_matches(&text[range.clone()], matcher))) }
+            };
+            links.push((range, link));
+        }
+    }
+    links.sort_by_key(|(range, _)| range.start);
+    let mut result = String::new();
+    let mut last = 0;
+    for (range, link) in links {
+        result.push_str(&mark_matches(&text[last..range.start], matcher));
+        result.push_str(&link.into_string());
+        last = range.end;
+    }
+    result.push_str(&mark_matches(&text[last..], matcher));
+    result
+}
+
+/// Length of the URL starting `text` once sentence punctuation is trimmed
+/// off its end. Closing brackets are only trimmed when unbalanced, so
+/// `https://en.wikipedia.org/wiki/Rust_(programming_language)` stays whole.
+fn trim_url(url: &str) -> usize {
+    let mut end = url.len();
+    while let Some(c) = url[..end].chars().next_back() {
+        let unbalanced = |open, close| url[..end].matches(close).count() > url[..end].matches(open).count();
+        let trim = match c {
+            '.' | ',' | ';' | ':' | '!' | '?' | '\'' | '"' => true,
+            ')' => unbalanced('(', ')'),
+            ']' => unbalanced('[', ']'),
+            _ => false,
         };
+        if !trim {
+            break;
+        }
+        end -= c.len_utf8();
+    }
+    end
+}
 
-        if scheme_end >= rest.len() {
+/// Links URLs starting with one of the configured schemes, highlighting
+/// matches in both plain text and link text so a match inside a URL never
+/// splits the link. Mentions are only looked for outside URLs; `leading`
+/// says `text` starts the message.
+fn linkify(text: &str, opts: &RenderOptions, matcher: Option<&Matcher>, leading: bool) -> Markup {
+    let mut result = String::new();
+    let mut last = 0;
+
+    for (i, _) in text.char_indices() {
+        if i < last || text[..i].chars().next_back().is_some_and(char::is_alphanumeric) {
             continue;
         }
+        let rest = &text[i..];
+        let Some(scheme) = opts.link_schemes.iter().find(|s| rest.get(..s.len()).is_some_and(|p| p.eq_ignore_ascii_case(s))) else {
+            continue;
+        };
 
-        let url_end = rest[scheme_end..]
-            .find(|c: char| c.is_whitespace())
-            .map(|p| scheme_end + p)
-            .unwrap_or(rest.len());
+        let url_end = trim_url(rest.split(char::is_whitespace).next().unwrap_or(rest));
+        if url_end <= scheme.len() {
+            continue;
+        }
 
         let url = &rest[..url_end];
         let before = &text[last..i];
 
-        result.push_str(&maud::html! { (before) }.into_string());
-        result.push_str(&maud::html! { a href=(url) target="_blank" rel="noopener" { (url) } }.into_string());
+        result.push_str(&mark_mentions(before, opts, matcher, leading && last == 0));
+        result.push_str(&maud::html! {
+            a href=(url) target="_blank" rel="noopener" { (PreEscaped(mark_matches(url, matcher))) }
+        }.into_string());
+        if let Some(preview) = media_preview(url).filter(|_| opts.media_previews) {
+            result.push_str(&preview.into_string());
+        }
         last = i + url_end;
     }
 
     if last < text.len() {
-        result.push_str(&maud::html! { (&text[last..]) }.into_string());
+        result.push_str(&mark_mentions(&text[last..], opts, matcher, leading && last == 0));
+    }
+
+    PreEscaped(result)
+}
+
+/// Thumbnail for an http(s) link to an image or video file, or to a YouTube
+/// video. Nothing is fetched until the line scrolls into view, and clicking
+/// opens the full media.
+fn media_preview(url: &str) -> Option<Markup> {
+    let lower = url.to_ascii_lowercase();
+    let rest = lower.strip_prefix("https://").or_else(|| lower.strip_prefix("http://"))?;
+    let path = rest.split(['?', '#']).next().unwrap_or(rest);
+    let thumbnail = if [".png", ".jpg", ".jpeg", ".gif", ".webp"].iter().any(|e| path.ends_with(e)) {
+        url.to_string()
+    } else if [".mp4", ".webm"].iter().any(|e| path.ends_with(e)) {
+        return Some(html! { video.media-preview src=(url) controls preload="none" {} });
+    } else {
+        format!("https://i.ytimg.com/vi/{}/hqdefault.jpg", youtube_id(url)?)
+    };
+    Some(html! {
+        a.media-preview href=(url) target="_blank" rel="noopener" {
+            img src=(thumbnail) loading="lazy" alt="" referrerpolicy="no-referrer";
+        }
+    })
+}
+
+/// Video ID of a `youtube.com/watch?v=` or `youtu.be/` link.
+fn youtube_id(url: &str) -> Option<&str> {
+    let rest = url.split_once("://")?.1;
+    let (host, path) = rest.split_once('/')?;
+    let id = match host.to_ascii_lowercase().trim_start_matches("www.").trim_start_matches("m.") {
+        "youtu.be" => path.split(['?', '#']).next()?,
+        "youtube.com" => {
+            let query = path.strip_prefix("watch?")?;
+            query.split(['&', '#']).find_map(|p| p.strip_prefix("v="))?
+        }
+        _ => return None,
+    };
+    (id.len() == 11 && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')).then_some(id)
+}
+
+/// The 16 standard mIRC colors; extended colors 16-98 are not rendered.
+const MIRC_COLORS: [&str; 16] = [
+    "#ffffff", "#000000", "#00007f", "#009300", "#ff0000", "#7f0000", "#9c009c", "#fc7f00",
+    "#ffff00", "#00fc00", "#009393", "#00ffff", "#0000fc", "#ff00ff", "#7f7f7f", "#d2d2d2",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct IrcStyle {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    strike: bool,
+    fg: Option<usize>,
+    bg: Option<usize>,
+}
+
+impl IrcStyle {
+    fn css(&self) -> String {
+        let mut css = Vec::new();
+        if self.bold {
+            css.push("font-weight:bold".to_string());
+        }
+        if self.italic {
+            css.push("font-style:italic".to_string());
+        }
+        match (self.underline, self.strike) {
+            (true, true) => css.push("text-decoration:underline line-through".to_string()),
+            (true, false) => css.push("text-decoration:underline".to_string()),
+            (false, true) => css.push("text-decoration:line-through".to_string()),
+            (false, false) => {}
+        }
+        if let Some(fg) = self.fg {
+            css.push(format!("color:{}", MIRC_COLORS[fg]));
+        }
+        if let Some(bg) = self.bg {
+            css.push(format!("background-color:{}", MIRC_COLORS[bg]));
+        }
+        css.join(";")
+    }
+}
+
+/// One or two color digits at the start of `b`, and how many bytes they took.
+fn color_digits(b: &[u8]) -> (Option<usize>, usize) {
+    let n = b.iter().take(2).take_while(|c| c.is_ascii_digit()).count();
+    let value = b[..n].iter().fold(0, |acc, c| acc * 10 + usize::from(c - b'0'));
+    ((n > 0).then_some(value), n)
+}
+
+fn is_format_code(b: u8) -> bool {
+    matches!(b, 0x02 | 0x03 | 0x04 | 0x0f | 0x11 | 0x16 | 0x1d | 0x1e | 0x1f)
+}
+
+/// `text` without formatting codes or their color arguments.
+fn plain_text(text: &str) -> String {
+    irc_runs(text).into_iter().map(|(_, run)| run).collect::<String>().replace(char::is_control, "")
+}
+
+/// Splits `text` on mIRC formatting codes into styled runs. Codes without an
+/// HTML rendering (hex colors, reverse, monospace) are dropped.
+fn irc_runs(text: &str) -> Vec<(IrcStyle, &str)> {
+    let bytes = text.as_bytes();
+    let mut runs = Vec::new();
+    let mut style = IrcStyle::default();
+    let (mut start, mut i) = (0, 0);
+    while i < bytes.len() {
+        let code = bytes[i];
+        if !is_format_code(code) {
+            i += 1;
+            continue;
+        }
+        if start < i {
+            runs.push((style, &text[start..i]));
+        }
+        i += 1;
+        match code {
+            0x02 => style.bold = !style.bold,
+            0x1d => style.italic = !style.italic,
+            0x1f => style.underline = !style.underline,
+            0x1e => style.strike = !style.strike,
+            0x0f => style = IrcStyle::default(),
+            0x03 => {
+                let (fg, n) = color_digits(&bytes[i..]);
+                i += n;
+                if fg.is_none() {
+                    style.fg = None;
+                    style.bg = None;
+                } else {
+                    style.fg = fg.filter(|&c| c < MIRC_COLORS.len());
+                    if bytes.get(i) == Some(&b',') {
+                        if let (bg @ Some(_), n) = color_digits(&bytes[i + 1..]) {
+                            style.bg = bg.filter(|&c| c < MIRC_COLORS.len());
+                            i += 1 + n;
+                        }
+                    }
+                }
+            }
+            0x04 => {
+                // \x04RRGGBB[,RRGGBB]
+                i += bytes[i..].iter().take(6).take_while(|c| c.is_ascii_hexdigit()).count();
+                if bytes.get(i) == Some(&b',') && bytes.get(i + 1).is_some_and(u8::is_ascii_hexdigit) {
+                    i += 1 + bytes[i + 1..].iter().take(6).take_while(|c| c.is_ascii_hexdigit()).count();
+                }
+            }
+            _ => {}
+        }
+        start = i;
+    }
+    if start < bytes.len() {
+        runs.push((style, &text[start..]));
+    }
+    runs
+}
+
+/// Message and action text as HTML. Every stage works on raw text runs and
+/// escapes exactly once when emitting, so stages compose without
+/// double-escaping: formatting codes split the text into styled runs first,
+/// then URLs are linked, and nicks, `#channel` names and search matches
+/// marked within each run.
+fn render_text(text: &str, opts: &RenderOptions) -> Markup {
+    render_text_marked(text, opts, None)
+}
+
+fn render_text_marked(text: &str, opts: &RenderOptions, matcher: Option<&Matcher>) -> Markup {
+    match render_code(text, opts, matcher) {
+        Some(markup) => markup,
+        None => render_runs(text, opts, matcher, true),
     }
+}
 
+/// `leading` says `text` starts the message, for mentions.
+fn render_runs(text: &str, opts: &RenderOptions, matcher: Option<&Matcher>, leading: bool) -> Markup {
+    html! {
+        @for (i, (style, run)) in irc_runs(text).into_iter().enumerate() {
+            @if opts.irc_formatting && style != IrcStyle::default() {
+                span style=(style.css()) { (linkify(run, opts, matcher, leading && i == 0)) }
+            } @else {
+                (linkify(run, opts, matcher, leading && i == 0))
+            }
+        }
+    }
+}
+
+/// Languages a ```` ```fence ```` may name before its code.
+const CODE_LANGUAGES: &[&str] = &[
+    "bash", "c", "cpp", "css", "diff", "go", "html", "java", "js", "json", "lua", "nix", "perl", "php",
+    "py", "python", "rb", "ruby", "rust", "sh", "sql", "toml", "ts", "yaml", "zig",
+];
+
+/// With `code_blocks`, a message indented by four spaces or a tab, or a
+/// ```` ``` ````-fenced part of one, becomes a `<pre>` block and
+/// `` `quoted` `` words become `<code>`. Code is shown as typed: no links,
+/// mentions or formatting inside. `None` when there is no code, or when
+/// mIRC formatting codes make the text unsuitable.
+fn render_code(text: &str, opts: &RenderOptions, matcher: Option<&Matcher>) -> Option<Markup> {
+    let indented = text.starts_with("    ") || text.starts_with('\t');
+    if !opts.code_blocks || !(indented || text.contains('`')) || text.bytes().any(is_format_code) {
+        return None;
+    }
+    if indented {
+        return Some(html! { pre.code { code { (PreEscaped(mark_matches(text, matcher))) } } });
+    }
+    if let Some(start) = text.find("```") {
+        let after = &text[start + 3..];
+        if let Some(len) = after.find("```").filter(|&n| !after[..n].trim().is_empty()) {
+            let code = &after[..len];
+            let lang = code.split_once(' ').filter(|(l, _)| CODE_LANGUAGES.contains(l));
+            let (lang, code) = match lang {
+                Some((lang, code)) => (Some(format!("language-{lang}")), code),
+                None => (None, code),
+            };
+            return Some(html! {
+                (render_inline_code(&text[..start], opts, matcher, true))
+                pre.code { code class=[lang] { (PreEscaped(mark_matches(code, matcher))) } }
+                (render_inline_code(&text[start + 3 + len + 3..], opts, matcher, false))
+            });
+        }
+    }
+    text.contains('`').then(|| render_inline_code(text, opts, matcher, true))
+}
+
+/// `` `quoted` `` spans as `<code>`, the rest as usual. A quote only opens
+/// after a space or bracket and closes before one or punctuation, so
+/// backticks inside URLs and words are left alone.
+fn render_inline_code(text: &str, opts: &RenderOptions, matcher: Option<&Matcher>, leading: bool) -> Markup {
+    let mut result = String::new();
+    let mut last = 0;
+    let mut from = 0;
+    while let Some(open) = text[from..].find('`').map(|i| from + i) {
+        from = open + 1;
+        if text[..open].chars().next_back().is_some_and(|c| !c.is_whitespace() && c != '(') {
+            continue;
+        }
+        let Some(len) = text[open + 1..].find('`') else { break };
+        let (code, close) = (&text[open + 1..open + 1 + len], open + 1 + len);
+        let closes = text[close + 1..].chars().next().is_none_or(|c| c.is_whitespace() || ".,;:!?)'\"".contains(c));
+        if code.is_empty() || code.starts_with(' ') || code.ends_with(' ') || !closes {
+            continue;
+        }
+        result.push_str(&render_runs(&text[last..open], opts, matcher, leading && last == 0).into_string());
+        result.push_str(&html! { code { (PreEscaped(mark_matches(code, matcher))) } }.into_string());
+        last = close + 1;
+        from = last;
+    }
+    result.push_str(&render_runs(&text[last..], opts, matcher, leading && last == 0).into_string());
     PreEscaped(result)
 }
 
-pub fn render_line(line: &LogLine) -> Markup {
+/// Event metadata (quit/part reasons) longer than `limit` chars collapses
+/// into a `<details>` showing the start; the full text stays in the DOM.
+fn fold_text(text: &str, limit: usize) -> Markup {
+    let cut = match text.char_indices().nth(limit) {
+        Some((i, _)) if limit > 0 => i,
+        _ => return html! { (text) },
+    };
+    html! {
+        details.fold {
+            summary { (&text[..cut]) span.fold-more { "…" } }
+            (&text[cut..])
+        }
+    }
+}
+
+/// `line_no` is the 1-based line in the source file, emitted as `data-line`
+/// when `line_numbers` is enabled.
+pub fn render_line(line: &LogLine, opts: &RenderOptions, line_no: Option<usize>) -> Markup {
     let anchor = line.time.to_anchor();
     let ts = line.time.to_hms();
-    let class = if line.is_event() { "line event" } else { "line" };
+    let class = match line.kind {
+        LineKind::Topic { .. } => "line event topic",
+        _ if line.is_event() => "line event",
+        _ => "line",
+    };
+    let data_line = line_no.filter(|_| opts.line_numbers);
+    // For the client-side nick filter and mention highlighting
+    let nicks = line.nicks();
+    let data_nick = (!nicks.is_empty()).then(|| nicks.join(" "));
 
     html! {
-        div class=(class) id=(&anchor) {
+        div class=(class) id=(&anchor) data-line=[data_line] data-nick=[data_nick] data-raw=(line.to_log_line()) {
             a.ts href=(format!("#{anchor}")) { (ts) }
             " "
             @match &line.kind {
                 LineKind::Message { nick, text } => {
-                    span.nick style=(nick_color_style(nick)) { "<" (nick) ">" }
+                    (nick_span(nick, opts, true))
                     " "
-                    span.msg { (linkify(text)) }
+                    span.msg { (render_text(text, opts)) }
                 },
                 LineKind::Action { nick, text } => {
                     span.action {
                         "* "
-                        span.nick style=(nick_color_style(nick)) { (nick) }
+                        (nick_span(nick, opts, false))
                         " "
-                        (linkify(text))
+                        (render_text(text, opts))
                     }
                 },
                 LineKind::Join { nick, userhost } => {
                     span.ev {
                         "→ "
-                        span.nick style=(nick_color_style(nick)) { (nick) }
+                        (nick_span(nick, opts, false))
                         " (" (userhost) ") joined"
                     }
                 },
                 LineKind::Quit { nick, userhost, reason } => {
                     span.ev {
                         "← "
-                        span.nick style=(nick_color_style(nick)) { (nick) }
+                        (nick_span(nick, opts, false))
                         " (" (userhost) ") quit"
                         @if !reason.is_empty() {
-                            " (" (reason) ")"
+                            " (" (fold_text(reason, opts.fold_reasons_at)) ")"
                         }
                     }
                 },
                 LineKind::Part { nick, userhost, reason } => {
                     span.ev {
                         "← "
-                        span.nick style=(nick_color_style(nick)) { (nick) }
+                        (nick_span(nick, opts, false))
                         " (" (userhost) ") left"
                         @if !reason.is_empty() {
-                            " (" (reason) ")"
+                            " (" (fold_text(reason, opts.fold_reasons_at)) ")"
                         }
                     }
                 },
                 LineKind::NickChange { old_nick, new_nick } => {
                     span.ev {
-                        span.nick style=(nick_color_style(old_nick)) { (old_nick) }
+                        (nick_span(old_nick, opts, false))
                         " → "
-                        span.nick style=(nick_color_style(new_nick)) { (new_nick) }
+                        (nick_span(new_nick, opts, false))
+                    }
+                },
+                LineKind::Topic { nick, topic } => {
+                    span.topic-change {
+                        "📌 "
+                        (nick_span(nick, opts, false))
+                        " set the topic: "
+                        (render_text(topic, opts))
                     }
                 },
+                LineKind::Raw { text } => {
+                    span.raw { (text) }
+                },
+                LineKind::Kick { nick, by, reason } => {
+                    span.ev.kick {
+                        "⛔ "
+                        (nick_span(nick, opts, false))
+                        " kicked by "
+                        (nick_span(by, opts, false))
+                        @if !reason.is_empty() {
+                            " (" (fold_text(reason, opts.fold_reasons_at)) ")"
+                        }
+                    }
+                },
+            }
+            // Glyphs come from CSS so selecting lines does not copy them
+            span.line-tools {
+                button.copy-raw type="button" title="copy line" aria-label="copy line" {}
+                button.copy-link type="button" title="copy link" aria-label="copy link" {}
             }
         }
     }
 }
 
-fn render_channel_tree(node: &ChannelNode, parent_path: &str, base_path: &str) -> Markup {
+/// The sidebar list under `node`. Nodes with children fold into a
+/// `details`; collapsed ones leave their children for the sidebar script to
+/// fetch from `/sidebar/{path}`.
+pub fn render_channel_tree(node: &ChannelNode, parent_path: &str, base_path: &str) -> Markup {
     html! {
         ul {
             @for (name, child) in &node.children {
@@ -126,13 +670,27 @@ fn render_channel_tree(node: &ChannelNode, parent_path: &str, base_path: &str) -
                         format!("{parent_path}/{name}")
                     };
                     @let encoded_path = child_path.replace('#', "%23");
-                    @if let Some(channel) = &child.channel {
-                        a href=(format!("{base_path}/{}/today", encoded_path)) { (&channel.name) }
+                    @let label = html! {
+                        @if let Some(channel) = &child.channel {
+                            a href=(format!("{base_path}/{}/today", encoded_path)) { (channel.display_name()) }
+                            " "
+                            time.tree-latest data-channel=(channel.path_segments.join("/")) {}
+                        } @else {
+                            span.tree-label { (name) }
+                        }
+                    };
+                    @if child.children.is_empty() {
+                        (label)
+                    } @else if child.collapsed {
+                        details.tree-node data-path=(format!("{base_path}/{encoded_path}/"))
+                            data-subtree=(format!("{base_path}/sidebar/{encoded_path}")) {
+                            summary { (label) }
+                        }
                     } @else {
-                        span.tree-label { (name) }
-                    }
-                    @if !child.children.is_empty() {
-                        (render_channel_tree(child, &child_path, base_path))
+                        details.tree-node open {
+                            summary { (label) }
+                            (render_channel_tree(child, &child_path, base_path))
+                        }
                     }
                 }
             }
@@ -140,7 +698,376 @@ fn render_channel_tree(node: &ChannelNode, parent_path: &str, base_path: &str) -
     }
 }
 
+fn collect_favorites<'a>(node: &'a ChannelNode, out: &mut Vec<&'a Channel>) {
+    if let Some(channel) = node.channel.as_ref().filter(|c| c.favorite) {
+        out.push(channel);
+    }
+    for child in node.children.values() {
+        collect_favorites(child, out);
+    }
+}
+
+/// Fetches a collapsed sidebar node's children the first time it opens,
+/// opening on the way those leading to the current page. Each channel's
+/// latest log date, from the uncached `data-latest` JSON so cached pages
+/// never hold a stale one, becomes its age, and channels quiet for longer
+/// than a month are dimmed.
+const SIDEBAR_SCRIPT: &str = r#"
+(function() {
+    var sidebar = document.getElementById('sidebar');
+    var here = window.location.pathname;
+    var now = Date.now();
+    var latest = fetch(sidebar.dataset.latest).then(function(r) {
+        return r.ok ? r.json() : {};
+    }).catch(function() { return {}; });
+    function annotate(root) {
+        latest.then(function(dates) {
+            root.querySelectorAll('time.tree-latest').forEach(function(t) {
+                var date = dates[t.dataset.channel];
+                var days = Math.floor((now - Date.parse(date + 'T00:00:00')) / 864e5);
+                if (!date || isNaN(days)) return;
+                t.setAttribute('datetime', date);
+                t.title = 'last log ' + date;
+                t.textContent = days < 1 ? 'today' : days < 365 ? days + 'd' : Math.floor(days / 365) + 'y';
+                t.closest('li').classList.toggle('stale', days > 30);
+            });
+        });
+        root.querySelectorAll('details[data-subtree]').forEach(function(d) {
+            if (here.indexOf(d.dataset.path) === 0) d.open = true;
+        });
+    }
+    sidebar.addEventListener('toggle', function(e) {
+        var node = e.target;
+        if (!node.open || !node.dataset.subtree || node.dataset.loaded) return;
+        node.dataset.loaded = '1';
+        fetch(node.dataset.subtree).then(function(r) {
+            return r.ok ? r.text() : '';
+        }).then(function(html) {
+            node.insertAdjacentHTML('beforeend', html);
+            annotate(node);
+        });
+    }, true);
+    annotate(sidebar);
+})();
+"#;
+
+/// Restores the saved line density before the page paints, and wires the
+/// `#density-toggle` button and the `d` key to flip it.
+const DENSITY_SCRIPT: &str = r#"
+(function() {
+    var key = 'irc-log-viewer.density';
+    var body = document.body;
+    body.classList.toggle('compact', localStorage.getItem(key) === 'compact');
+    function flip() {
+        var compact = body.classList.toggle('compact');
+        localStorage.setItem(key, compact ? 'compact' : 'comfortable');
+    }
+    document.addEventListener('click', function(e) {
+        if (e.target.id === 'density-toggle') flip();
+    });
+    document.addEventListener('keydown', function(e) {
+        if (e.key !== 'd' || e.ctrlKey || e.metaKey || e.altKey) return;
+        if (/^(INPUT|TEXTAREA|SELECT)$/.test(e.target.tagName)) return;
+        flip();
+    });
+})();
+"#;
+
+/// When `#log-top` scrolls into view, fetches the day in its `data-prev`
+/// as a fragment and prepends it under a date heading, keeping the reader's
+/// place. The fragment's `X-Prev-Date` header names the next day to load.
+/// Same-page links in the fragment (permalinks, mentions) are pointed at
+/// its own day, since its anchors repeat the current day's.
+const INFINITE_SCROLL_SCRIPT: &str = r##"
+(function() {
+    var top = document.getElementById('log-top');
+    var log = document.getElementById('log');
+    var base = window.location.pathname.replace(/\/[^\/]*$/, '/');
+    var loading = false;
+    function load() {
+        var date = top.dataset.prev;
+        if (loading || !date) return;
+        loading = true;
+        fetch(base + date + '/fragment' + window.location.search).then(function(r) {
+            if (!r.ok) throw new Error(r.status);
+            var next = r.headers.get('X-Prev-Date');
+            return r.text().then(function(html) { return [html, next]; });
+        }).then(function(res) {
+            var day = document.createElement('div');
+            day.className = 'scroll-day';
+            day.innerHTML = res[0];
+            day.querySelectorAll('[id]').forEach(function(el) { el.removeAttribute('id'); });
+            day.querySelectorAll('a[href^="#"]').forEach(function(a) {
+                a.href = base + date + a.getAttribute('href');
+            });
+            var heading = document.createElement('a');
+            heading.className = 'scroll-day-heading';
+            heading.href = base + date;
+            heading.textContent = date;
+            day.insertBefore(heading, day.firstChild);
+            var height = document.documentElement.scrollHeight;
+            log.insertBefore(day, log.firstChild);
+            window.scrollBy(0, document.documentElement.scrollHeight - height);
+            loading = false;
+            if (!next) return top.remove();
+            top.dataset.prev = next;
+            // A short day may leave the top in view without another scroll
+            if (top.getBoundingClientRect().bottom >= 0) load();
+        }).catch(function() {
+            top.remove();
+        });
+    }
+    new IntersectionObserver(function(entries) {
+        if (entries[0].isIntersecting) load();
+    }).observe(top);
+})();
+"##;
+
+/// Log page keys: `j`/`k` step through visible lines, `h`/`l` or the arrows
+/// follow the previous/next day links, `t` opens today and `/` focuses the
+/// search box. Ignored while typing in a form field.
+const KEYBOARD_SCRIPT: &str = r#"
+(function() {
+    var current = null;
+    function visibleLines() {
+        return Array.prototype.filter.call(document.querySelectorAll('#log .line'), function(l) {
+            return l.offsetParent !== null;
+        });
+    }
+    function step(dir) {
+        var lines = visibleLines();
+        if (!lines.length) return;
+        var i = lines.indexOf(current);
+        if (i === -1) {
+            // Start from the first line in view
+            i = lines.findIndex(function(l) { return l.getBoundingClientRect().top >= 0; });
+            if (i === -1) i = lines.length - 1;
+        } else {
+            i = Math.max(0, Math.min(lines.length - 1, i + dir));
+        }
+        if (current) current.classList.remove('kbd-current');
+        current = lines[i];
+        current.classList.add('kbd-current');
+        current.scrollIntoView({ block: 'nearest' });
+    }
+    function follow(selector) {
+        var link = document.querySelector(selector);
+        if (link) window.location.href = link.href;
+    }
+    document.addEventListener('keydown', function(e) {
+        if (e.ctrlKey || e.metaKey || e.altKey) return;
+        if (/^(INPUT|TEXTAREA|SELECT)$/.test(e.target.tagName) || e.target.isContentEditable) return;
+        switch (e.key) {
+            case 'j': step(1); break;
+            case 'k': step(-1); break;
+            case 'h': case 'ArrowLeft': follow('a[rel=prev]'); break;
+            case 'l': case 'ArrowRight': follow('a[rel=next]'); break;
+            case 't': follow('#today-link'); break;
+            case '/': document.getElementById('log-search').focus(); break;
+            default: return;
+        }
+        e.preventDefault();
+    });
+})();
+"#;
+
+/// `#T120000-T120530` highlights every line in that time span and scrolls to
+/// the first; shift-clicking a second timestamp after following one builds
+/// such a link. The single-line `#T120000` form is left to `:target`.
+const RANGE_SCRIPT: &str = r#"
+(function() {
+    var log = document.getElementById('log');
+    var anchor = /^#(T\d{6})/.exec(window.location.hash);
+    var first = anchor && anchor[1];
+    function apply(scroll) {
+        log.querySelectorAll('.line.range-hl').forEach(function(l) { l.classList.remove('range-hl'); });
+        var m = /^#(T\d{6})-(T\d{6})$/.exec(window.location.hash);
+        if (!m) return;
+        var from = m[1] < m[2] ? m[1] : m[2];
+        var to = m[1] < m[2] ? m[2] : m[1];
+        var start = null;
+        log.querySelectorAll('.line[id^="T"]').forEach(function(l) {
+            if (l.id < from || l.id > to) return;
+            l.classList.add('range-hl');
+            start = start || l;
+        });
+        if (!start || !scroll) return;
+        var burst = start.closest('details.event-burst');
+        if (burst) burst.open = true;
+        start.scrollIntoView();
+    }
+    apply(true);
+    window.addEventListener('hashchange', function() { apply(true); });
+    log.addEventListener('click', function(e) {
+        var line = e.target.closest('a.ts') && e.target.closest('.line[id]');
+        if (!line) return;
+        if (!e.shiftKey || !first) {
+            first = line.id;
+            return;
+        }
+        e.preventDefault();
+        var range = first < line.id ? first + '-' + line.id : line.id + '-' + first;
+        history.replaceState(null, '', '#' + range);
+        apply(false);
+    });
+})();
+"#;
+
+/// The buttons at the end of each line copy its `data-raw` text, or the
+/// absolute URL of its timestamp link.
+const COPY_SCRIPT: &str = r#"
+(function() {
+    var log = document.getElementById('log');
+    log.addEventListener('click', function(e) {
+        var button = e.target.closest('.line-tools button');
+        if (!button || !navigator.clipboard) return;
+        var line = button.closest('.line');
+        var text = button.classList.contains('copy-raw')
+            ? line.dataset.raw
+            : new URL(line.querySelector('a.ts').getAttribute('href'), window.location.href).href;
+        navigator.clipboard.writeText(text).then(function() {
+            button.classList.add('copied');
+            setTimeout(function() { button.classList.remove('copied'); }, 1000);
+        });
+    });
+})();
+"#;
+
+/// Explains a redirect from a day without a log (`?missing=`), then drops
+/// the parameter so reloading or sharing the URL does not repeat it.
+const MISSING_DATE_SCRIPT: &str = r#"
+(function() {
+    var url = new URL(window.location.href);
+    var missing = url.searchParams.get('missing');
+    if (!missing) return;
+    if (/^\d{4}-\d\d-\d\d$/.test(missing)) {
+        var notice = document.getElementById('date-notice');
+        notice.textContent = 'No log on ' + missing + '; showing the nearest day that has one.';
+        notice.hidden = false;
+    }
+    url.searchParams.delete('missing');
+    history.replaceState(null, '', url);
+})();
+"#;
+
+/// Hides log lines whose `data-nick` has no nick containing the typed
+/// text. Lines appended later by the live tail are filtered as they land.
+const NICK_FILTER_SCRIPT: &str = r#"
+(function() {
+    var input = document.getElementById('nick-filter');
+    var log = document.getElementById('log');
+    function apply(line) {
+        var q = input.value.trim().toLowerCase();
+        var nicks = (line.dataset.nick || '').toLowerCase().split(' ');
+        line.classList.toggle('nick-hidden', q !== '' && !nicks.some(function(n) { return n.indexOf(q) !== -1; }));
+    }
+    function applyAll() {
+        log.querySelectorAll('.line').forEach(apply);
+    }
+    input.addEventListener('input', applyAll);
+    new MutationObserver(function(records) {
+        records.forEach(function(r) {
+            r.addedNodes.forEach(function(n) {
+                if (n.classList && n.classList.contains('line')) apply(n);
+            });
+        });
+    }).observe(log, { childList: true });
+    if (input.value) applyAll();
+})();
+"#;
+
+/// Hovering a mention highlights that nick's lines, clicking one jumps to
+/// their last line before it, and mentions of the reader's own nick (kept
+/// in localStorage) are highlighted.
+const MENTION_SCRIPT: &str = r#"
+(function() {
+    var key = 'irc-log-viewer.nick';
+    var input = document.getElementById('self-nick');
+    var log = document.getElementById('log');
+    input.value = localStorage.getItem(key) || '';
+    function linesOf(nick) {
+        return log.querySelectorAll('.line[data-nick~="' + CSS.escape(nick) + '" i]');
+    }
+    function apply(line) {
+        var me = input.value.trim().toLowerCase();
+        var mentioned = false;
+        line.querySelectorAll('.mention').forEach(function(m) {
+            var self = me !== '' && m.dataset.nick.toLowerCase() === me;
+            m.classList.toggle('self', self);
+            mentioned = mentioned || self;
+        });
+        line.classList.toggle('mentions-self', mentioned);
+    }
+    function applyAll() {
+        log.querySelectorAll('.line').forEach(apply);
+    }
+    function hover(e, on) {
+        var m = e.target.closest('.mention');
+        if (m) linesOf(m.dataset.nick).forEach(function(l) { l.classList.toggle('mention-hl', on); });
+    }
+    input.addEventListener('input', function() {
+        localStorage.setItem(key, input.value.trim());
+        applyAll();
+    });
+    log.addEventListener('mouseover', function(e) { hover(e, true); });
+    log.addEventListener('mouseout', function(e) { hover(e, false); });
+    log.addEventListener('click', function(e) {
+        var m = e.target.closest('.mention');
+        if (!m) return;
+        var from = m.closest('.line'), target = null;
+        linesOf(m.dataset.nick).forEach(function(l) {
+            if (l !== from && l.compareDocumentPosition(from) & Node.DOCUMENT_POSITION_FOLLOWING) target = l;
+        });
+        if (target) {
+            e.preventDefault();
+            window.location.hash = target.id;
+        }
+    });
+    new MutationObserver(function(records) {
+        records.forEach(function(r) {
+            r.addedNodes.forEach(function(n) {
+                if (n.classList && n.classList.contains('line')) apply(n);
+            });
+        });
+    }).observe(log, { childList: true });
+    applyAll();
+})();
+"#;
+
+/// Runs in `<head>` so the theme applies before the first paint: the
+/// stored one, else the OS `prefers-color-scheme`, followed as it changes.
+const THEME_SCRIPT: &str = r#"
+(function() {
+    var key = 'irc-log-viewer.theme';
+    var root = document.documentElement;
+    var system = matchMedia('(prefers-color-scheme: light)');
+    function apply() {
+        root.dataset.theme = localStorage.getItem(key) || (system.matches ? 'light' : 'dark');
+    }
+    apply();
+    system.addEventListener('change', apply);
+    document.addEventListener('click', function(e) {
+        if (e.target.id !== 'theme-toggle') return;
+        root.dataset.theme = root.dataset.theme === 'light' ? 'dark' : 'light';
+        localStorage.setItem(key, root.dataset.theme);
+    });
+})();
+"#;
+
+fn density_toggle() -> Markup {
+    html! {
+        button id="density-toggle" type="button" title="toggle line density (d)" { "density" }
+    }
+}
+
 pub fn page(title: &str, tree: &ChannelNode, base_path: &str, content: Markup) -> Markup {
+    page_with_head(title, tree, base_path, html! {}, content)
+}
+
+/// [`page`] with extra elements, such as `meta` tags, at the end of `head`.
+pub fn page_with_head(title: &str, tree: &ChannelNode, base_path: &str, head: Markup, content: Markup) -> Markup {
+    let mut favorites = Vec::new();
+    collect_favorites(tree, &mut favorites);
     html! {
         (DOCTYPE)
         html lang="en" {
@@ -149,13 +1076,33 @@ pub fn page(title: &str, tree: &ChannelNode, base_path: &str, content: Markup) -
                 meta name="viewport" content="width=device-width, initial-scale=1";
                 title { (title) }
                 link rel="stylesheet" href=(format!("{base_path}/static/style.css"));
+                script { (PreEscaped(THEME_SCRIPT)) }
+                (head)
             }
             body {
+                script { (PreEscaped(DENSITY_SCRIPT)) }
                 button id="sidebar-toggle" onclick="document.getElementById('sidebar').classList.toggle('open')" { "☰" }
-                nav id="sidebar" {
+                nav id="sidebar" data-latest=(format!("{base_path}/latest.json")) {
                     h2 { (title) }
+                    form.sidebar-search action=(format!("{base_path}/search")) method="get" {
+                        input type="search" name="q" placeholder="search all…";
+                    }
+                    button id="theme-toggle" type="button" title="switch between dark and light" { "theme" }
+                    @if !favorites.is_empty() {
+                        h3 { "favorites" }
+                        ul.favorites {
+                            @for channel in &favorites {
+                                li {
+                                    a href=(format!("{base_path}/{}/today", channel.path_segments.join("/").replace('#', "%23")))
+                                        title=(channel.path_segments.join("/")) { (channel.display_name()) }
+                                }
+                            }
+                        }
+                        h3 { "all" }
+                    }
                     (render_channel_tree(tree, "", base_path))
                 }
+                script { (PreEscaped(SIDEBAR_SCRIPT)) }
                 main onclick="document.getElementById('sidebar').classList.remove('open')" {
                     (content)
                 }
@@ -164,17 +1111,179 @@ pub fn page(title: &str, tree: &ChannelNode, base_path: &str, content: Markup) -
     }
 }
 
+/// Which lines the log page shows: everything, only messages/actions, or
+/// only events. All lines stay in the DOM; this just picks a CSS class,
+/// except `Collapsed` which folds bursts of membership events server-side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventView {
+    All,
+    Messages,
+    Events,
+    Collapsed,
+}
+
+impl EventView {
+    pub fn from_param(s: Option<&str>) -> Self {
+        match s {
+            Some("messages") => EventView::Messages,
+            Some("events") => EventView::Events,
+            Some("collapsed") => EventView::Collapsed,
+            _ => EventView::All,
+        }
+    }
+
+    pub fn param(self) -> &'static str {
+        match self {
+            EventView::All => "all",
+            EventView::Messages => "messages",
+            EventView::Events => "events",
+            EventView::Collapsed => "collapsed",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            EventView::All => "all lines",
+            EventView::Messages => "messages only",
+            EventView::Events => "events only",
+            EventView::Collapsed => "collapse event bursts",
+        }
+    }
+
+    fn class(self) -> Option<&'static str> {
+        match self {
+            EventView::All | EventView::Collapsed => None,
+            EventView::Messages => Some("hide-events"),
+            EventView::Events => Some("only-events"),
+        }
+    }
+}
+
+/// Membership churn that `EventView::Collapsed` folds; topics and raw lines
+/// carry content and stay visible.
+fn is_churn(line: &LogLine) -> bool {
+    matches!(
+        line.kind,
+        LineKind::Join { .. } | LineKind::Quit { .. } | LineKind::Part { .. } | LineKind::NickChange { .. } | LineKind::Kick { .. }
+    )
+}
+
+/// "5 joins, 3 quits" for a run of churn events.
+fn churn_summary(run: &[(Option<usize>, LogLine)]) -> String {
+    let kinds = [("join", "joins"), ("quit", "quits"), ("part", "parts"), ("nick change", "nick changes"), ("kick", "kicks")];
+    let mut counts = [0usize; 5];
+    for (_, line) in run {
+        let i = match line.kind {
+            LineKind::Join { .. } => 0,
+            LineKind::Quit { .. } => 1,
+            LineKind::Part { .. } => 2,
+            LineKind::NickChange { .. } => 3,
+            _ => 4,
+        };
+        counts[i] += 1;
+    }
+    kinds
+        .iter()
+        .zip(counts)
+        .filter(|(_, n)| *n > 0)
+        .map(|((one, many), n)| format!("{n} {}", if n == 1 { one } else { many }))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Log lines, with runs of two or more churn events in the same minute
+/// folded into one `<details>` when `collapse` is set.
+fn render_lines(lines: &[(Option<usize>, LogLine)], opts: &RenderOptions, collapse: bool) -> Markup {
+    let mut out = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let first = &lines[i].1;
+        let same_minute = |l: &LogLine| (l.time.hour, l.time.minute) == (first.time.hour, first.time.minute);
+        let run = if collapse && is_churn(first) {
+            lines[i..].iter().take_while(|(_, l)| is_churn(l) && same_minute(l)).count()
+        } else {
+            1
+        };
+        if run < 2 {
+            out.push_str(&render_line(first, opts, lines[i].0).into_string());
+        } else {
+            let burst = &lines[i..i + run];
+            let time = format!("{:02}:{:02}", first.time.hour, first.time.minute);
+            out.push_str(&html! {
+                details.event-burst {
+                    summary { (time) " — " (churn_summary(burst)) }
+                    @for (line_no, line) in burst {
+                        (render_line(line, opts, *line_no))
+                    }
+                }
+            }.into_string());
+        }
+        i += run;
+    }
+    PreEscaped(out)
+}
+
+/// A day's lines without the page around them, for prepending to a log
+/// page as the reader scrolls up.
+pub fn log_fragment(lines: &[(Option<usize>, LogLine)], opts: &RenderOptions, view: EventView) -> Markup {
+    render_lines(lines, opts, view == EventView::Collapsed)
+}
+
+/// The slice of a day shown when a page is limited to `limit` lines, with
+/// the `from` times of the neighbouring slices.
+pub struct PageWindow {
+    pub limit: usize,
+    pub from: Option<Time>,
+    pub earlier: Option<Time>,
+    pub later: Option<Time>,
+}
+
 pub struct LogPageContext<'a> {
     pub title: &'a str,
     pub tree: &'a ChannelNode,
     pub channel: &'a Channel,
     pub date: &'a str,
-    pub lines: &'a [LogLine],
+    /// Parsed lines with their 1-based line number in the file, if any.
+    pub lines: &'a [(Option<usize>, LogLine)],
     pub prev_date: Option<&'a str>,
     pub next_date: Option<&'a str>,
     pub is_today: bool,
+    /// Bytes of the log file rendered, so the live tail resumes after them.
+    pub file_len: u64,
     pub ai_enabled: bool,
     pub base_path: &'a str,
+    pub render: RenderOptions<'a>,
+    pub view: EventView,
+    /// The view without `?view=`, per the channel's `hide_events_default`.
+    pub default_view: EventView,
+    pub window: Option<PageWindow>,
+    /// Prepend earlier days when scrolling past the top of the log.
+    pub infinite_scroll: bool,
+    /// Absolute URL of the page, for link previews.
+    pub url: &'a str,
+}
+
+/// Longest `og:description`, in characters.
+const PREVIEW_LEN: usize = 200;
+
+/// The first message of the page as `<nick> text`, for link previews.
+fn preview_text(lines: &[(Option<usize>, LogLine)]) -> Option<String> {
+    let text = lines.iter().find_map(|(_, line)| match &line.kind {
+        LineKind::Message { nick, text } => Some(format!("<{nick}> {}", plain_text(text))),
+        LineKind::Action { nick, text } => Some(format!("* {nick} {}", plain_text(text))),
+        _ => None,
+    })?;
+    match text.char_indices().nth(PREVIEW_LEN) {
+        Some((end, _)) => Some(format!("{}…", &text[..end])),
+        None => Some(text),
+    }
+}
+
+/// `?from=` link to another window of the day, naming the view unless it
+/// is the channel's default, which a missing `view` falls back to.
+fn window_href(window: &PageWindow, from: Time, view: EventView, default_view: EventView) -> String {
+    let view = if view == default_view { String::new() } else { format!("&view={}", view.param()) };
+    format!("?from={}&limit={}{view}", from.to_hms(), window.limit)
 }
 
 pub fn log_page(ctx: &LogPageContext) -> Markup {
@@ -189,12 +1298,25 @@ pub fn log_page(ctx: &LogPageContext) -> Markup {
     let ai_enabled = ctx.ai_enabled;
     let bp = ctx.base_path;
     let encoded = channel.path_segments.join("/").replace('#', "%23");
-    page(title, tree, bp, html! {
+    // Only when the page starts at the beginning of the day
+    let scroll_prev = prev_date.filter(|_| ctx.infinite_scroll && ctx.window.as_ref().is_none_or(|w| w.earlier.is_none()));
+    let description = preview_text(lines);
+    let head = html! {
+        meta property="og:type" content="website";
+        meta property="og:site_name" content=(title);
+        meta property="og:title" content=(format!("{} — {date}", channel.display_name()));
+        meta property="og:url" content=(ctx.url);
+        @if let Some(description) = &description {
+            meta property="og:description" content=(description);
+            meta name="description" content=(description);
+        }
+    };
+    page_with_head(title, tree, bp, head, html! {
         header id="log-header" {
-            h1 { (&channel.name) " — " (date) }
+            h1 { (channel.display_name()) " — " (date) }
             div.nav-links {
                 @if let Some(prev) = prev_date {
-                    a href=(format!("{bp}/{encoded}/{prev}")) { "← " (prev) }
+                    a rel="prev" href=(format!("{bp}/{encoded}/{prev}")) title="previous day (h, ←)" { "← " (prev) }
                 }
                 " "
                 input type="date" value=(date)
@@ -202,133 +1324,542 @@ pub fn log_page(ctx: &LogPageContext) -> Markup {
                     ;
                 " "
                 @if let Some(next) = next_date {
-                    a href=(format!("{bp}/{encoded}/{next}")) { (next) " →" }
+                    a rel="next" href=(format!("{bp}/{encoded}/{next}")) title="next day (l, →)" { (next) " →" }
                 }
                 " | "
-                a href=(format!("{bp}/{encoded}/today")) { "today" }
+                a id="today-link" href=(format!("{bp}/{encoded}/today")) title="today (t)" { "today" }
+                " "
+                a href=(format!("{bp}/{encoded}/random")) { "random" }
+                " "
+                a href=(format!("{bp}/{encoded}/calendar")) { "calendar" }
+                " "
+                a href=(format!("{bp}/{encoded}/stats")) { "stats" }
+                " "
+                a href=(format!("{bp}/{encoded}/feed.xml")) { "feed" }
                 " "
                 a href=(format!("{bp}/{encoded}/{date}/raw")) { "raw" }
+                " "
+                a href=(format!("{bp}/{encoded}/{date}/raw?wrap=0")) { "nowrap" }
             }
             div.controls {
-                label {
-                    input id="toggle-events" type="checkbox" checked;
-                    " show events"
+                select id="event-view" title="events" data-default=(ctx.default_view.param()) {
+                    @for view in [EventView::All, EventView::Messages, EventView::Events, EventView::Collapsed] {
+                        option value=(view.param()) selected[view == ctx.view] { (view.label()) }
+                    }
                 }
                 " "
                 form.search-form action=(format!("{bp}/{encoded}/search")) method="get" {
-                    input type="text" name="q" placeholder="search…";
+                    input id="log-search" type="text" name="q" placeholder="search…" title="search this channel (/)";
                     button type="submit" { "go" }
                 }
+                " "
+                input id="nick-filter" type="search" placeholder="nick…" title="show only lines involving this nick";
+                " "
+                input id="self-nick" type="text" placeholder="your nick" title="highlight mentions of this nick";
                 @if ai_enabled {
                     " "
                     a href=(format!("{bp}/{encoded}/ask")) { "ask" }
                 }
+                " "
+                (density_toggle())
+            }
+        }
+        div id="date-notice" hidden {}
+        @if let Some(earlier) = ctx.window.as_ref().and_then(|w| Some((w, w.earlier?))) {
+            a.window-link href=(window_href(earlier.0, earlier.1, ctx.view, ctx.default_view)) { "↑ load earlier" }
+        }
+        @if let Some(prev) = scroll_prev {
+            div id="log-top" data-prev=(prev) {}
+        }
+        div id="log" class=[ctx.view.class()] data-channel=(&encoded) data-pos=[is_today.then_some(ctx.file_len)]
+            data-limit=[ctx.window.as_ref().map(|w| w.limit)]
+            data-from=[ctx.window.as_ref().and_then(|w| w.from).map(Time::to_hms)] {
+            (render_lines(lines, &ctx.render, ctx.view == EventView::Collapsed))
+        }
+        @if let Some(later) = ctx.window.as_ref().and_then(|w| Some((w, w.later?))) {
+            a.window-link href=(window_href(later.0, later.1, ctx.view, ctx.default_view)) { "↓ load later" }
+        }
+        @if ctx.window.is_some() {
+            // A permalink to a line outside this slice reloads the slice
+            // starting at that line
+            script {
+                (PreEscaped(r#"
+(function() {
+    var log = document.getElementById('log');
+    var m = /^#T(\d\d)(\d\d)(\d\d)(-T\d{6})?$/.exec(window.location.hash);
+    if (!m || document.getElementById(window.location.hash.slice(1, 8))) return;
+    var from = m[1] + ':' + m[2] + ':' + m[3];
+    if (log.dataset.from === from) return;
+    var url = new URL(window.location.href);
+    url.searchParams.set('from', from);
+    url.searchParams.set('limit', log.dataset.limit);
+    window.location.replace(url);
+})();
+"#))
+            }
+        }
+        script { (PreEscaped(NICK_FILTER_SCRIPT)) }
+        script { (PreEscaped(MENTION_SCRIPT)) }
+        script { (PreEscaped(KEYBOARD_SCRIPT)) }
+        script { (PreEscaped(RANGE_SCRIPT)) }
+        script { (PreEscaped(COPY_SCRIPT)) }
+        script { (PreEscaped(MISSING_DATE_SCRIPT)) }
+        @if scroll_prev.is_some() {
+            script { (PreEscaped(INFINITE_SCROLL_SCRIPT)) }
+        }
+        @if is_today {
+            script {
+                (PreEscaped(format!(r#"
+(function() {{
+    var log = document.getElementById('log');
+    var src = new EventSource('{bp}/' + log.dataset.channel + '/latest?pos=' + log.dataset.pos);
+    var atBottom = true;
+    window.addEventListener('scroll', function() {{
+        atBottom = (window.innerHeight + window.scrollY) >= (document.body.offsetHeight - 50);
+    }});
+    src.onmessage = function(e) {{
+        log.insertAdjacentHTML('beforeend', e.data);
+        if (atBottom) window.scrollTo(0, document.body.scrollHeight);
+    }};
+    src.addEventListener('rollover', function(e) {{
+        src.close();
+        window.location.href = '{bp}/' + log.dataset.channel + '/' + e.data;
+    }});
+    // Too slow to keep up: some lines were dropped, so fetch the day again
+    src.addEventListener('lagged', function() {{
+        src.close();
+        window.location.reload();
+    }});
+}})();
+"#)))
+            }
+        }
+        script {
+            (PreEscaped(r#"
+(function() {
+    var sel = document.getElementById('event-view');
+    var log = document.getElementById('log');
+    var collapsed = sel.value === 'collapsed';
+    sel.addEventListener('change', function() {
+        var url = new URL(window.location.href);
+        if (sel.value === sel.dataset.default) url.searchParams.delete('view');
+        else url.searchParams.set('view', sel.value);
+        // Bursts are folded server-side, so entering or leaving that view reloads
+        if (collapsed || sel.value === 'collapsed') {
+            window.location.href = url;
+            return;
+        }
+        log.classList.toggle('hide-events', sel.value === 'messages');
+        log.classList.toggle('only-events', sel.value === 'events');
+        history.replaceState(null, '', url);
+    });
+    // Open the burst holding a permalinked line
+    var target = window.location.hash && document.getElementById(window.location.hash.slice(1));
+    var burst = target && target.closest('details.event-burst');
+    if (burst) {
+        burst.open = true;
+        target.scrollIntoView();
+    }
+})();
+"#))
+        }
+    })
+}
+
+/// A single dated search match, as listed on the search page and streamed
+/// by `search/stream`.
+pub fn search_result_line(
+    base_path: &str,
+    encoded: &str,
+    date: &str,
+    line: &LogLine,
+    opts: &RenderOptions,
+    matcher: Option<&Matcher>,
+) -> Markup {
+    html! {
+        div.line {
+            a.date href=(format!("{base_path}/{encoded}/{date}#{}", line.time.to_anchor())) {
+                (date)
+            }
+            " "
+            a.ts href=(format!("{base_path}/{encoded}/{date}#{}", line.time.to_anchor())) {
+                (line.time.to_hms())
+            }
+            " "
+            (search_line_body(line, opts, matcher))
+        }
+    }
+}
+
+pub struct SearchPageContext<'a> {
+    pub title: &'a str,
+    pub tree: &'a ChannelNode,
+    pub channel: &'a Channel,
+    pub query: &'a str,
+    pub groups: &'a [ResultGroup],
+    /// Results with context lines, used instead of `groups` when `context`
+    /// is not 0.
+    pub blocks: &'a [ContextBlock],
+    /// Matches per date, oldest first, shown instead of any lines with
+    /// `?counts=1`.
+    pub counts: Option<&'a [(String, usize)]>,
+    /// The counts stopped at `search_all_max_files`, leaving older days out.
+    pub counts_truncated: bool,
+    pub group_minutes: u32,
+    pub context: usize,
+    pub filter: &'a Filter,
+    pub regex: bool,
+    pub case: bool,
+    /// Why the query could not be run, e.g. an invalid regex.
+    pub error: Option<&'a str>,
+    /// Highlights the matches in results.
+    pub matcher: Option<&'a Matcher>,
+    pub base_path: &'a str,
+    pub render: RenderOptions<'a>,
+}
+
+fn search_line_body(line: &LogLine, opts: &RenderOptions, matcher: Option<&Matcher>) -> Markup {
+    html! {
+        @match &line.kind {
+            LineKind::Message { nick, text } => {
+                (nick_span(nick, opts, true))
+                " "
+                span.msg { (render_text_marked(text, opts, matcher)) }
+            },
+            LineKind::Action { nick, text } => {
+                span.action {
+                    "* "
+                    (nick_span(nick, opts, false))
+                    " "
+                    (render_text_marked(text, opts, matcher))
+                }
+            },
+            LineKind::Raw { text } => {
+                span.raw { (text) }
+            },
+            _ => {
+                span.ev { "event" }
+            },
+        }
+    }
+}
+
+pub struct SearchAllContext<'a> {
+    pub title: &'a str,
+    pub tree: &'a ChannelNode,
+    /// Channel path prefix searched under; empty for the whole tree.
+    pub scope: &'a str,
+    pub query: &'a str,
+    pub results: &'a [ChannelResults],
+    /// The match or file budget ran out before every channel was searched.
+    pub truncated: bool,
+    pub regex: bool,
+    pub case: bool,
+    pub error: Option<&'a str>,
+    pub matcher: Option<&'a Matcher>,
+    pub base_path: &'a str,
+    pub render: RenderOptions<'a>,
+}
+
+pub fn search_all_page(ctx: &SearchAllContext) -> Markup {
+    let base_path = ctx.base_path;
+    let where_ = if ctx.scope.is_empty() { "all channels" } else { ctx.scope };
+    let total: usize = ctx.results.iter().map(|r| r.results.len()).sum();
+    page(ctx.title, ctx.tree, base_path, html! {
+        header id="log-header" {
+            h1 { "search — " (where_) }
+            div.controls {
+                form.search-form action=(format!("{base_path}/search")) method="get" {
+                    input type="text" name="q" value=(ctx.query) placeholder="search all channels…";
+                    @if !ctx.scope.is_empty() {
+                        input type="hidden" name="scope" value=(ctx.scope);
+                    }
+                    label {
+                        input type="checkbox" name="regex" value="1" checked[ctx.regex];
+                        " regex"
+                    }
+                    label {
+                        input type="checkbox" name="case" value="1" checked[ctx.case];
+                        " case"
+                    }
+                    button type="submit" { "go" }
+                }
+                " "
+                (density_toggle())
+            }
+        }
+        div id="log" {
+            @if let Some(error) = ctx.error {
+                p.search-error { "invalid search: " (error) }
+            } @else if ctx.query.is_empty() {
+            } @else if ctx.results.is_empty() {
+                p { "no results for \"" (ctx.query) "\"" }
+            } @else {
+                p #search-status {
+                    (total) " matches in " (ctx.results.len()) " channels"
+                    @if ctx.truncated { " (search stopped early, refine the query for more)" }
+                }
+            }
+            @for found in ctx.results {
+                @let encoded = found.channel.path_segments.join("/").replace('#', "%23");
+                section.channel-results {
+                    h2 {
+                        a href=(format!("{base_path}/{encoded}/today")) {
+                            (found.channel.path_segments.join("/"))
+                        }
+                        span.ev { " " (found.results.len()) " matches" }
+                    }
+                    @for (date, line) in &found.results {
+                        (search_result_line(base_path, &encoded, date, line, &ctx.render, ctx.matcher))
+                    }
+                }
             }
         }
-        div id="log" data-channel=(&encoded) {
-            @for line in lines {
-                (render_line(line))
-            }
+    })
+}
+
+/// `s` escaped for a URL query string value.
+fn query_value(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => char::from(b).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// A bar per day with matches, scaled to the busiest, then the days from
+/// most to fewest matches, each linking to the day and to its matches.
+fn match_counts(ctx: &SearchPageContext, encoded: &str) -> Markup {
+    let bp = ctx.base_path;
+    let counts = ctx.counts.unwrap_or_default();
+    let total: usize = counts.iter().map(|(_, n)| n).sum();
+    let busiest = counts.iter().map(|(_, n)| *n).max().unwrap_or(1);
+    let mut ranked: Vec<&(String, usize)> = counts.iter().collect();
+    ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    // The same search, bounded to one day
+    let mut params = format!("q={}", query_value(ctx.query));
+    for (flag, on) in [("regex", ctx.regex), ("case", ctx.case), ("msg", ctx.filter.messages_only)] {
+        if on {
+            params.push_str(&format!("&{flag}=1"));
         }
-        @if is_today {
-            script {
-                (PreEscaped(format!(r#"
-(function() {{
-    var log = document.getElementById('log');
-    var src = new EventSource('{bp}/' + log.dataset.channel + '/latest');
-    var atBottom = true;
-    window.addEventListener('scroll', function() {{
-        atBottom = (window.innerHeight + window.scrollY) >= (document.body.offsetHeight - 50);
-    }});
-    src.onmessage = function(e) {{
-        log.insertAdjacentHTML('beforeend', e.data);
-        if (atBottom) window.scrollTo(0, document.body.scrollHeight);
-    }};
-    var cb = document.getElementById('toggle-events');
-    cb.addEventListener('change', function() {{
-        log.classList.toggle('hide-events', !cb.checked);
-    }});
-}})();
-"#)))
+    }
+    if let Some(nick) = &ctx.filter.nick {
+        params.push_str(&format!("&nick={}", query_value(nick)));
+    }
+    html! {
+        p.search-summary {
+            (total) " matches on " (counts.len()) " days"
+            @if ctx.counts_truncated { " (search stopped early, narrow the dates for more)" }
+        }
+        div.search-histogram {
+            @for (date, n) in counts {
+                a href=(format!("{bp}/{encoded}/{date}")) title=(format!("{date}: {n} matches"))
+                    style=(format!("height: {}%", (n * 100).div_ceil(busiest))) {}
             }
-        } @else {
-            script {
-                (PreEscaped(r#"
-(function() {
-    var cb = document.getElementById('toggle-events');
-    var log = document.getElementById('log');
-    cb.addEventListener('change', function() {
-        log.classList.toggle('hide-events', !cb.checked);
-    });
-})();
-"#))
+        }
+        ol.search-counts {
+            @for (date, n) in ranked {
+                li {
+                    a.date href=(format!("{bp}/{encoded}/{date}")) { (date) }
+                    " "
+                    a href=(format!("{bp}/{encoded}/search?{params}&from={date}&to={date}")) { (n) " matches" }
+                }
             }
         }
-    })
+    }
 }
 
-pub fn search_page(
-    title: &str,
-    tree: &ChannelNode,
-    channel: &Channel,
-    query: &str,
-    results: &[(String, LogLine)],
-    base_path: &str,
-) -> Markup {
+pub fn search_page(ctx: &SearchPageContext) -> Markup {
+    let channel = ctx.channel;
+    let query = ctx.query;
+    let base_path = ctx.base_path;
+    let opts = &ctx.render;
     let encoded = channel.path_segments.join("/").replace('#', "%23");
-    page(title, tree, base_path, html! {
+    page(ctx.title, ctx.tree, base_path, html! {
         header id="log-header" {
-            h1 { (&channel.name) " — search" }
+            h1 { (channel.display_name()) " — search" }
             div.controls {
                 form.search-form action=(format!("{base_path}/{encoded}/search")) method="get" {
                     input type="text" name="q" value=(query) placeholder="search…";
+                    label {
+                        input type="checkbox" name="group" value="5" checked[ctx.group_minutes > 0];
+                        " group"
+                    }
+                    label {
+                        input type="checkbox" name="regex" value="1" checked[ctx.regex];
+                        " regex"
+                    }
+                    label {
+                        input type="checkbox" name="case" value="1" checked[ctx.case];
+                        " case"
+                    }
+                    label {
+                        input type="checkbox" name="msg" value="1" checked[ctx.filter.messages_only];
+                        " messages"
+                    }
+                    input.search-nick type="text" name="nick" value=[ctx.filter.nick.as_deref()] placeholder="nick…"
+                        title="only lines involving this nick" list="search-nicks" autocomplete="off";
+                    datalist id="search-nicks" {}
+                    label title="count matches per day instead of listing them" {
+                        input type="checkbox" name="counts" value="1" checked[ctx.counts.is_some()];
+                        " per day"
+                    }
+                    label title="context lines around each match" {
+                        "context "
+                        input.search-context type="number" name="C" min="0" max="20" value=(ctx.context);
+                    }
+                    label {
+                        "from "
+                        input type="date" name="from" value=[ctx.filter.from.as_deref()];
+                    }
+                    label {
+                        "to "
+                        input type="date" name="to" value=[ctx.filter.to.as_deref()];
+                    }
                     button type="submit" { "go" }
+                    button id="search-stop" type="button" hidden { "stop" }
                 }
+                " "
+                span id="search-status" {}
+                " "
+                (density_toggle())
             }
         }
         div id="log" {
-            @if results.is_empty() {
+            @if let Some(error) = ctx.error {
+                p.search-error { "invalid search: " (error) }
+            } @else if ctx.counts.is_some_and(|c| !c.is_empty()) {
+                (match_counts(ctx, &encoded))
+            } @else if ctx.groups.is_empty() && ctx.blocks.is_empty() {
                 p { "no results for \"" (query) "\"" }
             }
-            @for (date, line) in results {
-                div.line {
-                    a.date href=(format!("{base_path}/{encoded}/{date}#{}", line.time.to_anchor())) {
+            @for block in ctx.blocks {
+                @let date = &block.date;
+                div.result-group {
+                    @let first = block.lines.first().map(|(l, _)| l.time.to_anchor()).unwrap_or_default();
+                    a.date href=(format!("{base_path}/{encoded}/{date}#{first}")) {
                         (date)
                     }
-                    " "
-                    a.ts href=(format!("{base_path}/{encoded}/{date}#{}", line.time.to_anchor())) {
-                        (line.time.to_hms())
-                    }
-                    " "
-                    @match &line.kind {
-                        LineKind::Message { nick, text } => {
-                            span.nick style=(nick_color_style(nick)) { "<" (nick) ">" }
+                    span.ev { " " (block.lines.iter().filter(|(_, matched)| *matched).count()) " matches" }
+                    @for (line, matched) in &block.lines {
+                        div class=(if *matched { "line" } else { "line context" }) {
+                            a.ts href=(format!("{base_path}/{encoded}/{date}#{}", line.time.to_anchor())) {
+                                (line.time.to_hms())
+                            }
                             " "
-                            span.msg { (linkify(text)) }
-                        },
-                        LineKind::Action { nick, text } => {
-                            span.action {
-                                "* "
-                                span.nick style=(nick_color_style(nick)) { (nick) }
+                            (search_line_body(line, opts, matched.then_some(ctx.matcher).flatten()))
+                        }
+                    }
+                }
+            }
+            @for group in ctx.groups {
+                @let date = &group.date;
+                @if let [line] = group.lines.as_slice() {
+                    (search_result_line(base_path, &encoded, date, line, opts, ctx.matcher))
+                } @else {
+                    div.result-group {
+                        @let first = group.lines.first().map(|l| l.time.to_anchor()).unwrap_or_default();
+                        a.date href=(format!("{base_path}/{encoded}/{date}#{first}")) {
+                            (date)
+                        }
+                        span.ev { " " (group.lines.len()) " matches" }
+                        @for line in &group.lines {
+                            div.line {
+                                a.ts href=(format!("{base_path}/{encoded}/{date}#{}", line.time.to_anchor())) {
+                                    (line.time.to_hms())
+                                }
                                 " "
-                                (linkify(text))
+                                (search_line_body(line, opts, ctx.matcher))
                             }
-                        },
-                        _ => {
-                            span.ev { "event" }
-                        },
+                        }
                     }
                 }
             }
         }
+        // Ungrouped searches stream in as they are found; grouping, counts
+        // and context need the full result set, so they keep the regular
+        // form submission.
+        script {
+            (PreEscaped(format!(r#"
+(function() {{
+    var form = document.querySelector('.search-form');
+    var stop = document.getElementById('search-stop');
+    var status = document.getElementById('search-status');
+    var log = document.getElementById('log');
+    var src = null;
+    function finish(text) {{
+        if (src) src.close();
+        src = null;
+        stop.hidden = true;
+        status.textContent = text;
+    }}
+    stop.onclick = function() {{ finish('stopped, ' + log.children.length + ' matches'); }};
+    // Known nicks are only fetched once the nick field is used
+    form.nick.addEventListener('focus', function() {{
+        fetch('{base_path}/{encoded}/nicks.json').then(function(r) {{ return r.json(); }}).then(function(nicks) {{
+            var list = document.getElementById('search-nicks');
+            nicks.forEach(function(nick) {{
+                var option = document.createElement('option');
+                option.value = nick;
+                list.append(option);
+            }});
+        }});
+    }}, {{ once: true }});
+    form.onsubmit = function(e) {{
+        var q = form.q.value.trim();
+        if (!q || form.group.checked || form.counts.checked || Number(form.C.value) > 0) return;
+        e.preventDefault();
+        if (src) src.close();
+        var params = new URLSearchParams({{ q: q }});
+        if (form.regex.checked) params.set('regex', '1');
+        if (form.case.checked) params.set('case', '1');
+        if (form.msg.checked) params.set('msg', '1');
+        ['nick', 'from', 'to'].forEach(function(k) {{
+            if (form[k].value.trim()) params.set(k, form[k].value.trim());
+        }});
+        history.replaceState(null, '', '?' + params);
+        log.innerHTML = '';
+        stop.hidden = false;
+        status.textContent = 'searching…';
+        src = new EventSource('{base_path}/{encoded}/search/stream?' + params);
+        src.onmessage = function(e) {{
+            log.insertAdjacentHTML('beforeend', e.data);
+            status.textContent = 'searching… ' + log.children.length + ' matches';
+        }};
+        src.addEventListener('done', function(e) {{
+            finish(e.data + ' matches');
+            if (e.data === '0') log.textContent = 'no results for "' + q + '"';
+        }});
+        src.addEventListener('search_error', function(e) {{
+            finish('');
+            var p = document.createElement('p');
+            p.className = 'search-error';
+            p.textContent = 'invalid search: ' + e.data;
+            log.append(p);
+        }});
+        src.onerror = function() {{ finish('search interrupted'); }};
+    }};
+}})();
+"#)))
+        }
+    })
+}
+
+pub fn not_found_page(title: &str, tree: &ChannelNode, base_path: &str, message: &str) -> Markup {
+    page(title, tree, base_path, html! {
+        h1 { "Not found" }
+        p { (message) }
+        p { a href=(format!("{base_path}/")) { "back to the channel list" } }
     })
 }
 
-pub fn ask_page(title: &str, tree: &ChannelNode, channel: &Channel, base_path: &str) -> Markup {
+/// `live_results` subscribes to reports other viewers of the channel finish.
+pub fn ask_page(title: &str, tree: &ChannelNode, channel: &Channel, base_path: &str, live_results: bool) -> Markup {
     let encoded = channel.path_segments.join("/").replace('#', "%23");
     page(title, tree, base_path, html! {
         header id="log-header" {
-            h1 { (&channel.name) " — ask" }
+            h1 { (channel.display_name()) " — ask" }
             div.nav-links {
                 a href=(format!("{base_path}/{encoded}/today")) { "back to logs" }
             }
@@ -338,9 +1869,32 @@ pub fn ask_page(title: &str, tree: &ChannelNode, channel: &Channel, base_path: &
                 input type="text" name="q" placeholder="ask about logs…" autocomplete="off";
                 button type="submit" { "ask" }
             }
+            div id="ask-usage" style="display:none" {}
             div id="ask-links" style="display:none" {}
             iframe id="ask-result" style="display:none" {}
             div id="ask-log" {}
+            @if live_results {
+                div id="ask-shared" {}
+            }
+        }
+        @if live_results {
+            script {
+                (PreEscaped(format!(r#"
+(function() {{
+    var shared = document.getElementById('ask-shared');
+    var src = new EventSource('{base_path}/{encoded}/ask/live');
+    src.addEventListener('report', function(e) {{
+        var d = JSON.parse(e.data);
+        var div = document.createElement('div');
+        var a = document.createElement('a');
+        a.href = d.url;
+        a.textContent = d.title;
+        div.append('new report: ', a);
+        shared.prepend(div);
+    }});
+}})();
+"#)))
+            }
         }
         script {
             (PreEscaped(format!(r#"
@@ -349,6 +1903,7 @@ pub fn ask_page(title: &str, tree: &ChannelNode, channel: &Channel, base_path: &
     var log = document.getElementById('ask-log');
     var links = document.getElementById('ask-links');
     var result = document.getElementById('ask-result');
+    var usage = document.getElementById('ask-usage');
     var btn = form.querySelector('button');
     var input = form.querySelector('input');
     function setBusy(busy) {{
@@ -366,7 +1921,21 @@ pub fn ask_page(title: &str, tree: &ChannelNode, channel: &Channel, base_path: &
         links.innerHTML = '';
         result.style.display = 'none';
         result.innerHTML = '';
-        var src = new EventSource('{base_path}/{encoded}/ask/stream?q=' + encodeURIComponent(q));
+        usage.style.display = 'none';
+        var tally = {{input: 0, output: 0, cache_read: 0, cache_create: 0, cost: null}};
+        var url = '{base_path}/{encoded}/ask/stream?q=' + encodeURIComponent(q);
+        var appendTo = new URLSearchParams(location.search).get('append_to');
+        if (appendTo) url += '&append_to=' + encodeURIComponent(appendTo);
+        var src = new EventSource(url);
+        var text = null;
+        src.addEventListener('text', function(e) {{
+            if (!text || text !== log.firstChild) {{
+                text = document.createElement('div');
+                text.className = 'ask-text';
+                log.prepend(text);
+            }}
+            text.textContent += JSON.parse(e.data).text;
+        }});
         src.addEventListener('display', function(e) {{
             var d = JSON.parse(e.data);
             var div = document.createElement('div');
@@ -402,6 +1971,16 @@ pub fn ask_page(title: &str, tree: &ChannelNode, channel: &Channel, base_path: &
                 }}
             }});
         }});
+        src.addEventListener('usage', function(e) {{
+            var d = JSON.parse(e.data);
+            ['input', 'output', 'cache_read', 'cache_create'].forEach(function(k) {{ tally[k] += d[k]; }});
+            if (d.cost !== null) tally.cost = (tally.cost || 0) + d.cost;
+            var n = function(v) {{ return v.toLocaleString(); }};
+            usage.textContent = 'tokens: ' + n(tally.input) + ' in, ' + n(tally.output) + ' out, '
+                + n(tally.cache_read) + ' cache read, ' + n(tally.cache_create) + ' cache write'
+                + (tally.cost === null ? '' : ' · ~$' + tally.cost.toFixed(4));
+            usage.style.display = 'block';
+        }});
         src.addEventListener('done', function(e) {{
             var d = JSON.parse(e.data);
             links.style.display = 'block';
@@ -431,6 +2010,227 @@ pub fn ask_page(title: &str, tree: &ChannelNode, channel: &Channel, base_path: &
     })
 }
 
+/// Plain-text summary of a line, used as a feed entry title.
+fn line_summary(line: &LogLine) -> String {
+    match &line.kind {
+        LineKind::Message { nick, text } => format!("<{nick}> {text}"),
+        LineKind::Action { nick, text } => format!("* {nick} {text}"),
+        LineKind::Join { nick, .. } => format!("{nick} joined"),
+        LineKind::Quit { nick, reason, .. } => format!("{nick} quit ({reason})"),
+        LineKind::Part { nick, reason, .. } => format!("{nick} left ({reason})"),
+        LineKind::NickChange { old_nick, new_nick } => format!("{old_nick} is now known as {new_nick}"),
+        LineKind::Kick { nick, by, reason } => format!("{nick} was kicked by {by} ({reason})"),
+        LineKind::Topic { nick, topic } => format!("{nick} changed the topic to: {topic}"),
+        LineKind::Raw { text } => text.clone(),
+    }
+}
+
+/// Atom feed of `entries` (date, line number, line), newest first. `site`
+/// is the absolute URL of the viewer root, used for links and entry IDs.
+pub fn atom_feed(channel: &Channel, site: &str, tz: &str, entries: &[(String, usize, LogLine)], opts: &RenderOptions) -> String {
+    let xml = |s: &str| html! { (s) }.into_string();
+    let encoded = channel.path_segments.join("/").replace('#', "%23");
+    let stamp = |date: &str, line: &LogLine| format!("{date}T{}{tz}", line.time);
+    let updated = entries.first().map_or_else(|| "1970-01-01T00:00:00Z".to_string(), |(d, _, l)| stamp(d, l));
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    out.push_str(&format!("  <title>{}</title>\n", xml(channel.display_name())));
+    out.push_str(&format!("  <id>{}</id>\n", xml(&format!("{site}/{encoded}/feed.xml"))));
+    out.push_str(&format!("  <link rel=\"self\" href=\"{}\"/>\n", xml(&format!("{site}/{encoded}/feed.xml"))));
+    out.push_str(&format!("  <link href=\"{}\"/>\n", xml(&format!("{site}/{encoded}/today"))));
+    out.push_str(&format!("  <updated>{updated}</updated>\n"));
+    for (date, n, line) in entries {
+        let link = format!("{site}/{encoded}/{date}#{}", line.time.to_anchor());
+        // Formatting codes and other C0 controls are not allowed in XML
+        let summary = plain_text(&line_summary(line));
+        let title: String = summary.chars().take(100).collect();
+        let author = match &line.kind {
+            LineKind::Message { nick, .. } | LineKind::Action { nick, .. } => nick.as_str(),
+            _ => "*",
+        };
+        let content = match &line.kind {
+            LineKind::Message { text, .. } | LineKind::Action { text, .. } => {
+                render_text(&plain_text(text), opts).into_string()
+            }
+            _ => xml(&summary),
+        };
+        out.push_str("  <entry>\n");
+        out.push_str(&format!("    <title>{}</title>\n", xml(&title)));
+        out.push_str(&format!("    <id>{}</id>\n", xml(&format!("{link}-{n}"))));
+        out.push_str(&format!("    <link href=\"{}\"/>\n", xml(&link)));
+        out.push_str(&format!("    <updated>{}</updated>\n", stamp(date, line)));
+        out.push_str(&format!("    <author><name>{}</name></author>\n", xml(author)));
+        out.push_str(&format!("    <content type=\"html\">{}</content>\n", xml(&content)));
+        out.push_str("  </entry>\n");
+    }
+    out.push_str("</feed>\n");
+    out
+}
+
+/// Day of the week, 0 = Monday.
+fn weekday(year: i32, month: u32, day: u32) -> u32 {
+    const T: [i32; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+    let y = if month < 3 { year - 1 } else { year };
+    let sunday_first = (y + y / 4 - y / 100 + y / 400 + T[month as usize - 1] + day as i32).rem_euclid(7);
+    ((sunday_first + 6) % 7) as u32
+}
+
+fn days_in_month(year: i32, month: u32) -> u32 {
+    match month {
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Month grids, newest first, of the days in `days` (date, message count)
+/// shaded by activity relative to the busiest day.
+pub fn calendar_page(title: &str, tree: &ChannelNode, channel: &Channel, base_path: &str, days: &[(String, usize)]) -> Markup {
+    let encoded = channel.path_segments.join("/").replace('#', "%23");
+    let counts: BTreeMap<&str, usize> = days.iter().map(|(d, n)| (d.as_str(), *n)).collect();
+    let max = days.iter().map(|(_, n)| *n).max().unwrap_or(0).max(1);
+    let mut months: Vec<&str> = days.iter().filter_map(|(d, _)| d.get(..7)).collect();
+    months.dedup();
+    months.reverse();
+    page(title, tree, base_path, html! {
+        header id="log-header" {
+            h1 { (channel.display_name()) " — calendar" }
+            div.nav-links {
+                a href=(format!("{base_path}/{encoded}/today")) { "back to logs" }
+            }
+        }
+        div.calendar {
+            @for month in &months {
+                @let (year, mon) = (month[..4].parse::<i32>().unwrap_or(1970), month[5..].parse::<u32>().unwrap_or(1));
+                table.month {
+                    caption { (month) }
+                    tr { @for d in ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"] { th { (d) } } }
+                    @let offset = weekday(year, mon, 1);
+                    @let cells: Vec<Option<u32>> = (0..offset).map(|_| None).chain((1..=days_in_month(year, mon)).map(Some)).collect();
+                    @for week in cells.chunks(7) {
+                        tr {
+                            @for cell in week {
+                                @match cell {
+                                    Some(day) => {
+                                        @let date = format!("{month}-{day:02}");
+                                        @if let Some(&n) = counts.get(date.as_str()) {
+                                            td class=(format!("has-log l{}", (n * 4).div_ceil(max))) {
+                                                a href=(format!("{base_path}/{encoded}/{date}")) title=(format!("{date}: {n} messages")) { (day) }
+                                            }
+                                        } @else {
+                                            td { (day) }
+                                        }
+                                    },
+                                    None => td {},
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Bar for a stats table, `n` as a share of the largest value `max`.
+fn stat_bar(n: usize, max: usize) -> Markup {
+    html! { div.stat-bar style=(format!("width:{:.1}%", n as f64 * 100.0 / max.max(1) as f64)) {} }
+}
+
+pub fn stats_page(
+    title: &str,
+    tree: &ChannelNode,
+    channel: &Channel,
+    base_path: &str,
+    opts: &RenderOptions,
+    stats: &ChannelStats,
+) -> Markup {
+    let encoded = channel.path_segments.join("/").replace('#', "%23");
+    let total = &stats.total;
+    let messages = total.total_messages();
+    let nicks = total.top_nicks();
+    let top = nicks.first().map_or(0, |(_, n)| *n);
+    let busiest_hour = total.hours.iter().copied().max().unwrap_or(0);
+    let churn = [("joins", total.joins), ("parts", total.parts), ("quits", total.quits), ("kicks", total.kicks)];
+    let busiest_churn = churn.iter().map(|(_, n)| *n).max().unwrap_or(0);
+    page(title, tree, base_path, html! {
+        header id="log-header" {
+            h1 { (channel.display_name()) " — stats" }
+            div.nav-links {
+                a href=(format!("{base_path}/{encoded}/today")) { "back to logs" }
+                " "
+                a href=(format!("{base_path}/{encoded}/calendar")) { "calendar" }
+            }
+        }
+        form.stats-range method="get" {
+            input type="date" name="from" value=[stats.first.as_deref()];
+            " – "
+            input type="date" name="to" value=[stats.last.as_deref()];
+            " "
+            button type="submit" { "show" }
+        }
+        div.stats {
+            p {
+                (messages) " messages from " (nicks.len()) " nicks over " (stats.days) @if stats.days == 1 { " day" } @else { " days" }
+                @if let (Some(first), Some(last)) = (&stats.first, &stats.last) {
+                    " (" (first) " to " (last) ")"
+                }
+            }
+            h2 { "Hours" }
+            table.stats-hours {
+                @for (hour, &n) in total.hours.iter().enumerate() {
+                    tr { th { (format!("{hour:02}")) } td.bar-cell { (stat_bar(n, busiest_hour)) } td.count { (n) } }
+                }
+            }
+            h2 { "Joins and departures" }
+            table.stats-churn {
+                @for (label, n) in churn {
+                    tr { th { (label) } td.bar-cell { (stat_bar(n, busiest_churn)) } td.count { (n) } }
+                }
+            }
+            h2 { "Nicks" }
+            table.stats-nicks {
+                @for (nick, n) in &nicks {
+                    @let alias = opts.nick_aliases.get(*nick);
+                    tr {
+                        th { span.nick style=(nick_color_style(nick, opts.nick_colors)) title=[alias.map(|_| nick)] { (alias.map_or(*nick, String::as_str)) } }
+                        td.bar-cell { (stat_bar(*n, top)) }
+                        td.count { (n) }
+                        td.share { (format!("{:.1}%", *n as f64 * 100.0 / messages.max(1) as f64)) }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Raw log as an unwrapped, horizontally scrolling `<pre>`.
+pub fn raw_page(title: &str, channel: &Channel, date: &str, content: &str, base_path: &str) -> Markup {
+    let encoded = channel.path_segments.join("/").replace('#', "%23");
+    html! {
+        (DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="utf-8";
+                meta name="viewport" content="width=device-width, initial-scale=1";
+                title { (title) " — " (channel.display_name()) " " (date) " raw" }
+                link rel="stylesheet" href=(format!("{base_path}/static/style.css"));
+                script { (PreEscaped(THEME_SCRIPT)) }
+            }
+            body {
+                main style="margin-left:0; padding:1em" {
+                    div.nav-links {
+                        a href=(format!("{base_path}/{encoded}/{date}")) { "back to log" }
+                        " "
+                        a href=(format!("{base_path}/{encoded}/{date}/raw")) { "plain text" }
+                    }
+                    pre.raw-nowrap { (content) }
+                }
+            }
+        }
+    }
+}
+
 pub fn ask_output_page(title: &str, md_filename: &str, content: &str, base_path: &str) -> Markup {
     let mut html_output = String::new();
     let parser = pulldown_cmark::Parser::new(content);
@@ -444,6 +2244,7 @@ pub fn ask_output_page(title: &str, md_filename: &str, content: &str, base_path:
                 meta name="viewport" content="width=device-width, initial-scale=1";
                 title { (title) " — " (md_filename) }
                 link rel="stylesheet" href=(format!("{base_path}/static/style.css"));
+                script { (PreEscaped(THEME_SCRIPT)) }
             }
             body {
                 main style="margin-left:0; max-width:800px; margin:0 auto; padding:1em; overflow-wrap:break-word; min-width:0; width:100%" {
@@ -460,16 +2261,22 @@ pub fn ask_output_page(title: &str, md_filename: &str, content: &str, base_path:
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parser::{LogFormat, parse_line};
+
+    fn link(text: &str) -> String {
+        let config = Config::default();
+        linkify(text, &RenderOptions::new(&config), None, true).into_string()
+    }
 
     #[test]
     fn test_linkify_plain() {
-        let out = linkify("hello world").into_string();
+        let out = link("hello world");
         assert_eq!(out, "hello world");
     }
 
     #[test]
     fn test_linkify_url() {
-        let out = linkify("see https://example.com/ here").into_string();
+        let out = link("see https://example.com/ here");
         assert!(out.contains(r#"<a href="https://example.com/""#));
         assert!(out.contains("see "));
         assert!(out.contains(" here"));
@@ -477,9 +2284,109 @@ mod tests {
 
     #[test]
     fn test_linkify_escapes_html() {
-        let out = linkify("<script>alert(1)</script>").into_string();
+        let out = link("<script>alert(1)</script>");
         assert!(!out.contains("<script>"));
         assert!(out.contains("&lt;script&gt;"));
+        let out = link(r#"https://x.com/"><b>"#);
+        assert!(out.contains(r#"href="https://x.com/&quot;&gt;&lt;b&gt;""#));
+        assert!(!out.contains("<b>"));
+    }
+
+    #[test]
+    fn test_linkify_trims_punctuation() {
+        assert_eq!(
+            link("(see https://x.com/a)."),
+            r#"(see <a href="https://x.com/a" target="_blank" rel="noopener">https://x.com/a</a>)."#
+        );
+        let out = link("https://en.wikipedia.org/wiki/Rust_(programming_language), ok");
+        assert!(out.contains(r#"href="https://en.wikipedia.org/wiki/Rust_(programming_language)""#));
+        assert!(out.ends_with("</a>, ok"));
+        assert_eq!(link("just https:// and http://."), "just https:// and http://.");
+    }
+
+    #[test]
+    fn test_linkify_schemes() {
+        assert_eq!(
+            link("mail mailto:alice@example.org"),
+            r#"mail <a href="mailto:alice@example.org" target="_blank" rel="noopener">mailto:alice@example.org</a>"#
+        );
+        assert!(link("join irc://irc.oftc.net/#bcachefs").contains(r#"href="irc://irc.oftc.net/#bcachefs""#));
+        assert!(link("get ftp://ftp.example.org/x.tar").contains("<a "));
+        assert!(!link("notmailto:x@y").contains("<a "));
+        let config = Config { link_schemes: vec!["http://".into()], ..Config::default() };
+        assert!(!linkify("ftp://example.org/", &RenderOptions::new(&config), None, true).into_string().contains("<a "));
+    }
+
+    #[test]
+    fn test_media_previews() {
+        let config = Config::default();
+        let opts = RenderOptions { media_previews: true, ..RenderOptions::new(&config) };
+        let preview = |text: &str| linkify(text, &opts, None, true).into_string();
+
+        let out = preview("look https://x.org/cat.PNG?size=2");
+        assert!(out.contains(r#"<a href="https://x.org/cat.PNG?size=2" target="_blank" rel="noopener">"#), "{out}");
+        assert!(out.contains(r#"<img src="https://x.org/cat.PNG?size=2" loading="lazy""#), "{out}");
+        let out = preview("https://youtu.be/dQw4w9WgXcQ?t=1 and https://www.youtube.com/watch?v=dQw4w9WgXcQ");
+        assert_eq!(out.matches(r#"<img src="https://i.ytimg.com/vi/dQw4w9WgXcQ/hqdefault.jpg""#).count(), 2, "{out}");
+        assert!(preview("https://x.org/clip.webm").contains(r#"<video class="media-preview" src="https://x.org/clip.webm" controls preload="none">"#));
+        let out = preview(r#"https://x.org/"><b>.gif"#);
+        assert!(out.contains(r#"src="https://x.org/&quot;&gt;&lt;b&gt;.gif""#) && !out.contains("<b>"), "{out}");
+
+        for text in ["https://x.org/page.html", "https://x.org/png", "ftp://x.org/cat.png", "https://youtube.com/watch?v=short"] {
+            assert!(!preview(text).contains("media-preview"), "{text}");
+        }
+        assert_eq!(link("https://x.org/cat.png"), r#"<a href="https://x.org/cat.png" target="_blank" rel="noopener">https://x.org/cat.png</a>"#);
+    }
+
+    #[test]
+    fn test_code_blocks() {
+        let config = Config::default();
+        let opts = RenderOptions { code_blocks: true, ..RenderOptions::new(&config) };
+        let code = |text: &str| render_text(text, &opts).into_string();
+
+        assert_eq!(code("try ```let x = a<b;``` then"), r#"try <pre class="code"><code>let x = a&lt;b;</code></pre> then"#);
+        assert_eq!(code("```rust fn main() {}```"), r#"<pre class="code"><code class="language-rust">fn main() {}</code></pre>"#);
+        assert_eq!(code("    at foo (bar.js:1)"), r#"<pre class="code"><code>    at foo (bar.js:1)</code></pre>"#);
+        assert_eq!(code("run `cargo test`, then (`ls`)"), "run <code>cargo test</code>, then (<code>ls</code>)");
+        // Code is not linked; links and their backticks are left alone
+        assert_eq!(code("`https://x.org/`"), "<code>https://x.org/</code>");
+        let out = code("see https://x.org/a`b` and it`s");
+        assert!(out.contains(r#"<a href="https://x.org/a`b`""#) && !out.contains("<code>"), "{out}");
+        for text in ["`` empty", "``` ```", "` spaced `", "\x02bold\x02 `x`"] {
+            assert!(!code(text).contains("<code>"), "{text}");
+        }
+        assert_eq!(render_text("```x```", &RenderOptions::new(&config)).into_string(), "```x```");
+    }
+
+    #[test]
+    fn test_mentions() {
+        let config = Config::default();
+        let day = [
+            parse_line("[10:00:00] <Alice> morning", LogFormat::Znc).unwrap(),
+            parse_line("[10:00:05] <bob> hey", LogFormat::Znc).unwrap(),
+        ];
+        let participants = participants(&day);
+        let opts = RenderOptions { participants: Some(&participants), ..RenderOptions::new(&config) };
+        let mention = |text: &str| render_text(text, &opts).into_string();
+
+        let out = mention("alice: hi");
+        assert!(out.starts_with(r##"<a class="mention" href="#T100000" data-nick="Alice""##), "{out}");
+        assert!(out.ends_with(">alice</a>: hi"));
+        assert!(mention("bob, look").contains(r#"data-nick="bob""#));
+        assert!(mention("thanks @bob!").contains(">bob</a>!"));
+        assert!(mention("ask alice: she knows").contains(r#"data-nick="Alice""#));
+
+        for text in [
+            "look, bob, here",
+            "carol: not here today",
+            "mail bob@alice.org",
+            "see https://x.com/@alice and https://x.com/bob:",
+            "run `alice: x` here",
+            "alice:x",
+        ] {
+            assert!(!mention(text).contains("mention"), "{text}");
+        }
+        assert!(!render_text("alice: hi", &RenderOptions::new(&config)).into_string().contains("mention"));
     }
 
     #[test]
@@ -489,4 +2396,209 @@ mod tests {
         assert_eq!(h1, h2);
         assert_ne!(nick_hue("py1hon"), nick_hue("TiCPU"));
     }
+
+    #[test]
+    fn test_nick_color_palette_and_pins() {
+        let yaml = "logs_dirs: []\nnick_colors:\n  palette: [10, \"#c678dd\"]\n  pinned:\n    ChanServ: gray\n    bot: 200\n";
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.nick_colors.validate(), Ok(()));
+        let colors = &config.nick_colors;
+        for nick in ["ChanServ", "chanserv", "CHANSERV"] {
+            assert_eq!(nick_color_style(nick, colors), "color:gray");
+        }
+        assert_eq!(nick_color_style("BOT", colors), "color:hsl(200,70%,var(--nick-lightness))");
+
+        // Every other nick lands on a palette entry, the same one each time
+        let styles: Vec<String> = ["alice", "bob", "carol", "dave"].iter().map(|n| nick_color_style(n, colors)).collect();
+        assert!(styles.iter().all(|s| s == "color:hsl(10,70%,var(--nick-lightness))" || s == "color:#c678dd"), "{styles:?}");
+        assert!(styles.iter().any(|s| s == "color:#c678dd"));
+        assert_eq!(nick_color_style("alice", colors), styles[0]);
+        let opts = RenderOptions::new(&config);
+        assert!(nick_span("ChanServ", &opts, true).into_string().contains(r#"style="color:gray""#));
+        let unset = NickColors::default();
+        assert_eq!(nick_color_style("alice", &unset), format!("color:hsl({},70%,var(--nick-lightness))", nick_hue("alice")));
+
+        let bad: Config = serde_yaml::from_str("logs_dirs: []\nnick_colors:\n  pinned:\n    x: \"red; display: none\"\n").unwrap();
+        assert!(bad.nick_colors.validate().is_err());
+        let bad: Config = serde_yaml::from_str("logs_dirs: []\nnick_colors:\n  palette: [360]\n").unwrap();
+        assert!(bad.nick_colors.validate().is_err());
+    }
+
+    #[test]
+    fn test_nick_alias_keeps_real_nick_color() {
+        let mut config = Config::default();
+        config.nick_aliases.insert("alice_irc".into(), "Alice".into());
+        let opts = RenderOptions::new(&config);
+        let out = nick_span("alice_irc", &opts, true).into_string();
+        assert!(out.contains("&lt;Alice&gt;"));
+        assert!(out.contains(r#"title="alice_irc""#));
+        assert!(out.contains(&nick_color_style("alice_irc", &config.nick_colors)));
+
+        let out = nick_span("bob", &opts, false).into_string();
+        assert!(out.contains(">bob<"));
+        assert!(!out.contains("title="));
+    }
+
+    #[test]
+    fn test_render_text_escapes_once() {
+        let config = Config::default();
+        let opts = RenderOptions::new(&config);
+        let out = render_text("<b> https://x.org/?a=1&b=2 in #chan & co", &opts).into_string();
+        assert_eq!(
+            out,
+            "&lt;b&gt; <a href=\"https://x.org/?a=1&amp;b=2\" target=\"_blank\" rel=\"noopener\">https://x.org/?a=1&amp;b=2</a> in #chan &amp; co"
+        );
+    }
+
+    #[test]
+    fn test_render_text_irc_formatting() {
+        let mut config = Config::default();
+        let out = render_text("\x02\x0304,01bold red https://x.org/\x0f #chan", &RenderOptions::new(&config));
+        assert_eq!(
+            out.into_string(),
+            "<span style=\"font-weight:bold;color:#ff0000;background-color:#000000\">bold red \
+             <a href=\"https://x.org/\" target=\"_blank\" rel=\"noopener\">https://x.org/</a></span> #chan"
+        );
+        let out = render_text("\x1dit\x1d \x0399x\x03,5 y\x16z", &RenderOptions::new(&config));
+        assert_eq!(out.into_string(), "<span style=\"font-style:italic\">it</span> x,5 yz");
+
+        config.irc_formatting = false;
+        let out = render_text("\x02bold\x02 <b>", &RenderOptions::new(&config));
+        assert_eq!(out.into_string(), "bold &lt;b&gt;");
+    }
+
+    #[test]
+    fn test_render_text_channel_refs() {
+        let config = Config::default();
+        let refs = ChannelRefs::from([("#rust".to_string(), "/OFTC/%23rust/today".to_string())]);
+        let opts = RenderOptions { channel_refs: Some(&refs), ..RenderOptions::new(&config) };
+        // Colored text with a URL, a known channel, an unknown one and a URL fragment
+        let out = render_text("\x0304see https://x.org/#rust in #Rust,\x03 #nope x#rust", &opts).into_string();
+        assert_eq!(
+            out,
+            "<span style=\"color:#ff0000\">see <a href=\"https://x.org/#rust\" target=\"_blank\" rel=\"noopener\">\
+             https://x.org/#rust</a> in <a class=\"channel-ref\" href=\"/OFTC/%23rust/today\">#Rust</a>,</span> \
+             #nope x#rust"
+        );
+        let out = render_text("(#rust) `#rust`", &opts).into_string();
+        assert!(out.starts_with("(<a class=\"channel-ref\""), "{out}");
+        assert!(out.ends_with("`#rust`"), "{out}");
+    }
+
+    #[test]
+    fn test_search_matches_marked() {
+        let config = Config::default();
+        let opts = RenderOptions::new(&config);
+        let matcher = Matcher::new("BAR", false, false).unwrap();
+        let out = render_text_marked("<bar> see https://x.org/bar bar", &opts, Some(&matcher)).into_string();
+        assert_eq!(
+            out,
+            "&lt;<mark>bar</mark>&gt; see <a href=\"https://x.org/bar\" target=\"_blank\" rel=\"noopener\">\
+             https://x.org/<mark>bar</mark></a> <mark>bar</mark>"
+        );
+        let matcher = Matcher::new("a+|aa", true, false).unwrap();
+        let out = render_text_marked("baaab", &opts, Some(&matcher)).into_string();
+        assert_eq!(out, "b<mark>aaa</mark>b");
+    }
+
+    #[test]
+    fn test_collapse_event_burst() {
+        let config = Config::default();
+        let opts = RenderOptions::new(&config);
+        let log = "[12:00:59] <a> hi\n\
+            [12:01:00] *** Joins: b (~b@h)\n\
+            [12:01:01] *** Joins: c (~c@h)\n\
+            [12:01:02] *** Joins: d (~d@h)\n\
+            [12:01:03] *** Quits: e (~e@h) (bye)\n\
+            [12:01:04] *** Quits: f (~f@h)\n\
+            [12:01:05] <a> still here\n\
+            [12:01:06] *** Joins: g (~g@h)\n\
+            [12:02:00] *** Parts: g (~g@h)\n";
+        let lines: Vec<_> = log
+            .lines()
+            .map(|l| (None, crate::parser::parse_line(l, crate::parser::LogFormat::Znc).unwrap()))
+            .collect();
+
+        let out = render_lines(&lines, &opts, true).into_string();
+        assert_eq!(out.matches("<details class=\"event-burst\">").count(), 1);
+        assert!(out.contains("<summary>12:01 — 3 joins, 2 quits</summary>"));
+        assert_eq!(out.matches("class=\"line event\"").count(), 7);
+        assert!(out.contains("</details><div class=\"line\" id=\"T120105\""));
+
+        let out = render_lines(&lines, &opts, false).into_string();
+        assert!(!out.contains("<details class=\"event-burst"));
+    }
+
+    #[test]
+    fn test_render_line_data_nick() {
+        let config = Config::default();
+        let opts = RenderOptions::new(&config);
+        let time = Time { hour: 1, minute: 2, second: 3 };
+        let msg = LogLine { time, kind: LineKind::Message { nick: "alice".into(), text: "hi".into() } };
+        assert!(render_line(&msg, &opts, None).into_string().contains(r#"data-nick="alice""#));
+        let change = LogLine { time, kind: LineKind::NickChange { old_nick: "bob".into(), new_nick: "bobby".into() } };
+        assert!(render_line(&change, &opts, None).into_string().contains(r#"data-nick="bob bobby""#));
+        let raw = LogLine { time, kind: LineKind::Raw { text: "???".into() } };
+        assert!(!render_line(&raw, &opts, None).into_string().contains("data-nick"));
+    }
+
+    #[test]
+    fn test_weekday() {
+        assert_eq!(weekday(2025, 1, 1), 2);
+        assert_eq!(weekday(2024, 2, 29), 3);
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(1900, 2), 28);
+    }
+
+    #[test]
+    fn test_fold_text() {
+        assert_eq!(fold_text("short", 10).into_string(), "short");
+        assert_eq!(fold_text("short", 0).into_string(), "short");
+        let out = fold_text("héllo world", 5).into_string();
+        assert!(out.starts_with("<details class=\"fold\"><summary>héllo<span"));
+        assert!(out.ends_with("</summary> world</details>"));
+    }
+
+    #[test]
+    fn test_sidebar_favorites() {
+        let mut tree = ChannelNode::default();
+        for (name, favorite) in [("#a", false), ("#b", true)] {
+            let node = tree.children.entry("OFTC".into()).or_default().children.entry(name.into()).or_default();
+            node.channel = Some(Channel {
+                name: name.into(),
+                path_segments: vec!["OFTC".into(), name.into()],
+                dirs: Vec::new(),
+                favorite,
+                sources: Vec::new(),
+                overrides: Default::default(),
+                latest: Default::default(),
+            });
+        }
+        let out = page("t", &tree, "", html! {}).into_string();
+        assert!(out.contains(r##"<ul class="favorites"><li><a href="/OFTC/%23b/today" title="OFTC/#b">#b</a></li></ul>"##));
+
+        tree.children.get_mut("OFTC").unwrap().children.get_mut("#b").unwrap().channel.as_mut().unwrap().favorite = false;
+        assert!(!page("t", &tree, "", html! {}).into_string().contains("favorites"));
+    }
+
+    #[test]
+    fn test_data_line_only_when_enabled() {
+        let line = crate::parser::parse_line("[12:00:00] <a> hi", crate::parser::LogFormat::Znc).unwrap();
+        let mut config = Config::default();
+        let out = render_line(&line, &RenderOptions::new(&config), Some(7)).into_string();
+        assert!(!out.contains("data-line"));
+
+        config.line_numbers = true;
+        let out = render_line(&line, &RenderOptions::new(&config), Some(7)).into_string();
+        assert!(out.contains(r#"data-line="7""#));
+    }
+
+    #[test]
+    fn test_data_raw_and_copy_buttons() {
+        let line = crate::parser::parse_line("[12:00:00] <a> hi & bye", crate::parser::LogFormat::Znc).unwrap();
+        let out = render_line(&line, &RenderOptions::new(&Config::default()), None).into_string();
+        assert!(out.contains(r#"data-raw="[12:00:00] &lt;a&gt; hi &amp; bye""#), "{out}");
+        assert!(out.contains(r#"<button class="copy-raw" type="button""#), "{out}");
+        assert!(out.contains(r#"<button class="copy-link" type="button""#), "{out}");
+    }
 }